@@ -0,0 +1,750 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::fs::Metadata;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use hold::blob::{satisfiable_range, Blob, BlobEntry, Head};
+use hold::config::Registry;
+use hold::error::Error;
+use hold::listing::{Cursor, Page};
+use hold::metadata::BlobMetadata;
+use hold::provider::{EntryStream, Provider};
+use hold::receipt::StoreReceipt;
+use hold::writer::BlobWriter;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+/// Prefix of the temporary files blobs are written to before being moved into place
+const TEMP_PREFIX: &str = ".hold-tmp-";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Attempts at creating the temporary file of a write, whose directory may be removed
+/// in between by a concurrent delete of the last blob in it
+const CREATE_ATTEMPTS: usize = 3;
+
+/// Hold Provider storing blobs as files under a root directory.
+///
+/// Keys map to paths relative to the root, `/` separating directories.
+/// Keys that would escape the root, like `../secret`, are rejected.
+/// Only the modification time of files is kept as blob metadata.
+/// Wrappers storing state in the metadata, like `CompressedProvider`, cannot be used over it.
+pub struct FileSystemProvider {
+    root: PathBuf,
+}
+
+impl FileSystemProvider {
+    pub fn new<P: Into<PathBuf>>(root: P) -> FileSystemProvider {
+        FileSystemProvider { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a key to its path under the root directory
+    pub fn path_for(&self, key: &str) -> hold::Result<PathBuf> {
+        match self.resolve(key)? {
+            (_, 0) => Err(Error::invalid_key(key, "empty key")),
+            (path, _) => Ok(path),
+        }
+    }
+
+    /// Maps a key to a path under the root directory, along with its number of components
+    fn resolve(&self, key: &str) -> hold::Result<(PathBuf, usize)> {
+        let mut path = self.root.clone();
+        let mut components = 0;
+        for segment in key.split('/') {
+            if segment.is_empty() || segment == "." {
+                continue;
+            }
+            if segment.contains('\0') || segment.contains('\\') {
+                return Err(Error::invalid_key(key, "invalid character in key"));
+            }
+            if segment.starts_with(TEMP_PREFIX) {
+                return Err(Error::invalid_key(key, "reserved name in key"));
+            }
+            match Path::new(segment).components().next() {
+                Some(Component::Normal(_)) => path.push(segment),
+                _ => return Err(Error::invalid_key(key, "key escapes the root directory")),
+            }
+            components += 1;
+        }
+        Ok((path, components))
+    }
+
+    /// Maps a path under the root directory back to its key
+    fn key_for(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let segments: Option<Vec<_>> = relative.iter().map(|segment| segment.to_str()).collect();
+        Some(segments?.join("/"))
+    }
+
+    /// Opens the file of a blob, if it exists
+    async fn open(&self, key: &str) -> hold::Result<Option<(File, Metadata)>> {
+        let path = self.path_for(key)?;
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                log::debug!("Blob {} not found", key);
+                return Ok(None);
+            }
+            Err(err) => return Err(Error::io(err)),
+        };
+        let metadata = file.metadata().await.map_err(Error::io)?;
+        if !metadata.is_file() {
+            log::debug!("Blob {} not found", key);
+            return Ok(None);
+        }
+        Ok(Some((file, metadata)))
+    }
+
+    /// Writes the content to a temporary file and moves it under the blob key,
+    /// unless the key already holds a blob if `if_absent` is set
+    async fn write_file<S>(
+        &self,
+        key: String,
+        content: S,
+        if_absent: bool,
+    ) -> hold::Result<StoreReceipt>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        let path = self.path_for(&key)?;
+        // write next to the final path and move into place once complete,
+        // so that partial writes are never visible under the blob key
+        let temp_path = temp_path_for(&path);
+        let mut file = create_file(&temp_path).await.map_err(Error::io)?;
+        futures::pin_mut!(content);
+        let mut size = 0;
+        let written: std::io::Result<()> = async {
+            while let Some(chunk) = content.next().await {
+                let chunk = chunk?;
+                file.write_all(&chunk).await?;
+                size += chunk.len();
+            }
+            file.flush().await?;
+            file.sync_all().await
+        }
+        .await;
+        drop(file);
+
+        if let Err(err) = written {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(Error::io(err));
+        }
+        // linking fails if the path exists, while renaming replaces it
+        let moved = if if_absent {
+            fs::hard_link(&temp_path, &path).await
+        } else {
+            fs::rename(&temp_path, &path).await
+        };
+        if if_absent || moved.is_err() {
+            let _ = fs::remove_file(&temp_path).await;
+        }
+        match moved {
+            Ok(()) => Ok(StoreReceipt::new(key, size)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                Err(Error::precondition_failed(key))
+            }
+            Err(err) => Err(Error::io(err)),
+        }
+    }
+
+    /// Directory listings of the prefix start from, as only it and its subdirectories
+    /// can hold matching keys
+    fn start_dir(&self, prefix: &str) -> hold::Result<PathBuf> {
+        match prefix.rfind('/') {
+            Some(slash) => self.resolve(&prefix[..slash]).map(|(dir, _)| dir),
+            None => Ok(self.root.clone()),
+        }
+    }
+
+    /// Reads a directory, returning the files matching the prefix and the directories
+    /// that may contain some
+    async fn children(&self, dir: &Path, prefix: &str) -> std::io::Result<Vec<Child>> {
+        let mut entries = match fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) if err.kind() == ErrorKind::NotADirectory => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut children = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX);
+            let key = match self.key_for(&path) {
+                Some(key) if !hidden && key.starts_with(prefix) => key,
+                _ => continue,
+            };
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                children.push(Child::Dir(path, format!("{}/", key)));
+            } else if metadata.is_file() {
+                children.push(Child::File(BlobEntry {
+                    last_modified: metadata.modified().ok(),
+                    ..BlobEntry::new(key, metadata.len() as usize)
+                }));
+            }
+        }
+        Ok(children)
+    }
+
+    /// Reads a directory, queueing the files matching the prefix and the directories
+    /// that may contain some
+    async fn read_dir(&self, dir: &Path, prefix: &str, walk: &mut Walk) -> std::io::Result<()> {
+        for child in self.children(dir, prefix).await? {
+            match child {
+                Child::Dir(path, _) => walk.dirs.push(path),
+                Child::File(entry) => walk.entries.push_back(entry),
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the directories left empty by a deletion, up to the root.
+    /// Concurrent writes into them create them again, see `create_file`.
+    async fn remove_empty_parents(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if current == self.root || !current.starts_with(&self.root) {
+                break;
+            }
+            // fails, and stops, on the first directory that is not empty
+            if fs::remove_dir(current).await.is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+/// Entry of a directory
+enum Child {
+    /// Subdirectory, with the prefix of the keys it holds
+    Dir(PathBuf, String),
+    File(BlobEntry),
+}
+
+impl Child {
+    /// Position of the entry in key order, a subdirectory sorting like the keys it holds
+    fn sort_key(&self) -> &str {
+        match self {
+            Child::Dir(_, prefix) => prefix,
+            Child::File(entry) => &entry.key,
+        }
+    }
+}
+
+/// Pending state of a listing
+struct Walk {
+    dirs: Vec<PathBuf>,
+    entries: VecDeque<BlobEntry>,
+}
+
+/// Metadata of a blob stored in a file
+fn blob_metadata(metadata: &Metadata) -> BlobMetadata {
+    BlobMetadata {
+        last_modified: metadata.modified().ok(),
+        ..BlobMetadata::default()
+    }
+}
+
+/// Creates a file along with its parent directories, again if a delete removes them
+/// in between. Once the file exists its directory is no longer empty, and stays.
+async fn create_file(path: &Path) -> std::io::Result<File> {
+    let mut attempt = 1;
+    loop {
+        let created = async {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            File::create(path).await
+        }
+        .await;
+        match created {
+            // creating the directories fails with AlreadyExists too, when one of them
+            // is removed right after being found to exist
+            Err(err)
+                if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::AlreadyExists)
+                    && attempt < CREATE_ATTEMPTS =>
+            {
+                log::debug!(
+                    "Directory of {} removed concurrently, retrying",
+                    path.display()
+                );
+                attempt += 1;
+            }
+            created => return created,
+        }
+    }
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(
+        "{}{}-{}-{}",
+        TEMP_PREFIX,
+        std::process::id(),
+        counter,
+        name
+    ))
+}
+
+#[async_trait]
+impl Provider for FileSystemProvider {
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn get_blob(&self, key: &str) -> hold::Result<Option<Blob>> {
+        log::debug!("Fetching blob {}", key);
+        let (file, metadata) = match self.open(key).await? {
+            Some(opened) => opened,
+            None => return Ok(None),
+        };
+
+        let stream = ReaderStream::new(file).map_ok(|chunk| Bytes::from(Vec::from(chunk)));
+        let blob = Blob::new(key, metadata.len() as usize, stream);
+        Ok(Some(blob.with_metadata(blob_metadata(&metadata))))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn head_blob(&self, key: &str) -> hold::Result<Option<Head>> {
+        log::debug!("Fetching blob {} metadata", key);
+        let path = self.path_for(key)?;
+        match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Ok(Some(Head {
+                size: metadata.len() as usize,
+                metadata: blob_metadata(&metadata),
+            })),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::io(err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> hold::Result<Option<Blob>> {
+        log::debug!("Fetching blob {} range {:?}", key, range);
+        let (mut file, metadata) = match self.open(key).await? {
+            Some(opened) => opened,
+            None => return Ok(None),
+        };
+        let range = satisfiable_range(key, metadata.len() as usize, range)?;
+        file.seek(SeekFrom::Start(range.start as u64))
+            .await
+            .map_err(Error::io)?;
+
+        let content = file.take(range.len() as u64);
+        let stream = ReaderStream::new(content).map_ok(|chunk| Bytes::from(Vec::from(chunk)));
+        let blob = Blob::new(key, range.len(), stream);
+        Ok(Some(blob.with_metadata(blob_metadata(&metadata))))
+    }
+
+    #[tracing::instrument(skip(self, blob), fields(provider = "fs", key = blob.key()))]
+    async fn store_blob(&self, blob: Blob) -> hold::Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        log::debug!("Storing blob {} of {} bytes", key, blob.size());
+        self.write_file(key, blob.into_byte_stream(), false).await
+    }
+
+    #[tracing::instrument(skip(self, blob), fields(provider = "fs", key = blob.key()))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> hold::Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        log::debug!("Storing blob {} of {} bytes if absent", key, blob.size());
+        self.write_file(key, blob.into_byte_stream(), true).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, _metadata: BlobMetadata) -> BlobWriter<'a> {
+        log::debug!("Opening writer for blob {}", key);
+        BlobWriter::new(move |content| self.write_file(key.to_string(), content, false))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn is_blob_present(&self, key: &str) -> hold::Result<bool> {
+        log::debug!("Checking blob {} presence", key);
+        let path = self.path_for(key)?;
+        match fs::metadata(&path).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(Error::io(err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn delete_blob(&self, key: &str) -> hold::Result<()> {
+        log::debug!("Deleting blob {}", key);
+        let path = self.path_for(key)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => {
+                self.remove_empty_parents(&path).await;
+                Ok(())
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::io(err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> hold::Result<Option<StoreReceipt>> {
+        log::debug!("Copying blob {} to {}", src_key, dst_key);
+        let src_path = self.path_for(src_key)?;
+        let dst_path = self.path_for(dst_key)?;
+        if !fs::metadata(&src_path)
+            .await
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false)
+        {
+            log::debug!("Blob {} not found", src_key);
+            return Ok(None);
+        }
+        if let Some(parent) = dst_path.parent() {
+            fs::create_dir_all(parent).await.map_err(Error::io)?;
+        }
+
+        let temp_path = temp_path_for(&dst_path);
+        let size = match fs::copy(&src_path, &temp_path).await {
+            Ok(size) => size,
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path).await;
+                return match err.kind() {
+                    ErrorKind::NotFound => Ok(None),
+                    _ => Err(Error::io(err)),
+                };
+            }
+        };
+        if let Err(err) = fs::rename(&temp_path, &dst_path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(Error::io(err));
+        }
+        Ok(Some(StoreReceipt::new(dst_key, size as usize)))
+    }
+
+    /// Only the modification time of files is kept, so the metadata is ignored
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        _metadata: BlobMetadata,
+    ) -> hold::Result<Option<StoreReceipt>> {
+        self.copy_blob(src_key, dst_key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        log::debug!("Listing blobs with prefix {}", prefix);
+        let start = match self.start_dir(prefix) {
+            Ok(start) => start,
+            Err(err) => return Box::pin(stream::once(async { Err(err) })),
+        };
+        let walk = Walk {
+            dirs: vec![start],
+            entries: VecDeque::new(),
+        };
+
+        Box::pin(stream::unfold(walk, move |mut walk| async move {
+            loop {
+                if let Some(entry) = walk.entries.pop_front() {
+                    return Some((Ok(entry), walk));
+                }
+                let dir = walk.dirs.pop()?;
+                if let Err(err) = self.read_dir(&dir, prefix, &mut walk).await {
+                    return Some((Err(Error::io(err)), walk));
+                }
+            }
+        }))
+    }
+
+    /// Pages walk the directories in key order, skipping those only holding keys
+    /// up to the cursor, and stop once the page is full
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn list_page(
+        &self,
+        prefix: &str,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> hold::Result<Page> {
+        let after = cursor.map(Cursor::token);
+        let limit = limit.max(1);
+        let mut pending = vec![Child::Dir(self.start_dir(prefix)?, String::new())];
+        let mut entries = Vec::new();
+        while let Some(child) = pending.pop() {
+            match child {
+                Child::File(entry) => {
+                    if after.is_none_or(|after| entry.key.as_str() > after) {
+                        entries.push(entry);
+                        if entries.len() > limit {
+                            break;
+                        }
+                    }
+                }
+                Child::Dir(path, dir_prefix) => {
+                    // every key of the directory is up to the cursor
+                    let passed = after.is_some_and(|after| {
+                        dir_prefix.as_str() <= after && !after.starts_with(&dir_prefix)
+                    });
+                    if !passed {
+                        let mut children = self.children(&path, prefix).await.map_err(Error::io)?;
+                        // popped from the end, the first key last
+                        children.sort_by(|a, b| b.sort_key().cmp(a.sort_key()));
+                        pending.extend(children);
+                    }
+                }
+            }
+        }
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|entry| Cursor::new(&entry.key))
+        } else {
+            None
+        };
+        Ok(Page { entries, next })
+    }
+
+    /// Checks that the root directory is accessible,
+    /// and loads the metadata of the given keys in the OS caches
+    #[tracing::instrument(skip(self), fields(provider = "fs"))]
+    async fn warm_up(&self, keys: &[&str]) -> hold::Result<()> {
+        log::debug!("Warming up {}", self.root.display());
+        fs::metadata(&self.root).await.map_err(Error::io)?;
+        for key in keys {
+            match fs::metadata(self.path_for(key)?).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(Error::io(err)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for FileSystemProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileSystemProvider")
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+/// Registers the `fs` provider, storing blobs under the directory in the `FS_ROOT` variable,
+/// and the `file` URL scheme, storing blobs under the path of URLs like `file:///var/data`
+pub fn register(registry: Registry) -> Registry {
+    registry
+        .with_provider("fs", |config| {
+            let root = config.require("FS_ROOT")?;
+            Ok(Box::new(FileSystemProvider::new(root)))
+        })
+        .with_scheme("file", |url| {
+            if !matches!(url.host(), "" | "localhost") || url.path().is_empty() {
+                return Err(Error::config("file URLs must be absolute local paths"));
+            }
+            Ok(Box::new(FileSystemProvider::new(url.path())))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use futures::io::AsyncWriteExt;
+    use futures::TryStreamExt;
+    use hold::blob::Blob;
+    use hold::metadata::BlobMetadata;
+    use hold::provider::Provider;
+
+    use crate::FileSystemProvider;
+
+    #[test]
+    fn it_rejects_keys_escaping_the_root() {
+        let provider = FileSystemProvider::new("/data");
+
+        assert_eq!(
+            provider.path_for("a/./b//c.txt").unwrap(),
+            std::path::PathBuf::from("/data/a/b/c.txt")
+        );
+        assert!(provider.path_for("../etc/passwd").is_err());
+        assert!(provider.path_for("a/../../b").is_err());
+        assert!(provider.path_for("/").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_stores_blobs_if_absent() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+
+        let store = |content: &[u8]| {
+            provider.store_blob_if_absent(Blob::from_bytes("dir/key", content.to_vec()))
+        };
+        store(b"first").await.unwrap();
+        let err = store(b"second").await.unwrap_err();
+        assert_eq!(err.code(), "precondition_failed");
+
+        let blob = provider.get_blob("dir/key").await.unwrap().unwrap();
+        let chunks: Vec<_> = blob.into_byte_stream().try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"first".to_vec());
+        // no temporary file is left behind
+        let files = std::fs::read_dir(root.path().join("dir")).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[tokio::test]
+    async fn it_stores_and_fetches_blobs() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+
+        let receipt = provider
+            .store_blob(Blob::from_bytes("dir/key", b"content".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(receipt.size, 7);
+        assert!(provider.is_blob_present("dir/key").await.unwrap());
+        let head = provider.head_blob("dir/key").await.unwrap().unwrap();
+        assert_eq!(head.size, 7);
+        assert!(provider.head_blob("dir").await.unwrap().is_none());
+
+        let blob = provider.get_blob("dir/key").await.unwrap().unwrap();
+        assert_eq!(blob.size(), 7);
+        let chunks: Vec<_> = blob.into_byte_stream().try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"content".to_vec());
+
+        let range = provider.get_blob_range("dir/key", 3..usize::MAX).await;
+        let range = range.unwrap().unwrap();
+        assert_eq!(range.size(), 4);
+        let chunks: Vec<_> = range.into_byte_stream().try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"tent".to_vec());
+        assert!(provider.get_blob_range("dir/key", 7..8).await.is_err());
+
+        let receipt = provider
+            .copy_blob("dir/key", "copy")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.size, 7);
+        assert!(provider
+            .copy_blob("missing", "copy")
+            .await
+            .unwrap()
+            .is_none());
+
+        provider.delete_blob("dir/key").await.unwrap();
+        assert!(!provider.is_blob_present("dir/key").await.unwrap());
+        assert!(provider.get_blob("dir/key").await.unwrap().is_none());
+        assert!(!root.path().join("dir").exists());
+    }
+
+    #[tokio::test]
+    async fn it_writes_into_directories_removed_concurrently() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+
+        for _ in 0..50 {
+            provider
+                .store_blob(Blob::from_bytes("dir/old", b"old".to_vec()))
+                .await
+                .unwrap();
+            // deleting the last blob of the directory removes it, maybe mid-write
+            let (deleted, stored) = futures::join!(
+                provider.delete_blob("dir/old"),
+                provider.store_blob(Blob::from_bytes("dir/new", b"new".to_vec()))
+            );
+            deleted.unwrap();
+            stored.unwrap();
+            assert!(provider.is_blob_present("dir/new").await.unwrap());
+            provider.delete_blob("dir/new").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn it_streams_written_blobs_into_files() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+
+        let mut writer = provider.open_writer("dir/key", BlobMetadata::default());
+        writer.write_all(b"con").await.unwrap();
+        writer.write_all(b"tent").await.unwrap();
+        assert!(!provider.is_blob_present("dir/key").await.unwrap());
+        let receipt = writer.finish().await.unwrap();
+        assert_eq!(receipt.size, 7);
+
+        let blob = provider.get_blob("dir/key").await.unwrap().unwrap();
+        let chunks: Vec<_> = blob.into_byte_stream().try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"content".to_vec());
+    }
+
+    #[tokio::test]
+    async fn it_lists_blobs_by_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+        for key in &["logs/2020/a", "logs/2021/b", "logs.txt", "data/c"] {
+            provider
+                .store_blob(Blob::from_bytes(*key, vec![0; 3]))
+                .await
+                .unwrap();
+        }
+
+        let entries: Vec<_> = provider.list_blobs("logs").try_collect().await.unwrap();
+        let mut keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["logs.txt", "logs/2020/a", "logs/2021/b"]);
+
+        let entries: Vec<_> = provider
+            .list_blobs("logs/2021/")
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, 3);
+    }
+
+    #[tokio::test]
+    async fn it_pages_through_blobs_in_key_order() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = FileSystemProvider::new(root.path());
+        for key in &[
+            "logs/2021/b",
+            "logs/2020/a",
+            "logs.txt",
+            "logs/2020/c",
+            "data/d",
+        ] {
+            provider
+                .store_blob(Blob::from_bytes(*key, vec![0; 3]))
+                .await
+                .unwrap();
+        }
+
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = provider
+                .list_page("logs", cursor.as_ref(), 2)
+                .await
+                .unwrap();
+            assert!(page.entries.len() <= 2);
+            keys.extend(page.entries.into_iter().map(|entry| entry.key));
+            cursor = match page.next {
+                Some(next) => Some(next),
+                None => break,
+            };
+        }
+        assert_eq!(
+            keys,
+            vec!["logs.txt", "logs/2020/a", "logs/2020/c", "logs/2021/b"]
+        );
+    }
+
+    mod conformance {
+        use crate::FileSystemProvider;
+
+        hold_test::provider_conformance!(
+            || FileSystemProvider::new(tempfile::tempdir().unwrap().keep()),
+            #[tokio::test]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use aws_smithy_types::byte_stream::ByteStream;
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use http_body::{Body, Frame, SizeHint};
+
+/// Request body streaming the content of a blob.
+/// The size is known upfront, so uploads are not buffered in memory.
+pub(crate) struct BlobBody<S> {
+    content: S,
+    size: u64,
+}
+
+impl<S> BlobBody<S> {
+    pub(crate) fn new(content: S, size: usize) -> Self {
+        Self {
+            content,
+            size: size as u64,
+        }
+    }
+}
+
+impl<S: Stream<Item = io::Result<Bytes>> + Unpin> Body for BlobBody<S> {
+    type Data = bytes1::Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.get_mut()
+            .content
+            .poll_next_unpin(cx)
+            .map_ok(|chunk| Frame::data(bytes1::Bytes::from_owner(chunk)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.size)
+    }
+}
+
+/// Adapts the body of an S3 response to the content stream of a blob
+pub(crate) fn content_stream(
+    body: ByteStream,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync + 'static {
+    let mut body = Box::pin(body);
+    stream::poll_fn(move |cx| {
+        body.as_mut().poll_next(cx).map(|chunk| {
+            chunk.map(|chunk| {
+                chunk
+                    .map(|chunk| Bytes::from(Vec::from(chunk)))
+                    .map_err(io::Error::other)
+            })
+        })
+    })
+}
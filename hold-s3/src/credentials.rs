@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::BehaviorVersion;
+use aws_credential_types::provider::{
+    self, error::CredentialsError, ProvideCredentials, SharedCredentialsProvider,
+};
+use aws_credential_types::Credentials;
+use hold::credentials::{CredentialsProvider, RefreshingCredentials};
+use tokio::sync::OnceCell;
+
+use crate::S3AssumeRole;
+
+/// Adapts Hold credentials providers to the ones used by the AWS SDK.
+/// Expiring credentials are cached and refreshed before they expire.
+#[derive(Debug)]
+pub(crate) struct HoldCredentialsProvider {
+    provider: RefreshingCredentials<Arc<dyn CredentialsProvider>>,
+}
+
+impl HoldCredentialsProvider {
+    pub(crate) fn new(provider: Arc<dyn CredentialsProvider>) -> Self {
+        Self {
+            provider: RefreshingCredentials::new(provider),
+        }
+    }
+}
+
+impl ProvideCredentials for HoldCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        provider::future::ProvideCredentials::new(async move {
+            let credentials = self
+                .provider
+                .credentials()
+                .await
+                .map_err(CredentialsError::provider_error)?;
+            Ok(Credentials::new(
+                credentials.access_key_id,
+                credentials.secret_access_key,
+                credentials.session_token,
+                credentials.expires_at,
+                "hold",
+            ))
+        })
+    }
+}
+
+/// The default AWS credentials chain (environment, profiles, web identity, instance metadata...).
+/// Building the chain is async, so it is built when credentials are first needed.
+#[derive(Debug, Default)]
+pub(crate) struct DefaultCredentials {
+    chain: OnceCell<DefaultCredentialsChain>,
+}
+
+impl ProvideCredentials for DefaultCredentials {
+    fn provide_credentials<'a>(&'a self) -> provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        provider::future::ProvideCredentials::new(async move {
+            let chain = self
+                .chain
+                .get_or_init(|| DefaultCredentialsChain::builder().build())
+                .await;
+            chain.provide_credentials().await
+        })
+    }
+}
+
+/// Temporary credentials of an IAM role, assumed through STS with base credentials.
+/// Building the STS client is async, so it is built when credentials are first needed,
+/// while the SDK caches the credentials and assumes the role again before they expire.
+#[derive(Debug)]
+pub(crate) struct AssumedRoleCredentials {
+    role: S3AssumeRole,
+    region: String,
+    base: SharedCredentialsProvider,
+    provider: OnceCell<AssumeRoleProvider>,
+}
+
+impl AssumedRoleCredentials {
+    pub(crate) fn new(role: S3AssumeRole, region: String, base: SharedCredentialsProvider) -> Self {
+        Self {
+            role,
+            region,
+            base,
+            provider: OnceCell::new(),
+        }
+    }
+
+    async fn build(&self) -> AssumeRoleProvider {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .region(aws_config::Region::new(self.region.clone()))
+            .load()
+            .await;
+        let mut builder = AssumeRoleProvider::builder(&self.role.role_arn).configure(&config);
+        if let Some(external_id) = &self.role.external_id {
+            builder = builder.external_id(external_id);
+        }
+        if let Some(session_name) = &self.role.session_name {
+            builder = builder.session_name(session_name);
+        }
+        builder.build_from_provider(self.base.clone()).await
+    }
+}
+
+impl ProvideCredentials for AssumedRoleCredentials {
+    fn provide_credentials<'a>(&'a self) -> provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        provider::future::ProvideCredentials::new(async move {
+            let provider = self.provider.get_or_init(|| self.build()).await;
+            provider.provide_credentials().await
+        })
+    }
+}
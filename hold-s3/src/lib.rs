@@ -1,164 +1,1562 @@
+use std::cmp::max;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use async_trait::async_trait;
-use hold::blob::Blob;
-use hold::error::Error;
-use hold::provider::Provider;
-use rusoto_core::{HttpClient, Region, RusotoError};
-use rusoto_credential::StaticProvider;
-use rusoto_s3::{
-    DeleteObjectRequest, GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest,
-    PutObjectRequest, S3Client, StreamingBody, S3,
+use aws_sdk_s3::config::{
+    BehaviorVersion, Credentials, Region, RequestChecksumCalculation, SharedCredentialsProvider,
+};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::{ByteStream, DateTime};
+use aws_sdk_s3::types::{
+    BucketLocationConstraint, CompletedMultipartUpload, CompletedPart, CreateBucketConfiguration,
+    MetadataDirective, ObjectCannedAcl, ObjectLockLegalHold, ObjectLockLegalHoldStatus,
+    ObjectLockRetention, ObjectLockRetentionMode, ObjectOwnership, ServerSideEncryption,
+    StorageClass,
 };
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
+use hold::admin::{AdminProvider, BucketPolicy};
+use hold::blob::{etag_matches, Blob, BlobEntry, Head, RangeRead};
+use hold::budget::MemoryBudget;
+use hold::config::{EnvConfig, Registry};
+use hold::credentials::CredentialsProvider;
+use hold::digest::DigestAlgorithm;
+use hold::error::Error;
+use hold::listing::{Cursor, Page};
+use hold::metadata::BlobMetadata;
+use hold::middleware::prefix::PrefixedProvider;
+use hold::presign::SignedUrlProvider;
+use hold::provider::{EntryStream, Provider};
+use hold::receipt::StoreReceipt;
+use hold::registry::StorageUrl;
+use hold::retention::{Retention, RetentionProvider};
+use hold::tier::StorageTier;
+use hold::versioning::VersionedProvider;
+use hold::writer::BlobWriter;
 use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, SystemTime};
+
+use crate::body::{content_stream, BlobBody};
+use crate::credentials::{AssumedRoleCredentials, DefaultCredentials, HoldCredentialsProvider};
+
+mod body;
+mod credentials;
+
+/// Size above which blobs are uploaded in parts, unless configured otherwise
+pub const DEFAULT_MULTIPART_THRESHOLD: usize = 64 * 1024 * 1024;
 
-/// Hold Provider for S3-compatible object storage services
+/// Size of the parts of multipart uploads, unless configured otherwise
+pub const DEFAULT_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Smallest part size accepted by S3, except for the last part of an upload
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Largest number of parts of a multipart upload accepted by S3
+const MAX_PARTS: usize = 10_000;
+
+/// Largest object S3 copies with a single `CopyObject` request, 5 GiB
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Hold Provider for S3-compatible object storage services.
+///
+/// Requests are sent with the official AWS SDK, so they must be made
+/// from within a Tokio runtime.
 pub struct S3Provider {
-    s3: S3Client,
+    s3: Client,
     bucket: String,
+    object_lock: bool,
+    multipart_threshold: usize,
+    part_size: usize,
+    upload_buffer: MemoryBudget,
+    encryption: Option<S3Encryption>,
+    storage_class: Option<StorageClass>,
+    verified_checksums: &'static [DigestAlgorithm],
+    acl: Option<ObjectCannedAcl>,
+    object_ownership: Option<ObjectOwnership>,
+    /// Set once the endpoint rejected a multipart upload, to buffer the next ones upfront
+    multipart_rejected: AtomicBool,
 }
 
 impl S3Provider {
     pub fn new<B: ToString>(bucket: B) -> S3Provider {
-        let s3 = S3Client::new(Region::default());
-        S3Provider {
-            s3,
+        S3Provider::new_with_config(S3Config {
             bucket: bucket.to_string(),
-        }
+            ..S3Config::default()
+        })
     }
 
     pub fn new_with_config(config: S3Config) -> S3Provider {
-        let bucket = config.bucket;
-        let region = match config.region {
-            Some(region) => Region::from_str(region.as_str()).unwrap_or(Region::default()),
-            None => Region::default(),
-        };
+        let region = config.region.unwrap_or_else(default_region);
+        let mut builder = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(region.clone()));
 
-        let region = match config.endpoint {
-            Some(endpoint) => Region::Custom {
-                name: region.name().to_string(),
-                endpoint,
-            },
-            None => region,
+        let credentials = match (config.credentials, config.credentials_provider) {
+            (Some(creds), _) => SharedCredentialsProvider::new(Credentials::new(
+                creds.access_key_id,
+                creds.secret_access_key,
+                None,
+                None,
+                "hold",
+            )),
+            (None, Some(provider)) => {
+                SharedCredentialsProvider::new(HoldCredentialsProvider::new(provider))
+            }
+            (None, None) => SharedCredentialsProvider::new(DefaultCredentials::default()),
+        };
+        builder = match config.assume_role {
+            Some(role) => builder.credentials_provider(AssumedRoleCredentials::new(
+                role,
+                region.clone(),
+                credentials,
+            )),
+            None => builder.credentials_provider(credentials),
         };
 
-        let s3 = match config.credentials {
-            Some(creds) => {
-                let provider =
-                    StaticProvider::new_minimal(creds.access_key_id, creds.secret_access_key);
-                S3Client::new_with(HttpClient::new().unwrap(), provider, region)
-            }
-            None => S3Client::new(region),
+        // S3-compatible services verify MD5 checksums, but seldom the other ones
+        let mut verified_checksums: &[DigestAlgorithm] =
+            &[DigestAlgorithm::Crc32c, DigestAlgorithm::Md5];
+        if let Some(endpoint) = config.endpoint {
+            verified_checksums = &[DigestAlgorithm::Md5];
+            // S3-compatible services are addressed by path, and most of them
+            // reject the checksums the SDK sends to AWS by default
+            builder = builder
+                .endpoint_url(endpoint)
+                .force_path_style(true)
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired);
+        }
+
+        S3Provider {
+            s3: Client::from_conf(builder.build()),
+            bucket: config.bucket,
+            object_lock: config.object_lock,
+            multipart_threshold: config
+                .multipart_threshold
+                .unwrap_or(DEFAULT_MULTIPART_THRESHOLD),
+            part_size: max(config.part_size.unwrap_or(DEFAULT_PART_SIZE), MIN_PART_SIZE),
+            upload_buffer: config.upload_buffer.unwrap_or_else(|| {
+                MemoryBudget::with_spill(DEFAULT_MULTIPART_THRESHOLD, std::env::temp_dir())
+            }),
+            encryption: config.encryption,
+            storage_class: config
+                .storage_class
+                .map(|class| StorageClass::from(class.as_str())),
+            verified_checksums,
+            acl: config.acl.map(|acl| ObjectCannedAcl::from(acl.as_str())),
+            object_ownership: config
+                .object_ownership
+                .map(|ownership| ObjectOwnership::from(ownership.as_str())),
+            multipart_rejected: AtomicBool::new(false),
+        }
+    }
+
+    /// Storage class of a stored blob, the one of its tier or the configured one
+    fn storage_class_of(&self, metadata: &BlobMetadata) -> Option<StorageClass> {
+        metadata
+            .storage_tier
+            .as_ref()
+            .map(storage_class)
+            .or_else(|| self.storage_class.clone())
+    }
+
+    fn sse(&self) -> Option<ServerSideEncryption> {
+        self.encryption.as_ref().map(S3Encryption::algorithm)
+    }
+
+    fn sse_kms_key_id(&self) -> Option<String> {
+        match &self.encryption {
+            Some(S3Encryption::Kms { key_id }) => key_id.clone(),
+            _ => None,
+        }
+    }
+
+    /// Lists the blobs under a prefix folder-style: keys containing the delimiter after
+    /// the prefix are grouped into their common prefix instead of being listed, so that
+    /// a level of a hierarchy is listed without walking the subtrees below it.
+    ///
+    /// Listing `photos/` with `/` as delimiter yields `photos/cat.jpg` as a blob
+    /// and `photos/2020/` as a common prefix, each common prefix once.
+    pub fn list_delimited<'a>(&'a self, prefix: &'a str, delimiter: &'a str) -> ItemStream<'a> {
+        log::debug!(
+            "Listing blobs with prefix {} delimited by {}",
+            prefix,
+            delimiter
+        );
+        self.list_items(prefix, Some(delimiter))
+    }
+
+    fn list_items<'a>(&'a self, prefix: &'a str, delimiter: Option<&'a str>) -> ItemStream<'a> {
+        let listing = Listing {
+            items: VecDeque::new(),
+            continuation_token: None,
+            done: false,
         };
 
-        S3Provider { bucket, s3 }
+        Box::pin(stream::unfold(listing, move |mut listing| async move {
+            loop {
+                if let Some(item) = listing.items.pop_front() {
+                    return Some((Ok(item), listing));
+                }
+                if listing.done {
+                    return None;
+                }
+                let token = listing.continuation_token.take();
+                match self.list_objects(prefix, delimiter, token, None).await {
+                    Ok((items, continuation_token)) => {
+                        listing.items.extend(items);
+                        listing.done = continuation_token.is_none();
+                        listing.continuation_token = continuation_token;
+                    }
+                    Err(err) => {
+                        listing.done = true;
+                        return Some((Err(err), listing));
+                    }
+                }
+            }
+        }))
     }
 }
 
+/// An item of a delimited listing, see [`S3Provider::list_delimited`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListItem {
+    Blob(BlobEntry),
+
+    /// A prefix shared by the keys grouped under it, ending with the delimiter
+    CommonPrefix(String),
+}
+
+pub type ItemStream<'a> = Pin<Box<dyn Stream<Item = hold::Result<ListItem>> + Send + 'a>>;
+
 #[async_trait]
 impl Provider for S3Provider {
-    #[tracing::instrument]
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
     async fn get_blob(&self, key: &str) -> hold::Result<Option<Blob>> {
         log::debug!("Fetching blob {}", key);
-        let req = GetObjectRequest {
-            bucket: self.bucket.clone(),
-            key: key.to_string(),
-            ..GetObjectRequest::default()
+        self.fetch_object(key, None, None, None).await
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> hold::Result<Option<Blob>> {
+        log::debug!("Fetching blob {} range {:?}", key, range);
+        if range.is_empty() {
+            // S3 cannot express empty ranges, but the blob may not exist either
+            return match self.object_info(key).await? {
+                Some((size, _)) => Err(Error::range_not_satisfiable(key, size)),
+                None => Ok(None),
+            };
+        }
+        // the last byte position is clamped to the size of the object by S3
+        let range = format!("bytes={}-{}", range.start, range.end - 1);
+        self.fetch_object(key, None, Some(range), None).await
+    }
+
+    /// The range is fetched with an `If-Match` precondition, and the whole object
+    /// is fetched again if it fails
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> hold::Result<Option<RangeRead>> {
+        log::debug!(
+            "Fetching blob {} range {:?} if it matches {}",
+            key,
+            range,
+            etag
+        );
+        if !range.is_empty() && !etag.starts_with("W/") {
+            let header = format!("bytes={}-{}", range.start, range.end - 1);
+            if let Some(blob) = self
+                .fetch_object(key, None, Some(header), Some(etag))
+                .await?
+            {
+                return Ok(Some(RangeRead::Range(blob)));
+            }
+        }
+        let blob = match self.fetch_object(key, None, None, None).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
         };
+        if range.is_empty() && etag_matches(blob.metadata().etag.as_deref(), etag) {
+            return Err(Error::range_not_satisfiable(key, blob.size()));
+        }
+        Ok(Some(RangeRead::Full(blob)))
+    }
 
-        let output = match self.s3.get_object(req).await {
+    /// Looks up the object with `HeadObject`
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn head_blob(&self, key: &str) -> hold::Result<Option<Head>> {
+        log::debug!("Fetching blob {} metadata", key);
+        let res = self
+            .s3
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let output = match res {
             Ok(output) => output,
-            Err(err) => {
-                return match err {
-                    RusotoError::Service(err) => match err {
-                        GetObjectError::NoSuchKey(_) => {
-                            log::debug!("Blob {} not found", key);
-                            Ok(None)
-                        }
-                    },
-                    _ => Err(Error::provider(err)),
-                };
+            Err(err) if is_not_found(&err) => {
+                log::debug!("Blob {} not found", key);
+                return Ok(None);
             }
+            Err(err) => return Err(request_error(key, err)),
         };
-        match output.body {
-            None => Err(Error::body_error("no body found in S3 response")),
-            Some(body) => Ok(Some(Blob::new(
-                key.to_string(),
-                output.content_length.unwrap() as usize,
-                body,
-            ))),
-        }
+        let metadata = BlobMetadata {
+            content_type: output.content_type,
+            content_encoding: output.content_encoding,
+            last_modified: output
+                .last_modified
+                .and_then(|date| SystemTime::try_from(date).ok()),
+            etag: output.e_tag,
+            // S3 omits the storage class of STANDARD objects
+            storage_tier: Some(
+                output
+                    .storage_class
+                    .as_ref()
+                    .map_or(StorageTier::Hot, storage_tier),
+            ),
+            custom: output.metadata.unwrap_or_default().into_iter().collect(),
+        };
+        Ok(Some(Head {
+            size: output.content_length.unwrap_or_default() as usize,
+            metadata,
+        }))
     }
 
-    #[tracing::instrument]
-    async fn store_blob(&self, blob: Blob) -> hold::Result<Blob> {
+    #[tracing::instrument(skip(self, blob), fields(provider = "s3", key = blob.key()))]
+    async fn store_blob(&self, blob: Blob) -> hold::Result<StoreReceipt> {
         let key = blob.key().to_string();
         let size = blob.size();
+        // blobs of known size need no buffering once multipart uploads are rejected
+        if size > self.multipart_threshold && !self.multipart_rejected.load(Ordering::Relaxed) {
+            let part_size = max(self.part_size, size.div_ceil(MAX_PARTS));
+            log::debug!(
+                "Storing blob {} of {} bytes in parts of {} bytes",
+                key,
+                size,
+                part_size
+            );
+            let metadata = blob.metadata().clone();
+            return self
+                .store_multipart(key, &metadata, blob.into_byte_stream(), part_size)
+                .await;
+        }
+
         log::debug!("Storing blob {} of {} bytes", key, size);
-        let req = PutObjectRequest {
-            bucket: self.bucket.clone(),
-            key: key.clone(),
-            content_length: Some(blob.size() as i64),
-            body: Some(StreamingBody::new(blob.into_byte_stream())),
-            ..PutObjectRequest::default()
-        };
+        let metadata = blob.metadata().clone();
+        self.put_sized(key, &metadata, blob, false).await
+    }
 
-        self.s3
-            .put_object(req)
-            .await
-            .map(|_| Blob::empty(key, size))
-            .map_err(|err| Error::provider(err))
+    /// Blobs uploaded with a single request are stored with an `If-None-Match`
+    /// precondition, while blobs uploaded in parts are checked before being stored.
+    #[tracing::instrument(skip(self, blob), fields(provider = "s3", key = blob.key()))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> hold::Result<StoreReceipt> {
+        if blob.size() > self.multipart_threshold
+            && !self.multipart_rejected.load(Ordering::Relaxed)
+        {
+            if self.is_blob_present(blob.key()).await? {
+                return Err(Error::precondition_failed(blob.key()));
+            }
+            return self.store_blob(blob).await;
+        }
+
+        let key = blob.key().to_string();
+        log::debug!("Storing blob {} of {} bytes if absent", key, blob.size());
+        let metadata = blob.metadata().clone();
+        self.put_sized(key, &metadata, blob, true).await
+    }
+
+    /// Written blobs are uploaded in parts of the configured part size, so they are
+    /// limited to 10,000 parts of that size. Endpoints rejecting multipart uploads
+    /// get them buffered instead, see [`S3Config::upload_buffer`].
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        log::debug!(
+            "Opening writer for blob {} in parts of {} bytes",
+            key,
+            self.part_size
+        );
+        BlobWriter::new(move |content| async move {
+            self.store_multipart(key.to_string(), &metadata, content, self.part_size)
+                .await
+        })
     }
 
-    #[tracing::instrument]
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
     async fn is_blob_present(&self, key: &str) -> hold::Result<bool> {
         log::debug!("Checking blob {} presence", key);
-        let req = HeadObjectRequest {
-            bucket: self.bucket.clone(),
-            key: key.to_string(),
-            ..HeadObjectRequest::default()
-        };
-
-        let res = self.s3.head_object(req).await;
+        let res = self
+            .s3
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
         match res {
             Ok(_) => {
                 log::debug!("Blob {} found", key);
                 Ok(true)
             }
-            Err(err) => match &err {
-                RusotoError::Service(err) => match err {
-                    HeadObjectError::NoSuchKey(_) => {
-                        log::debug!("Blob {} not found", key);
-                        Ok(false)
+            Err(err) if is_not_found(&err) => {
+                log::debug!("Blob {} not found", key);
+                Ok(false)
+            }
+            Err(err) => Err(request_error(key, err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn delete_blob(&self, key: &str) -> hold::Result<()> {
+        log::debug!("Deleting blob {}", key);
+        // On versioned buckets a plain delete would only hide locked versions
+        // behind a delete marker, so held blobs are refused upfront
+        if self.object_lock && self.get_retention(key).await?.is_held() {
+            return Err(Error::retained(key));
+        }
+
+        self.s3
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| request_error(key, err))
+    }
+
+    /// Copies natively with `CopyObject`, or in parts with `UploadPartCopy`
+    /// for sources over the 5 GiB a single copy accepts
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> hold::Result<Option<StoreReceipt>> {
+        log::debug!("Copying blob {} to {}", src_key, dst_key);
+        let (size, storage_class) = match self.object_info(src_key).await? {
+            Some(info) => info,
+            None => {
+                log::debug!("Blob {} not found", src_key);
+                return Ok(None);
+            }
+        };
+        if size as u64 > MAX_COPY_SIZE {
+            return self
+                .copy_multipart(src_key, dst_key, size, None, storage_class)
+                .await
+                .map(Some);
+        }
+
+        self.s3
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(dst_key)
+            .copy_source(format!("{}/{}", self.bucket, encode_copy_source(src_key)))
+            // copies would be stored as STANDARD otherwise
+            .set_storage_class(storage_class)
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.sse_kms_key_id())
+            .set_acl(self.acl.clone())
+            .send()
+            .await
+            .map(|output| {
+                Some(StoreReceipt {
+                    etag: output.copy_object_result.and_then(|result| result.e_tag),
+                    version_id: output.version_id,
+                    ..StoreReceipt::new(dst_key, size)
+                })
+            })
+            .map_err(|err| request_error(src_key, err))
+    }
+
+    /// Copies natively, replacing the metadata of the source object. Like
+    /// `copy_blob`, sources over 5 GiB are copied in parts.
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> hold::Result<Option<StoreReceipt>> {
+        log::debug!("Copying blob {} to {} with new metadata", src_key, dst_key);
+        let (size, source_class) = match self.object_info(src_key).await? {
+            Some(info) => info,
+            None => {
+                log::debug!("Blob {} not found", src_key);
+                return Ok(None);
+            }
+        };
+        if size as u64 > MAX_COPY_SIZE {
+            let storage_class = self.storage_class_of(&metadata).or(source_class);
+            return self
+                .copy_multipart(src_key, dst_key, size, Some(metadata), storage_class)
+                .await
+                .map(Some);
+        }
+
+        self.s3
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(dst_key)
+            .copy_source(format!("{}/{}", self.bucket, encode_copy_source(src_key)))
+            .metadata_directive(MetadataDirective::Replace)
+            .set_content_type(metadata.content_type.clone())
+            .set_content_encoding(metadata.content_encoding.clone())
+            .set_metadata(custom_metadata(&metadata))
+            .set_storage_class(
+                metadata
+                    .storage_tier
+                    .as_ref()
+                    .map(storage_class)
+                    .or(source_class),
+            )
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.sse_kms_key_id())
+            .set_acl(self.acl.clone())
+            .send()
+            .await
+            .map(|output| {
+                Some(StoreReceipt {
+                    etag: output.copy_object_result.and_then(|result| result.e_tag),
+                    version_id: output.version_id,
+                    ..StoreReceipt::new(dst_key, size)
+                })
+            })
+            .map_err(|err| request_error(src_key, err))
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        log::debug!("Listing blobs with prefix {}", prefix);
+        // without a delimiter, only blobs are listed
+        Box::pin(
+            self.list_items(prefix, None)
+                .try_filter_map(|item| async move {
+                    match item {
+                        ListItem::Blob(entry) => Ok(Some(entry)),
+                        ListItem::CommonPrefix(_) => Ok(None),
                     }
-                },
-                RusotoError::Unknown(response) => {
-                    if response.status == 404 {
+                }),
+        )
+    }
+
+    /// Pages are listed natively, S3 returning at most 1,000 entries per page
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn list_page(
+        &self,
+        prefix: &str,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> hold::Result<Page> {
+        log::debug!("Listing a page of {} blobs with prefix {}", limit, prefix);
+        let token = cursor.map(|cursor| cursor.token().to_string());
+        let (items, next) = self
+            .list_objects(prefix, None, token, Some(limit.max(1)))
+            .await?;
+        let entries = items
+            .into_iter()
+            .filter_map(|item| match item {
+                ListItem::Blob(entry) => Some(entry),
+                ListItem::CommonPrefix(_) => None,
+            })
+            .collect();
+        Ok(Page {
+            entries,
+            next: next.map(Cursor::new),
+        })
+    }
+
+    /// Loads credentials and opens a first connection to the bucket.
+    /// The given keys are then looked up concurrently, opening more pooled connections;
+    /// their content is not fetched.
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn warm_up(&self, keys: &[&str]) -> hold::Result<()> {
+        log::debug!("Warming up bucket {}", self.bucket);
+        self.s3
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .map_err(|err| request_error(&self.bucket, err))?;
+        future::try_join_all(keys.iter().map(|key| self.object_info(key))).await?;
+        Ok(())
+    }
+
+    /// CRC32C and MD5 on AWS, only MD5 on custom endpoints. The checksums of blobs
+    /// uploaded in parts are not sent.
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        self.verified_checksums
+    }
+}
+
+/// Pending state of a listing
+struct Listing {
+    items: VecDeque<ListItem>,
+    continuation_token: Option<String>,
+    done: bool,
+}
+
+/// Presigned GetObject URLs. URLs signed with temporary credentials stop working
+/// when the credentials expire, even if `expires_in` has not elapsed yet.
+#[async_trait]
+impl SignedUrlProvider for S3Provider {
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> hold::Result<String> {
+        log::debug!("Presigning blob {} download for {:?}", key, expires_in);
+        let config = PresigningConfig::expires_in(expires_in).map_err(Error::provider)?;
+        self.s3
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(config)
+            .await
+            .map(|req| req.uri().to_string())
+            .map_err(|err| request_error(key, err))
+    }
+}
+
+/// Retention backed by S3 Object Lock, in compliance mode.
+/// The bucket must have Object Lock enabled, and `object_lock` should be set
+/// in [`S3Config`] so that deleting a held blob fails instead of adding a delete marker.
+#[async_trait]
+impl RetentionProvider for S3Provider {
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn get_retention(&self, key: &str) -> hold::Result<Retention> {
+        log::debug!("Fetching blob {} retention", key);
+        let res = self
+            .s3
+            .get_object_retention()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let retain_until = match res {
+            Ok(output) => output
+                .retention
+                .and_then(|retention| retention.retain_until_date)
+                .map(|date| SystemTime::try_from(date).map_err(Error::provider))
+                .transpose()?,
+            Err(err) if is_not_found(&err) => None,
+            Err(err) => return Err(request_error(key, err)),
+        };
+
+        let res = self
+            .s3
+            .get_object_legal_hold()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let legal_hold = match res {
+            Ok(output) => {
+                let status = output.legal_hold.and_then(|legal_hold| legal_hold.status);
+                status == Some(ObjectLockLegalHoldStatus::On)
+            }
+            Err(err) if is_not_found(&err) => false,
+            Err(err) => return Err(request_error(key, err)),
+        };
+
+        Ok(Retention {
+            retain_until,
+            legal_hold,
+        })
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn set_retention(&self, key: &str, retention: Retention) -> hold::Result<()> {
+        log::debug!("Setting blob {} retention", key);
+        if let Some(retain_until) = retention.retain_until {
+            let lock = ObjectLockRetention::builder()
+                .mode(ObjectLockRetentionMode::Compliance)
+                .retain_until_date(DateTime::from(retain_until))
+                .build();
+            self.s3
+                .put_object_retention()
+                .bucket(&self.bucket)
+                .key(key)
+                .retention(lock)
+                .send()
+                .await
+                .map_err(|err| request_error(key, err))?;
+        }
+        self.put_legal_hold(key, retention.legal_hold).await
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn clear_legal_hold(&self, key: &str) -> hold::Result<()> {
+        log::debug!("Clearing blob {} legal hold", key);
+        self.put_legal_hold(key, false).await
+    }
+}
+
+/// Time-travel reads on buckets with versioning enabled.
+///
+/// S3 only keeps the time of versions to the second. Among versions of the same second,
+/// the one S3 marks as latest is picked, and otherwise the one listed first: versions are
+/// listed from the newest, and before delete markers.
+#[async_trait]
+impl VersionedProvider for S3Provider {
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn get_blob_at(&self, key: &str, at: SystemTime) -> hold::Result<Option<Blob>> {
+        log::debug!(
+            "Fetching blob {} as of {}",
+            key,
+            humantime::format_rfc3339(at)
+        );
+        // the version current at the given moment is the latest one created before it,
+        // which may be a delete marker
+        let mut current: Option<ListedVersion> = None;
+        let mut key_marker = None;
+        let mut version_id_marker = None;
+        loop {
+            let output = self
+                .s3
+                .list_object_versions()
+                .bucket(&self.bucket)
+                .prefix(key)
+                .set_key_marker(key_marker.take())
+                .set_version_id_marker(version_id_marker.take())
+                .send()
+                .await
+                .map_err(|err| request_error(key, err))?;
+
+            let versions = output
+                .versions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| (v.key, v.last_modified, v.version_id, v.is_latest, false));
+            let delete_markers = output
+                .delete_markers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|m| (m.key, m.last_modified, m.version_id, m.is_latest, true));
+            for (version_key, last_modified, version_id, is_latest, deleted) in
+                versions.chain(delete_markers)
+            {
+                if version_key.as_deref() != Some(key) {
+                    continue;
+                }
+                let last_modified = match last_modified {
+                    Some(date) => SystemTime::try_from(date).map_err(Error::provider)?,
+                    None => continue,
+                };
+                let version = ListedVersion {
+                    last_modified,
+                    version_id,
+                    is_latest: is_latest == Some(true),
+                    deleted,
+                };
+                if version.replaces(current.as_ref(), at) {
+                    current = Some(version);
+                }
+            }
+
+            if !lists_more_versions(key, output.is_truncated, output.next_key_marker.as_deref()) {
+                break;
+            }
+            key_marker = output.next_key_marker;
+            version_id_marker = output.next_version_id_marker;
+        }
+
+        match current {
+            Some(ListedVersion {
+                version_id,
+                deleted: false,
+                ..
+            }) => self.fetch_object(key, version_id, None, None).await,
+            _ => {
+                log::debug!(
+                    "Blob {} not found as of {}",
+                    key,
+                    humantime::format_rfc3339(at)
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Whether the listing of the versions of keys starting with the given key, truncated
+/// after the given key marker, goes on with more versions of the key itself.
+/// Keys are listed in order, the key first among those it prefixes, so its versions
+/// are all listed once a page ends on another key.
+fn lists_more_versions(
+    key: &str,
+    is_truncated: Option<bool>,
+    next_key_marker: Option<&str>,
+) -> bool {
+    is_truncated == Some(true) && next_key_marker == Some(key)
+}
+
+/// A version of an object, or a delete marker, as listed by S3
+#[derive(Debug)]
+struct ListedVersion {
+    last_modified: SystemTime,
+    version_id: Option<String>,
+    is_latest: bool,
+    deleted: bool,
+}
+
+impl ListedVersion {
+    /// Whether the version, listed after the current one, is the one current at the given moment
+    fn replaces(&self, current: Option<&ListedVersion>, at: SystemTime) -> bool {
+        if self.last_modified > at {
+            return false;
+        }
+        match current {
+            Some(current) if self.last_modified == current.last_modified => {
+                self.is_latest && !current.is_latest
+            }
+            Some(current) => self.last_modified > current.last_modified,
+            None => true,
+        }
+    }
+}
+
+/// Bucket administration with the client of the provider, in its region
+#[async_trait]
+impl AdminProvider for S3Provider {
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn create_bucket(&self, name: &str) -> hold::Result<()> {
+        log::debug!("Creating bucket {}", name);
+        // buckets are created in us-east-1 unless constrained to another region
+        let location = self
+            .s3
+            .config()
+            .region()
+            .map(|region| region.as_ref())
+            .filter(|region| *region != "us-east-1")
+            .map(|region| {
+                CreateBucketConfiguration::builder()
+                    .location_constraint(BucketLocationConstraint::from(region))
+                    .build()
+            });
+        let res = self
+            .s3
+            .create_bucket()
+            .bucket(name)
+            .set_create_bucket_configuration(location)
+            .set_object_ownership(self.object_ownership.clone())
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(err) => match err.as_service_error() {
+                Some(service_err)
+                    if service_err.is_bucket_already_exists()
+                        || service_err.is_bucket_already_owned_by_you() =>
+                {
+                    Err(Error::already_exists(name))
+                }
+                _ => Err(request_error(name, err)),
+            },
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn delete_bucket(&self, name: &str) -> hold::Result<()> {
+        log::debug!("Deleting bucket {}", name);
+        self.s3
+            .delete_bucket()
+            .bucket(name)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| request_error(name, err))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn list_buckets(&self) -> hold::Result<Vec<String>> {
+        log::debug!("Listing buckets");
+        let mut names = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let output = self
+                .s3
+                .list_buckets()
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await
+                .map_err(|err| request_error("", err))?;
+            names.extend(
+                output
+                    .buckets
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|bucket| bucket.name),
+            );
+            match output.continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(names),
+            }
+        }
+    }
+
+    /// Policies are bucket policies: public reads are granted with a policy allowing
+    /// `s3:GetObject` to anyone, which the public access block of the bucket may still
+    /// refuse, and private buckets have their policy removed
+    #[tracing::instrument(skip(self), fields(provider = "s3"))]
+    async fn set_bucket_policy(&self, name: &str, policy: BucketPolicy) -> hold::Result<()> {
+        log::debug!("Setting bucket {} policy to {:?}", name, policy);
+        let document = match policy {
+            BucketPolicy::Private => {
+                return self
+                    .s3
+                    .delete_bucket_policy()
+                    .bucket(name)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| request_error(name, err));
+            }
+            BucketPolicy::PublicRead => format!(
+                r#"{{"Version":"2012-10-17","Statement":[{{"Sid":"PublicRead","Effect":"Allow","Principal":"*","Action":"s3:GetObject","Resource":"arn:aws:s3:::{}/*"}}]}}"#,
+                name
+            ),
+            BucketPolicy::Custom(document) => document,
+        };
+        self.s3
+            .put_bucket_policy()
+            .bucket(name)
+            .policy(document)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| request_error(name, err))
+    }
+}
+
+impl S3Provider {
+    /// Fetches an object, or a range of it. With `if_match`, no blob is returned
+    /// if the entity tag of the object does not match.
+    async fn fetch_object(
+        &self,
+        key: &str,
+        version_id: Option<String>,
+        range: Option<String>,
+        if_match: Option<&str>,
+    ) -> hold::Result<Option<Blob>> {
+        let res = self
+            .s3
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .set_range(range)
+            .set_if_match(if_match.map(str::to_string))
+            .send()
+            .await;
+        let output = match res {
+            Ok(output) => output,
+            Err(err) => {
+                if let Some(size) = unsatisfiable_range_size(&err) {
+                    return Err(Error::range_not_satisfiable(key, size));
+                }
+                if is_precondition_failed(&err) {
+                    log::debug!("Blob {} does not match {:?}", key, if_match);
+                    return Ok(None);
+                }
+                return match err.as_service_error() {
+                    Some(service_err) if service_err.is_no_such_key() => {
                         log::debug!("Blob {} not found", key);
-                        Ok(false)
-                    } else {
-                        Err(Error::provider(err))
+                        Ok(None)
                     }
+                    _ => Err(request_error(key, err)),
+                };
+            }
+        };
+
+        let metadata = BlobMetadata {
+            content_type: output.content_type,
+            content_encoding: output.content_encoding,
+            last_modified: output
+                .last_modified
+                .and_then(|date| SystemTime::try_from(date).ok()),
+            etag: output.e_tag,
+            // S3 omits the storage class of STANDARD objects
+            storage_tier: Some(
+                output
+                    .storage_class
+                    .as_ref()
+                    .map_or(StorageTier::Hot, storage_tier),
+            ),
+            custom: output.metadata.unwrap_or_default().into_iter().collect(),
+        };
+        let blob = Blob::new(
+            key.to_string(),
+            output.content_length.unwrap_or_default() as usize,
+            content_stream(output.body),
+        );
+        Ok(Some(blob.with_metadata(metadata)))
+    }
+
+    /// Uploads a blob with a single request declaring its size,
+    /// only if its key holds no object yet when `if_absent` is set
+    async fn put_sized(
+        &self,
+        key: String,
+        metadata: &BlobMetadata,
+        blob: Blob,
+        if_absent: bool,
+    ) -> hold::Result<StoreReceipt> {
+        let size = blob.size();
+        let checksum = |algorithm| {
+            blob.checksums()
+                .get(algorithm)
+                .map(aws_smithy_types::base64::encode)
+        };
+        let req = self
+            .s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_length(size as i64)
+            .set_content_type(metadata.content_type.clone())
+            .set_content_encoding(metadata.content_encoding.clone())
+            .set_metadata(custom_metadata(metadata))
+            .set_storage_class(self.storage_class_of(metadata))
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.sse_kms_key_id())
+            .set_acl(self.acl.clone())
+            .set_checksum_crc32_c(checksum(DigestAlgorithm::Crc32c))
+            .set_content_md5(checksum(DigestAlgorithm::Md5))
+            .set_if_none_match(if_absent.then(|| "*".to_string()))
+            .body(ByteStream::from_body_1_x(BlobBody::new(
+                blob.into_byte_stream(),
+                size,
+            )));
+
+        let output = req.send().await.map_err(|err| request_error(&key, err))?;
+        Ok(StoreReceipt {
+            etag: output.e_tag,
+            version_id: output.version_id,
+            ..StoreReceipt::new(key, size)
+        })
+    }
+
+    /// Uploads a blob of unknown size by buffering it within the upload budget,
+    /// for endpoints that do not support multipart uploads
+    async fn put_buffered<S>(
+        &self,
+        key: String,
+        metadata: &BlobMetadata,
+        content: S,
+    ) -> hold::Result<StoreReceipt>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        let buffered = self.upload_buffer.buffer_stream(content).await?;
+        log::debug!(
+            "Storing buffered blob {} of {} bytes (spilled: {})",
+            key,
+            buffered.len(),
+            buffered.is_spilled()
+        );
+        let blob = buffered.into_blob(&key);
+        self.put_sized(key, metadata, blob, false).await
+    }
+
+    /// Uploads a blob in parts, aborting the upload if any part fails
+    /// so that S3 does not keep (and bill) the parts already uploaded.
+    ///
+    /// When the endpoint rejects multipart uploads as unsupported, the blob is
+    /// buffered and stored with a single request instead, and so are the next ones.
+    async fn store_multipart<S>(
+        &self,
+        key: String,
+        metadata: &BlobMetadata,
+        content: S,
+        part_size: usize,
+    ) -> hold::Result<StoreReceipt>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        if self.multipart_rejected.load(Ordering::Relaxed) {
+            return self.put_buffered(key, metadata, content).await;
+        }
+        let res = self
+            .s3
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .set_content_type(metadata.content_type.clone())
+            .set_content_encoding(metadata.content_encoding.clone())
+            .set_metadata(custom_metadata(metadata))
+            .set_storage_class(self.storage_class_of(metadata))
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.sse_kms_key_id())
+            .set_acl(self.acl.clone())
+            .send()
+            .await;
+        let upload_id = match res {
+            Ok(output) => output
+                .upload_id
+                .ok_or_else(|| Error::body_error("no upload id found in S3 response"))?,
+            Err(err) if is_unsupported(&err) => {
+                log::warn!(
+                    "Endpoint does not support multipart uploads, buffering blob {} instead",
+                    key
+                );
+                self.multipart_rejected.store(true, Ordering::Relaxed);
+                return self.put_buffered(key, metadata, content).await;
+            }
+            Err(err) => return Err(request_error(&key, err)),
+        };
+
+        match self
+            .upload_parts(&key, &upload_id, content, part_size)
+            .await
+        {
+            Ok(receipt) => Ok(receipt),
+            Err(err) => {
+                self.abort_multipart(&key, upload_id).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Aborts a multipart upload, so that S3 does not keep (and bill) its parts
+    async fn abort_multipart(&self, key: &str, upload_id: String) {
+        let res = self
+            .s3
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+        if let Err(abort_err) = res {
+            log::warn!(
+                "Failed to abort multipart upload of blob {}: {}",
+                key,
+                abort_err
+            );
+        }
+    }
+
+    /// Copies an object too large for a single `CopyObject` request in parts,
+    /// with `UploadPartCopy`. The copy gets the given metadata, or that of the source.
+    async fn copy_multipart(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        size: usize,
+        metadata: Option<BlobMetadata>,
+        storage_class: Option<StorageClass>,
+    ) -> hold::Result<StoreReceipt> {
+        log::debug!(
+            "Copying blob {} of {} bytes to {} in parts",
+            src_key,
+            size,
+            dst_key
+        );
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => match self.head_blob(src_key).await? {
+                Some(head) => head.metadata,
+                None => {
+                    let source =
+                        std::io::Error::new(std::io::ErrorKind::NotFound, "copy source deleted");
+                    return Err(Error::not_found(src_key.to_string(), source));
                 }
-                _ => Err(Error::provider(err)),
             },
+        };
+        let upload_id = self
+            .s3
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(dst_key)
+            .set_content_type(metadata.content_type.clone())
+            .set_content_encoding(metadata.content_encoding.clone())
+            .set_metadata(custom_metadata(&metadata))
+            .set_storage_class(storage_class)
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.sse_kms_key_id())
+            .set_acl(self.acl.clone())
+            .send()
+            .await
+            .map_err(|err| request_error(dst_key, err))?
+            .upload_id
+            .ok_or_else(|| Error::body_error("no upload id found in S3 response"))?;
+
+        match self.copy_parts(src_key, dst_key, &upload_id, size).await {
+            Ok(receipt) => Ok(receipt),
+            Err(err) => {
+                self.abort_multipart(dst_key, upload_id).await;
+                Err(err)
+            }
         }
     }
 
-    #[tracing::instrument]
-    async fn delete_blob(&self, key: &str) -> hold::Result<()> {
-        log::debug!("Deleting blob {}", key);
-        let req = DeleteObjectRequest {
-            bucket: self.bucket.clone(),
-            key: key.to_string(),
-            ..DeleteObjectRequest::default()
+    async fn copy_parts(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        upload_id: &str,
+        size: usize,
+    ) -> hold::Result<StoreReceipt> {
+        let copy_source = format!("{}/{}", self.bucket, encode_copy_source(src_key));
+        let part_size = max(self.part_size, size.div_ceil(MAX_PARTS));
+        let mut parts = Vec::new();
+        for (index, range) in part_ranges(size, part_size).into_iter().enumerate() {
+            let part_number = index as i32 + 1;
+            let output = self
+                .s3
+                .upload_part_copy()
+                .bucket(&self.bucket)
+                .key(dst_key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .copy_source(&copy_source)
+                .copy_source_range(format!("bytes={}-{}", range.start, range.end - 1))
+                .send()
+                .await
+                .map_err(|err| request_error(src_key, err))?;
+            parts.push(
+                CompletedPart::builder()
+                    .set_e_tag(output.copy_part_result.and_then(|result| result.e_tag))
+                    .part_number(part_number)
+                    .build(),
+            );
+        }
+
+        self.s3
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(dst_key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(|output| StoreReceipt {
+                etag: output.e_tag,
+                version_id: output.version_id,
+                ..StoreReceipt::new(dst_key, size)
+            })
+            .map_err(|err| request_error(dst_key, err))
+    }
+
+    async fn upload_parts<S>(
+        &self,
+        key: &str,
+        upload_id: &str,
+        content: S,
+        part_size: usize,
+    ) -> hold::Result<StoreReceipt>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        futures::pin_mut!(content);
+        let mut buffer = bytes1::BytesMut::with_capacity(part_size);
+        let mut parts = Vec::new();
+        let mut size = 0;
+        loop {
+            let chunk = content.next().await.transpose().map_err(Error::provider)?;
+            let done = chunk.is_none();
+            if let Some(chunk) = chunk {
+                size += chunk.len();
+                buffer.extend_from_slice(&chunk);
+            }
+            while buffer.len() >= part_size || (done && !buffer.is_empty()) {
+                let len = buffer.len().min(part_size);
+                let part = buffer.split_to(len).freeze();
+                let part_number = parts.len() as i32 + 1;
+                parts.push(self.upload_part(key, upload_id, part_number, part).await?);
+            }
+            if done {
+                break;
+            }
+        }
+        if parts.is_empty() {
+            // an upload needs at least one part, which may be empty when it is the last one
+            parts.push(
+                self.upload_part(key, upload_id, 1, bytes1::Bytes::new())
+                    .await?,
+            );
+        }
+
+        self.s3
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(|output| StoreReceipt {
+                etag: output.e_tag,
+                version_id: output.version_id,
+                ..StoreReceipt::new(key, size)
+            })
+            .map_err(|err| request_error(key, err))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        part: bytes1::Bytes,
+    ) -> hold::Result<CompletedPart> {
+        log::debug!(
+            "Uploading part {} of blob {} ({} bytes)",
+            part_number,
+            key,
+            part.len()
+        );
+        let output = self
+            .s3
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .content_length(part.len() as i64)
+            .body(ByteStream::from(part))
+            .send()
+            .await
+            .map_err(|err| request_error(key, err))?;
+        Ok(CompletedPart::builder()
+            .set_e_tag(output.e_tag)
+            .part_number(part_number)
+            .build())
+    }
+
+    /// Fetches the size and storage class of an object, if it exists
+    async fn object_info(&self, key: &str) -> hold::Result<Option<(usize, Option<StorageClass>)>> {
+        let res = self
+            .s3
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        match res {
+            Ok(output) => {
+                let size = output.content_length.unwrap_or_default() as usize;
+                Ok(Some((size, output.storage_class)))
+            }
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(request_error(key, err)),
+        }
+    }
+
+    /// Fetches a page of a listing, along with the token of the next page if any
+    async fn list_objects(
+        &self,
+        prefix: &str,
+        delimiter: Option<&str>,
+        continuation_token: Option<String>,
+        max_keys: Option<usize>,
+    ) -> hold::Result<(Vec<ListItem>, Option<String>)> {
+        let output = self
+            .s3
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .set_delimiter(delimiter.map(str::to_string))
+            .set_continuation_token(continuation_token)
+            .set_max_keys(max_keys.map(|max| max.min(i32::MAX as usize) as i32))
+            .send()
+            .await
+            .map_err(|err| request_error(prefix, err))?;
+
+        let mut items: Vec<_> = output
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|object| {
+                let key = object.key?;
+                let last_modified = object
+                    .last_modified
+                    .and_then(|date| SystemTime::try_from(date).ok());
+                Some(ListItem::Blob(BlobEntry {
+                    last_modified,
+                    ..BlobEntry::new(key, object.size.unwrap_or_default() as usize)
+                }))
+            })
+            .collect();
+        items.extend(
+            output
+                .common_prefixes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|common| common.prefix.map(ListItem::CommonPrefix)),
+        );
+        let next = match output.is_truncated {
+            Some(true) => output.next_continuation_token,
+            _ => None,
         };
+        Ok((items, next))
+    }
 
+    async fn put_legal_hold(&self, key: &str, legal_hold: bool) -> hold::Result<()> {
+        let status = if legal_hold {
+            ObjectLockLegalHoldStatus::On
+        } else {
+            ObjectLockLegalHoldStatus::Off
+        };
         self.s3
-            .delete_object(req)
+            .put_object_legal_hold()
+            .bucket(&self.bucket)
+            .key(key)
+            .legal_hold(ObjectLockLegalHold::builder().status(status).build())
+            .send()
             .await
             .map(|_| ())
-            .map_err(|err| Error::provider(err))
+            .map_err(|err| request_error(key, err))
+    }
+}
+
+/// Region used when none is configured, read from the environment like the AWS CLI does
+fn default_region() -> String {
+    ["AWS_REGION", "AWS_DEFAULT_REGION"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|region| !region.is_empty()))
+        .unwrap_or_else(|| "us-east-1".to_string())
+}
+
+/// Maps a failed request to the error reporting its outcome, by HTTP status:
+/// denied access, missing objects or buckets, failed preconditions, throttling
+/// and timeouts each have their own error, other client errors are invalid requests,
+/// and server and transport failures are provider errors, which are retried
+fn request_error<E>(key: &str, err: SdkError<E>) -> Error
+where
+    E: ProvideErrorMetadata,
+    SdkError<E>: std::error::Error + Send + Sync + 'static,
+{
+    // some transient failures are sent as 400 Bad Request, so the code is checked first
+    match err.code() {
+        Some("BadDigest") => return Error::checksum_mismatch(key),
+        Some("RequestTimeout") => return Error::timeout(DisplayErrorContext(&err)),
+        // the credentials are refreshed before the request is attempted again
+        Some("ExpiredToken") | Some("TokenRefreshRequired") => return Error::provider(err),
+        _ => {}
+    }
+    match err {
+        SdkError::TimeoutError(_) => return Error::timeout(DisplayErrorContext(&err)),
+        SdkError::ConstructionFailure(_) => {
+            return Error::invalid_request(DisplayErrorContext(&err))
+        }
+        _ => {}
+    }
+    let response = match err.raw_response() {
+        Some(response) => response,
+        None => return Error::provider(err),
+    };
+    match response.status().as_u16() {
+        403 => Error::permission_denied(DisplayErrorContext(&err)),
+        404 => Error::not_found(key.to_string(), err),
+        412 => Error::precondition_failed(key),
+        // S3 throttles with 503 Slow Down
+        429 | 503 => {
+            let retry_after = response
+                .headers()
+                .get("retry-after")
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs);
+            Error::rate_limited(DisplayErrorContext(&err), retry_after)
+        }
+        408 | 504 => Error::timeout(DisplayErrorContext(&err)),
+        // conflicts are concurrent operations on the same resource, which may be retried
+        409 => Error::provider(err),
+        400..=499 => Error::invalid_request(DisplayErrorContext(&err)),
+        _ => Error::provider(err),
+    }
+}
+
+/// Whether a request failed because the object (or its retention) does not exist
+fn is_not_found<E>(err: &SdkError<E>) -> bool {
+    err.raw_response()
+        .is_some_and(|response| response.status().as_u16() == 404)
+}
+
+/// Whether the endpoint rejected a request as an operation it does not implement,
+/// as minimal S3-compatible servers do with multipart uploads
+fn is_unsupported<E>(err: &SdkError<E>) -> bool {
+    err.raw_response()
+        .is_some_and(|response| matches!(response.status().as_u16(), 405 | 501))
+}
+
+fn is_precondition_failed<E>(err: &SdkError<E>) -> bool {
+    err.raw_response()
+        .is_some_and(|response| response.status().as_u16() == 412)
+}
+
+/// Size of the object a ranged request was not satisfiable for,
+/// as reported by the `Content-Range: bytes */<size>` header of 416 responses
+fn unsatisfiable_range_size<E>(err: &SdkError<E>) -> Option<usize> {
+    let response = err
+        .raw_response()
+        .filter(|response| response.status().as_u16() == 416)?;
+    let size = response
+        .headers()
+        .get("content-range")
+        .and_then(|range| range.strip_prefix("bytes */"))
+        .and_then(|size| size.parse().ok());
+    Some(size.unwrap_or_default())
+}
+
+/// Byte ranges of the parts an object of the given size is copied in
+fn part_ranges(size: usize, part_size: usize) -> Vec<Range<usize>> {
+    (0..size)
+        .step_by(part_size.max(1))
+        .map(|start| start..(start + part_size).min(size))
+        .collect()
+}
+
+/// User-defined metadata of a blob, as sent with uploads
+fn custom_metadata(metadata: &BlobMetadata) -> Option<HashMap<String, String>> {
+    if metadata.custom.is_empty() {
+        None
+    } else {
+        Some(metadata.custom.clone().into_iter().collect())
+    }
+}
+
+/// Percent-encodes a key for the `x-amz-copy-source` header
+fn encode_copy_source(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Maps a storage tier to the S3 storage class it is stored with
+fn storage_class(tier: &StorageTier) -> StorageClass {
+    match tier {
+        StorageTier::Hot => StorageClass::Standard,
+        StorageTier::Cool => StorageClass::StandardIa,
+        StorageTier::Cold => StorageClass::GlacierIr,
+        StorageTier::Archive => StorageClass::DeepArchive,
+        StorageTier::Custom(class) => StorageClass::from(class.as_str()),
+    }
+}
+
+fn storage_tier(class: &StorageClass) -> StorageTier {
+    match class {
+        StorageClass::Standard => StorageTier::Hot,
+        StorageClass::StandardIa => StorageTier::Cool,
+        StorageClass::GlacierIr => StorageTier::Cold,
+        StorageClass::DeepArchive => StorageTier::Archive,
+        class => StorageTier::custom(class.as_str()),
     }
 }
 
@@ -170,15 +1568,496 @@ impl Debug for S3Provider {
     }
 }
 
+/// Configuration of an [`S3Provider`].
+///
+/// With the `serde` feature, it can be deserialized from application configuration,
+/// except for the credentials provider and the upload buffer, which are set in code.
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct S3Config {
     pub bucket: String,
     pub endpoint: Option<String>,
+    /// Region of the bucket, read from `AWS_REGION` or `AWS_DEFAULT_REGION` by default
     pub region: Option<String>,
     pub credentials: Option<S3Credentials>,
+    /// Source of credentials, used when no static `credentials` are set.
+    /// Expiring credentials are refreshed transparently before they expire.
+    /// When neither is set, the default AWS credentials chain is used.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub credentials_provider: Option<Arc<dyn CredentialsProvider>>,
+    /// IAM role assumed through STS with the credentials above, whose temporary
+    /// credentials are used instead, assuming the role again before they expire
+    pub assume_role: Option<S3AssumeRole>,
+    /// Set when the bucket has Object Lock enabled, to refuse deleting held blobs
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub object_lock: bool,
+    /// Size above which blobs are uploaded in parts, 64 MiB by default
+    pub multipart_threshold: Option<usize>,
+    /// Size of the parts of multipart uploads, 16 MiB by default.
+    /// S3 requires parts of at least 5 MiB, so smaller sizes are raised to it.
+    pub part_size: Option<usize>,
+    /// Budget buffering uploads of unknown size for endpoints that reject multipart
+    /// uploads, which are then stored with a single request declaring their size.
+    /// Defaults to 64 MiB of memory, spilling to the temporary directory beyond it.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub upload_buffer: Option<MemoryBudget>,
+    /// Server-side encryption requested for stored and copied blobs,
+    /// the default encryption of the bucket applying when not set
+    pub encryption: Option<S3Encryption>,
+    /// Native storage class of stored blobs without a storage tier, such as `STANDARD_IA`
+    /// or `GLACIER_IR`, the default class of the bucket applying when not set
+    pub storage_class: Option<String>,
+    /// Canned ACL of stored and copied blobs, such as `bucket-owner-full-control`
+    /// for uploads to the buckets of other accounts
+    pub acl: Option<String>,
+    /// Object ownership of the buckets created through [`AdminProvider::create_bucket`],
+    /// such as `BucketOwnerPreferred`
+    pub object_ownership: Option<String>,
+}
+
+impl S3Config {
+    /// Reads the configuration from the `S3_BUCKET`, `S3_ENDPOINT`, `S3_REGION`,
+    /// `S3_ACCESS_KEY_ID`, `S3_SECRET_ACCESS_KEY`, `S3_OBJECT_LOCK`,
+    /// `S3_MULTIPART_THRESHOLD`, `S3_PART_SIZE`, `S3_SSE`, `S3_SSE_KMS_KEY_ID`,
+    /// `S3_STORAGE_CLASS`, `S3_ACL`, `S3_OBJECT_OWNERSHIP`, `S3_ROLE_ARN`,
+    /// `S3_EXTERNAL_ID` and `S3_ROLE_SESSION_NAME` variables.
+    /// Without an access key, the default AWS credentials chain is used.
+    pub fn from_env(config: &EnvConfig) -> hold::Result<S3Config> {
+        let credentials = match (
+            config.get("S3_ACCESS_KEY_ID"),
+            config.get("S3_SECRET_ACCESS_KEY"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(S3Credentials {
+                access_key_id: access_key_id.to_string(),
+                secret_access_key: secret_access_key.to_string(),
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(Error::config(
+                    "S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY must be set together",
+                ))
+            }
+        };
+        Ok(S3Config {
+            bucket: config.require("S3_BUCKET")?.to_string(),
+            endpoint: config.get("S3_ENDPOINT").map(str::to_string),
+            region: config.get("S3_REGION").map(str::to_string),
+            credentials,
+            credentials_provider: None,
+            assume_role: assume_role(
+                config.get("S3_ROLE_ARN"),
+                config.get("S3_EXTERNAL_ID"),
+                config.get("S3_ROLE_SESSION_NAME"),
+            )?,
+            object_lock: config.flag("S3_OBJECT_LOCK")?,
+            multipart_threshold: config.parse("S3_MULTIPART_THRESHOLD")?,
+            part_size: config.parse("S3_PART_SIZE")?,
+            upload_buffer: None,
+            encryption: encryption(config.parse("S3_SSE")?, config.get("S3_SSE_KMS_KEY_ID"))?,
+            storage_class: config.get("S3_STORAGE_CLASS").map(str::to_string),
+            acl: config.get("S3_ACL").map(str::to_string),
+            object_ownership: config.get("S3_OBJECT_OWNERSHIP").map(str::to_string),
+        })
+    }
+}
+
+impl S3Config {
+    /// Reads the configuration from a URL like `s3://bucket/prefix?region=eu-west-1`,
+    /// with the `endpoint`, `region`, `object_lock`, `multipart_threshold`, `part_size`,
+    /// `sse`, `sse_kms_key_id`, `storage_class`, `acl`, `object_ownership`, `role_arn`,
+    /// `external_id` and `session_name` parameters. Credentials are not read from URLs,
+    /// so the default AWS credentials chain is used, to assume the role if one is given.
+    pub fn from_url(url: &StorageUrl) -> hold::Result<S3Config> {
+        if url.host().is_empty() {
+            return Err(Error::config("S3 URLs must name a bucket"));
+        }
+        Ok(S3Config {
+            bucket: url.host().to_string(),
+            endpoint: url.param("endpoint").map(str::to_string),
+            region: url.param("region").map(str::to_string),
+            assume_role: assume_role(
+                url.param("role_arn"),
+                url.param("external_id"),
+                url.param("session_name"),
+            )?,
+            object_lock: url.parse_param("object_lock")?.unwrap_or(false),
+            multipart_threshold: url.parse_param("multipart_threshold")?,
+            part_size: url.parse_param("part_size")?,
+            encryption: encryption(url.parse_param("sse")?, url.param("sse_kms_key_id"))?,
+            storage_class: url.param("storage_class").map(str::to_string),
+            acl: url.param("acl").map(str::to_string),
+            object_ownership: url.param("object_ownership").map(str::to_string),
+            ..S3Config::default()
+        })
+    }
+}
+
+/// Server-side encryption of the objects stored by an [`S3Provider`].
+///
+/// Parsed from `s3` (or `AES256`) and `kms` (or `aws:kms`), as configured
+/// by [`S3Config::from_env`] and [`S3Config::from_url`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum S3Encryption {
+    /// SSE-S3, with keys managed by S3
+    S3,
+
+    /// SSE-KMS, with the given AWS KMS key, or the AWS managed key of S3 if none is given
+    Kms { key_id: Option<String> },
+}
+
+impl S3Encryption {
+    fn algorithm(&self) -> ServerSideEncryption {
+        match self {
+            S3Encryption::S3 => ServerSideEncryption::Aes256,
+            S3Encryption::Kms { .. } => ServerSideEncryption::AwsKms,
+        }
+    }
+}
+
+impl FromStr for S3Encryption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s3" | "AES256" => Ok(S3Encryption::S3),
+            "kms" | "aws:kms" => Ok(S3Encryption::Kms { key_id: None }),
+            _ => Err(format!("unknown server-side encryption {}", s)),
+        }
+    }
+}
+
+/// Sets the KMS key of the configured encryption, which must then be SSE-KMS
+fn encryption(
+    encryption: Option<S3Encryption>,
+    kms_key_id: Option<&str>,
+) -> hold::Result<Option<S3Encryption>> {
+    match (encryption, kms_key_id) {
+        (Some(S3Encryption::Kms { .. }), key_id) => Ok(Some(S3Encryption::Kms {
+            key_id: key_id.map(str::to_string),
+        })),
+        (encryption, None) => Ok(encryption),
+        (_, Some(_)) => Err(Error::config(
+            "a KMS key id requires kms server-side encryption",
+        )),
+    }
+}
+
+/// IAM role assumed by an [`S3Provider`], see [`S3Config::assume_role`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct S3AssumeRole {
+    /// ARN of the role, like `arn:aws:iam::123456789012:role/example`
+    pub role_arn: String,
+    /// External id required by the trust policy of the role, usually for roles
+    /// of other accounts
+    pub external_id: Option<String>,
+    /// Name of the sessions, as seen in CloudTrail, generated when not set
+    pub session_name: Option<String>,
+}
+
+/// The role to assume, which must have an ARN when its other settings are given
+fn assume_role(
+    role_arn: Option<&str>,
+    external_id: Option<&str>,
+    session_name: Option<&str>,
+) -> hold::Result<Option<S3AssumeRole>> {
+    match role_arn {
+        Some(role_arn) => Ok(Some(S3AssumeRole {
+            role_arn: role_arn.to_string(),
+            external_id: external_id.map(str::to_string),
+            session_name: session_name.map(str::to_string),
+        })),
+        None if external_id.is_none() && session_name.is_none() => Ok(None),
+        None => Err(Error::config(
+            "an external id or session name requires a role ARN",
+        )),
+    }
 }
 
+/// Registers the `s3` provider, configured as described by [`S3Config::from_env`],
+/// and the `s3` URL scheme, configured as described by [`S3Config::from_url`].
+/// Blobs of URLs with a path are stored under it as a prefix.
+pub fn register(registry: Registry) -> Registry {
+    registry
+        .with_provider("s3", |config| {
+            let config = S3Config::from_env(config)?;
+            Ok(Box::new(S3Provider::new_with_config(config)))
+        })
+        .with_scheme("s3", |url| {
+            let provider = S3Provider::new_with_config(S3Config::from_url(url)?);
+            if url.prefix().is_empty() {
+                Ok(Box::new(provider))
+            } else {
+                Ok(Box::new(PrefixedProvider::new(provider, url.prefix())))
+            }
+        })
+}
+
+/// Static credentials of an [`S3Provider`]. The secret is redacted from debug
+/// output and left out when serializing.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct S3Credentials {
     pub access_key_id: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     pub secret_access_key: String,
 }
+
+impl Debug for S3Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryInto;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use aws_sdk_s3::config::http::HttpResponse;
+    use aws_sdk_s3::error::{ErrorMetadata, SdkError};
+    use aws_sdk_s3::operation::get_object::GetObjectError;
+    use aws_sdk_s3::types::StorageClass;
+    use aws_smithy_types::body::SdkBody;
+    use hold::config::EnvConfig;
+    use hold::registry::StorageUrl;
+    use hold::tier::StorageTier;
+
+    use crate::{
+        encode_copy_source, lists_more_versions, part_ranges, request_error, storage_class,
+        storage_tier, unsatisfiable_range_size, ListedVersion, S3Config, S3Credentials,
+        S3Encryption,
+    };
+
+    /// An error response of the given status, with the given error code if any
+    fn response_error(
+        status: u16,
+        code: Option<&str>,
+        headers: &[(&'static str, &str)],
+    ) -> SdkError<GetObjectError, HttpResponse> {
+        let mut metadata = ErrorMetadata::builder();
+        if let Some(code) = code {
+            metadata = metadata.code(code);
+        }
+        let mut response = HttpResponse::new(status.try_into().unwrap(), SdkBody::empty());
+        for (name, value) in headers {
+            response.headers_mut().insert(*name, value.to_string());
+        }
+        SdkError::service_error(GetObjectError::generic(metadata.build()), response)
+    }
+
+    fn error_code(status: u16, code: Option<&str>) -> &'static str {
+        request_error("key", response_error(status, code, &[])).code()
+    }
+
+    #[test]
+    fn it_classifies_request_errors_by_code_then_status() {
+        // transient failures sent as 400 Bad Request
+        assert_eq!(error_code(400, Some("BadDigest")), "checksum_mismatch");
+        assert_eq!(error_code(400, Some("RequestTimeout")), "timeout");
+        assert_eq!(error_code(400, Some("ExpiredToken")), "provider_error");
+        assert_eq!(
+            error_code(400, Some("TokenRefreshRequired")),
+            "provider_error"
+        );
+        assert_eq!(error_code(400, Some("InvalidArgument")), "invalid_request");
+
+        assert_eq!(error_code(403, None), "permission_denied");
+        assert_eq!(error_code(404, Some("NoSuchKey")), "not_found");
+        assert_eq!(error_code(412, None), "precondition_failed");
+        assert_eq!(error_code(409, None), "provider_error");
+        assert_eq!(error_code(504, None), "timeout");
+        assert_eq!(error_code(500, None), "provider_error");
+
+        let err = request_error(
+            "key",
+            response_error(503, Some("SlowDown"), &[("retry-after", "3")]),
+        );
+        assert_eq!(err.code(), "rate_limited");
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(3)));
+
+        let err: SdkError<GetObjectError, HttpResponse> = SdkError::timeout_error("too slow");
+        assert_eq!(request_error("key", err).code(), "timeout");
+    }
+
+    #[test]
+    fn it_reads_the_size_of_unsatisfiable_ranges() {
+        let err = response_error(416, None, &[("content-range", "bytes */1024")]);
+        assert_eq!(unsatisfiable_range_size(&err), Some(1024));
+        let err = response_error(416, None, &[]);
+        assert_eq!(unsatisfiable_range_size(&err), Some(0));
+        let err = response_error(404, None, &[("content-range", "bytes */1024")]);
+        assert_eq!(unsatisfiable_range_size(&err), None);
+    }
+
+    #[test]
+    fn it_encodes_copy_sources() {
+        assert_eq!(encode_copy_source("dir/file-1_a.txt~"), "dir/file-1_a.txt~");
+        assert_eq!(encode_copy_source("a b+c&d"), "a%20b%2Bc%26d");
+        assert_eq!(encode_copy_source("é"), "%C3%A9");
+    }
+
+    #[test]
+    fn it_splits_copies_in_parts() {
+        assert_eq!(part_ranges(10, 4), vec![0..4, 4..8, 8..10]);
+        assert_eq!(part_ranges(8, 4), vec![0..4, 4..8]);
+        assert!(part_ranges(0, 4).is_empty());
+    }
+
+    #[test]
+    fn it_maps_storage_tiers_to_classes() {
+        for (tier, class) in [
+            (StorageTier::Hot, StorageClass::Standard),
+            (StorageTier::Cool, StorageClass::StandardIa),
+            (StorageTier::Cold, StorageClass::GlacierIr),
+            (StorageTier::Archive, StorageClass::DeepArchive),
+        ] {
+            assert_eq!(storage_class(&tier), class);
+            assert_eq!(storage_tier(&class), tier);
+        }
+        let tier = StorageTier::custom("INTELLIGENT_TIERING");
+        assert_eq!(storage_class(&tier), StorageClass::IntelligentTiering);
+        assert_eq!(storage_tier(&StorageClass::IntelligentTiering), tier);
+    }
+
+    #[test]
+    fn it_reads_the_config_from_urls() {
+        let url: StorageUrl =
+            "s3://bucket/prefix?region=eu-west-1&sse=kms&sse_kms_key_id=key&part_size=8"
+                .parse()
+                .unwrap();
+        let config = S3Config::from_url(&url).unwrap();
+        assert_eq!(config.bucket, "bucket");
+        assert_eq!(config.region.as_deref(), Some("eu-west-1"));
+        assert_eq!(
+            config.encryption,
+            Some(S3Encryption::Kms {
+                key_id: Some("key".to_string())
+            })
+        );
+        assert_eq!(config.part_size, Some(8));
+        assert!(config.credentials.is_none());
+
+        let url: StorageUrl = "s3://bucket?part_size=many".parse().unwrap();
+        assert!(S3Config::from_url(&url).is_err());
+    }
+
+    #[test]
+    fn it_reads_the_config_from_env() {
+        let config = EnvConfig::from_vars(
+            "HOLD",
+            vec![
+                ("HOLD_S3_BUCKET", "bucket"),
+                ("HOLD_S3_ENDPOINT", "http://localhost:9000"),
+                ("HOLD_S3_ACCESS_KEY_ID", "id"),
+                ("HOLD_S3_SECRET_ACCESS_KEY", "secret"),
+                ("HOLD_S3_OBJECT_LOCK", "true"),
+                ("HOLD_S3_SSE", "s3"),
+            ],
+        );
+        let config = S3Config::from_env(&config).unwrap();
+        assert_eq!(config.bucket, "bucket");
+        assert_eq!(config.endpoint.as_deref(), Some("http://localhost:9000"));
+        assert_eq!(config.credentials.unwrap().access_key_id, "id");
+        assert!(config.object_lock);
+        assert_eq!(config.encryption, Some(S3Encryption::S3));
+
+        // the access key and secret go together
+        let config = EnvConfig::from_vars(
+            "HOLD",
+            vec![
+                ("HOLD_S3_BUCKET", "bucket"),
+                ("HOLD_S3_ACCESS_KEY_ID", "id"),
+            ],
+        );
+        assert!(S3Config::from_env(&config).is_err());
+        assert!(
+            S3Config::from_env(&EnvConfig::from_vars("HOLD", Vec::<(&str, &str)>::new())).is_err()
+        );
+    }
+
+    #[test]
+    fn it_redacts_the_secret_access_key() {
+        let credentials = S3Credentials {
+            access_key_id: "id".to_string(),
+            secret_access_key: "hunter2".to_string(),
+        };
+        let debug = format!("{:?}", credentials);
+
+        assert!(debug.contains("id"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    fn version(id: &str, secs: u64, is_latest: bool, deleted: bool) -> ListedVersion {
+        ListedVersion {
+            last_modified: UNIX_EPOCH + Duration::from_secs(secs),
+            version_id: Some(id.to_string()),
+            is_latest,
+            deleted,
+        }
+    }
+
+    /// The id of the version current at the given moment, going through them in listing order
+    fn version_at(versions: Vec<ListedVersion>, at: u64) -> Option<String> {
+        let at = UNIX_EPOCH + Duration::from_secs(at);
+        let mut current: Option<ListedVersion> = None;
+        for version in versions {
+            if version.replaces(current.as_ref(), at) {
+                current = Some(version);
+            }
+        }
+        current.and_then(|version| version.version_id)
+    }
+
+    #[test]
+    fn it_picks_the_latest_version_created_before_the_moment() {
+        let versions = || {
+            vec![
+                version("v3", 30, true, false),
+                version("v2", 20, false, false),
+                version("v1", 10, false, false),
+                version("d1", 15, false, true),
+            ]
+        };
+        assert_eq!(version_at(versions(), 40).as_deref(), Some("v3"));
+        assert_eq!(version_at(versions(), 25).as_deref(), Some("v2"));
+        assert_eq!(version_at(versions(), 17).as_deref(), Some("d1"));
+        assert_eq!(version_at(versions(), 10).as_deref(), Some("v1"));
+        assert_eq!(version_at(versions(), 5), None);
+    }
+
+    #[test]
+    fn it_stops_listing_versions_past_the_key() {
+        assert!(lists_more_versions("logs", Some(true), Some("logs")));
+        assert!(!lists_more_versions("logs", Some(true), Some("logs/2020")));
+        assert!(!lists_more_versions("logs", Some(false), Some("logs")));
+        assert!(!lists_more_versions("logs", None, None));
+    }
+
+    #[test]
+    fn it_breaks_ties_by_latest_then_listing_order() {
+        // versions of the same second are listed from the newest
+        let versions = vec![
+            version("v2", 10, false, false),
+            version("v1", 10, false, false),
+        ];
+        assert_eq!(version_at(versions, 10).as_deref(), Some("v2"));
+
+        // a delete marker marked as latest wins over a version of the same second
+        let versions = vec![
+            version("v1", 10, false, false),
+            version("d1", 10, true, true),
+        ];
+        assert_eq!(version_at(versions, 10).as_deref(), Some("d1"));
+
+        let versions = vec![
+            version("v1", 10, true, false),
+            version("d1", 10, false, true),
+        ];
+        assert_eq!(version_at(versions, 10).as_deref(), Some("v1"));
+    }
+}
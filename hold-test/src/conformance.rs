@@ -0,0 +1,397 @@
+//! Checks of the semantics every [`Provider`] is expected to follow, for authors of
+//! providers to run against their implementation, usually through
+//! [`provider_conformance!`](crate::provider_conformance).
+//!
+//! Each check uses its own keys under `conformance/`, and deletes the blobs it stored
+//! once it passed, so that checks can run against shared or persistent backends.
+//! Checks panic with a description of the expected behaviour when it is not met.
+
+use bytes::Bytes;
+use futures::io::AsyncWriteExt;
+use futures::{stream, TryStreamExt};
+use hold::blob::Blob;
+use hold::metadata::BlobMetadata;
+use hold::provider::Provider;
+
+const LARGE_BLOB_SIZE: usize = 8 * 1024 * 1024;
+const LARGE_BLOB_CHUNK: usize = 64 * 1024;
+
+async fn content(blob: Blob) -> Vec<u8> {
+    blob.into_bytes(None)
+        .await
+        .expect("the content of fetched blobs can be read")
+        .to_vec()
+}
+
+async fn store<P: Provider + Sync + ?Sized>(provider: &P, key: &str, content: &[u8]) {
+    let receipt = provider
+        .store_blob(Blob::from_bytes(key, content.to_vec()))
+        .await
+        .expect("blobs can be stored");
+    assert_eq!(
+        receipt.key, key,
+        "receipts hold the key the blob is stored under"
+    );
+    assert_eq!(
+        receipt.size,
+        content.len(),
+        "receipts hold the size of the blob"
+    );
+}
+
+async fn get<P: Provider + Sync + ?Sized>(provider: &P, key: &str) -> Option<Blob> {
+    provider.get_blob(key).await.expect("blobs can be fetched")
+}
+
+async fn delete<P: Provider + Sync + ?Sized>(provider: &P, keys: &[&str]) {
+    for key in keys {
+        provider
+            .delete_blob(key)
+            .await
+            .expect("blobs can be deleted");
+    }
+}
+
+/// Stored blobs are fetched with the same key, size and content
+pub async fn stores_and_gets_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/store/blob";
+    store(provider, key, b"hello world").await;
+
+    let blob = get(provider, key).await.expect("stored blobs are found");
+    assert_eq!(blob.key(), key, "fetched blobs hold their key");
+    assert_eq!(blob.size(), 11, "fetched blobs hold their size");
+    assert_eq!(content(blob).await, b"hello world");
+    delete(provider, &[key]).await;
+}
+
+/// Missing blobs are not errors: they are not found, not present, not copied,
+/// and deleting them succeeds
+pub async fn reports_missing_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/missing/blob";
+    assert!(
+        get(provider, key).await.is_none(),
+        "missing blobs are not found"
+    );
+    assert!(
+        !provider
+            .is_blob_present(key)
+            .await
+            .expect("presence can be checked"),
+        "missing blobs are not present"
+    );
+    assert!(
+        provider
+            .get_blob_range(key, 0..1)
+            .await
+            .expect("ranges of missing blobs are not errors")
+            .is_none(),
+        "ranges of missing blobs are not found"
+    );
+    assert!(
+        provider
+            .copy_blob(key, "conformance/missing/copy")
+            .await
+            .expect("copies of missing blobs are not errors")
+            .is_none(),
+        "copies of missing blobs return nothing"
+    );
+    provider
+        .delete_blob(key)
+        .await
+        .expect("deleting missing blobs succeeds");
+}
+
+/// Blobs are present once stored, and missing once deleted
+pub async fn deletes_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/delete/blob";
+    store(provider, key, b"content").await;
+    assert!(
+        provider
+            .is_blob_present(key)
+            .await
+            .expect("presence can be checked"),
+        "stored blobs are present"
+    );
+
+    delete(provider, &[key]).await;
+    assert!(
+        !provider
+            .is_blob_present(key)
+            .await
+            .expect("presence can be checked"),
+        "deleted blobs are not present"
+    );
+    assert!(
+        get(provider, key).await.is_none(),
+        "deleted blobs are not found"
+    );
+}
+
+/// Storing under an existing key replaces the blob
+pub async fn overwrites_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/overwrite/blob";
+    store(provider, key, b"first version").await;
+    store(provider, key, b"second").await;
+
+    let blob = get(provider, key)
+        .await
+        .expect("overwritten blobs are found");
+    assert_eq!(
+        blob.size(),
+        6,
+        "overwritten blobs have the size of the new content"
+    );
+    assert_eq!(content(blob).await, b"second");
+    delete(provider, &[key]).await;
+}
+
+/// Blobs without content are stored and fetched like any other
+pub async fn stores_empty_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/empty/blob";
+    store(provider, key, b"").await;
+
+    let blob = get(provider, key).await.expect("empty blobs are found");
+    assert_eq!(blob.size(), 0, "empty blobs have no size");
+    assert!(
+        content(blob).await.is_empty(),
+        "empty blobs have no content"
+    );
+    delete(provider, &[key]).await;
+}
+
+/// Blobs of several megabytes streamed in many chunks are stored whole
+pub async fn stores_large_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/large/blob";
+    let expected: Vec<u8> = (0..LARGE_BLOB_SIZE).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<_> = expected
+        .chunks(LARGE_BLOB_CHUNK)
+        .map(|chunk| Ok(Bytes::from(chunk.to_vec())))
+        .collect();
+    let blob = Blob::new(key, LARGE_BLOB_SIZE, stream::iter(chunks));
+    let receipt = provider
+        .store_blob(blob)
+        .await
+        .expect("large blobs can be stored");
+    assert_eq!(
+        receipt.size, LARGE_BLOB_SIZE,
+        "receipts hold the size of large blobs"
+    );
+
+    let blob = get(provider, key).await.expect("large blobs are found");
+    assert_eq!(blob.size(), LARGE_BLOB_SIZE, "large blobs keep their size");
+    assert!(
+        content(blob).await == expected,
+        "large blobs keep their content"
+    );
+    delete(provider, &[key]).await;
+}
+
+/// Keys are not restricted to ASCII
+pub async fn supports_unicode_keys<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/unicode/données/日本語 ✓.txt";
+    store(provider, key, "contenu ✓".as_bytes()).await;
+
+    let blob = get(provider, key)
+        .await
+        .expect("blobs with unicode keys are found");
+    assert_eq!(blob.key(), key, "unicode keys are kept as they are");
+    assert_eq!(content(blob).await, "contenu ✓".as_bytes());
+    let keys: Vec<String> = provider
+        .list_blobs("conformance/unicode/")
+        .map_ok(|entry| entry.key)
+        .try_collect()
+        .await
+        .expect("blobs can be listed");
+    assert_eq!(keys, vec![key], "unicode keys are listed as they are");
+    delete(provider, &[key]).await;
+}
+
+/// Ranges are clamped to the content, and fail when starting past its end
+pub async fn reads_ranges<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/range/blob";
+    store(provider, key, b"0123456789").await;
+
+    let range = |range| async move {
+        provider
+            .get_blob_range(key, range)
+            .await
+            .map(|blob| blob.expect("ranges of stored blobs are found"))
+    };
+    let blob = range(2..5).await.expect("ranges can be read");
+    assert_eq!(blob.size(), 3, "ranges have the size of the range");
+    assert_eq!(content(blob).await, b"234");
+    let blob = range(8..100)
+        .await
+        .expect("ranges past the end can be read");
+    assert_eq!(
+        content(blob).await,
+        b"89",
+        "ranges are clamped to the content"
+    );
+    let err = range(20..30)
+        .await
+        .expect_err("ranges starting past the end fail");
+    assert_eq!(err.code(), "range_not_satisfiable");
+    delete(provider, &[key]).await;
+}
+
+/// Copies hold the content of their source, which is left in place
+pub async fn copies_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let (src, dst) = ("conformance/copy/source", "conformance/copy/destination");
+    store(provider, src, b"content").await;
+
+    let receipt = provider
+        .copy_blob(src, dst)
+        .await
+        .expect("blobs can be copied")
+        .expect("copies of stored blobs return a receipt");
+    assert_eq!(receipt.key, dst, "copy receipts hold the destination key");
+    for key in &[src, dst] {
+        let blob = get(provider, key)
+            .await
+            .expect("sources and copies are found");
+        assert_eq!(content(blob).await, b"content");
+    }
+    delete(provider, &[src, dst]).await;
+}
+
+/// Listings return the blobs whose key starts with the prefix, with their size
+pub async fn lists_blobs_by_prefix<P: Provider + Sync + ?Sized>(provider: &P) {
+    let keys = [
+        "conformance/list/dir/a",
+        "conformance/list/dir/b",
+        "conformance/list/dir-other",
+    ];
+    for (size, key) in keys.iter().enumerate() {
+        store(provider, key, &vec![0; size]).await;
+    }
+
+    let mut entries: Vec<_> = provider
+        .list_blobs("conformance/list/dir/")
+        .map_ok(|entry| (entry.key, entry.size))
+        .try_collect()
+        .await
+        .expect("blobs can be listed");
+    entries.sort();
+    let expected = vec![(keys[0].to_string(), 0), (keys[1].to_string(), 1)];
+    assert_eq!(
+        entries, expected,
+        "listings only return blobs under the prefix"
+    );
+    delete(provider, &keys).await;
+}
+
+/// Writers store what is written to them once finished
+pub async fn writes_blobs<P: Provider + Sync + ?Sized>(provider: &P) {
+    let key = "conformance/writer/blob";
+    let mut writer = provider.open_writer(key, BlobMetadata::default());
+    writer
+        .write_all(b"written ")
+        .await
+        .expect("writers accept content");
+    writer
+        .write_all(b"in chunks")
+        .await
+        .expect("writers accept content");
+    let receipt = writer
+        .finish()
+        .await
+        .expect("finished writers store the blob");
+    assert_eq!(
+        receipt.size, 17,
+        "writer receipts hold the size of the written content"
+    );
+
+    let blob = get(provider, key).await.expect("written blobs are found");
+    assert_eq!(content(blob).await, b"written in chunks");
+    delete(provider, &[key]).await;
+}
+
+/// Runs every check in turn
+pub async fn check_all<P: Provider + Sync + ?Sized>(provider: &P) {
+    stores_and_gets_blobs(provider).await;
+    reports_missing_blobs(provider).await;
+    deletes_blobs(provider).await;
+    overwrites_blobs(provider).await;
+    stores_empty_blobs(provider).await;
+    stores_large_blobs(provider).await;
+    supports_unicode_keys(provider).await;
+    reads_ranges(provider).await;
+    copies_blobs(provider).await;
+    lists_blobs_by_prefix(provider).await;
+    writes_blobs(provider).await;
+}
+
+/// Generates a test for each check of the [conformance suite](crate::conformance), run
+/// against a fresh provider built by the given factory.
+///
+/// Tests block on the checks with the `futures` executor by default. Providers needing
+/// a runtime pass the attribute of its async tests instead, e.g. `#[tokio::test]`.
+/// Since the tests are named after the checks, the macro is best invoked in a module
+/// of its own:
+///
+/// ```
+/// mod conformance {
+///     use hold::memory::MemoryProvider;
+///
+///     hold_test::provider_conformance!(MemoryProvider::new);
+/// }
+/// ```
+#[macro_export]
+macro_rules! provider_conformance {
+    ($factory:expr) => {
+        $crate::__conformance_checks!(__conformance_test, $factory);
+    };
+    ($factory:expr, #[$test:meta]) => {
+        $crate::__conformance_checks!(__conformance_async_test, $factory, $test);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_checks {
+    ($generate:ident, $($args:tt)*) => {
+        $crate::$generate!(stores_and_gets_blobs, $($args)*);
+        $crate::$generate!(reports_missing_blobs, $($args)*);
+        $crate::$generate!(deletes_blobs, $($args)*);
+        $crate::$generate!(overwrites_blobs, $($args)*);
+        $crate::$generate!(stores_empty_blobs, $($args)*);
+        $crate::$generate!(stores_large_blobs, $($args)*);
+        $crate::$generate!(supports_unicode_keys, $($args)*);
+        $crate::$generate!(reads_ranges, $($args)*);
+        $crate::$generate!(copies_blobs, $($args)*);
+        $crate::$generate!(lists_blobs_by_prefix, $($args)*);
+        $crate::$generate!(writes_blobs, $($args)*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_test {
+    ($check:ident, $factory:expr) => {
+        #[test]
+        fn $check() {
+            let provider = ($factory)();
+            $crate::__futures::executor::block_on($crate::conformance::$check(&provider));
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_async_test {
+    ($check:ident, $factory:expr, $test:meta) => {
+        #[$test]
+        async fn $check() {
+            let provider = ($factory)();
+            $crate::conformance::$check(&provider).await;
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use hold::memory::MemoryProvider;
+
+    provider_conformance!(MemoryProvider::new);
+}
@@ -0,0 +1,553 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::stream;
+use hold::blob::{satisfiable_range, Blob, RangeRead};
+use hold::error::Error;
+use hold::listing::{Cursor, Page};
+use hold::memory::MemoryProvider;
+use hold::metadata::BlobMetadata;
+use hold::provider::{EntryStream, Provider};
+use hold::receipt::StoreReceipt;
+use hold::Result;
+
+pub mod conformance;
+
+#[doc(hidden)]
+pub use futures as __futures;
+
+/// An operation of the [`Provider`] trait, as scripted and recorded by a [`MockProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    GetBlob,
+    GetBlobRange,
+    GetBlobIfRange,
+    StoreBlob,
+    IsBlobPresent,
+    DeleteBlob,
+    CopyBlob,
+    CopyBlobWithMetadata,
+    ListBlobs,
+    ListPage,
+    WarmUp,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+/// A call received by a [`MockProvider`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub operation: Operation,
+
+    /// Key the operation was called with: the source key of copies,
+    /// the prefix of listings and the comma-separated keys of warm ups
+    pub key: String,
+
+    /// Destination key of copies
+    pub target: Option<String>,
+}
+
+type ErrorFactory = Arc<dyn Fn() -> Error + Send + Sync>;
+
+#[derive(Clone)]
+enum Response {
+    Content(Vec<u8>, BlobMetadata),
+    Nothing,
+    Fail(ErrorFactory),
+}
+
+impl Debug for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Content(content, metadata) => f
+                .debug_tuple("Content")
+                .field(&content.len())
+                .field(metadata)
+                .finish(),
+            Response::Nothing => f.write_str("Nothing"),
+            Response::Fail(_) => f.write_str("Fail"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Expectation {
+    operation: Operation,
+    key: Option<String>,
+    remaining: Option<usize>,
+    response: Response,
+}
+
+impl Expectation {
+    fn matches(&self, operation: Operation, key: &str) -> bool {
+        self.operation == operation
+            && self.key.as_deref().is_none_or(|expected| expected == key)
+            && self.remaining != Some(0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    calls: Vec<Call>,
+}
+
+/// Provider answering with scripted responses and recording the calls it receives,
+/// for testing code using a [`Provider`] without writing a fake for it.
+///
+/// Responses are scripted per operation and key with [`MockProvider::expect`],
+/// the first matching expectation answering each call. Calls without a matching
+/// expectation go to an in-memory provider, so that the mock also behaves as a
+/// working store, unless the mock is [strict](MockProvider::strict) and fails them.
+///
+/// ```
+/// # futures::executor::block_on(async {
+/// use hold::error::Error;
+/// use hold::provider::Provider;
+/// use hold_test::{MockProvider, Operation};
+///
+/// let provider = MockProvider::new();
+/// provider.expect(Operation::GetBlob, "config").returns(b"debug = true".to_vec());
+/// provider
+///     .expect(Operation::DeleteBlob, "config")
+///     .once()
+///     .fails_with(|| Error::body_error("connection reset"));
+///
+/// assert!(provider.get_blob("config").await.unwrap().is_some());
+/// assert!(provider.delete_blob("config").await.is_err());
+/// assert_eq!(provider.call_count(Operation::GetBlob, "config"), 1);
+/// provider.verify();
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct MockProvider {
+    inner: MemoryProvider,
+    strict: bool,
+    state: Mutex<State>,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self {
+            inner: MemoryProvider::new(),
+            strict: false,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A mock failing the calls no expectation matches
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::new()
+        }
+    }
+
+    /// The in-memory provider answering the calls no expectation matches
+    pub fn inner(&self) -> &MemoryProvider {
+        &self.inner
+    }
+
+    /// Scripts the response to an operation on a key
+    pub fn expect<K: ToString>(&self, operation: Operation, key: K) -> ExpectationBuilder<'_> {
+        ExpectationBuilder::new(self, operation, Some(key.to_string()))
+    }
+
+    /// Scripts the response to an operation on any key
+    pub fn expect_any(&self, operation: Operation) -> ExpectationBuilder<'_> {
+        ExpectationBuilder::new(self, operation, None)
+    }
+
+    /// The calls received so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.lock().calls.clone()
+    }
+
+    /// Number of calls received so far for an operation on a key
+    pub fn call_count(&self, operation: Operation, key: &str) -> usize {
+        self.lock()
+            .calls
+            .iter()
+            .filter(|call| call.operation == operation && call.key == key)
+            .count()
+    }
+
+    /// Forgets the calls received so far
+    pub fn clear_calls(&self) {
+        self.lock().calls.clear();
+    }
+
+    /// Panics if an expectation scripted for a number of calls was not called that many times
+    pub fn verify(&self) {
+        let state = self.lock();
+        for expectation in &state.expectations {
+            if let Some(remaining) = expectation.remaining.filter(|remaining| *remaining > 0) {
+                panic!(
+                    "{} on {} expected {} more call(s)",
+                    expectation.operation,
+                    expectation.key.as_deref().unwrap_or("any key"),
+                    remaining
+                );
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a call and returns the response of the first matching expectation
+    fn respond(&self, operation: Operation, key: &str, target: Option<&str>) -> Option<Response> {
+        let mut state = self.lock();
+        state.calls.push(Call {
+            operation,
+            key: key.to_string(),
+            target: target.map(ToString::to_string),
+        });
+        let expectation = state
+            .expectations
+            .iter_mut()
+            .find(|expectation| expectation.matches(operation, key))?;
+        if let Some(remaining) = expectation.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        Some(expectation.response.clone())
+    }
+
+    fn unexpected(&self, operation: Operation, key: &str) -> Option<Error> {
+        if !self.strict {
+            return None;
+        }
+        let message = format!("unexpected call to {} on {}", operation, key);
+        Some(Error::provider(io::Error::other(message)))
+    }
+}
+
+/// Builder of an expectation of a [`MockProvider`], registered by its response
+#[must_use = "expectations are only registered once given a response"]
+pub struct ExpectationBuilder<'a> {
+    mock: &'a MockProvider,
+    operation: Operation,
+    key: Option<String>,
+    times: Option<usize>,
+}
+
+impl<'a> ExpectationBuilder<'a> {
+    fn new(mock: &'a MockProvider, operation: Operation, key: Option<String>) -> Self {
+        Self {
+            mock,
+            operation,
+            key,
+            times: None,
+        }
+    }
+
+    /// Answers the given number of calls only, instead of all of them.
+    /// Later calls go to the next matching expectation.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    /// Answers a single call
+    pub fn once(self) -> Self {
+        self.times(1)
+    }
+
+    /// Reads find a blob with the given content, checks find it present,
+    /// and writes succeed without being performed
+    pub fn returns<C: Into<Vec<u8>>>(self, content: C) -> &'a MockProvider {
+        self.returns_with_metadata(content, BlobMetadata::default())
+    }
+
+    /// Like [`ExpectationBuilder::returns`], the blob having the given metadata
+    pub fn returns_with_metadata<C: Into<Vec<u8>>>(
+        self,
+        content: C,
+        metadata: BlobMetadata,
+    ) -> &'a MockProvider {
+        self.respond(Response::Content(content.into(), metadata))
+    }
+
+    /// Reads and checks find no blob, and writes succeed without being performed
+    pub fn returns_nothing(self) -> &'a MockProvider {
+        self.respond(Response::Nothing)
+    }
+
+    /// Calls fail with the error the closure returns
+    pub fn fails_with<F: Fn() -> Error + Send + Sync + 'static>(
+        self,
+        error: F,
+    ) -> &'a MockProvider {
+        self.respond(Response::Fail(Arc::new(error)))
+    }
+
+    fn respond(self, response: Response) -> &'a MockProvider {
+        self.mock.lock().expectations.push(Expectation {
+            operation: self.operation,
+            key: self.key,
+            remaining: self.times,
+            response,
+        });
+        self.mock
+    }
+}
+
+fn blob(key: &str, content: Vec<u8>, metadata: BlobMetadata) -> Blob {
+    Blob::from_bytes(key, content).with_metadata(metadata)
+}
+
+fn blob_range(
+    key: &str,
+    mut content: Vec<u8>,
+    metadata: BlobMetadata,
+    range: Range<usize>,
+) -> Result<Blob> {
+    let range = satisfiable_range(key, content.len(), range)?;
+    content.truncate(range.end);
+    content.drain(..range.start);
+    Ok(blob(key, content, metadata))
+}
+
+/// Receipt of a write acknowledged without being performed
+fn receipt(key: &str, response: Response) -> Result<StoreReceipt> {
+    match response {
+        Response::Content(content, _) => Ok(StoreReceipt::new(key, content.len())),
+        Response::Nothing => Ok(StoreReceipt::new(key, 0)),
+        Response::Fail(error) => Err(error()),
+    }
+}
+
+#[async_trait]
+impl Provider for MockProvider {
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        match self.respond(Operation::GetBlob, key, None) {
+            Some(Response::Content(content, metadata)) => Ok(Some(blob(key, content, metadata))),
+            Some(Response::Nothing) => Ok(None),
+            Some(Response::Fail(error)) => Err(error()),
+            None => match self.unexpected(Operation::GetBlob, key) {
+                Some(err) => Err(err),
+                None => self.inner.get_blob(key).await,
+            },
+        }
+    }
+
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        match self.respond(Operation::GetBlobRange, key, None) {
+            Some(Response::Content(content, metadata)) => {
+                blob_range(key, content, metadata, range).map(Some)
+            }
+            Some(Response::Nothing) => Ok(None),
+            Some(Response::Fail(error)) => Err(error()),
+            None => match self.unexpected(Operation::GetBlobRange, key) {
+                Some(err) => Err(err),
+                None => self.inner.get_blob_range(key, range).await,
+            },
+        }
+    }
+
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        match self.respond(Operation::GetBlobIfRange, key, None) {
+            Some(Response::Content(content, metadata)) => {
+                if hold::blob::etag_matches(metadata.etag.as_deref(), etag) {
+                    blob_range(key, content, metadata, range)
+                        .map(|blob| Some(RangeRead::Range(blob)))
+                } else {
+                    Ok(Some(RangeRead::Full(blob(key, content, metadata))))
+                }
+            }
+            Some(Response::Nothing) => Ok(None),
+            Some(Response::Fail(error)) => Err(error()),
+            None => match self.unexpected(Operation::GetBlobIfRange, key) {
+                Some(err) => Err(err),
+                None => self.inner.get_blob_if_range(key, range, etag).await,
+            },
+        }
+    }
+
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        match self.respond(Operation::StoreBlob, &key, None) {
+            Some(response) => receipt(&key, response),
+            None => match self.unexpected(Operation::StoreBlob, &key) {
+                Some(err) => Err(err),
+                None => self.inner.store_blob(blob).await,
+            },
+        }
+    }
+
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        match self.respond(Operation::IsBlobPresent, key, None) {
+            Some(Response::Content(..)) => Ok(true),
+            Some(Response::Nothing) => Ok(false),
+            Some(Response::Fail(error)) => Err(error()),
+            None => match self.unexpected(Operation::IsBlobPresent, key) {
+                Some(err) => Err(err),
+                None => self.inner.is_blob_present(key).await,
+            },
+        }
+    }
+
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        match self.respond(Operation::DeleteBlob, key, None) {
+            Some(response) => receipt(key, response).map(|_| ()),
+            None => match self.unexpected(Operation::DeleteBlob, key) {
+                Some(err) => Err(err),
+                None => self.inner.delete_blob(key).await,
+            },
+        }
+    }
+
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        match self.respond(Operation::CopyBlob, src_key, Some(dst_key)) {
+            Some(Response::Nothing) => Ok(None),
+            Some(response) => receipt(dst_key, response).map(Some),
+            None => match self.unexpected(Operation::CopyBlob, src_key) {
+                Some(err) => Err(err),
+                None => self.inner.copy_blob(src_key, dst_key).await,
+            },
+        }
+    }
+
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        match self.respond(Operation::CopyBlobWithMetadata, src_key, Some(dst_key)) {
+            Some(Response::Nothing) => Ok(None),
+            Some(response) => receipt(dst_key, response).map(Some),
+            None => match self.unexpected(Operation::CopyBlobWithMetadata, src_key) {
+                Some(err) => Err(err),
+                None => {
+                    self.inner
+                        .copy_blob_with_metadata(src_key, dst_key, metadata)
+                        .await
+                }
+            },
+        }
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        match self.respond(Operation::ListBlobs, prefix, None) {
+            Some(Response::Fail(error)) => Box::pin(stream::iter(vec![Err(error())])),
+            Some(_) => Box::pin(stream::empty()),
+            None => match self.unexpected(Operation::ListBlobs, prefix) {
+                Some(err) => Box::pin(stream::iter(vec![Err(err)])),
+                None => self.inner.list_blobs(prefix),
+            },
+        }
+    }
+
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        match self.respond(Operation::ListPage, prefix, None) {
+            Some(Response::Fail(error)) => Err(error()),
+            Some(_) => Ok(Page {
+                entries: Vec::new(),
+                next: None,
+            }),
+            None => match self.unexpected(Operation::ListPage, prefix) {
+                Some(err) => Err(err),
+                None => self.inner.list_page(prefix, cursor, limit).await,
+            },
+        }
+    }
+
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        let key = keys.join(",");
+        match self.respond(Operation::WarmUp, &key, None) {
+            Some(response) => receipt(&key, response).map(|_| ()),
+            None => match self.unexpected(Operation::WarmUp, &key) {
+                Some(err) => Err(err),
+                None => self.inner.warm_up(keys).await,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use hold::blob::Blob;
+    use hold::error::Error;
+    use hold::provider::Provider;
+
+    use crate::{Call, MockProvider, Operation};
+
+    #[test]
+    fn it_answers_with_scripted_responses() {
+        let provider = MockProvider::new();
+        provider
+            .expect(Operation::StoreBlob, "flaky")
+            .times(2)
+            .fails_with(|| Error::body_error("connection reset"));
+        provider
+            .expect_any(Operation::GetBlobRange)
+            .returns(b"scripted".to_vec());
+        block_on(async {
+            let store = || provider.store_blob(Blob::from_bytes("flaky", b"content".to_vec()));
+            assert_eq!(store().await.unwrap_err().code(), "body_error");
+            assert!(store().await.is_err());
+            store().await.unwrap();
+            provider.verify();
+
+            // unscripted calls reach the in-memory provider
+            let blob = provider.get_blob("flaky").await.unwrap().unwrap();
+            assert_eq!(blob.into_bytes(None).await.unwrap(), &b"content"[..]);
+            let range = provider.get_blob_range("any", 2..5).await.unwrap().unwrap();
+            assert_eq!(range.into_bytes(None).await.unwrap(), &b"rip"[..]);
+        });
+
+        assert_eq!(provider.call_count(Operation::StoreBlob, "flaky"), 3);
+        assert_eq!(
+            provider.calls().last(),
+            Some(&Call {
+                operation: Operation::GetBlobRange,
+                key: "any".to_string(),
+                target: None,
+            })
+        );
+    }
+
+    #[test]
+    fn it_fails_unexpected_calls_when_strict() {
+        let provider = MockProvider::strict();
+        provider
+            .expect(Operation::IsBlobPresent, "a")
+            .returns_nothing();
+        block_on(async {
+            assert!(!provider.is_blob_present("a").await.unwrap());
+            assert!(provider.is_blob_present("b").await.is_err());
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "DeleteBlob on a expected 1 more call(s)")]
+    fn it_verifies_expected_calls() {
+        let provider = MockProvider::new();
+        provider
+            .expect(Operation::DeleteBlob, "a")
+            .once()
+            .returns_nothing();
+        provider.verify();
+    }
+}
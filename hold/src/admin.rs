@@ -0,0 +1,37 @@
+use async_trait::async_trait;
+
+use crate::provider::Provider;
+use crate::Result;
+
+/// Access to the blobs of a bucket, see [`AdminProvider::set_bucket_policy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BucketPolicy {
+    /// Only clients granted access by the backend can read the blobs
+    Private,
+
+    /// Anyone can download the blobs, without credentials
+    PublicRead,
+
+    /// A policy document in the format of the backend
+    Custom(String),
+}
+
+/// A storage provider able to manage the buckets, or containers, of its backend,
+/// so that provisioning tooling can create them through the same configuration.
+/// Buckets are managed with the credentials of the provider, whichever bucket
+/// the provider itself stores blobs in.
+#[async_trait]
+pub trait AdminProvider: Provider {
+    /// Creates a bucket, failing with [`Error::AlreadyExists`] if it already exists
+    ///
+    /// [`Error::AlreadyExists`]: crate::error::Error::AlreadyExists
+    async fn create_bucket(&self, name: &str) -> Result<()>;
+
+    /// Deletes a bucket, which backends usually refuse unless it is empty
+    async fn delete_bucket(&self, name: &str) -> Result<()>;
+
+    /// Lists the names of the buckets visible to the credentials of the provider
+    async fn list_buckets(&self) -> Result<Vec<String>>;
+
+    async fn set_bucket_policy(&self, name: &str, policy: BucketPolicy) -> Result<()>;
+}
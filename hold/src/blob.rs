@@ -1,11 +1,24 @@
 use std::fmt::{self, Debug, Formatter};
 use std::io;
+use std::ops::Range;
+use std::time::SystemTime;
 
-use bytes::Bytes;
-use futures::{stream, Stream};
+use bytes::{Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use std::pin::Pin;
 
-type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send + Sync + 'static>>;
+use crate::digest::Digests;
+use crate::error::Error;
+use crate::metadata::BlobMetadata;
+use crate::reader::BlobReader;
+use crate::tier::StorageTier;
+
+/// Size of the chunks a file is read in by [`Blob::from_path`]
+#[cfg(feature = "tokio")]
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+pub(crate) type ByteStream =
+    Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send + Sync + 'static>>;
 
 /// A blob is an object that can be stored onto a provider
 pub struct Blob {
@@ -18,6 +31,12 @@ pub struct Blob {
 
     /// The actual binary content of the blob.
     content_stream: ByteStream,
+
+    /// Metadata persisted alongside the content.
+    metadata: BlobMetadata,
+
+    /// Checksums of the content for the backend to verify, see [`Blob::with_checksums`].
+    checksums: Digests,
 }
 
 impl Blob {
@@ -30,6 +49,8 @@ impl Blob {
             key: key.to_string(),
             size,
             content_stream: Box::pin(stream),
+            metadata: BlobMetadata::default(),
+            checksums: Digests::default(),
         }
     }
 
@@ -40,7 +61,33 @@ impl Blob {
             stream::once(async move { Ok(Bytes::from(content)) }),
         )
     }
-    
+
+    /// A blob streaming the content of a file in chunks, sized after the file length.
+    /// The file is read through `tokio::fs`, so this requires a Tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub async fn from_path<K: ToString, P: AsRef<std::path::Path>>(
+        key: K,
+        path: P,
+    ) -> crate::Result<Self> {
+        use tokio::io::AsyncReadExt;
+
+        let file = tokio::fs::File::open(path).await.map_err(Error::io)?;
+        let size = file.metadata().await.map_err(Error::io)?.len() as usize;
+        let content = stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut chunk = vec![0; FILE_CHUNK_SIZE];
+            match file.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Some((Ok(Bytes::from(chunk)), Some(file)))
+                }
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        Ok(Self::new(key, size, content))
+    }
+
     pub fn empty<K: ToString>(key: K, size: usize) -> Self {
         Self::new(key, size, stream::empty())
     }
@@ -53,9 +100,227 @@ impl Blob {
         self.size
     }
 
+    pub fn metadata(&self) -> &BlobMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut BlobMetadata {
+        &mut self.metadata
+    }
+
+    pub fn with_metadata(mut self, metadata: BlobMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.metadata.content_type.as_deref()
+    }
+
+    /// Sets the content type to store the blob with
+    pub fn with_content_type<T: ToString>(mut self, content_type: T) -> Self {
+        self.metadata.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn checksums(&self) -> &Digests {
+        &self.checksums
+    }
+
+    /// Sets checksums of the content, which providers verifying them natively send to
+    /// their backend so that it rejects corrupted uploads with [`Error::ChecksumMismatch`],
+    /// see [`Provider::verified_checksums`]. Other checksums are ignored.
+    /// The checksums are not kept by blobs rebuilt from the content, as transformed
+    /// content would not match them.
+    ///
+    /// [`Provider::verified_checksums`]: crate::provider::Provider::verified_checksums
+    pub fn with_checksums(mut self, checksums: Digests) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    pub fn storage_tier(&self) -> Option<&StorageTier> {
+        self.metadata.storage_tier.as_ref()
+    }
+
+    /// Sets the storage tier to store the blob with
+    pub fn with_storage_tier<T: Into<Option<StorageTier>>>(mut self, tier: T) -> Self {
+        self.metadata.storage_tier = tier.into();
+        self
+    }
+
     pub fn into_byte_stream(self) -> impl Stream<Item = Result<Bytes, io::Error>> {
         self.content_stream
     }
+
+    /// Drains the content into memory, failing with [`Error::TooLarge`] if it is larger
+    /// than `max_size`
+    pub async fn into_bytes(self, max_size: Option<usize>) -> crate::Result<Bytes> {
+        let limit = max_size.unwrap_or(usize::MAX);
+        if self.size > limit {
+            return Err(Error::too_large(self.key, limit));
+        }
+        let mut content = BytesMut::with_capacity(self.size);
+        let mut stream = self.content_stream;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(Error::body_error)?;
+            // the declared size may be wrong, so the limit is enforced on the content too
+            if content.len() + chunk.len() > limit {
+                return Err(Error::too_large(self.key, limit));
+            }
+            content.extend_from_slice(&chunk);
+        }
+        Ok(content.freeze())
+    }
+
+    /// Drains the content into a UTF-8 string, see [`Blob::into_bytes`]
+    pub async fn into_string(self, max_size: Option<usize>) -> crate::Result<String> {
+        let content = self.into_bytes(max_size).await?;
+        String::from_utf8(content.to_vec()).map_err(Error::body_error)
+    }
+
+    /// Reads the content of the blob through [`AsyncRead`](futures::io::AsyncRead),
+    /// or tokio's `AsyncRead` with the `tokio` feature
+    pub fn into_async_read(self) -> BlobReader {
+        BlobReader::new(self.content_stream.into_async_read())
+    }
+
+    /// Restricts the blob to a byte range of its content, see [`satisfiable_range`]
+    pub(crate) fn into_range(self, range: Range<usize>) -> crate::Result<Blob> {
+        let range = satisfiable_range(&self.key, self.size, range)?;
+        let (start, end) = (range.start, range.end);
+        let content = stream::unfold(
+            (self.content_stream, 0),
+            move |(mut content, mut position)| async move {
+                while position < end {
+                    let chunk = match content.next().await? {
+                        Ok(chunk) => chunk,
+                        Err(err) => return Some((Err(err), (content, end))),
+                    };
+                    let chunk_start = position;
+                    position += chunk.len();
+                    let from = start.saturating_sub(chunk_start).min(chunk.len());
+                    let to = (end - chunk_start).min(chunk.len());
+                    if from < to {
+                        return Some((Ok(chunk.slice(from..to)), (content, position)));
+                    }
+                }
+                None
+            },
+        );
+        Ok(Blob::new(self.key, range.len(), content).with_metadata(self.metadata))
+    }
+
+    /// Drains the content stream into memory
+    pub(crate) async fn read_content(self) -> crate::Result<Vec<u8>> {
+        self.content_stream
+            .try_fold(Vec::new(), |mut content, chunk| async move {
+                content.extend_from_slice(&chunk);
+                Ok(content)
+            })
+            .await
+            .map_err(Error::body_error)
+    }
+}
+
+/// Outcome of a conditional ranged read, see [`Provider::get_blob_if_range`]
+///
+/// [`Provider::get_blob_if_range`]: crate::provider::Provider::get_blob_if_range
+#[derive(Debug)]
+pub enum RangeRead {
+    /// The blob still matched the validator, so only the range was read
+    Range(Blob),
+
+    /// The blob changed since the validator was taken, so it was read whole
+    Full(Blob),
+}
+
+impl RangeRead {
+    pub fn is_range(&self) -> bool {
+        matches!(self, RangeRead::Range(_))
+    }
+
+    pub fn into_blob(self) -> Blob {
+        match self {
+            RangeRead::Range(blob) | RangeRead::Full(blob) => blob,
+        }
+    }
+
+    /// Transforms the blob that was read, keeping whether it is a range
+    pub fn map<F: FnOnce(Blob) -> Blob>(self, f: F) -> Self {
+        match self {
+            RangeRead::Range(blob) => RangeRead::Range(f(blob)),
+            RangeRead::Full(blob) => RangeRead::Full(f(blob)),
+        }
+    }
+}
+
+/// Whether the entity tag of a blob matches an `If-Range` validator.
+/// Ranges are only combined with strong validators, so weak tags never match.
+pub fn etag_matches(etag: Option<&str>, validator: &str) -> bool {
+    let strong = |tag: &str| !tag.starts_with("W/");
+    match etag {
+        Some(etag) if strong(etag) && strong(validator) => {
+            etag.trim_matches('"') == validator.trim_matches('"')
+        }
+        _ => false,
+    }
+}
+
+/// Clamps a byte range to the size of a blob, so that ranges can be open-ended
+/// (e.g. `start..usize::MAX`), failing with [`Error::RangeNotSatisfiable`]
+/// if the range is empty or starts past the end of the content
+pub fn satisfiable_range(
+    key: &str,
+    size: usize,
+    range: Range<usize>,
+) -> crate::Result<Range<usize>> {
+    let end = range.end.min(size);
+    if range.start >= end {
+        return Err(Error::range_not_satisfiable(key, size));
+    }
+    Ok(range.start..end)
+}
+
+/// Size and metadata of a blob, as fetched without its content
+/// by [`Provider::head_blob`]
+///
+/// [`Provider::head_blob`]: crate::provider::Provider::head_blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub size: usize,
+    pub metadata: BlobMetadata,
+}
+
+impl Head {
+    pub(crate) fn of(blob: &Blob) -> Self {
+        Self {
+            size: blob.size,
+            metadata: blob.metadata.clone(),
+        }
+    }
+}
+
+/// A blob as listed by a provider, without its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobEntry {
+    pub key: String,
+
+    /// Total binary size in bytes of the blob
+    pub size: usize,
+
+    /// When the blob was last stored, if known
+    pub last_modified: Option<SystemTime>,
+}
+
+impl BlobEntry {
+    pub fn new<K: ToString>(key: K, size: usize) -> Self {
+        Self {
+            key: key.to_string(),
+            size,
+            last_modified: None,
+        }
+    }
 }
 
 impl Debug for Blob {
@@ -63,12 +328,16 @@ impl Debug for Blob {
         f.debug_struct("Blob")
             .field("key", &self.key)
             .field("size", &self.size)
+            .field("metadata", &self.metadata)
+            .field("checksums", &self.checksums)
             .finish()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use futures::executor::block_on;
+    use futures::stream;
     use rand::Rng;
 
     use crate::blob::Blob;
@@ -81,4 +350,51 @@ mod test {
         assert_eq!(blob.key(), "key");
         assert_eq!(blob.size(), bytes.len());
     }
+
+    #[test]
+    fn it_restricts_blobs_to_a_range() {
+        let chunks = vec![
+            Ok("hello ".into()),
+            Ok("range".into()),
+            Ok("d world".into()),
+        ];
+        let blob = Blob::new("key", 18, stream::iter(chunks));
+
+        let range = blob.into_range(4..13).unwrap();
+        assert_eq!(range.size(), 9);
+        assert_eq!(block_on(range.read_content()).unwrap(), b"o ranged ");
+        assert!(Blob::from_bytes("key", vec![0; 4])
+            .into_range(4..8)
+            .is_err());
+    }
+
+    #[test]
+    fn it_collects_blob_content() {
+        let blob = Blob::from_bytes("key", b"content".to_vec());
+        assert_eq!(block_on(blob.into_string(Some(7))).unwrap(), "content");
+
+        let blob = Blob::from_bytes("key", b"content".to_vec());
+        assert!(block_on(blob.into_bytes(Some(6))).is_err());
+
+        // the limit holds even when the declared size is wrong
+        let blob = Blob::new("key", 2, stream::iter(vec![Ok("content".into())]));
+        assert!(block_on(blob.into_bytes(Some(6))).is_err());
+        let blob = Blob::from_bytes("key", vec![0xff]);
+        assert!(block_on(blob.into_string(None)).is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn it_streams_blobs_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, b"content").unwrap();
+
+        let blob = Blob::from_path("key", &path).await.unwrap();
+        assert_eq!(blob.size(), 7);
+        assert_eq!(blob.read_content().await.unwrap(), b"content".to_vec());
+        assert!(Blob::from_path("key", dir.path().join("missing"))
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,360 @@
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use tempfile::NamedTempFile;
+
+use crate::blob::Blob;
+use crate::error::Error;
+use crate::Result;
+
+const SPILL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What to do when buffering a blob would exceed a [`MemoryBudget`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Fail with [`Error::MemoryBudgetExceeded`] when the blob alone is over the limit,
+    /// or with [`Error::Overloaded`] when it is not, but the budget is in use by others
+    Reject,
+
+    /// Spill the buffered content to a temporary file in the given directory
+    Spill(PathBuf),
+}
+
+struct BudgetState {
+    limit: usize,
+    used: AtomicUsize,
+    policy: OverflowPolicy,
+}
+
+/// A cap on the total bytes buffered in memory, shared by all the components it is
+/// handed to, so that composing several buffering wrappers cannot exceed it.
+///
+/// Cloning a budget shares it rather than creating a new one.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    state: Arc<BudgetState>,
+}
+
+impl MemoryBudget {
+    /// A budget rejecting anything over the given limit
+    pub fn new(limit: usize) -> Self {
+        Self::with_policy(limit, OverflowPolicy::Reject)
+    }
+
+    /// A budget spilling to temporary files in `dir` anything over the given limit
+    pub fn with_spill<D: Into<PathBuf>>(limit: usize, dir: D) -> Self {
+        Self::with_policy(limit, OverflowPolicy::Spill(dir.into()))
+    }
+
+    pub fn with_policy(limit: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Arc::new(BudgetState {
+                limit,
+                used: AtomicUsize::new(0),
+                policy,
+            }),
+        }
+    }
+
+    /// A budget that never runs out
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.limit
+    }
+
+    pub fn used(&self) -> usize {
+        self.state.used.load(Ordering::Acquire)
+    }
+
+    pub fn available(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    pub fn policy(&self) -> &OverflowPolicy {
+        &self.state.policy
+    }
+
+    /// Reserves bytes from the budget, if available.
+    /// They are given back when the reservation is dropped.
+    pub fn try_reserve(&self, bytes: usize) -> Option<Reservation> {
+        let mut reservation = Reservation {
+            budget: self.clone(),
+            bytes: 0,
+        };
+        if reservation.try_grow(bytes) {
+            Some(reservation)
+        } else {
+            None
+        }
+    }
+
+    /// Buffers the content of a blob within the budget, applying the overflow policy
+    /// once the budget runs out
+    pub async fn buffer(&self, blob: Blob) -> Result<Buffered> {
+        self.buffer_stream(blob.into_byte_stream()).await
+    }
+
+    /// Buffers a content stream within the budget, like [`MemoryBudget::buffer`]
+    pub async fn buffer_stream<S>(&self, stream: S) -> Result<Buffered>
+    where
+        S: Stream<Item = io::Result<Bytes>>,
+    {
+        futures::pin_mut!(stream);
+        let mut reservation = Reservation {
+            budget: self.clone(),
+            bytes: 0,
+        };
+        let mut content = Vec::new();
+        let mut spill: Option<NamedTempFile> = None;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(Error::body_error)?;
+            if let Some(file) = spill.as_mut() {
+                file.write_all(&chunk).map_err(Error::body_error)?;
+                continue;
+            }
+            if reservation.try_grow(chunk.len()) {
+                content.extend_from_slice(&chunk);
+                continue;
+            }
+            match self.policy() {
+                OverflowPolicy::Reject if reservation.bytes() + chunk.len() > self.limit() => {
+                    return Err(Error::memory_budget_exceeded(self.limit()))
+                }
+                OverflowPolicy::Reject => {
+                    return Err(Error::overloaded(
+                        format!(
+                            "{} of the {} bytes of the memory budget are in use",
+                            self.used(),
+                            self.limit()
+                        ),
+                        None,
+                    ))
+                }
+                OverflowPolicy::Spill(dir) => {
+                    let mut file = NamedTempFile::new_in(dir).map_err(Error::body_error)?;
+                    file.write_all(&content).map_err(Error::body_error)?;
+                    file.write_all(&chunk).map_err(Error::body_error)?;
+                    content = Vec::new();
+                    reservation.release();
+                    spill = Some(file);
+                }
+            }
+        }
+
+        let content = match spill {
+            Some(mut file) => {
+                let size = file.as_file().metadata().map_err(Error::body_error)?.len() as usize;
+                file.seek(SeekFrom::Start(0)).map_err(Error::body_error)?;
+                Content::Disk(file, size)
+            }
+            None => Content::Memory(content, reservation),
+        };
+        Ok(Buffered { content })
+    }
+}
+
+impl Debug for MemoryBudget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .field("policy", self.policy())
+            .finish()
+    }
+}
+
+/// Bytes reserved from a [`MemoryBudget`]
+#[derive(Debug)]
+pub struct Reservation {
+    budget: MemoryBudget,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Reserves additional bytes, if available
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        let state = &self.budget.state;
+        let reserved = state
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes)
+                    .filter(|total| *total <= state.limit)
+            })
+            .is_ok();
+        if reserved {
+            self.bytes += bytes;
+        }
+        reserved
+    }
+
+    fn release(&mut self) {
+        self.budget
+            .state
+            .used
+            .fetch_sub(self.bytes, Ordering::AcqRel);
+        self.bytes = 0;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+enum Content {
+    Memory(Vec<u8>, Reservation),
+    Disk(NamedTempFile, usize),
+}
+
+/// Blob content buffered by a [`MemoryBudget`], either in memory or spilled to disk
+pub struct Buffered {
+    content: Content,
+}
+
+impl Buffered {
+    pub fn len(&self) -> usize {
+        match &self.content {
+            Content::Memory(content, _) => content.len(),
+            Content::Disk(_, size) => *size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.content, Content::Disk(..))
+    }
+
+    /// Reads the buffered content from the start
+    pub fn reader(&self) -> Result<Box<dyn Read + Send + '_>> {
+        match &self.content {
+            Content::Memory(content, _) => Ok(Box::new(Cursor::new(content.as_slice()))),
+            Content::Disk(file, _) => {
+                let file = file.reopen().map_err(Error::body_error)?;
+                Ok(Box::new(file))
+            }
+        }
+    }
+
+    /// Turns the buffered content back into a blob.
+    /// The budget is given back, or the spill file removed, once the blob is consumed.
+    pub fn into_blob<K: ToString>(self, key: K) -> Blob {
+        let size = self.len();
+        match self.content {
+            Content::Memory(content, reservation) => {
+                let stream = stream::once(async move {
+                    let _reservation = reservation;
+                    Ok(Bytes::from(content))
+                });
+                Blob::new(key, size, stream)
+            }
+            Content::Disk(spill, _) => {
+                let stream = stream::unfold(Some(spill), |spill| async move {
+                    let mut spill = spill?;
+                    let mut chunk = vec![0; SPILL_CHUNK_SIZE];
+                    match read_chunk(spill.as_file_mut(), &mut chunk) {
+                        Ok(0) => None,
+                        Ok(read) => {
+                            chunk.truncate(read);
+                            Some((Ok(Bytes::from(chunk)), Some(spill)))
+                        }
+                        Err(err) => Some((Err(err), None)),
+                    }
+                });
+                Blob::new(key, size, stream)
+            }
+        }
+    }
+}
+
+fn read_chunk(file: &mut File, chunk: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match file.read(chunk) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+impl Debug for Buffered {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buffered")
+            .field("len", &self.len())
+            .field("spilled", &self.is_spilled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use std::io::Read;
+
+    use crate::blob::Blob;
+    use crate::budget::MemoryBudget;
+
+    #[test]
+    fn it_shares_the_budget_between_reservations() {
+        let budget = MemoryBudget::new(100);
+        let first = budget.try_reserve(60).unwrap();
+
+        assert!(budget.clone().try_reserve(50).is_none());
+        assert_eq!(budget.available(), 40);
+        drop(first);
+        assert_eq!(budget.available(), 100);
+    }
+
+    #[test]
+    fn it_rejects_blobs_over_the_budget() {
+        let budget = MemoryBudget::new(8);
+        let blob = Blob::from_bytes("key", vec![0; 16]);
+
+        let err = block_on(budget.buffer(blob)).unwrap_err();
+        assert_eq!(err.code(), "memory_budget_exceeded");
+        assert_eq!(budget.used(), 0);
+
+        // blobs within the limit are shed while the budget is in use
+        let reservation = budget.try_reserve(4).unwrap();
+        let blob = Blob::from_bytes("key", vec![0; 8]);
+        let err = block_on(budget.buffer(blob)).unwrap_err();
+        assert_eq!(err.code(), "overloaded");
+        drop(reservation);
+    }
+
+    #[test]
+    fn it_spills_blobs_over_the_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let budget = MemoryBudget::with_spill(8, dir.path());
+        let buffered = block_on(budget.buffer(Blob::from_bytes("key", vec![7; 16]))).unwrap();
+
+        assert!(buffered.is_spilled());
+        assert_eq!(budget.used(), 0);
+        let mut content = Vec::new();
+        buffered
+            .reader()
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, vec![7; 16]);
+
+        let blob = buffered.into_blob("key");
+        assert_eq!(block_on(blob.read_content()).unwrap(), vec![7; 16]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::Result;
+
+pub use crate::registry::{BoxedProvider, Registry};
+
+/// The environment variables under a prefix, looked up by their name without it:
+/// with the `HOLD` prefix, `S3_BUCKET` is read from `HOLD_S3_BUCKET`.
+#[derive(Clone, Default)]
+pub struct EnvConfig {
+    prefix: String,
+    vars: HashMap<String, String>,
+}
+
+impl EnvConfig {
+    /// Reads the variables under the prefix from the environment of the process
+    pub fn from_env(prefix: &str) -> Self {
+        Self::from_vars(prefix, std::env::vars())
+    }
+
+    /// Keeps the given variables that are under the prefix
+    pub fn from_vars<I, K, V>(prefix: &str, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let full_prefix = format!("{}_", prefix);
+        let vars = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let name = name.into();
+                let name = name.strip_prefix(&full_prefix)?.to_string();
+                Some((name, value.into()))
+            })
+            .collect();
+        Self {
+            prefix: prefix.to_string(),
+            vars,
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The value of a variable, empty values counting as unset
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars
+            .get(name)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The value of a variable, failing with [`Error::ConfigError`] if it is unset
+    pub fn require(&self, name: &str) -> Result<&str> {
+        self.get(name)
+            .ok_or_else(|| Error::config(format!("{} is not set", self.var_name(name))))
+    }
+
+    /// Parses the value of a variable, if set
+    pub fn parse<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get(name)
+            .map(|value| {
+                value.parse().map_err(|err| {
+                    Error::config(format!("invalid {}: {}", self.var_name(name), err))
+                })
+            })
+            .transpose()
+    }
+
+    /// Parses the value of a variable, failing with [`Error::ConfigError`] if it is unset
+    pub fn parse_required<T>(&self, name: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.parse(name)?
+            .ok_or_else(|| Error::config(format!("{} is not set", self.var_name(name))))
+    }
+
+    /// Parses a boolean flag, unset flags being false
+    pub fn flag(&self, name: &str) -> Result<bool> {
+        match self.get(name) {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("true") | Some("1") => Ok(true),
+            Some(value) => Err(Error::config(format!(
+                "invalid {}: {} is not a boolean",
+                self.var_name(name),
+                value
+            ))),
+        }
+    }
+
+    /// Parses a number of milliseconds, if set
+    pub fn millis(&self, name: &str) -> Result<Option<Duration>> {
+        Ok(self.parse(name)?.map(Duration::from_millis))
+    }
+
+    /// Parses a comma separated list, unset lists being empty
+    pub fn list(&self, name: &str) -> Vec<&str> {
+        self.get(name)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn var_name(&self, name: &str) -> String {
+        format!("{}_{}", self.prefix, name)
+    }
+}
+
+impl Debug for EnvConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // values may be secrets
+        let mut names: Vec<_> = self.vars.keys().collect();
+        names.sort();
+        f.debug_struct("EnvConfig")
+            .field("prefix", &self.prefix)
+            .field("vars", &names)
+            .finish()
+    }
+}
+
+/// Builds the provider stack described by the environment variables under the prefix,
+/// from the providers and middleware of the default [`Registry`].
+/// Use [`Registry::build`] to build providers registered by backend crates.
+pub fn from_env(prefix: &str) -> Result<BoxedProvider> {
+    Registry::default().build(&EnvConfig::from_env(prefix))
+}
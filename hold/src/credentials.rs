@@ -0,0 +1,334 @@
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use futures::lock::Mutex;
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::Result;
+
+/// Credentials used by providers to authenticate against their backend.
+/// What the key and secret are depends on the backend (e.g. an AWS access key pair).
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+
+    /// Session token of temporary credentials
+    pub session_token: Option<String>,
+
+    /// When temporary credentials stop being valid
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Credentials {
+    pub fn new<K: ToString, S: ToString>(access_key_id: K, secret_access_key: S) -> Self {
+        Self {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// A source of credentials, consumed by providers each time they need to sign requests
+#[async_trait]
+pub trait CredentialsProvider: Debug + Send + Sync {
+    async fn credentials(&self) -> Result<Credentials>;
+}
+
+#[async_trait]
+impl<P: CredentialsProvider + ?Sized> CredentialsProvider for Arc<P> {
+    async fn credentials(&self) -> Result<Credentials> {
+        (**self).credentials().await
+    }
+}
+
+/// Credentials that never change
+#[derive(Debug, Clone)]
+pub struct StaticCredentials {
+    credentials: Credentials,
+}
+
+impl StaticCredentials {
+    pub fn new(credentials: Credentials) -> Self {
+        Self { credentials }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for StaticCredentials {
+    async fn credentials(&self) -> Result<Credentials> {
+        Ok(self.credentials.clone())
+    }
+}
+
+/// Credentials read from `<PREFIX>_ACCESS_KEY_ID`, `<PREFIX>_SECRET_ACCESS_KEY`
+/// and, optionally, `<PREFIX>_SESSION_TOKEN` environment variables
+#[derive(Debug, Clone)]
+pub struct EnvCredentials {
+    prefix: String,
+}
+
+impl EnvCredentials {
+    pub fn new<P: ToString>(prefix: P) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(format!("{}_{}", self.prefix, name))
+            .ok()
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvCredentials {
+    async fn credentials(&self) -> Result<Credentials> {
+        let access_key_id = self.var("ACCESS_KEY_ID").ok_or_else(|| {
+            Error::credentials(format!("{}_ACCESS_KEY_ID is not set", self.prefix))
+        })?;
+        let secret_access_key = self.var("SECRET_ACCESS_KEY").ok_or_else(|| {
+            Error::credentials(format!("{}_SECRET_ACCESS_KEY is not set", self.prefix))
+        })?;
+        Ok(Credentials {
+            session_token: self.var("SESSION_TOKEN"),
+            ..Credentials::new(access_key_id, secret_access_key)
+        })
+    }
+}
+
+/// Credentials read from a file of `key = value` lines, with `access_key_id`,
+/// `secret_access_key` and optionally `session_token` keys.
+/// The file is read again every time credentials are requested, so it can be rotated.
+#[derive(Debug, Clone)]
+pub struct FileCredentials {
+    path: PathBuf,
+}
+
+impl FileCredentials {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for FileCredentials {
+    async fn credentials(&self) -> Result<Credentials> {
+        let content = std::fs::read_to_string(&self.path).map_err(|err| {
+            Error::credentials(format!("cannot read {}: {}", self.path.display(), err))
+        })?;
+        parse_credentials_file(&content)
+    }
+}
+
+fn parse_credentials_file(content: &str) -> Result<Credentials> {
+    let mut access_key_id = None;
+    let mut secret_access_key = None;
+    let mut session_token = None;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("access_key_id", value)) => access_key_id = Some(value),
+            Some(("secret_access_key", value)) => secret_access_key = Some(value),
+            Some(("session_token", value)) => session_token = Some(value.to_string()),
+            Some(_) => {}
+            None => return Err(Error::credentials("invalid credentials file")),
+        }
+    }
+    match (access_key_id, secret_access_key) {
+        (Some(access_key_id), Some(secret_access_key)) => Ok(Credentials {
+            session_token,
+            ..Credentials::new(access_key_id, secret_access_key)
+        }),
+        _ => Err(Error::credentials(
+            "credentials file is missing access_key_id or secret_access_key",
+        )),
+    }
+}
+
+type CredentialsFuture = Pin<Box<dyn Future<Output = Result<Credentials>> + Send>>;
+
+/// Credentials produced by an async callback, called every time credentials are requested
+pub struct CallbackCredentials {
+    callback: Box<dyn Fn() -> CredentialsFuture + Send + Sync>,
+}
+
+impl CallbackCredentials {
+    pub fn new<F, Fut>(callback: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Credentials>> + Send + 'static,
+    {
+        Self {
+            callback: Box::new(move || Box::pin(callback())),
+        }
+    }
+}
+
+impl Debug for CallbackCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackCredentials").finish()
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for CallbackCredentials {
+    async fn credentials(&self) -> Result<Credentials> {
+        (self.callback)().await
+    }
+}
+
+/// Caches expiring credentials from another source, only asking it for new ones
+/// when the cached ones are about to expire.
+/// Credentials without an expiration are never cached.
+#[derive(Debug)]
+pub struct RefreshingCredentials<P> {
+    inner: P,
+    margin: Duration,
+    clock: Arc<dyn Clock>,
+    cached: Mutex<Option<Credentials>>,
+}
+
+impl<P: CredentialsProvider> RefreshingCredentials<P> {
+    /// Refreshes credentials five minutes before they expire
+    pub fn new(inner: P) -> Self {
+        Self::with_margin(inner, Duration::from_secs(5 * 60))
+    }
+
+    /// Refreshes credentials the given time before they expire
+    pub fn with_margin(inner: P, margin: Duration) -> Self {
+        Self {
+            inner,
+            margin,
+            clock: Arc::new(SystemClock),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Uses the given clock to decide when credentials expire
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+#[async_trait]
+impl<P: CredentialsProvider> CredentialsProvider for RefreshingCredentials<P> {
+    async fn credentials(&self) -> Result<Credentials> {
+        // holding the lock while refreshing makes concurrent callers wait for a single refresh
+        let mut cached = self.cached.lock().await;
+        let now = self.clock.now();
+        let refresh_at = now + self.margin;
+        if let Some(credentials) = cached.as_ref() {
+            if credentials
+                .expires_at
+                .is_some_and(|expires_at| expires_at > refresh_at)
+            {
+                return Ok(credentials.clone());
+            }
+        }
+
+        match self.inner.credentials().await {
+            Ok(credentials) => {
+                *cached = credentials.expires_at.map(|_| credentials.clone());
+                Ok(credentials)
+            }
+            // keep using the cached credentials while they are still valid
+            Err(_) if cached.as_ref().is_some_and(|c| !c.is_expired_at(now)) => {
+                Ok(cached.clone().unwrap())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use futures::executor::block_on;
+
+    use crate::credentials::{
+        parse_credentials_file, CallbackCredentials, Credentials, CredentialsProvider,
+        RefreshingCredentials,
+    };
+
+    #[test]
+    fn it_parses_credentials_files() {
+        let credentials = parse_credentials_file(
+            "# rotated hourly\naccess_key_id = AKIA\nsecret_access_key=secret\n",
+        )
+        .unwrap();
+
+        assert_eq!(credentials.access_key_id, "AKIA");
+        assert_eq!(credentials.secret_access_key, "secret");
+        assert_eq!(credentials.session_token, None);
+        assert!(parse_credentials_file("access_key_id = AKIA").is_err());
+    }
+
+    #[test]
+    fn it_refreshes_credentials_before_they_expire() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lifetime = Arc::new(AtomicUsize::new(60));
+        let callback = {
+            let calls = calls.clone();
+            let lifetime = lifetime.clone();
+            CallbackCredentials::new(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let lifetime = Duration::from_secs(lifetime.load(Ordering::SeqCst) as u64);
+                async move {
+                    Ok(Credentials {
+                        expires_at: Some(SystemTime::now() + lifetime),
+                        ..Credentials::new("AKIA", "secret")
+                    })
+                }
+            })
+        };
+        let credentials = RefreshingCredentials::new(callback);
+
+        // credentials expiring within the refresh margin are not reused
+        block_on(credentials.credentials()).unwrap();
+        block_on(credentials.credentials()).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        lifetime.store(3600, Ordering::SeqCst);
+        block_on(credentials.credentials()).unwrap();
+        block_on(credentials.credentials()).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,73 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+use futures_timer::Delay;
+
+use crate::error::Error;
+use crate::Result;
+
+/// The moment by which a caller needs an operation to complete, so that storage calls
+/// respect the end-to-end budget of the request they serve.
+/// See [`DeadlineProvider`](crate::middleware::deadline::DeadlineProvider).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    pub fn at(at: Instant) -> Self {
+        Self { at }
+    }
+
+    pub fn after(timeout: Duration) -> Self {
+        Self::at(Instant::now() + timeout)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.at
+    }
+
+    /// Time left before the deadline, zero once exceeded
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// Runs an operation, failing with [`Error::DeadlineExceeded`] if it does not
+    /// complete in time. Operations are not started at all once the deadline is exceeded.
+    pub async fn run<T, F: Future<Output = Result<T>>>(&self, operation: F) -> Result<T> {
+        if self.is_exceeded() {
+            return Err(Error::deadline_exceeded());
+        }
+        match future::select(Box::pin(operation), Delay::new(self.remaining())).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::deadline_exceeded()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures::future;
+
+    use crate::deadline::Deadline;
+    use crate::error::Error;
+
+    #[test]
+    fn it_fails_operations_past_the_deadline() {
+        let deadline = Deadline::after(Duration::from_millis(10));
+        let pending = block_on(deadline.run(future::pending::<crate::Result<()>>()));
+        assert!(matches!(pending, Err(Error::DeadlineExceeded)));
+
+        assert!(deadline.is_exceeded());
+        let ready = block_on(deadline.run(future::ok(())));
+        assert!(matches!(ready, Err(Error::DeadlineExceeded)));
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read};
+use std::iter::FromIterator;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::blob::Blob;
+use crate::provider::Provider;
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// A hash algorithm used to compute blob digests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+    Blake3,
+
+    /// The Castagnoli CRC-32, a cheap checksum rather than a hash, as a big-endian integer
+    Crc32c,
+}
+
+impl DigestAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "md5",
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Blake3 => "blake3",
+            DigestAlgorithm::Crc32c => "crc32c",
+        }
+    }
+
+    /// The code identifying the algorithm in the multicodec table, if it has one
+    pub fn multihash_code(&self) -> Option<u64> {
+        match self {
+            DigestAlgorithm::Md5 => Some(0xd5),
+            DigestAlgorithm::Sha256 => Some(0x12),
+            DigestAlgorithm::Blake3 => Some(0x1e),
+            DigestAlgorithm::Crc32c => None,
+        }
+    }
+
+    /// Computes the digest of an in-memory content
+    pub fn digest(&self, content: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(*self);
+        hasher.update(content);
+        hasher.finish()
+    }
+
+    /// Computes the digest of a content read until its end
+    pub fn digest_reader<R: Read + ?Sized>(&self, content: &mut R) -> io::Result<Vec<u8>> {
+        let digests = digest_reader(&[*self], content)?;
+        Ok(digests.0.into_iter().next().expect("no digest").1)
+    }
+}
+
+/// Computes several digests of a content read until its end, in a single pass
+pub fn digest_reader<R: Read + ?Sized>(
+    algorithms: &[DigestAlgorithm],
+    content: &mut R,
+) -> io::Result<Digests> {
+    let mut hashers: Vec<_> = algorithms
+        .iter()
+        .map(|algorithm| (*algorithm, Hasher::new(*algorithm)))
+        .collect();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match content.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => {
+                for (_, hasher) in hashers.iter_mut() {
+                    hasher.update(&chunk[..read]);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Digests(
+        hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finish()))
+            .collect(),
+    ))
+}
+
+impl Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Crc32c(crc_fast::Digest),
+}
+
+impl Hasher {
+    fn new(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            DigestAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            DigestAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            DigestAlgorithm::Crc32c => {
+                Hasher::Crc32c(crc_fast::Digest::new(crc_fast::CrcAlgorithm::Crc32Iscsi))
+            }
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(chunk),
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Blake3(hasher) => {
+                hasher.update(chunk);
+            }
+            Hasher::Crc32c(hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Crc32c(hasher) => (hasher.finalize() as u32).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Digests computed over a blob content
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Digests(Vec<(DigestAlgorithm, Vec<u8>)>);
+
+impl Digests {
+    pub fn get(&self, algorithm: DigestAlgorithm) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|(a, _)| *a == algorithm)
+            .map(|(_, digest)| digest.as_slice())
+    }
+
+    /// Lowercase hex representation of a digest
+    pub fn hex(&self, algorithm: DigestAlgorithm) -> Option<String> {
+        self.get(algorithm).map(to_hex)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DigestAlgorithm, &[u8])> {
+        self.0
+            .iter()
+            .map(|(algorithm, digest)| (*algorithm, digest.as_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Only the digests of the given algorithms
+    pub fn only(&self, algorithms: &[DigestAlgorithm]) -> Digests {
+        Digests(
+            self.0
+                .iter()
+                .filter(|(algorithm, _)| algorithms.contains(algorithm))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+impl FromIterator<(DigestAlgorithm, Vec<u8>)> for Digests {
+    fn from_iter<I: IntoIterator<Item = (DigestAlgorithm, Vec<u8>)>>(digests: I) -> Self {
+        Digests(digests.into_iter().collect())
+    }
+}
+
+#[cfg(feature = "cid")]
+impl Digests {
+    /// Multihash of the content, as used by IPFS/IPLD tooling
+    pub fn multihash(&self, algorithm: DigestAlgorithm) -> Option<cid::multihash::Multihash<64>> {
+        let code = algorithm.multihash_code()?;
+        self.get(algorithm)
+            .and_then(|digest| cid::multihash::Multihash::wrap(code, digest).ok())
+    }
+
+    /// Content identifier (CIDv1, `raw` codec) of the content
+    pub fn cid(&self, algorithm: DigestAlgorithm) -> Option<cid::Cid> {
+        const RAW: u64 = 0x55;
+        self.multihash(algorithm)
+            .map(|multihash| cid::Cid::new_v1(RAW, multihash))
+    }
+}
+
+/// Handle to the digests of a blob wrapped by [`hashing`], available once the
+/// blob content has been fully consumed
+pub struct DigestHandle {
+    hashers: Arc<Mutex<Vec<(DigestAlgorithm, Hasher)>>>,
+}
+
+impl DigestHandle {
+    /// Finishes the computation over the content consumed so far
+    pub fn finish(self) -> Digests {
+        let hashers = match Arc::try_unwrap(self.hashers) {
+            Ok(hashers) => hashers.into_inner().unwrap_or_else(|err| err.into_inner()),
+            Err(shared) => {
+                // the stream is still alive somewhere, so finish over a snapshot
+                let mut hashers = shared.lock().unwrap_or_else(|err| err.into_inner());
+                std::mem::take(&mut *hashers)
+            }
+        };
+        Digests(
+            hashers
+                .into_iter()
+                .map(|(algorithm, hasher)| (algorithm, hasher.finish()))
+                .collect(),
+        )
+    }
+}
+
+/// Wraps a blob so that the given digests are computed while its content is streamed,
+/// without a separate pass over it
+pub fn hashing(blob: Blob, algorithms: &[DigestAlgorithm]) -> (Blob, DigestHandle) {
+    let key = blob.key().to_string();
+    let size = blob.size();
+    let metadata = blob.metadata().clone();
+    let (stream, handle) = hashing_stream(blob.into_byte_stream(), algorithms);
+    (Blob::new(key, size, stream).with_metadata(metadata), handle)
+}
+
+/// Wraps a content stream so that the given digests are computed while it is consumed,
+/// like [`hashing`]
+pub fn hashing_stream<S>(
+    content: S,
+    algorithms: &[DigestAlgorithm],
+) -> (impl Stream<Item = io::Result<Bytes>>, DigestHandle)
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let hashers: Vec<_> = algorithms
+        .iter()
+        .map(|algorithm| (*algorithm, Hasher::new(*algorithm)))
+        .collect();
+    let hashers = Arc::new(Mutex::new(hashers));
+
+    let stream_hashers = hashers.clone();
+    let stream = content.inspect_ok(move |chunk| {
+        let mut hashers = stream_hashers.lock().unwrap_or_else(|err| err.into_inner());
+        for (_, hasher) in hashers.iter_mut() {
+            hasher.update(chunk);
+        }
+    });
+    (stream, DigestHandle { hashers })
+}
+
+/// Wraps a blob so that its digest is checked against the expected one once its content
+/// has been streamed, the content failing at its end with an
+/// [`io::ErrorKind::InvalidData`] error if they differ
+pub fn verifying(blob: Blob, algorithm: DigestAlgorithm, expected: Vec<u8>) -> Blob {
+    let key = blob.key().to_string();
+    let size = blob.size();
+    let metadata = blob.metadata().clone();
+    let message = format!("{} digest mismatch for blob {}", algorithm, key);
+    let state = (blob.into_byte_stream(), Some(Hasher::new(algorithm)));
+    let stream = stream::unfold(state, move |(mut content, hasher)| {
+        let (expected, message) = (expected.clone(), message.clone());
+        async move {
+            let mut hasher = hasher?;
+            match content.next().await {
+                Some(Ok(chunk)) => {
+                    hasher.update(&chunk);
+                    Some((Ok(chunk), (content, Some(hasher))))
+                }
+                Some(Err(err)) => Some((Err(err), (content, None))),
+                None if hasher.finish() == expected => None,
+                None => {
+                    let err = io::Error::new(io::ErrorKind::InvalidData, message);
+                    Some((Err(err), (content, None)))
+                }
+            }
+        }
+    });
+    Blob::new(key, size, stream).with_metadata(metadata)
+}
+
+/// Stores a blob computing the given digests while it is being uploaded.
+/// The digests are returned as the receipt checksums.
+pub async fn store_hashed<P: Provider + ?Sized>(
+    provider: &P,
+    blob: Blob,
+    algorithms: &[DigestAlgorithm],
+) -> Result<StoreReceipt> {
+    let (blob, handle) = hashing(blob, algorithms);
+    let receipt = provider.store_blob(blob).await?;
+    Ok(StoreReceipt {
+        checksums: handle.finish(),
+        ..receipt
+    })
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use crate::blob::Blob;
+    use crate::digest::{hashing, DigestAlgorithm};
+
+    #[test]
+    fn it_computes_digests_while_streaming() {
+        let blob = Blob::from_bytes("key", b"hello world".to_vec());
+        let (blob, handle) = hashing(blob, &[DigestAlgorithm::Md5, DigestAlgorithm::Sha256]);
+        block_on(blob.into_byte_stream().try_collect::<Vec<_>>()).unwrap();
+        let digests = handle.finish();
+
+        assert_eq!(
+            digests.hex(DigestAlgorithm::Md5).unwrap(),
+            "5eb63bbbe01eeed093cb22bb8f5acdc3"
+        );
+        assert_eq!(
+            digests.hex(DigestAlgorithm::Sha256).unwrap(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(digests.get(DigestAlgorithm::Blake3), None);
+
+        let crc = DigestAlgorithm::Crc32c.digest(b"123456789");
+        assert_eq!(crc, vec![0xe3, 0x06, 0x92, 0x83]);
+    }
+
+    #[cfg(feature = "cid")]
+    #[test]
+    fn it_computes_content_identifiers() {
+        let blob = Blob::from_bytes("key", b"hello world".to_vec());
+        let (blob, handle) = hashing(blob, &[DigestAlgorithm::Sha256]);
+        block_on(blob.into_byte_stream().try_collect::<Vec<_>>()).unwrap();
+        let cid = handle.finish().cid(DigestAlgorithm::Sha256).unwrap();
+
+        assert_eq!(
+            cid.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+
+use futures::{stream, StreamExt, TryStreamExt};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::error::Error;
+use crate::provider::Provider;
+use crate::Result;
+
+/// How [`download_to_path_parallel`] splits blobs into ranged reads
+#[derive(Debug, Clone)]
+pub struct ParallelDownload {
+    /// Size of each ranged read, except the last one
+    pub part_size: usize,
+
+    /// Ranged reads in flight at once
+    pub concurrency: usize,
+}
+
+impl Default for ParallelDownload {
+    fn default() -> Self {
+        Self {
+            part_size: 8 * 1024 * 1024,
+            concurrency: 8,
+        }
+    }
+}
+
+/// Downloads a blob to a file with concurrent ranged reads, writing each part directly
+/// at its offset in the file, pre-allocated to the size of the blob, so that very large
+/// blobs are never reassembled in memory. Returns the size of the blob, or `None` if
+/// it is missing, in which case the file is not created.
+///
+/// Parts must all have the entity tag of the first one read, so that a blob overwritten
+/// during the download fails it rather than mixing versions. The file is left partially
+/// written when the download fails. It is written through `tokio::fs`, so this requires
+/// a Tokio runtime.
+pub async fn download_to_path_parallel<P: Provider + Sync + ?Sized, T: AsRef<Path>>(
+    provider: &P,
+    key: &str,
+    path: T,
+    options: &ParallelDownload,
+) -> Result<Option<usize>> {
+    let path = path.as_ref();
+    let page = provider.list_page(key, None, 1).await?;
+    let size = match page.entries.into_iter().find(|entry| entry.key == key) {
+        Some(entry) => entry.size,
+        None => return Ok(None),
+    };
+
+    let file = File::create(path).await.map_err(Error::io)?;
+    file.set_len(size as u64).await.map_err(Error::io)?;
+
+    let part_size = options.part_size.max(1);
+    let parts = (0..size.div_ceil(part_size))
+        .map(|part| part * part_size..((part + 1) * part_size).min(size));
+    let etag = Mutex::new(None);
+    stream::iter(parts.map(Ok))
+        .try_for_each_concurrent(options.concurrency.max(1), |range| {
+            download_part(provider, key, path, range, &etag)
+        })
+        .await?;
+    Ok(Some(size))
+}
+
+async fn download_part<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    key: &str,
+    path: &Path,
+    range: Range<usize>,
+    etag: &Mutex<Option<Option<String>>>,
+) -> Result<()> {
+    let changed = || Error::body_error(format!("Blob {} changed during the download", key));
+    let blob = provider
+        .get_blob_range(key, range.clone())
+        .await?
+        .ok_or_else(changed)?;
+    {
+        let mut etag = etag.lock().unwrap_or_else(|err| err.into_inner());
+        let part_etag = &blob.metadata().etag;
+        if etag.get_or_insert_with(|| part_etag.clone()) != part_etag {
+            return Err(changed());
+        }
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(Error::io)?;
+    file.seek(SeekFrom::Start(range.start as u64))
+        .await
+        .map_err(Error::io)?;
+    let mut content = blob.into_byte_stream();
+    let mut written = 0;
+    while let Some(chunk) = content.next().await {
+        let chunk = chunk.map_err(Error::body_error)?;
+        written += chunk.len();
+        if written > range.len() {
+            return Err(changed());
+        }
+        file.write_all(&chunk).await.map_err(Error::io)?;
+    }
+    if written != range.len() {
+        return Err(changed());
+    }
+    file.flush().await.map_err(Error::io)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use crate::blob::Blob;
+    use crate::download::{download_to_path_parallel, ParallelDownload};
+    use crate::memory::MemoryProvider;
+    use crate::provider::Provider;
+
+    #[tokio::test]
+    async fn it_downloads_parts_at_their_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob");
+        let provider = MemoryProvider::new();
+        let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        provider
+            .store_blob(Blob::from_bytes("key", content.clone()))
+            .await
+            .unwrap();
+
+        let options = ParallelDownload {
+            part_size: 64,
+            concurrency: 4,
+        };
+        let size = download_to_path_parallel(&provider, "key", &path, &options)
+            .await
+            .unwrap();
+        assert_eq!(size, Some(1000));
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+
+        let missing = dir.path().join("missing");
+        let size = download_to_path_parallel(&provider, "missing", &missing, &options)
+            .await
+            .unwrap();
+        assert_eq!(size, None);
+        assert!(!missing.exists());
+    }
+}
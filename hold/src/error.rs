@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use snafu::Snafu;
 
 #[derive(Debug, Snafu)]
@@ -13,6 +15,50 @@ pub enum Error {
     },
     #[snafu(display("Error while reading body: {}", message))]
     BodyError { message: String },
+    #[snafu(display("Blob {} is under retention", key))]
+    Retained { key: String },
+    #[snafu(display("Memory budget of {} bytes exceeded", limit))]
+    MemoryBudgetExceeded { limit: usize },
+    #[snafu(display("Credentials error: {}", message))]
+    CredentialsError { message: String },
+    #[snafu(display("Blob {} exceeds the maximum size of {} bytes", key, limit))]
+    TooLarge { key: String, limit: usize },
+    #[snafu(display("Blob {} has a rejected content type {}", key, content_type))]
+    ContentTypeRejected { key: String, content_type: String },
+    #[snafu(display("Blob {} already exists", key))]
+    AlreadyExists { key: String },
+    #[snafu(display("Invalid key {}: {}", key, message))]
+    InvalidKey { key: String, message: String },
+    #[snafu(display("Invalid access token: {}", message))]
+    InvalidToken { message: String },
+    #[snafu(display("Deadline exceeded"))]
+    DeadlineExceeded,
+    #[snafu(display("Range not satisfiable for blob {} of {} bytes", key, size))]
+    RangeNotSatisfiable { key: String, size: usize },
+    #[snafu(display("Configuration error: {}", message))]
+    ConfigError { message: String },
+    #[snafu(display("Blob {} exceeds the storage quota: {}", key, message))]
+    QuotaExceeded { key: String, message: String },
+    #[snafu(display("Overloaded: {}", message))]
+    Overloaded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[snafu(display("Permission denied: {}", message))]
+    PermissionDenied { message: String },
+    #[snafu(display("Precondition failed for blob {}", key))]
+    PreconditionFailed { key: String },
+    #[snafu(display("Rate limited: {}", message))]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[snafu(display("Timed out: {}", message))]
+    Timeout { message: String },
+    #[snafu(display("Invalid request: {}", message))]
+    InvalidRequest { message: String },
+    #[snafu(display("Checksum mismatch for blob {}", key))]
+    ChecksumMismatch { key: String },
 }
 
 impl Error {
@@ -34,6 +80,235 @@ impl Error {
             message: message.to_string(),
         }
     }
+
+    pub fn retained<K: ToString>(key: K) -> Self {
+        Error::Retained {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn memory_budget_exceeded(limit: usize) -> Self {
+        Error::MemoryBudgetExceeded { limit }
+    }
+
+    pub fn credentials<S: ToString>(message: S) -> Self {
+        Error::CredentialsError {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn too_large<K: ToString>(key: K, limit: usize) -> Self {
+        Error::TooLarge {
+            key: key.to_string(),
+            limit,
+        }
+    }
+
+    pub fn content_type_rejected<K: ToString, T: ToString>(key: K, content_type: T) -> Self {
+        Error::ContentTypeRejected {
+            key: key.to_string(),
+            content_type: content_type.to_string(),
+        }
+    }
+
+    pub fn already_exists<K: ToString>(key: K) -> Self {
+        Error::AlreadyExists {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn invalid_key<K: ToString, S: ToString>(key: K, message: S) -> Self {
+        Error::InvalidKey {
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_token<S: ToString>(message: S) -> Self {
+        Error::InvalidToken {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn deadline_exceeded() -> Self {
+        Error::DeadlineExceeded
+    }
+
+    pub fn range_not_satisfiable<K: ToString>(key: K, size: usize) -> Self {
+        Error::RangeNotSatisfiable {
+            key: key.to_string(),
+            size,
+        }
+    }
+
+    pub fn config<S: ToString>(message: S) -> Self {
+        Error::ConfigError {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn quota_exceeded<K: ToString, S: ToString>(key: K, message: S) -> Self {
+        Error::QuotaExceeded {
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// A request shed because of saturated resources, which may be attempted again
+    /// after the given delay, if known
+    pub fn overloaded<S: ToString>(message: S, retry_after: Option<Duration>) -> Self {
+        Error::Overloaded {
+            message: message.to_string(),
+            retry_after,
+        }
+    }
+
+    /// The backend refused access to the blob or the storage, as opposed to the
+    /// credentials being invalid
+    pub fn permission_denied<S: ToString>(message: S) -> Self {
+        Error::PermissionDenied {
+            message: message.to_string(),
+        }
+    }
+
+    /// A conditional operation whose condition on the stored blob did not hold
+    pub fn precondition_failed<K: ToString>(key: K) -> Self {
+        Error::PreconditionFailed {
+            key: key.to_string(),
+        }
+    }
+
+    /// The backend throttled the request, hinting how long to wait if it did
+    pub fn rate_limited<S: ToString>(message: S, retry_after: Option<Duration>) -> Self {
+        Error::RateLimited {
+            message: message.to_string(),
+            retry_after,
+        }
+    }
+
+    /// The backend or the connection to it timed out, unlike [`Error::DeadlineExceeded`]
+    /// which reports the deadline of the caller running out
+    pub fn timeout<S: ToString>(message: S) -> Self {
+        Error::Timeout {
+            message: message.to_string(),
+        }
+    }
+
+    /// A request the backend rejected as invalid, and would reject again
+    pub fn invalid_request<S: ToString>(message: S) -> Self {
+        Error::InvalidRequest {
+            message: message.to_string(),
+        }
+    }
+
+    /// The content received by the backend does not match the checksum it was sent with,
+    /// having been corrupted on the way
+    pub fn checksum_mismatch<K: ToString>(key: K) -> Self {
+        Error::ChecksumMismatch {
+            key: key.to_string(),
+        }
+    }
+
+    /// Maps an I/O error of a backend by its kind, e.g. for providers storing blobs
+    /// in files: denied access and timeouts have their own error, and other failures
+    /// are provider errors
+    pub fn io(err: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match err.kind() {
+            ErrorKind::PermissionDenied => Error::permission_denied(err),
+            ErrorKind::TimedOut => Error::timeout(err),
+            _ => Error::provider(err),
+        }
+    }
+
+    /// Stable machine-readable code of the error, for APIs exposing storage errors to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::IDNotFound { .. } => "not_found",
+            Error::ProviderError { .. } => "provider_error",
+            Error::BodyError { .. } => "body_error",
+            Error::Retained { .. } => "retained",
+            Error::MemoryBudgetExceeded { .. } => "memory_budget_exceeded",
+            Error::CredentialsError { .. } => "credentials_error",
+            Error::TooLarge { .. } => "too_large",
+            Error::ContentTypeRejected { .. } => "content_type_rejected",
+            Error::AlreadyExists { .. } => "already_exists",
+            Error::InvalidKey { .. } => "invalid_key",
+            Error::InvalidToken { .. } => "invalid_token",
+            Error::DeadlineExceeded => "deadline_exceeded",
+            Error::RangeNotSatisfiable { .. } => "range_not_satisfiable",
+            Error::ConfigError { .. } => "config_error",
+            Error::QuotaExceeded { .. } => "quota_exceeded",
+            Error::Overloaded { .. } => "overloaded",
+            Error::PermissionDenied { .. } => "permission_denied",
+            Error::PreconditionFailed { .. } => "precondition_failed",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::Timeout { .. } => "timeout",
+            Error::InvalidRequest { .. } => "invalid_request",
+            Error::ChecksumMismatch { .. } => "checksum_mismatch",
+        }
+    }
+
+    /// Whether the operation may succeed if attempted again, as with backend
+    /// and transport failures, corrupted uploads, throttling, timeouts and shed requests.
+    /// Rejections, denied access, invalid requests and exceeded deadlines are final,
+    /// so providers map the failures of their backend to the matching variant rather
+    /// than to a provider error, which is always retryable.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::ProviderError { .. }
+                | Error::BodyError { .. }
+                | Error::Overloaded { .. }
+                | Error::RateLimited { .. }
+                | Error::Timeout { .. }
+                | Error::ChecksumMismatch { .. }
+        )
+    }
+
+    /// How long to wait before attempting the operation again, if hinted
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Overloaded { retry_after, .. } | Error::RateLimited { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// Key of the blob the error is about, if any
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Error::IDNotFound { id, .. } => Some(id),
+            Error::Retained { key }
+            | Error::TooLarge { key, .. }
+            | Error::ContentTypeRejected { key, .. }
+            | Error::AlreadyExists { key }
+            | Error::InvalidKey { key, .. }
+            | Error::RangeNotSatisfiable { key, .. }
+            | Error::QuotaExceeded { key, .. }
+            | Error::PreconditionFailed { key }
+            | Error::ChecksumMismatch { key } => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes errors as their code, message and key, e.g.
+/// `{"code": "too_large", "message": "Blob a exceeds the maximum size of 1 bytes", "key": "a"}`
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self.key() {
+            Some(key) => state.serialize_field("key", key)?,
+            None => state.skip_field("key")?,
+        }
+        state.end()
+    }
 }
 
 impl From<Error> for std::io::Error {
@@ -41,8 +316,79 @@ impl From<Error> for std::io::Error {
         use std::io::ErrorKind;
         match err {
             Error::IDNotFound { source, .. } => Self::new(ErrorKind::NotFound, source),
-            Error::ProviderError { source, .. } => Self::new(ErrorKind::Other, source),
-            Error::BodyError { message } => Self::new(ErrorKind::Other, message),
+            Error::ProviderError { source, .. } => Self::other(source),
+            Error::BodyError { message } => Self::other(message),
+            Error::Retained { .. } => Self::new(ErrorKind::PermissionDenied, err.to_string()),
+            Error::MemoryBudgetExceeded { .. } => {
+                Self::new(ErrorKind::OutOfMemory, err.to_string())
+            }
+            Error::CredentialsError { message } => Self::new(ErrorKind::PermissionDenied, message),
+            Error::TooLarge { .. } => Self::new(ErrorKind::InvalidInput, err.to_string()),
+            Error::ContentTypeRejected { .. } => Self::new(ErrorKind::InvalidData, err.to_string()),
+            Error::AlreadyExists { .. } => Self::new(ErrorKind::AlreadyExists, err.to_string()),
+            Error::InvalidKey { .. } => Self::new(ErrorKind::InvalidInput, err.to_string()),
+            Error::InvalidToken { message } => Self::new(ErrorKind::PermissionDenied, message),
+            Error::DeadlineExceeded => Self::new(ErrorKind::TimedOut, err.to_string()),
+            Error::RangeNotSatisfiable { .. } => {
+                Self::new(ErrorKind::InvalidInput, err.to_string())
+            }
+            Error::ConfigError { message } => Self::new(ErrorKind::InvalidInput, message),
+            Error::QuotaExceeded { .. } => Self::new(ErrorKind::StorageFull, err.to_string()),
+            Error::Overloaded { .. } => Self::new(ErrorKind::ResourceBusy, err.to_string()),
+            Error::PermissionDenied { message } => Self::new(ErrorKind::PermissionDenied, message),
+            Error::PreconditionFailed { .. } => Self::other(err.to_string()),
+            Error::RateLimited { .. } => Self::new(ErrorKind::ResourceBusy, err.to_string()),
+            Error::Timeout { message } => Self::new(ErrorKind::TimedOut, message),
+            Error::InvalidRequest { message } => Self::new(ErrorKind::InvalidInput, message),
+            Error::ChecksumMismatch { .. } => Self::new(ErrorKind::InvalidData, err.to_string()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::error::Error;
+
+    #[test]
+    fn it_exposes_stable_codes() {
+        let err = Error::too_large("a", 1);
+        assert_eq!(err.code(), "too_large");
+        assert_eq!(err.key(), Some("a"));
+        assert_eq!(Error::body_error("closed").key(), None);
+
+        let err = Error::rate_limited("slow down", Some(Duration::from_secs(1)));
+        assert_eq!(err.code(), "rate_limited");
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(1)));
+        assert!(!Error::permission_denied("denied").is_retryable());
+    }
+
+    #[test]
+    fn it_classifies_io_errors() {
+        use std::io::{self, ErrorKind};
+
+        let err = Error::io(io::Error::new(ErrorKind::PermissionDenied, "denied"));
+        assert_eq!(err.code(), "permission_denied");
+        assert!(!err.is_retryable());
+        let err = Error::io(io::Error::new(ErrorKind::TimedOut, "timed out"));
+        assert!(err.is_retryable());
+        let err = Error::io(io::Error::other("broken"));
+        assert_eq!(err.code(), "provider_error");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_serializes_errors() {
+        let json = serde_json::to_value(Error::already_exists("a")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "code": "already_exists",
+                "message": "Blob a already exists",
+                "key": "a",
+            })
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use futures::TryStreamExt;
+
+use crate::blob::{Blob, BlobEntry};
+use crate::provider::Provider;
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Format of index documents, see [`IndexOptions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// A JSON object with the `prefix`, the sub-`prefixes` and the `blobs` of the level,
+    /// each blob with its `key`, `size` and `last_modified` time in seconds since the epoch
+    Json,
+
+    /// An HTML page linking to the blobs and to the index documents of the sub-prefixes
+    Html,
+}
+
+impl IndexFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            IndexFormat::Json => "application/json",
+            IndexFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// How index documents are generated, see [`write_index`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexOptions {
+    pub format: IndexFormat,
+
+    /// Name of the index document of each prefix, which is not listed in the indexes.
+    /// Blobs stored with this name are overwritten by the index documents.
+    pub document_name: String,
+}
+
+impl IndexOptions {
+    /// Indexes stored as `index.json`
+    pub fn json() -> Self {
+        Self {
+            format: IndexFormat::Json,
+            document_name: "index.json".to_string(),
+        }
+    }
+
+    /// Indexes stored as `index.html`
+    pub fn html() -> Self {
+        Self {
+            format: IndexFormat::Html,
+            document_name: "index.html".to_string(),
+        }
+    }
+
+    pub fn with_document_name<N: ToString>(mut self, document_name: N) -> Self {
+        self.document_name = document_name.to_string();
+        self
+    }
+
+    /// Key of the index document of a prefix
+    pub fn document_key(&self, prefix: &str) -> String {
+        format!("{}{}", normalize_prefix(prefix), self.document_name)
+    }
+
+    /// Whether a key is the one of an index document
+    pub fn is_document(&self, key: &str) -> bool {
+        key.rsplit('/').next() == Some(self.document_name.as_str())
+    }
+}
+
+/// A level of the hierarchy of keys, whose segments are separated by `/`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    /// Prefix of the level, empty or ending with `/`
+    pub prefix: String,
+
+    /// Prefixes of the levels right below, in key order
+    pub prefixes: Vec<String>,
+
+    /// Blobs of the level, in key order
+    pub blobs: Vec<BlobEntry>,
+}
+
+impl Index {
+    /// Lists the level of a prefix, a missing `/` at the end of the prefix being added
+    pub async fn list<P: Provider + Sync + ?Sized>(
+        provider: &P,
+        prefix: &str,
+        options: &IndexOptions,
+    ) -> Result<Index> {
+        let prefix = normalize_prefix(prefix);
+        let mut levels = list_levels(provider, &prefix, options).await?;
+        Ok(levels.remove(&prefix).unwrap_or(Index {
+            prefix,
+            ..Index::default()
+        }))
+    }
+
+    /// Whether the level has neither blobs nor prefixes below it
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty() && self.blobs.is_empty()
+    }
+
+    /// The index document of the level, linking to the documents of the levels below
+    pub fn render(&self, options: &IndexOptions) -> String {
+        match options.format {
+            IndexFormat::Json => self.render_json(),
+            IndexFormat::Html => self.render_html(&options.document_name),
+        }
+    }
+
+    /// The index document of the level, as a blob stored under its prefix
+    pub fn to_blob(&self, options: &IndexOptions) -> Blob {
+        let document = self.render(options);
+        Blob::from_bytes(options.document_key(&self.prefix), document.into_bytes())
+            .with_content_type(options.format.content_type())
+    }
+
+    fn render_json(&self) -> String {
+        let prefixes: Vec<_> = self.prefixes.iter().map(|p| json_string(p)).collect();
+        let blobs: Vec<_> = self
+            .blobs
+            .iter()
+            .map(|entry| {
+                let last_modified = entry
+                    .last_modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or("null".to_string(), |since| since.as_secs().to_string());
+                format!(
+                    r#"{{"key":{},"size":{},"last_modified":{}}}"#,
+                    json_string(&entry.key),
+                    entry.size,
+                    last_modified
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"prefix":{},"prefixes":[{}],"blobs":[{}]}}"#,
+            json_string(&self.prefix),
+            prefixes.join(","),
+            blobs.join(",")
+        )
+    }
+
+    fn render_html(&self, document_name: &str) -> String {
+        let title = html_escape(&format!("Index of /{}", self.prefix));
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+             <body>\n<h1>{0}</h1>\n<ul>\n",
+            title
+        );
+        if !self.prefix.is_empty() {
+            let _ = writeln!(
+                html,
+                "<li><a href=\"../{}\">../</a></li>",
+                html_escape(document_name)
+            );
+        }
+        for prefix in &self.prefixes {
+            let name = &prefix[self.prefix.len()..];
+            let _ = writeln!(
+                html,
+                "<li><a href=\"{}{}\">{}</a></li>",
+                html_escape(&encode_segment(name.trim_end_matches('/'))),
+                html_escape(&format!("/{}", document_name)),
+                html_escape(name)
+            );
+        }
+        for entry in &self.blobs {
+            let name = &entry.key[self.prefix.len()..];
+            let _ = writeln!(
+                html,
+                "<li><a href=\"{}\">{}</a> {} bytes</li>",
+                html_escape(&encode_segment(name)),
+                html_escape(name),
+                entry.size
+            );
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// Generates the index document of a prefix and stores it as a blob under the prefix,
+/// for clients that cannot list blobs, such as the browsers of a static site.
+/// Only the level of the prefix is indexed, see [`write_indexes`] for the levels below.
+pub async fn write_index<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    prefix: &str,
+    options: &IndexOptions,
+) -> Result<StoreReceipt> {
+    let index = Index::list(provider, prefix, options).await?;
+    provider.store_blob(index.to_blob(options)).await
+}
+
+/// Generates the index documents of a prefix and of every level below it from a single
+/// listing, returning how many were stored. Indexes of levels that no longer have blobs
+/// are left as they are.
+pub async fn write_indexes<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    prefix: &str,
+    options: &IndexOptions,
+) -> Result<usize> {
+    let prefix = normalize_prefix(prefix);
+    let mut levels = list_levels(provider, &prefix, options).await?;
+    levels.entry(prefix.clone()).or_insert_with(|| Index {
+        prefix,
+        ..Index::default()
+    });
+    for index in levels.values() {
+        provider.store_blob(index.to_blob(options)).await?;
+    }
+    Ok(levels.len())
+}
+
+/// Lists the levels under a prefix, including the ones only holding other levels
+async fn list_levels<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    prefix: &str,
+    options: &IndexOptions,
+) -> Result<BTreeMap<String, Index>> {
+    let mut prefixes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut blobs: BTreeMap<String, Vec<BlobEntry>> = BTreeMap::new();
+    let mut entries = provider.list_blobs(prefix);
+    while let Some(entry) = entries.try_next().await? {
+        if options.is_document(&entry.key) || !entry.key.starts_with(prefix) {
+            continue;
+        }
+        let mut level = prefix.to_string();
+        let segments: Vec<_> = entry.key[prefix.len()..].split('/').collect();
+        let (_, parents) = segments.split_last().expect("no segments");
+        for segment in parents {
+            let below = format!("{}{}/", level, segment);
+            prefixes.entry(level).or_default().insert(below.clone());
+            level = below;
+        }
+        blobs.entry(level).or_default().push(entry);
+    }
+
+    let mut levels = BTreeMap::new();
+    let keys: BTreeSet<_> = prefixes.keys().chain(blobs.keys()).cloned().collect();
+    for level in keys {
+        let mut level_blobs = blobs.remove(&level).unwrap_or_default();
+        level_blobs.sort_by(|a, b| a.key.cmp(&b.key));
+        let index = Index {
+            prefix: level.clone(),
+            prefixes: prefixes
+                .remove(&level)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            blobs: level_blobs,
+        };
+        levels.insert(level, index);
+    }
+    Ok(levels)
+}
+
+/// The prefix of the level holding a key or a prefix
+pub(crate) fn parent_prefix(key: &str) -> &str {
+    let key = key.strip_suffix('/').unwrap_or(key);
+    match key.rfind('/') {
+        Some(end) => &key[..=end],
+        None => "",
+    }
+}
+
+fn normalize_prefix(prefix: &str) -> String {
+    if prefix.is_empty() || prefix.ends_with('/') {
+        prefix.to_string()
+    } else {
+        format!("{}/", prefix)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn html_escape(value: &str) -> String {
+    let mut html = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Percent-encodes a path segment for a relative link
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::index::{write_indexes, IndexOptions};
+    use crate::memory::MemoryProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_writes_the_index_of_every_level() {
+        let provider = MemoryProvider::new();
+        block_on(async {
+            for key in &["docs/a.txt", "docs/guides/intro.md", "readme"] {
+                provider
+                    .store_blob(Blob::from_bytes(*key, vec![0; 3]))
+                    .await
+                    .unwrap();
+            }
+            let written = write_indexes(&provider, "", &IndexOptions::json())
+                .await
+                .unwrap();
+            assert_eq!(written, 3);
+
+            let index = provider.get_blob("docs/index.json").await.unwrap().unwrap();
+            assert_eq!(index.content_type(), Some("application/json"));
+            let json: serde_json::Value =
+                serde_json::from_slice(&index.read_content().await.unwrap()).unwrap();
+            assert_eq!(json["prefix"], "docs/");
+            assert_eq!(json["prefixes"], serde_json::json!(["docs/guides/"]));
+            assert_eq!(json["blobs"][0]["key"], "docs/a.txt");
+            assert_eq!(json["blobs"][0]["size"], 3);
+
+            // index documents are not indexed themselves
+            write_indexes(&provider, "", &IndexOptions::json())
+                .await
+                .unwrap();
+            let root = provider.get_blob("index.json").await.unwrap().unwrap();
+            let json: serde_json::Value =
+                serde_json::from_slice(&root.read_content().await.unwrap()).unwrap();
+            assert_eq!(json["blobs"].as_array().unwrap().len(), 1);
+        });
+    }
+}
@@ -1,7 +1,35 @@
 use crate::error::Error;
 
+pub mod admin;
 pub mod blob;
+pub mod budget;
+pub mod clock;
+pub mod config;
+pub mod credentials;
+pub mod deadline;
+pub mod digest;
+#[cfg(feature = "tokio")]
+pub mod download;
 pub mod error;
+pub mod index;
+pub mod listing;
+#[cfg(feature = "memory")]
+pub mod memory;
+pub mod metadata;
+pub mod middleware;
+pub mod naming;
+pub mod presign;
 pub mod provider;
+pub mod reader;
+pub mod receipt;
+pub mod registry;
+pub mod retention;
+pub mod retry;
+pub mod stack;
+pub mod tee;
+pub mod tier;
+pub mod token;
+pub mod versioning;
+pub mod writer;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,201 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::blob::BlobEntry;
+use crate::digest::{from_hex, to_hex};
+use crate::error::Error;
+
+/// Opaque position in a paged listing, see [`Provider::list_page`].
+///
+/// Cursors wrap the native continuation token of the provider that returned them,
+/// and are only meaningful to that provider and listing prefix. They are displayed
+/// and serialized as URL-safe strings, so that web APIs can hand them to clients.
+///
+/// [`Provider::list_page`]: crate::provider::Provider::list_page
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    token: String,
+}
+
+impl Cursor {
+    /// A cursor wrapping a provider-native continuation token
+    pub fn new<T: ToString>(token: T) -> Self {
+        Self {
+            token: token.to_string(),
+        }
+    }
+
+    /// The provider-native continuation token
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(self.token.as_bytes()))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_hex(s)
+            .and_then(|token| String::from_utf8(token).ok())
+            .map(|token| Cursor { token })
+            .ok_or_else(|| Error::body_error("malformed listing cursor"))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Cursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Cursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let cursor = String::deserialize(deserializer)?;
+        cursor.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A page of a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub entries: Vec<BlobEntry>,
+
+    /// Cursor to the next page, if there are more entries
+    pub next: Option<Cursor>,
+}
+
+/// Filters of a listing, see [`Provider::list_blobs_with_options`].
+///
+/// Modification times bound a half-open interval, so that consecutive runs of an
+/// incremental job passing the start of the previous run as `modified_after` see
+/// every change once.
+///
+/// [`Provider::list_blobs_with_options`]: crate::provider::Provider::list_blobs_with_options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Only lists blobs last modified at or after this moment
+    pub modified_after: Option<SystemTime>,
+
+    /// Only lists blobs last modified strictly before this moment
+    pub modified_before: Option<SystemTime>,
+}
+
+impl ListOptions {
+    /// Lists the blobs modified since the given moment
+    pub fn modified_after(modified_after: SystemTime) -> Self {
+        Self {
+            modified_after: Some(modified_after),
+            modified_before: None,
+        }
+    }
+
+    pub fn with_modified_before(mut self, modified_before: SystemTime) -> Self {
+        self.modified_before = Some(modified_before);
+        self
+    }
+
+    /// Whether an entry passes the filters.
+    /// Entries whose modification time is unknown always do, so that no change is missed.
+    pub fn matches(&self, entry: &BlobEntry) -> bool {
+        let modified = match entry.last_modified {
+            Some(modified) => modified,
+            None => return true,
+        };
+        self.modified_after.is_none_or(|after| modified >= after)
+            && self.modified_before.is_none_or(|before| modified < before)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::listing::Cursor;
+
+    #[test]
+    fn it_round_trips_cursors_through_strings() {
+        let cursor = Cursor::new("photos/2020/cat.jpg");
+        let encoded = cursor.to_string();
+
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(encoded.parse::<Cursor>().unwrap(), cursor);
+        assert!("not a cursor".parse::<Cursor>().is_err());
+    }
+
+    #[cfg(feature = "memory")]
+    #[test]
+    fn it_pages_listings_in_key_order() {
+        use futures::executor::block_on;
+
+        use crate::blob::Blob;
+        use crate::memory::MemoryProvider;
+        use crate::provider::Provider;
+
+        let provider = MemoryProvider::new();
+        block_on(async {
+            for key in &["c", "a", "b", "other"] {
+                provider
+                    .store_blob(Blob::from_bytes(*key, vec![0]))
+                    .await
+                    .unwrap();
+            }
+            let first = provider.list_page("", None, 2).await.unwrap();
+            let keys: Vec<_> = first
+                .entries
+                .iter()
+                .map(|entry| entry.key.as_str())
+                .collect();
+            assert_eq!(keys, vec!["a", "b"]);
+
+            let second = provider
+                .list_page("", first.next.as_ref(), 2)
+                .await
+                .unwrap();
+            let keys: Vec<_> = second
+                .entries
+                .iter()
+                .map(|entry| entry.key.as_str())
+                .collect();
+            assert_eq!(keys, vec!["c", "other"]);
+            assert!(second.next.is_none());
+        });
+    }
+
+    #[test]
+    fn it_filters_entries_by_modification_time() {
+        use std::time::{Duration, SystemTime};
+
+        use crate::blob::BlobEntry;
+        use crate::listing::ListOptions;
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let options = ListOptions::modified_after(start)
+            .with_modified_before(start + Duration::from_secs(10));
+        let entry = |secs| BlobEntry {
+            last_modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            ..BlobEntry::new("key", 0)
+        };
+
+        assert!(options.matches(&entry(100)));
+        assert!(!options.matches(&entry(99)));
+        assert!(!options.matches(&entry(110)));
+        assert!(options.matches(&BlobEntry::new("key", 0)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_serializes_cursors_as_strings() {
+        let cursor = Cursor::new("token");
+        let json = serde_json::to_value(&cursor).unwrap();
+
+        assert_eq!(json, serde_json::json!("746f6b656e"));
+        assert_eq!(serde_json::from_value::<Cursor>(json).unwrap(), cursor);
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream;
+
+use crate::blob::{Blob, BlobEntry, Head};
+use crate::digest::{to_hex, DigestAlgorithm};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Provider keeping blobs in memory, for tests and ephemeral storage.
+/// Blobs are lost when the provider is dropped.
+/// Entity tags are the quoted MD5 of the content, as with S3 single-part uploads.
+#[derive(Debug, Default)]
+pub struct MemoryProvider {
+    blobs: RwLock<BTreeMap<String, Stored>>,
+}
+
+#[derive(Debug, Clone)]
+struct Stored {
+    content: Bytes,
+    metadata: BlobMetadata,
+    stored_at: SystemTime,
+}
+
+impl Stored {
+    fn entry(&self, key: &str) -> BlobEntry {
+        BlobEntry {
+            last_modified: Some(self.stored_at),
+            ..BlobEntry::new(key, self.content.len())
+        }
+    }
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored blobs
+    pub fn len(&self) -> usize {
+        self.blobs
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keys of the stored blobs, in key order
+    pub fn keys(&self) -> Vec<String> {
+        self.blobs
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Stores a blob, unless `if_absent` is set and its key already holds one
+    async fn put(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let metadata = blob.metadata().clone();
+        let checksum = blob
+            .checksums()
+            .get(DigestAlgorithm::Md5)
+            .map(<[u8]>::to_vec);
+        let content = blob.read_content().await?;
+        let md5 = DigestAlgorithm::Md5.digest(&content);
+        if checksum.is_some_and(|checksum| checksum != md5) {
+            return Err(Error::checksum_mismatch(key));
+        }
+        let etag = format!("\"{}\"", to_hex(&md5));
+        let metadata = BlobMetadata {
+            last_modified: None,
+            etag: Some(etag.clone()),
+            ..metadata
+        };
+        let receipt = StoreReceipt {
+            etag: Some(etag),
+            ..StoreReceipt::new(&key, content.len())
+        };
+        let mut blobs = self.blobs.write().unwrap_or_else(|err| err.into_inner());
+        if if_absent && blobs.contains_key(&key) {
+            return Err(Error::precondition_failed(key));
+        }
+        blobs.insert(
+            key,
+            Stored {
+                content: Bytes::from(content),
+                metadata,
+                stored_at: receipt.stored_at,
+            },
+        );
+        Ok(receipt)
+    }
+
+    /// Copies a stored blob, replacing its metadata if given
+    fn copy(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: Option<BlobMetadata>,
+    ) -> Option<StoreReceipt> {
+        let mut blobs = self.blobs.write().unwrap_or_else(|err| err.into_inner());
+        let stored = blobs.get(src_key)?.clone();
+        let metadata = match metadata {
+            Some(metadata) => BlobMetadata {
+                last_modified: None,
+                etag: stored.metadata.etag.clone(),
+                ..metadata
+            },
+            None => stored.metadata,
+        };
+        let receipt = StoreReceipt::new(dst_key, stored.content.len());
+        blobs.insert(
+            dst_key.to_string(),
+            Stored {
+                content: stored.content,
+                metadata,
+                stored_at: receipt.stored_at,
+            },
+        );
+        Some(receipt)
+    }
+
+    /// Removes all the stored blobs
+    pub fn clear(&self) {
+        self.blobs
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+    }
+}
+
+#[async_trait]
+impl Provider for MemoryProvider {
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let blobs = self.blobs.read().unwrap_or_else(|err| err.into_inner());
+        Ok(blobs.get(key).cloned().map(|stored| {
+            let size = stored.content.len();
+            let content = stored.content;
+            let metadata = BlobMetadata {
+                last_modified: Some(stored.stored_at),
+                ..stored.metadata
+            };
+            Blob::new(key, size, stream::once(async move { Ok(content) })).with_metadata(metadata)
+        }))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        let blobs = self.blobs.read().unwrap_or_else(|err| err.into_inner());
+        Ok(blobs.get(key).map(|stored| Head {
+            size: stored.content.len(),
+            metadata: BlobMetadata {
+                last_modified: Some(stored.stored_at),
+                ..stored.metadata.clone()
+            },
+        }))
+    }
+
+    #[tracing::instrument(skip(self, blob), fields(provider = "memory", key = blob.key()))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.put(blob, false).await
+    }
+
+    #[tracing::instrument(skip(self, blob), fields(provider = "memory", key = blob.key()))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.put(blob, true).await
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        let blobs = self.blobs.read().unwrap_or_else(|err| err.into_inner());
+        Ok(blobs.contains_key(key))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.blobs
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(key);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        Ok(self.copy(src_key, dst_key, None))
+    }
+
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        Ok(self.copy(src_key, dst_key, Some(metadata)))
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.list_blobs_with_options(prefix, ListOptions::default())
+    }
+
+    /// Blobs are filtered while being listed, without copying the entries left out
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        let blobs = self.blobs.read().unwrap_or_else(|err| err.into_inner());
+        let entries: Vec<_> = blobs
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, stored)| {
+                options
+                    .modified_after
+                    .is_none_or(|after| stored.stored_at >= after)
+                    && options
+                        .modified_before
+                        .is_none_or(|before| stored.stored_at < before)
+            })
+            .map(|(key, stored)| stored.entry(key))
+            .collect();
+        Box::pin(stream::iter(entries.into_iter().map(Ok)))
+    }
+
+    /// Pages are read from the ordered keys, without going through the rest of the prefix
+    #[tracing::instrument(skip(self), fields(provider = "memory"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let start = match cursor {
+            Some(cursor) if cursor.token() >= prefix => Bound::Excluded(cursor.token()),
+            _ => Bound::Included(prefix),
+        };
+        let limit = limit.max(1);
+        let blobs = self.blobs.read().unwrap_or_else(|err| err.into_inner());
+        let mut entries: Vec<_> = blobs
+            .range::<str, _>((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit + 1)
+            .map(|(key, stored)| stored.entry(key))
+            .collect();
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|entry| Cursor::new(&entry.key))
+        } else {
+            None
+        };
+        Ok(Page { entries, next })
+    }
+
+    /// The MD5 of the content is computed for its entity tag anyway
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        &[DigestAlgorithm::Md5]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::metadata::BlobMetadata;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_stores_and_fetches_blobs() {
+        let provider = MemoryProvider::new();
+        let blob = Blob::from_bytes("key", b"content".to_vec())
+            .with_metadata(BlobMetadata::new().with_custom("owner", "alice"))
+            .with_content_type("text/plain");
+        block_on(provider.store_blob(blob)).unwrap();
+
+        assert!(block_on(provider.is_blob_present("key")).unwrap());
+        let blob = block_on(provider.get_blob("key")).unwrap().unwrap();
+        assert_eq!(blob.size(), 7);
+        assert_eq!(blob.content_type(), Some("text/plain"));
+        assert_eq!(blob.metadata().custom["owner"], "alice");
+        assert!(blob.metadata().last_modified.is_some());
+        assert_eq!(block_on(blob.read_content()).unwrap(), b"content".to_vec());
+
+        block_on(provider.delete_blob("key")).unwrap();
+        assert!(block_on(provider.get_blob("key")).unwrap().is_none());
+        assert!(provider.is_empty());
+    }
+
+    #[test]
+    fn it_copies_blobs_with_new_metadata() {
+        let provider = MemoryProvider::new();
+        let blob = Blob::from_bytes("key", b"{}".to_vec()).with_content_type("text/plain");
+        block_on(provider.store_blob(blob)).unwrap();
+
+        let metadata = BlobMetadata::new().with_content_type("application/json");
+        block_on(provider.copy_blob_with_metadata("key", "key", metadata)).unwrap();
+        let blob = block_on(provider.get_blob("key")).unwrap().unwrap();
+        assert_eq!(blob.content_type(), Some("application/json"));
+        assert!(blob.metadata().etag.is_some());
+        assert_eq!(block_on(blob.read_content()).unwrap(), b"{}".to_vec());
+    }
+
+    #[test]
+    fn it_lists_blobs_by_prefix() {
+        let provider = MemoryProvider::new();
+        for key in &["logs/b", "logs/a", "data/a"] {
+            block_on(provider.store_blob(Blob::from_bytes(*key, vec![0; 3]))).unwrap();
+        }
+
+        let entries: Vec<_> = block_on(provider.list_blobs("logs/").try_collect()).unwrap();
+        let keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["logs/a", "logs/b"]);
+        assert_eq!(entries[0].size, 3);
+    }
+
+    #[test]
+    fn it_pages_through_blobs_by_prefix() {
+        let provider = MemoryProvider::new();
+        for key in &["logs/c", "logs/a", "logs/b", "data/a", "m"] {
+            block_on(provider.store_blob(Blob::from_bytes(*key, vec![0; 3]))).unwrap();
+        }
+
+        let first = block_on(provider.list_page("logs/", None, 2)).unwrap();
+        let keys: Vec<_> = first
+            .entries
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["logs/a", "logs/b"]);
+        let second = block_on(provider.list_page("logs/", first.next.as_ref(), 2)).unwrap();
+        let keys: Vec<_> = second
+            .entries
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["logs/c"]);
+        assert!(second.next.is_none());
+    }
+
+    #[test]
+    fn it_reads_whole_blobs_once_changed_since_a_range() {
+        let provider = MemoryProvider::new();
+        let receipt =
+            block_on(provider.store_blob(Blob::from_bytes("key", b"v1 content".to_vec())));
+        let etag = receipt.unwrap().etag.unwrap();
+
+        let read = block_on(provider.get_blob_if_range("key", 3..10, &etag)).unwrap();
+        let read = read.unwrap();
+        assert!(read.is_range());
+        assert_eq!(
+            block_on(read.into_blob().read_content()).unwrap(),
+            b"content"
+        );
+
+        block_on(provider.store_blob(Blob::from_bytes("key", b"v2 content".to_vec()))).unwrap();
+        let read = block_on(provider.get_blob_if_range("key", 3..10, &etag)).unwrap();
+        let read = read.unwrap();
+        assert!(!read.is_range());
+        assert_eq!(
+            block_on(read.into_blob().read_content()).unwrap(),
+            b"v2 content"
+        );
+    }
+}
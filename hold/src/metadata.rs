@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use crate::tier::StorageTier;
+
+/// Metadata of a blob, persisted by providers alongside its content.
+///
+/// `last_modified` and `etag` are assigned by the backend, so they are populated
+/// on fetched blobs and ignored when storing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlobMetadata {
+    /// MIME type of the content
+    pub content_type: Option<String>,
+
+    /// Encoding the content is stored with, as in a `Content-Encoding` header (e.g. `gzip`)
+    pub content_encoding: Option<String>,
+
+    /// When the blob was last stored
+    pub last_modified: Option<SystemTime>,
+
+    /// Entity tag or checksum of the content assigned by the backend
+    pub etag: Option<String>,
+
+    /// Storage tier the blob is stored with, when not the provider default
+    pub storage_tier: Option<StorageTier>,
+
+    /// User-defined key/value pairs
+    pub custom: BTreeMap<String, String>,
+}
+
+impl BlobMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_content_type<T: ToString>(mut self, content_type: T) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Adds a user-defined key/value pair
+    pub fn with_custom<K: ToString, V: ToString>(mut self, key: K, value: V) -> Self {
+        self.custom.insert(key.to_string(), value.to_string());
+        self
+    }
+}
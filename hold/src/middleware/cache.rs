@@ -0,0 +1,686 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{stream, StreamExt};
+
+pub use crate::blob::Head;
+
+use crate::blob::{etag_matches, Blob, RangeRead};
+use crate::budget::{MemoryBudget, Reservation};
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Provider wrapper keeping the most recently fetched blobs in memory,
+/// so that repeated gets of hot keys do not reach the wrapped provider.
+///
+/// The cache is bounded by the total size of the cached content and by its number of
+/// entries, evicting the least recently used blobs first. Blobs larger than the whole
+/// cache, including those declaring a smaller size than their content, are streamed
+/// without being cached. Cached content is also drawn from the [`MemoryBudget`] given to
+/// [`CachedProvider::with_budget`], blobs being streamed uncached once it runs out.
+///
+/// Stores, deletes and copies through the wrapper invalidate the blobs they write,
+/// but writes from other clients of the wrapped provider are not seen until the
+/// cached blobs are evicted, see [`CachedProvider::invalidate`].
+///
+/// Metadata lookups, [`CachedProvider::head`] and existence checks, can also be cached
+/// apart from the content, for a short time given to
+/// [`CachedProvider::with_metadata_ttl`]. Blobs missing from the wrapped provider are
+/// then remembered as missing too. Up to `max_entries` metadata lookups are cached.
+#[derive(Debug)]
+pub struct CachedProvider<P> {
+    inner: P,
+    max_bytes: usize,
+    max_entries: usize,
+    metadata_ttl: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    budget: MemoryBudget,
+    cache: Mutex<Cache>,
+}
+
+/// What a metadata lookup found about a blob
+#[derive(Debug, Clone)]
+enum Known {
+    Absent,
+    Present,
+    Head(Head),
+}
+
+#[derive(Debug)]
+struct CachedHead {
+    known: Known,
+    expires_at: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<String, Cached>,
+    heads: HashMap<String, CachedHead>,
+    /// Keys by the tick they were last used at, oldest first
+    recency: BTreeMap<u64, String>,
+    bytes: usize,
+    tick: u64,
+    /// Keys being fetched, so that fetches racing with a write do not cache the
+    /// content it replaced
+    in_flight: HashMap<String, InFlight>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    content: Bytes,
+    metadata: BlobMetadata,
+    used_at: u64,
+    _reservation: Reservation,
+}
+
+/// Fetches of a key in flight
+#[derive(Debug, Default)]
+struct InFlight {
+    fetches: usize,
+    /// Incremented on every invalidation of the key
+    generation: u64,
+}
+
+/// A fetch from the wrapped provider, whose outcome is only cached if its key
+/// has not been invalidated since it started
+struct Fetch<'a> {
+    cache: &'a Mutex<Cache>,
+    key: &'a str,
+    generation: u64,
+}
+
+impl Drop for Fetch<'_> {
+    fn drop(&mut self) {
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(in_flight) = cache.in_flight.get_mut(self.key) {
+            in_flight.fetches -= 1;
+            if in_flight.fetches == 0 {
+                cache.in_flight.remove(self.key);
+            }
+        }
+    }
+}
+
+/// Content read from a fetched blob
+enum Fetched {
+    Content(Bytes, Reservation),
+    /// The blob turned out too large to be cached, and is streamed instead
+    Uncached(Blob),
+}
+
+impl Cache {
+    fn get(&mut self, key: &str) -> Option<(Bytes, BlobMetadata)> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.used_at);
+        self.recency.insert(self.tick, key.to_string());
+        entry.used_at = self.tick;
+        Some((entry.content.clone(), entry.metadata.clone()))
+    }
+
+    fn insert(
+        &mut self,
+        key: &str,
+        content: Bytes,
+        metadata: BlobMetadata,
+        reservation: Reservation,
+    ) {
+        self.remove(key);
+        self.tick += 1;
+        self.bytes += content.len();
+        self.recency.insert(self.tick, key.to_string());
+        let entry = Cached {
+            content,
+            metadata,
+            used_at: self.tick,
+            _reservation: reservation,
+        };
+        self.entries.insert(key.to_string(), entry);
+    }
+
+    /// Whether a fetch of the key started at the given generation is still current
+    fn is_current(&self, key: &str, generation: u64) -> bool {
+        self.in_flight
+            .get(key)
+            .is_some_and(|in_flight| in_flight.generation == generation)
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used_at);
+            self.bytes -= entry.content.len();
+        }
+    }
+
+    fn evict(&mut self, max_bytes: usize, max_entries: usize) {
+        while self.bytes > max_bytes || self.entries.len() > max_entries {
+            let key = match self.recency.values().next() {
+                Some(key) => key.clone(),
+                None => return,
+            };
+            self.remove(&key);
+        }
+    }
+}
+
+impl<P: Provider + Send + Sync> CachedProvider<P> {
+    /// Caches up to `max_entries` blobs totalling at most `max_bytes` of content
+    pub fn new(inner: P, max_bytes: usize, max_entries: usize) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            max_entries,
+            metadata_ttl: None,
+            clock: Arc::new(SystemClock),
+            budget: MemoryBudget::unlimited(),
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Draws the cached content from the given budget, shared with other components
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Caches metadata lookups for the given time, see [`CachedProvider::head`]
+    pub fn with_metadata_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata_ttl = Some(ttl);
+        self
+    }
+
+    /// Uses the given clock to expire cached metadata
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Number of cached blobs
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size of the cached content
+    pub fn cached_bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    /// Drops the cached copy and metadata of a blob, for blobs written by other clients
+    pub fn invalidate(&self, key: &str) {
+        let mut cache = self.lock();
+        if let Some(in_flight) = cache.in_flight.get_mut(key) {
+            in_flight.generation += 1;
+        }
+        cache.heads.remove(key);
+        cache.remove(key);
+    }
+
+    /// Drops all the cached blobs
+    pub fn clear(&self) {
+        let mut cache = self.lock();
+        for in_flight in cache.in_flight.values_mut() {
+            in_flight.generation += 1;
+        }
+        cache.entries.clear();
+        cache.heads.clear();
+        cache.recency.clear();
+        cache.bytes = 0;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn begin_fetch<'a>(&'a self, key: &'a str) -> Fetch<'a> {
+        let mut cache = self.lock();
+        let in_flight = cache.in_flight.entry(key.to_string()).or_default();
+        in_flight.fetches += 1;
+        Fetch {
+            cache: &self.cache,
+            key,
+            generation: in_flight.generation,
+        }
+    }
+
+    /// Reads the content of a fetched blob within the size of the cache and the budget
+    async fn read(&self, blob: Blob) -> Result<Fetched> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        let mut reservation = match self.budget.try_reserve(size) {
+            Some(reservation) => reservation,
+            None => return Ok(Fetched::Uncached(blob)),
+        };
+        let mut content = BytesMut::with_capacity(size);
+        let mut stream = blob.into_byte_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(Error::body_error)?;
+            let read = content.len() + chunk.len();
+            // the declared size may be wrong, so the content read so far is streamed
+            // along with the rest when it is too large after all
+            let reserved =
+                read <= reservation.bytes() || reservation.try_grow(read - reservation.bytes());
+            if read > self.max_bytes || !reserved {
+                let consumed = stream::iter(vec![Ok(content.freeze()), Ok(chunk)]);
+                let blob = Blob::new(key, size, consumed.chain(stream)).with_metadata(metadata);
+                return Ok(Fetched::Uncached(blob));
+            }
+            content.extend_from_slice(&chunk);
+        }
+        Ok(Fetched::Content(content.freeze(), reservation))
+    }
+
+    /// Size and metadata of a blob, without reaching the wrapped provider when they are
+    /// cached. Otherwise they are looked up with [`Provider::head_blob`].
+    pub async fn head(&self, key: &str) -> Result<Option<Head>> {
+        if let Some(blob) = self.cached(key) {
+            return Ok(Some(Head::of(&blob)));
+        }
+        match self.known(key) {
+            Some(Known::Head(head)) => return Ok(Some(head)),
+            Some(Known::Absent) => return Ok(None),
+            Some(Known::Present) | None => {}
+        }
+
+        let fetch = self.begin_fetch(key);
+        let head = self.inner.head_blob(key).await?;
+        let known = head.clone().map_or(Known::Absent, Known::Head);
+        self.remember(&fetch, known);
+        Ok(head)
+    }
+
+    fn cached(&self, key: &str) -> Option<Blob> {
+        let (content, metadata) = self.lock().get(key)?;
+        Some(to_blob(key, content, metadata))
+    }
+
+    /// The cached outcome of a metadata lookup, if not expired
+    fn known(&self, key: &str) -> Option<Known> {
+        self.metadata_ttl?;
+        let now = self.clock.now();
+        let mut cache = self.lock();
+        match cache.heads.get(key) {
+            Some(head) if head.expires_at > now => Some(head.known.clone()),
+            Some(_) => {
+                cache.heads.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the outcome of a metadata lookup, unless invalidated since it started
+    fn remember(&self, fetch: &Fetch<'_>, known: Known) {
+        let key = fetch.key;
+        let ttl = match self.metadata_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let now = self.clock.now();
+        let mut cache = self.lock();
+        if !cache.is_current(key, fetch.generation) {
+            return;
+        }
+        if cache.heads.len() >= self.max_entries && !cache.heads.contains_key(key) {
+            cache.heads.retain(|_, head| head.expires_at > now);
+            if cache.heads.len() >= self.max_entries {
+                return;
+            }
+        }
+        let head = CachedHead {
+            known,
+            expires_at: now + ttl,
+        };
+        cache.heads.insert(key.to_string(), head);
+    }
+}
+
+fn to_blob(key: &str, content: Bytes, metadata: BlobMetadata) -> Blob {
+    let size = content.len();
+    Blob::new(key, size, stream::once(async move { Ok(content) })).with_metadata(metadata)
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for CachedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        if let Some(blob) = self.cached(key) {
+            return Ok(Some(blob));
+        }
+
+        let fetch = self.begin_fetch(key);
+        let blob = match self.inner.get_blob(key).await? {
+            Some(blob) => blob,
+            None => {
+                self.remember(&fetch, Known::Absent);
+                return Ok(None);
+            }
+        };
+        self.remember(&fetch, Known::Head(Head::of(&blob)));
+        if blob.size() > self.max_bytes || self.max_entries == 0 {
+            return Ok(Some(blob));
+        }
+        let metadata = blob.metadata().clone();
+        let (content, reservation) = match self.read(blob).await? {
+            Fetched::Content(content, reservation) => (content, reservation),
+            Fetched::Uncached(blob) => return Ok(Some(blob)),
+        };
+
+        let mut cache = self.lock();
+        if cache.is_current(key, fetch.generation) {
+            cache.insert(key, content.clone(), metadata.clone(), reservation);
+            cache.evict(self.max_bytes, self.max_entries);
+        }
+        drop(cache);
+        Ok(Some(to_blob(key, content, metadata)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        match self.cached(key) {
+            Some(blob) => blob.into_range(range).map(Some),
+            None => self.inner.get_blob_range(key, range).await,
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        match self.cached(key) {
+            Some(blob) if etag_matches(blob.metadata().etag.as_deref(), etag) => blob
+                .into_range(range)
+                .map(|blob| Some(RangeRead::Range(blob))),
+            Some(blob) => Ok(Some(RangeRead::Full(blob))),
+            None => self.inner.get_blob_if_range(key, range, etag).await,
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.head(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let result = self.inner.store_blob(blob).await;
+        self.invalidate(&key);
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let result = self.inner.store_blob_if_absent(blob).await;
+        self.invalidate(&key);
+        result
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            let result = write_through(content, self.inner.open_writer(key, metadata)).await;
+            self.invalidate(key);
+            result
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        if self.lock().entries.contains_key(key) {
+            return Ok(true);
+        }
+        if let Some(known) = self.known(key) {
+            return Ok(!matches!(known, Known::Absent));
+        }
+
+        let fetch = self.begin_fetch(key);
+        let present = self.inner.is_blob_present(key).await?;
+        let known = if present {
+            Known::Present
+        } else {
+            Known::Absent
+        };
+        self.remember(&fetch, known);
+        Ok(present)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        let result = self.inner.delete_blob(key).await;
+        self.invalidate(key);
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let result = self.inner.copy_blob(src_key, dst_key).await;
+        self.invalidate(dst_key);
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let result = self
+            .inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await;
+        self.invalidate(dst_key);
+        result
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "cache"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::budget::MemoryBudget;
+    use crate::clock::ManualClock;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::cache::CachedProvider;
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider returning blobs that declare a single byte, whatever their content
+    #[derive(Debug, Default)]
+    struct Understated {
+        inner: MemoryProvider,
+    }
+
+    #[async_trait]
+    impl Provider for Understated {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            let blob = self.inner.get_blob(key).await?;
+            Ok(blob.map(|blob| Blob::new(key, 1, blob.into_byte_stream())))
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_caches_fetched_blobs_until_written() {
+        let provider = CachedProvider::new(MemoryProvider::new(), 1024, 2);
+        block_on(async {
+            let store = |key: &'static str, content: &[u8]| {
+                provider
+                    .inner()
+                    .store_blob(Blob::from_bytes(key, content.to_vec()))
+            };
+            store("a", b"first").await.unwrap();
+            let blob = provider.get_blob("a").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"first");
+
+            // writes bypassing the cache are not seen
+            store("a", b"second").await.unwrap();
+            let blob = provider.get_blob("a").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"first");
+
+            provider
+                .store_blob(Blob::from_bytes("a", b"third".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("a").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"third");
+            assert_eq!(provider.cached_bytes(), 5);
+
+            // the least recently used blob is evicted first
+            store("b", b"b").await.unwrap();
+            store("c", b"c").await.unwrap();
+            provider.get_blob("b").await.unwrap();
+            provider.get_blob("a").await.unwrap();
+            provider.get_blob("c").await.unwrap();
+            assert_eq!(provider.len(), 2);
+            store("b", b"updated").await.unwrap();
+            let blob = provider.get_blob("b").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"updated");
+        });
+    }
+
+    #[test]
+    fn it_caches_metadata_lookups_until_they_expire() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let provider = CachedProvider::new(MemoryProvider::new(), 4, 10)
+            .with_metadata_ttl(Duration::from_secs(5))
+            .with_clock(clock.clone());
+        block_on(async {
+            let store = |key: &'static str, content: &[u8]| {
+                provider
+                    .inner()
+                    .store_blob(Blob::from_bytes(key, content.to_vec()))
+            };
+            assert!(!provider.is_blob_present("key").await.unwrap());
+            store("key", b"too large to cache").await.unwrap();
+            assert!(!provider.is_blob_present("key").await.unwrap());
+            assert!(provider.head("key").await.unwrap().is_none());
+
+            clock.advance(Duration::from_secs(5));
+            let head = provider.head("key").await.unwrap().unwrap();
+            assert_eq!(head.size, 18);
+            store("key", b"changed").await.unwrap();
+            assert_eq!(provider.head("key").await.unwrap().unwrap().size, 18);
+            assert!(provider.is_empty());
+
+            provider.invalidate("key");
+            assert_eq!(provider.head("key").await.unwrap().unwrap().size, 7);
+        });
+    }
+
+    #[test]
+    fn it_streams_blobs_larger_than_declared_uncached() {
+        let provider = CachedProvider::new(Understated::default(), 4, 10);
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"larger than declared".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"larger than declared");
+            assert!(provider.is_empty());
+        });
+    }
+
+    #[test]
+    fn it_draws_cached_content_from_the_budget() {
+        let budget = MemoryBudget::new(8);
+        let provider =
+            CachedProvider::new(MemoryProvider::new(), 1024, 10).with_budget(budget.clone());
+        block_on(async {
+            for (key, content) in &[("a", b"first"), ("b", b"other")] {
+                provider
+                    .inner()
+                    .store_blob(Blob::from_bytes(*key, content.to_vec()))
+                    .await
+                    .unwrap();
+            }
+            provider.get_blob("a").await.unwrap();
+            assert_eq!(budget.used(), 5);
+
+            // the budget has no room left for the second blob, which is streamed
+            let blob = provider.get_blob("b").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"other");
+            assert_eq!(provider.len(), 1);
+
+            provider.invalidate("a");
+            assert_eq!(budget.used(), 0);
+        });
+    }
+
+    #[test]
+    fn it_only_drops_fetches_of_invalidated_keys() {
+        let provider = CachedProvider::new(MemoryProvider::new(), 1024, 10);
+        let fetch = provider.begin_fetch("a");
+        provider.invalidate("b");
+        assert!(provider.lock().is_current("a", fetch.generation));
+        provider.invalidate("a");
+        assert!(!provider.lock().is_current("a", fetch.generation));
+        drop(fetch);
+        assert!(provider.lock().in_flight.is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+use std::io::{self, Write};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use futures::io::AsyncWriteExt;
+use futures::{stream, Stream, StreamExt};
+
+use crate::blob::Blob;
+use crate::budget::MemoryBudget;
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::middleware::encoding::{decode_blob_sized, Encoding};
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Custom metadata key the size of the content before compression is stored under
+const UNCOMPRESSED_SIZE: &str = "hold-uncompressed-size";
+
+/// A compression algorithm and level used by [`CompressedProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Levels range from 0 (none) to 9 (best)
+    Gzip { level: u32 },
+
+    /// Levels range from 1 to 22, 0 selecting the zstd default
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    /// Gzip with the default level of 6
+    pub fn gzip() -> Self {
+        Compression::Gzip { level: 6 }
+    }
+
+    /// Zstd with the default level of 3
+    pub fn zstd() -> Self {
+        Compression::Zstd { level: 3 }
+    }
+
+    /// The content encoding compressed blobs are stored with
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            Compression::Gzip { .. } => Encoding::Gzip,
+            Compression::Zstd { .. } => Encoding::Zstd,
+        }
+    }
+
+    /// The bytes content compressed with the algorithm starts with
+    fn magic(&self) -> &'static [u8] {
+        match self {
+            Compression::Gzip { .. } => b"\x1f\x8b",
+            Compression::Zstd { .. } => b"\x28\xb5\x2f\xfd",
+        }
+    }
+
+    fn encoder(&self) -> io::Result<Encoder> {
+        Ok(match *self {
+            Compression::Gzip { level } => Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level.min(9)),
+            )),
+            Compression::Zstd { level } => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level)?)
+            }
+        })
+    }
+}
+
+/// Streaming compression of content
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    /// Compresses a chunk, returning the content compressed so far
+    fn push(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let compressed = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(std::mem::take(compressed))
+    }
+
+    /// Returns the rest of the compressed content
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Provider wrapper compressing blobs on store and decompressing them on get,
+/// streaming so that blobs are never buffered whole.
+///
+/// Blobs are stored with their content encoding set to the compression algorithm,
+/// so that HTTP clients downloading them directly can decompress them too.
+/// Blobs that already have a content encoding are stored as they are, and blobs
+/// that were not compressed by the wrapper are returned as they are.
+///
+/// The wrapped provider must keep the content encoding and custom metadata of blobs,
+/// which the uncompressed size is stored in. Blobs read back without them, but starting
+/// like content compressed with the configured algorithm, fail with
+/// [`Error::ConfigError`] instead of being returned compressed.
+///
+/// Compressed blobs are stored through [`Provider::open_writer`], as their compressed
+/// size is not known upfront, except for conditional stores, which buffer the compressed
+/// content within the configured [`MemoryBudget`]. Ranged reads decompress the blob from its start, and
+/// listed sizes are the compressed sizes.
+#[derive(Debug)]
+pub struct CompressedProvider<P> {
+    inner: P,
+    compression: Compression,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> CompressedProvider<P> {
+    pub fn new(inner: P, compression: Compression) -> Self {
+        Self {
+            inner,
+            compression,
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers conditionally stored blobs within the given budget instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The metadata a blob is stored with once compressed
+    fn compressed_metadata(&self, blob: &Blob) -> BlobMetadata {
+        let mut metadata = BlobMetadata {
+            content_encoding: Some(self.compression.encoding().name().to_string()),
+            ..blob.metadata().clone()
+        };
+        metadata
+            .custom
+            .insert(UNCOMPRESSED_SIZE.to_string(), blob.size().to_string());
+        metadata
+    }
+
+    /// Returns a blob stored without compression metadata, after checking that it was
+    /// not compressed by a wrapper whose provider then dropped the metadata
+    async fn uncompressed(&self, blob: Blob) -> Result<Blob> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        let mut content = blob.into_byte_stream();
+        let first = content
+            .next()
+            .await
+            .transpose()
+            .map_err(Error::body_error)?;
+        if first
+            .as_ref()
+            .is_some_and(|chunk| chunk.starts_with(self.compression.magic()))
+        {
+            return Err(Error::config(format!(
+                "blob {} looks compressed, but was returned without its compression metadata",
+                key
+            )));
+        }
+        let content = stream::iter(first.map(Ok)).chain(content);
+        Ok(Blob::new(key, size, content).with_metadata(metadata))
+    }
+}
+
+/// Compresses content as it is streamed
+fn compressing<S>(encoder: Encoder, content: S) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    stream::unfold(
+        (content, Some(encoder)),
+        |(mut content, mut encoder)| async move {
+            // the stream ends once the encoder is finished
+            let encoding = encoder.as_mut()?;
+            let compressed = match content.next().await {
+                Some(Ok(chunk)) => encoding.push(&chunk),
+                Some(Err(err)) => Err(err),
+                None => encoder.take()?.finish(),
+            };
+            Some((compressed.map(Bytes::from), (content, encoder)))
+        },
+    )
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for CompressedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let blob = match self.inner.get_blob(key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let size = match blob.metadata().custom.get(UNCOMPRESSED_SIZE) {
+            Some(size) => size.parse().map_err(Error::body_error)?,
+            None if blob.metadata().content_encoding.is_some() => return Ok(Some(blob)),
+            None => return self.uncompressed(blob).await.map(Some),
+        };
+        let mut blob = decode_blob_sized(blob, size)?;
+        blob.metadata_mut().custom.remove(UNCOMPRESSED_SIZE);
+        Ok(Some(blob))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        if blob.metadata().content_encoding.is_some() {
+            return self.inner.store_blob(blob).await;
+        }
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = self.compressed_metadata(&blob);
+
+        let mut encoder = self.compression.encoder().map_err(Error::body_error)?;
+        let mut writer = self.inner.open_writer(&key, metadata);
+        let content = blob.into_byte_stream();
+        futures::pin_mut!(content);
+        // content errors abandon the writer, while write errors are those of the store
+        // the writer reports when finished
+        let mut written = Ok(());
+        while let Some(chunk) = content.next().await {
+            let chunk = chunk.map_err(Error::body_error)?;
+            let compressed = encoder.push(&chunk).map_err(Error::body_error)?;
+            written = writer.write_all(&compressed).await;
+            if written.is_err() {
+                break;
+            }
+        }
+        if written.is_ok() {
+            let rest = encoder.finish().map_err(Error::body_error)?;
+            written = writer.write_all(&rest).await;
+        }
+        let receipt = writer.finish().await?;
+        written.map_err(Error::body_error)?;
+        Ok(StoreReceipt { size, ..receipt })
+    }
+
+    /// Writers cannot store conditionally, so the compressed content is buffered instead
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        if blob.metadata().content_encoding.is_some() {
+            return self.inner.store_blob_if_absent(blob).await;
+        }
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = self.compressed_metadata(&blob);
+
+        let encoder = self.compression.encoder().map_err(Error::body_error)?;
+        let compressed = self
+            .budget
+            .buffer_stream(compressing(encoder, blob.into_byte_stream()))
+            .await?;
+        let receipt = self
+            .inner
+            .store_blob_if_absent(compressed.into_blob(key).with_metadata(metadata))
+            .await?;
+        Ok(StoreReceipt { size, ..receipt })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.inner.copy_blob(src_key, dst_key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "compression"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::metadata::BlobMetadata;
+    use crate::middleware::compression::{CompressedProvider, Compression};
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider only keeping the content of blobs, like hold-fs
+    #[derive(Debug, Default)]
+    struct ContentOnly {
+        inner: MemoryProvider,
+    }
+
+    #[async_trait]
+    impl Provider for ContentOnly {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            self.inner.get_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            let blob = blob.with_metadata(BlobMetadata::default());
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_compresses_blobs_transparently() {
+        let content = b"hello hello hello hello hello".repeat(100);
+        for compression in &[Compression::gzip(), Compression::Zstd { level: 19 }] {
+            let provider = CompressedProvider::new(MemoryProvider::new(), *compression);
+            block_on(async {
+                let receipt = provider
+                    .store_blob(Blob::from_bytes("key", content.clone()))
+                    .await
+                    .unwrap();
+                assert_eq!(receipt.size, content.len());
+
+                let stored = provider.inner().get_blob("key").await.unwrap().unwrap();
+                assert!(stored.size() < content.len() / 10);
+                assert_eq!(
+                    stored.metadata().content_encoding.as_deref(),
+                    Some(compression.encoding().name())
+                );
+
+                let blob = provider.get_blob("key").await.unwrap().unwrap();
+                assert_eq!(blob.size(), content.len());
+                assert!(blob.metadata().content_encoding.is_none());
+                assert!(blob.metadata().custom.is_empty());
+                assert_eq!(blob.read_content().await.unwrap(), content);
+
+                let range = provider.get_blob_range("key", 6..11).await.unwrap();
+                assert_eq!(range.unwrap().read_content().await.unwrap(), b"hello");
+            });
+        }
+    }
+
+    #[test]
+    fn it_fails_reading_blobs_whose_compression_metadata_was_dropped() {
+        let provider = CompressedProvider::new(ContentOnly::default(), Compression::zstd());
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"hello".repeat(100)))
+                .await
+                .unwrap();
+            let err = provider.get_blob("key").await.unwrap_err();
+            assert_eq!(err.code(), "config_error");
+
+            // blobs the wrapper did not compress are still returned as they are
+            provider
+                .inner()
+                .store_blob(Blob::from_bytes("plain", b"hello".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("plain").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"hello");
+        });
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::future;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Weight of each request in the moving average of latencies
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Configuration of the AIMD algorithm used by [`AdaptiveConcurrencyProvider`]
+#[derive(Debug, Clone)]
+pub struct AimdConfig {
+    pub initial_limit: usize,
+    pub min_limit: usize,
+    pub max_limit: usize,
+
+    /// Requests added to the limit for each full window of successful requests
+    pub increase: f64,
+
+    /// Factor the limit is multiplied by when congestion is detected
+    pub backoff_ratio: f64,
+
+    /// Successful requests slower than this are considered a congestion signal
+    pub latency_threshold: Option<Duration>,
+
+    /// Requests allowed to wait for a slot, beyond which requests are shed with
+    /// [`Error::Overloaded`] rather than queued. Unbounded if not set.
+    pub max_queued: Option<usize>,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            initial_limit: 16,
+            min_limit: 1,
+            max_limit: 1024,
+            increase: 1.0,
+            backoff_ratio: 0.9,
+            latency_threshold: None,
+            max_queued: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+    queued: usize,
+    waiters: VecDeque<Waker>,
+
+    /// Moving average of the latency of requests
+    latency: Option<Duration>,
+}
+
+/// Provider wrapper limiting the number of in-flight requests, tuning the limit with
+/// additive-increase/multiplicative-decrease based on observed latencies and errors.
+///
+/// The limit grows while requests succeed, and shrinks on provider, rate limiting and
+/// timeout errors, or on requests slower than the latency threshold.
+/// Requests over the limit wait for a slot to be released, unless
+/// [`AimdConfig::max_queued`] requests are already waiting: they then fail right away
+/// with [`Error::Overloaded`], hinting to retry after the average latency of requests.
+#[derive(Debug)]
+pub struct AdaptiveConcurrencyProvider<P> {
+    inner: P,
+    config: AimdConfig,
+    state: Mutex<State>,
+}
+
+impl<P: Provider + Send + Sync> AdaptiveConcurrencyProvider<P> {
+    pub fn new(inner: P, config: AimdConfig) -> Self {
+        let limit = config
+            .initial_limit
+            .clamp(config.min_limit.max(1), config.max_limit.max(1));
+        Self {
+            inner,
+            config,
+            state: Mutex::new(State {
+                limit: limit as f64,
+                in_flight: 0,
+                queued: 0,
+                waiters: VecDeque::new(),
+                latency: None,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The current in-flight requests limit
+    pub fn current_limit(&self) -> usize {
+        self.state().limit as usize
+    }
+
+    /// The number of requests currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.state().in_flight
+    }
+
+    /// The number of requests currently waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.state().queued
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    async fn limited<T, F: Future<Output = Result<T>>>(&self, op: F) -> Result<T> {
+        let _permit = self.acquire().await?;
+        let started = Instant::now();
+        let result = op.await;
+        self.record(&result, started.elapsed());
+        result
+    }
+
+    async fn acquire(&self) -> Result<Permit<'_, P>> {
+        let _queued = {
+            let mut state = self.state();
+            if (state.in_flight as f64) < state.limit.floor() {
+                state.in_flight += 1;
+                return Ok(Permit { provider: self });
+            }
+            if let Some(max_queued) = self.config.max_queued {
+                if state.queued >= max_queued {
+                    return Err(Error::overloaded(
+                        format!(
+                            "{} requests in flight and {} queued",
+                            state.in_flight, state.queued
+                        ),
+                        state.latency,
+                    ));
+                }
+            }
+            state.queued += 1;
+            Queued { provider: self }
+        };
+
+        future::poll_fn(|cx| {
+            let mut state = self.state();
+            if (state.in_flight as f64) < state.limit.floor() {
+                state.in_flight += 1;
+                Poll::Ready(())
+            } else {
+                state.waiters.push_back(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+        Ok(Permit { provider: self })
+    }
+
+    fn record<T>(&self, result: &Result<T>, latency: Duration) {
+        let congested = match result {
+            Err(
+                Error::ProviderError { .. } | Error::RateLimited { .. } | Error::Timeout { .. },
+            ) => true,
+            Err(_) => false,
+            Ok(_) => self
+                .config
+                .latency_threshold
+                .is_some_and(|threshold| latency > threshold),
+        };
+
+        let mut state = self.state();
+        state.latency = Some(match state.latency {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT)
+            }
+            None => latency,
+        });
+        let limit = if congested {
+            state.limit * self.config.backoff_ratio
+        } else {
+            state.limit + self.config.increase / state.limit
+        };
+        state.limit = limit.clamp(
+            self.config.min_limit.max(1) as f64,
+            self.config.max_limit.max(1) as f64,
+        );
+    }
+
+    fn release(&self) {
+        let mut state = self.state();
+        state.in_flight -= 1;
+        // waiters may have given up, so wake them all rather than risking a lost wake-up
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// A request waiting for a slot, no longer counted once it gets one or gives up
+struct Queued<'a, P: Provider + Send + Sync> {
+    provider: &'a AdaptiveConcurrencyProvider<P>,
+}
+
+impl<P: Provider + Send + Sync> Drop for Queued<'_, P> {
+    fn drop(&mut self) {
+        self.provider.state().queued -= 1;
+    }
+}
+
+struct Permit<'a, P: Provider + Send + Sync> {
+    provider: &'a AdaptiveConcurrencyProvider<P>,
+}
+
+impl<P: Provider + Send + Sync> Drop for Permit<'_, P> {
+    fn drop(&mut self) {
+        self.provider.release();
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for AdaptiveConcurrencyProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.limited(self.inner.get_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.limited(self.inner.get_blob_range(key, range)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.limited(self.inner.get_blob_if_range(key, range, etag))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.limited(self.inner.head_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.limited(self.inner.store_blob(blob)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.limited(self.inner.store_blob_if_absent(blob)).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| {
+            self.limited(write_through(
+                content,
+                self.inner.open_writer(key, metadata),
+            ))
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.limited(self.inner.is_blob_present(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.limited(self.inner.delete_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.limited(self.inner.copy_blob(src_key, dst_key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.limited(
+            self.inner
+                .copy_blob_with_metadata(src_key, dst_key, metadata),
+        )
+        .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "concurrency"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::concurrency::{AdaptiveConcurrencyProvider, AimdConfig};
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_sheds_requests_over_the_queue_limit() {
+        let provider = AdaptiveConcurrencyProvider::new(
+            MemoryProvider::new(),
+            AimdConfig {
+                initial_limit: 1,
+                max_limit: 1,
+                max_queued: Some(0),
+                ..AimdConfig::default()
+            },
+        );
+        block_on(async {
+            provider.get_blob("key").await.unwrap();
+            let permit = provider.acquire().await.unwrap();
+            let err = provider.get_blob("key").await.unwrap_err();
+            assert_eq!(err.code(), "overloaded");
+            assert!(err.is_retryable());
+            assert!(err.retry_after().is_some());
+            assert_eq!(provider.queued(), 0);
+
+            drop(permit);
+            provider.get_blob("key").await.unwrap();
+        });
+    }
+
+    fn aimd(initial_limit: usize, min_limit: usize) -> AdaptiveConcurrencyProvider<MemoryProvider> {
+        AdaptiveConcurrencyProvider::new(
+            MemoryProvider::new(),
+            AimdConfig {
+                initial_limit,
+                min_limit,
+                backoff_ratio: 0.5,
+                latency_threshold: Some(Duration::from_millis(100)),
+                ..AimdConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn it_grows_the_limit_by_one_per_window_of_successes() {
+        let provider = aimd(4, 1);
+        for _ in 0..4 {
+            provider.record(&Ok(()), Duration::from_millis(1));
+        }
+        // each success adds a fraction of a request, a full window adding about one
+        let limit = provider.state().limit;
+        assert!(limit > 4.9 && limit < 5.0, "{}", limit);
+        provider.record(&Ok(()), Duration::from_millis(1));
+        assert_eq!(provider.current_limit(), 5);
+    }
+
+    #[test]
+    fn it_backs_off_on_congestion() {
+        let provider = aimd(16, 1);
+        provider.record::<()>(&Err(Error::rate_limited("slow down", None)), Duration::ZERO);
+        assert_eq!(provider.current_limit(), 8);
+        provider.record::<()>(&Err(Error::timeout("no response")), Duration::ZERO);
+        assert_eq!(provider.current_limit(), 4);
+        provider.record(&Ok(()), Duration::from_millis(200));
+        assert_eq!(provider.current_limit(), 2);
+
+        // other errors are not congestion signals
+        provider.record::<()>(&Err(Error::precondition_failed("key")), Duration::ZERO);
+        assert_eq!(provider.current_limit(), 2);
+    }
+
+    #[test]
+    fn it_never_backs_off_below_the_minimum_limit() {
+        let provider = aimd(4, 3);
+        for _ in 0..10 {
+            provider.record::<()>(&Err(Error::timeout("no response")), Duration::ZERO);
+        }
+        assert_eq!(provider.current_limit(), 3);
+    }
+}
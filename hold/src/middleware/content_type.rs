@@ -0,0 +1,282 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::provider::{put_blob, EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Bytes of content inspected to sniff its type
+const SNIFF_LEN: usize = 512;
+
+/// Allowed and denied content types, matching either exact types (`image/png`)
+/// or whole families (`image/*`).
+/// Denied types take precedence, and an empty allowlist allows everything not denied.
+#[derive(Debug, Clone, Default)]
+pub struct ContentTypePolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl ContentTypePolicy {
+    pub fn allow<T: ToString>(types: &[T]) -> Self {
+        Self {
+            allow: types.iter().map(ToString::to_string).collect(),
+            deny: Vec::new(),
+        }
+    }
+
+    pub fn deny<T: ToString>(types: &[T]) -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: types.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    pub fn is_allowed(&self, content_type: &str) -> bool {
+        let matches = |pattern: &String| matches_type(pattern, content_type);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+fn matches_type(pattern: &str, content_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(family) => content_type
+            .split('/')
+            .next()
+            .is_some_and(|f| f.eq_ignore_ascii_case(family)),
+        None => pattern == "*" || pattern.eq_ignore_ascii_case(content_type),
+    }
+}
+
+/// Provider wrapper enforcing a [`ContentTypePolicy`] on stored blobs.
+///
+/// The content type is sniffed from the first bytes of the content, so that a blob
+/// cannot pass as an image while being an executable.
+/// The declared `content_type` of the blob metadata, without its parameters,
+/// must be allowed as well, so that it cannot be served as a denied type either.
+/// Rejected blobs fail with [`Error::ContentTypeRejected`] before reaching the wrapped provider.
+#[derive(Debug)]
+pub struct ContentTypeProvider<P> {
+    inner: P,
+    policy: ContentTypePolicy,
+}
+
+impl<P: Provider + Send + Sync> ContentTypeProvider<P> {
+    pub fn new(inner: P, policy: ContentTypePolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Stores a blob, conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        if let Some(declared) = &metadata.content_type {
+            let essence = declared.split(';').next().unwrap_or_default().trim();
+            if !self.policy.is_allowed(essence) {
+                return Err(Error::content_type_rejected(key, declared));
+            }
+        }
+        let mut stream = blob.into_byte_stream();
+
+        let mut head: Vec<Bytes> = Vec::new();
+        let mut head_len = 0;
+        while head_len < SNIFF_LEN {
+            match stream.next().await {
+                Some(chunk) => {
+                    let chunk = chunk.map_err(Error::body_error)?;
+                    head_len += chunk.len();
+                    head.push(chunk);
+                }
+                None => break,
+            }
+        }
+
+        let sniffed: Vec<u8> = head
+            .iter()
+            .flat_map(|chunk| chunk.iter().copied())
+            .collect();
+        let content_type = sniff(&sniffed);
+        if !self.policy.is_allowed(content_type) {
+            return Err(Error::content_type_rejected(key, content_type));
+        }
+
+        let content = stream::iter(head.into_iter().map(Ok)).chain(stream);
+        let blob = Blob::new(key, size, content).with_metadata(metadata);
+        put_blob(&self.inner, blob, if_absent).await
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for ContentTypeProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "content_type"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+/// Sniffs the content type from the magic bytes at the start of the content
+pub fn sniff(content: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-executable"),
+        (b"MZ", "application/x-msdownload"),
+        (b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+        (b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+        (b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+        (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+        (b"#!", "text/x-shellscript"),
+    ];
+    if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    for (signature, content_type) in SIGNATURES {
+        if content.starts_with(signature) {
+            return content_type;
+        }
+    }
+    match std::str::from_utf8(content) {
+        Ok(_) => "text/plain",
+        // the sniffed head may end in the middle of a character
+        Err(err) if err.error_len().is_none() => "text/plain",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::middleware::content_type::{sniff, ContentTypePolicy};
+
+    #[cfg(feature = "memory")]
+    #[test]
+    fn it_rejects_denied_declared_content_types() {
+        use futures::executor::block_on;
+
+        use crate::blob::Blob;
+        use crate::error::Error;
+        use crate::memory::MemoryProvider;
+        use crate::metadata::BlobMetadata;
+        use crate::middleware::content_type::ContentTypeProvider;
+        use crate::provider::Provider;
+
+        let provider = ContentTypeProvider::new(
+            MemoryProvider::new(),
+            ContentTypePolicy::deny(&["text/html"]),
+        );
+        let blob = |content_type: &str| {
+            Blob::from_bytes("page", b"<script>alert(1)</script>".to_vec())
+                .with_metadata(BlobMetadata::default().with_content_type(content_type))
+        };
+        block_on(async {
+            // sniffed as text/plain, but declared as a denied type
+            let err = provider
+                .store_blob(blob("text/html; charset=utf-8"))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::ContentTypeRejected { .. }));
+            assert!(!provider.is_blob_present("page").await.unwrap());
+
+            provider.store_blob(blob("text/plain")).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn it_sniffs_content_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"\x7fELF\x02\x01\x01"), "application/x-executable");
+        assert_eq!(sniff("hello wörld".as_bytes()), "text/plain");
+        assert_eq!(sniff(b"\0\x01\x02\xff"), "application/octet-stream");
+    }
+
+    #[test]
+    fn it_applies_allow_and_deny_lists() {
+        let policy = ContentTypePolicy::allow(&["image/*", "application/pdf"]);
+        assert!(policy.is_allowed("image/png"));
+        assert!(policy.is_allowed("application/pdf"));
+        assert!(!policy.is_allowed("application/x-executable"));
+
+        let policy = ContentTypePolicy {
+            deny: vec!["image/gif".to_string()],
+            ..policy
+        };
+        assert!(!policy.is_allowed("image/gif"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures::{future, StreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::deadline::Deadline;
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Provider wrapper bounding every operation by the deadline of the caller.
+///
+/// It is meant to be built for each request, usually around a reference to a shared
+/// provider: `DeadlineProvider::new(&provider, deadline)`.
+/// Operations fail with [`Error::DeadlineExceeded`] once the deadline is exceeded,
+/// without reaching the wrapped provider. Streamed content and listings fail
+/// on the first chunk or entry read past the deadline.
+#[derive(Debug)]
+pub struct DeadlineProvider<P> {
+    inner: P,
+    deadline: Deadline,
+}
+
+impl<P: Provider + Send + Sync> DeadlineProvider<P> {
+    pub fn new(inner: P, deadline: Deadline) -> Self {
+        Self { inner, deadline }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+
+    /// Fails the content stream of a blob once the deadline is exceeded
+    fn bounded(&self, blob: Blob) -> Blob {
+        let deadline = self.deadline;
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        let stream = blob.into_byte_stream().map(move |chunk| {
+            if deadline.is_exceeded() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    Error::deadline_exceeded().to_string(),
+                ));
+            }
+            chunk
+        });
+        Blob::new(key, size, stream).with_metadata(metadata)
+    }
+
+    /// Fails a listing once the deadline is exceeded
+    fn bounded_entries<'a>(&self, entries: EntryStream<'a>) -> EntryStream<'a> {
+        let deadline = self.deadline;
+        let entries = entries.scan(false, move |exceeded, entry| {
+            if *exceeded {
+                return future::ready(None);
+            }
+            if deadline.is_exceeded() {
+                *exceeded = true;
+                return future::ready(Some(Err(Error::deadline_exceeded())));
+            }
+            future::ready(Some(entry))
+        });
+        Box::pin(entries)
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for DeadlineProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let blob = self.deadline.run(self.inner.get_blob(key)).await?;
+        Ok(blob.map(|blob| self.bounded(blob)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        let blob = self
+            .deadline
+            .run(self.inner.get_blob_range(key, range))
+            .await?;
+        Ok(blob.map(|blob| self.bounded(blob)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let read = self
+            .deadline
+            .run(self.inner.get_blob_if_range(key, range, etag))
+            .await?;
+        Ok(read.map(|read| read.map(|blob| self.bounded(blob))))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.deadline.run(self.inner.head_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.deadline.run(self.inner.store_blob(blob)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.deadline
+            .run(self.inner.store_blob_if_absent(blob))
+            .await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| {
+            self.deadline.run(write_through(
+                content,
+                self.inner.open_writer(key, metadata),
+            ))
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.deadline.run(self.inner.is_blob_present(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.deadline.run(self.inner.delete_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.deadline
+            .run(self.inner.copy_blob(src_key, dst_key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.deadline
+            .run(
+                self.inner
+                    .copy_blob_with_metadata(src_key, dst_key, metadata),
+            )
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.bounded_entries(self.inner.list_blobs(prefix))
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.bounded_entries(self.inner.list_blobs_with_options(prefix, options))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.deadline
+            .run(self.inner.list_page(prefix, cursor, limit))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "deadline"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.deadline.run(self.inner.warm_up(keys)).await
+    }
+}
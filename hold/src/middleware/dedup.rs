@@ -0,0 +1,353 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+
+use crate::blob::{etag_matches, Blob, BlobEntry, RangeRead};
+use crate::budget::MemoryBudget;
+use crate::digest::{to_hex, DigestAlgorithm};
+use crate::error::Error;
+use crate::listing::{Cursor, Page};
+use crate::metadata::BlobMetadata;
+use crate::middleware::prefix::PrefixedProvider;
+use crate::provider::{put_blob, EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Key prefix of the blobs mapping keys to content hashes
+const REFS_PREFIX: &str = "refs";
+
+/// Key prefix of the content, stored under its hash
+const CONTENT_PREFIX: &str = "content";
+
+/// Reference from a key to the content it holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pointer {
+    hash: String,
+    size: usize,
+}
+
+impl Pointer {
+    fn encode(&self) -> Vec<u8> {
+        format!("{}\t{}\n", self.hash, self.size).into_bytes()
+    }
+
+    fn decode(key: &str, content: &[u8]) -> Result<Self> {
+        let invalid = || Error::body_error(format!("invalid content reference for {}", key));
+        let content = std::str::from_utf8(content).map_err(|_| invalid())?;
+        let (hash, size) = content.trim_end().split_once('\t').ok_or_else(invalid)?;
+        Ok(Self {
+            hash: hash.to_string(),
+            size: size.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Provider wrapper storing identical content once, for stores where the same
+/// payloads are uploaded under many keys, such as build artifacts across versions.
+///
+/// Content is stored in the wrapped provider under its SHA-256 hash, below
+/// `content/`, and each key is a small reference to a hash, below `refs/`.
+/// Content already stored under the same hash is not uploaded again, and copies
+/// only write a new reference. Fetched blobs carry the hex hash as entity tag.
+///
+/// Blobs are buffered while being hashed, within the configured [`MemoryBudget`].
+/// Deleting a blob only deletes its reference: content no longer referenced is
+/// deleted by [`DedupProvider::collect_garbage`].
+#[derive(Debug)]
+pub struct DedupProvider<P> {
+    refs: PrefixedProvider<Arc<P>>,
+    contents: PrefixedProvider<Arc<P>>,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> DedupProvider<P> {
+    pub fn new(inner: P) -> Self {
+        let inner = Arc::new(inner);
+        Self {
+            refs: PrefixedProvider::new(inner.clone(), REFS_PREFIX),
+            contents: PrefixedProvider::new(inner, CONTENT_PREFIX),
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers blobs within the given budget while hashing them instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        self.refs.inner()
+    }
+
+    /// Deletes the content no blob refers to anymore, returning how many were deleted.
+    /// Content stored concurrently may not be referenced yet, so blobs must not be
+    /// stored or copied while garbage is collected.
+    pub async fn collect_garbage(&self) -> Result<usize> {
+        let referenced: HashSet<String> = self
+            .refs
+            .list_blobs("")
+            .try_filter_map(|entry| async move {
+                let pointer = self.pointer(&entry.key).await?;
+                Ok(pointer.map(|(pointer, _)| pointer.hash))
+            })
+            .try_collect()
+            .await?;
+        let unreferenced: Vec<BlobEntry> = self
+            .contents
+            .list_blobs("")
+            .try_filter(|entry| future::ready(!referenced.contains(&entry.key)))
+            .try_collect()
+            .await?;
+        for entry in &unreferenced {
+            self.contents.delete_blob(&entry.key).await?;
+        }
+        Ok(unreferenced.len())
+    }
+
+    /// The content reference of a key, with the metadata of the blob
+    async fn pointer(&self, key: &str) -> Result<Option<(Pointer, BlobMetadata)>> {
+        let blob = match self.refs.get_blob(key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let mut metadata = blob.metadata().clone();
+        let pointer = Pointer::decode(key, &blob.read_content().await?)?;
+        metadata.etag = Some(pointer.hash.clone());
+        Ok(Some((pointer, metadata)))
+    }
+
+    async fn store_pointer(
+        &self,
+        key: &str,
+        pointer: &Pointer,
+        metadata: BlobMetadata,
+        if_absent: bool,
+    ) -> Result<StoreReceipt> {
+        let reference = Blob::from_bytes(key, pointer.encode()).with_metadata(metadata);
+        let receipt = put_blob(&self.refs, reference, if_absent).await?;
+        Ok(StoreReceipt {
+            size: pointer.size,
+            etag: Some(pointer.hash.clone()),
+            ..receipt
+        })
+    }
+
+    /// Stores the content of a blob unless it is already stored, then its reference,
+    /// conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let metadata = blob.metadata().clone();
+        let content = self.budget.buffer(blob).await?;
+        let hash = DigestAlgorithm::Sha256
+            .digest_reader(&mut content.reader()?)
+            .map_err(Error::body_error)?;
+        let pointer = Pointer {
+            hash: to_hex(&hash),
+            size: content.len(),
+        };
+
+        if !self.contents.is_blob_present(&pointer.hash).await? {
+            self.contents
+                .store_blob(content.into_blob(&pointer.hash))
+                .await?;
+        }
+        self.store_pointer(&key, &pointer, metadata, if_absent)
+            .await
+    }
+
+    /// The content a key refers to, or the given range of it
+    async fn content(
+        &self,
+        key: &str,
+        pointer: &Pointer,
+        metadata: BlobMetadata,
+        range: Option<Range<usize>>,
+    ) -> Result<Blob> {
+        let content = match range {
+            Some(range) => self.contents.get_blob_range(&pointer.hash, range).await?,
+            None => self.contents.get_blob(&pointer.hash).await?,
+        };
+        let content = content.ok_or_else(|| {
+            Error::body_error(format!("content {} of {} is missing", pointer.hash, key))
+        })?;
+        Ok(Blob::new(key, content.size(), content.into_byte_stream()).with_metadata(metadata))
+    }
+
+    /// The entry of a listed reference with the size of its content
+    async fn entry(&self, entry: BlobEntry) -> Result<Option<BlobEntry>> {
+        let pointer = self.pointer(&entry.key).await?;
+        Ok(pointer.map(|(pointer, _)| BlobEntry {
+            size: pointer.size,
+            ..entry
+        }))
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for DedupProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        match self.pointer(key).await? {
+            Some((pointer, metadata)) => {
+                self.content(key, &pointer, metadata, None).await.map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        match self.pointer(key).await? {
+            Some((pointer, metadata)) => self
+                .content(key, &pointer, metadata, Some(range))
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let (pointer, metadata) = match self.pointer(key).await? {
+            Some(pointer) => pointer,
+            None => return Ok(None),
+        };
+        let read = if etag_matches(Some(&pointer.hash), etag) {
+            RangeRead::Range(self.content(key, &pointer, metadata, Some(range)).await?)
+        } else {
+            RangeRead::Full(self.content(key, &pointer, metadata, None).await?)
+        };
+        Ok(Some(read))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.refs.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.refs.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        match self.pointer(src_key).await? {
+            Some((pointer, metadata)) => self
+                .store_pointer(dst_key, &pointer, metadata, false)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        match self.pointer(src_key).await? {
+            Some((pointer, _)) => self
+                .store_pointer(dst_key, &pointer, metadata, false)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        // blobs deleted while being listed are skipped
+        Box::pin(
+            self.refs
+                .list_blobs(prefix)
+                .and_then(move |entry| self.entry(entry))
+                .try_filter_map(future::ok),
+        )
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let page = self.refs.list_page(prefix, cursor, limit).await?;
+        let entries =
+            future::try_join_all(page.entries.into_iter().map(|entry| self.entry(entry))).await?;
+        Ok(Page {
+            entries: entries.into_iter().flatten().collect(),
+            next: page.next,
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dedup"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.refs.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::dedup::DedupProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_stores_identical_content_once() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = DedupProvider::new(inner.clone());
+        block_on(async {
+            for (key, content) in &[
+                ("v1/app", "build"),
+                ("v2/app", "build"),
+                ("v3/app", "other"),
+            ] {
+                let receipt = provider
+                    .store_blob(Blob::from_bytes(*key, content.as_bytes().to_vec()))
+                    .await
+                    .unwrap();
+                assert_eq!(receipt.key, *key);
+                assert_eq!(receipt.size, content.len());
+            }
+            provider.copy_blob("v2/app", "v4/app").await.unwrap();
+
+            let contents: Vec<_> = inner.list_blobs("content/").try_collect().await.unwrap();
+            assert_eq!(contents.len(), 2);
+            let blob = provider.get_blob("v4/app").await.unwrap().unwrap();
+            assert_eq!(blob.key(), "v4/app");
+            assert_eq!(blob.read_content().await.unwrap(), b"build");
+            let entries: Vec<_> = provider.list_blobs("v").try_collect().await.unwrap();
+            assert_eq!(entries.len(), 4);
+            assert!(entries.iter().all(|entry| entry.size == 5));
+
+            for key in &["v1/app", "v2/app", "v3/app"] {
+                provider.delete_blob(key).await.unwrap();
+            }
+            assert_eq!(provider.collect_garbage().await.unwrap(), 1);
+            assert!(provider.get_blob("v4/app").await.unwrap().is_some());
+        });
+    }
+}
@@ -0,0 +1,238 @@
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::BlobWriter;
+use crate::Result;
+
+/// A write a [`DryRunProvider`] skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DryRunAction {
+    Store { key: String, size: usize },
+    Delete { key: String },
+    Copy { src_key: String, dst_key: String },
+}
+
+/// Provider wrapper previewing writes instead of executing them.
+///
+/// Stores, deletes and copies are logged and collected, so that the intended actions
+/// of a cleanup job can be reviewed before running it for real. Reads go through,
+/// so they do not see the skipped writes. Stores and copies return the receipt the
+/// write would have had, without anything assigned by the backend.
+#[derive(Debug)]
+pub struct DryRunProvider<P> {
+    inner: P,
+    actions: Mutex<Vec<DryRunAction>>,
+}
+
+impl<P: Provider + Send + Sync> DryRunProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            actions: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The actions skipped so far
+    pub fn actions(&self) -> Vec<DryRunAction> {
+        self.lock().clone()
+    }
+
+    /// The actions skipped so far, clearing them
+    pub fn take_actions(&self) -> Vec<DryRunAction> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<DryRunAction>> {
+        self.actions.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    async fn copy(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let source = match self.inner.get_blob(src_key).await? {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+        log::info!("Dry run: would copy blob {} to {}", src_key, dst_key);
+        self.lock().push(DryRunAction::Copy {
+            src_key: src_key.to_string(),
+            dst_key: dst_key.to_string(),
+        });
+        Ok(Some(StoreReceipt::new(dst_key, source.size())))
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for DryRunProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        log::info!("Dry run: would store blob {}", blob.key());
+        self.lock().push(DryRunAction::Store {
+            key: blob.key().to_string(),
+            size: blob.size(),
+        });
+        Ok(StoreReceipt::new(blob.key(), blob.size()))
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, _metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            // the content is only read to tell its size
+            let size = content
+                .try_fold(0, |size, chunk| future::ready(Ok(size + chunk.len())))
+                .await
+                .map_err(Error::body_error)?;
+            log::info!("Dry run: would store blob {}", key);
+            self.lock().push(DryRunAction::Store {
+                key: key.to_string(),
+                size,
+            });
+            Ok(StoreReceipt::new(key, size))
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        log::info!("Dry run: would delete blob {}", key);
+        self.lock().push(DryRunAction::Delete {
+            key: key.to_string(),
+        });
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.copy(src_key, dst_key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        _metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.copy(src_key, dst_key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "dry_run"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::dry_run::{DryRunAction, DryRunProvider};
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_skips_writes_and_reads_through() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = DryRunProvider::new(inner.clone());
+        block_on(async {
+            inner
+                .store_blob(Blob::from_bytes("old", b"content".to_vec()))
+                .await
+                .unwrap();
+
+            let receipt = provider
+                .store_blob(Blob::from_bytes("new", b"abc".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(receipt.size, 3);
+            provider.delete_blob("old").await.unwrap();
+            let copy = provider.copy_blob("old", "copy").await.unwrap().unwrap();
+            assert_eq!(copy.size, 7);
+            assert!(provider
+                .copy_blob("missing", "copy")
+                .await
+                .unwrap()
+                .is_none());
+            assert_eq!(inner.keys(), vec!["old"]);
+
+            let blob = provider.get_blob("old").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content");
+            assert_eq!(
+                provider.take_actions(),
+                vec![
+                    DryRunAction::Store {
+                        key: "new".to_string(),
+                        size: 3
+                    },
+                    DryRunAction::Delete {
+                        key: "old".to_string()
+                    },
+                    DryRunAction::Copy {
+                        src_key: "old".to_string(),
+                        dst_key: "copy".to_string()
+                    },
+                ]
+            );
+            assert!(provider.actions().is_empty());
+        });
+    }
+}
@@ -0,0 +1,769 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::write::{GzDecoder, GzEncoder};
+use flate2::Compression;
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::budget::MemoryBudget;
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// A content encoding a blob variant can be stored with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// The encoding token, as used in `Accept-Encoding` and `Content-Encoding` headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// Parses an encoding token, as used in `Content-Encoding` headers
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(Encoding::Identity),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "br" => Some(Encoding::Brotli),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Derives the key the variant with this encoding is stored under
+    pub fn variant_key(&self, key: &str) -> String {
+        format!("{}{}", key, self.extension())
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "",
+            Encoding::Gzip => ".gz",
+            Encoding::Brotli => ".br",
+            Encoding::Zstd => ".zst",
+        }
+    }
+
+    /// Encodes the content read from a reader as it is streamed
+    fn encode<'a, R: Read + Send + 'a>(
+        &self,
+        content: R,
+    ) -> io::Result<impl Stream<Item = io::Result<Bytes>> + Send + 'a> {
+        let encoder = Encoder::new(*self)?;
+        Ok(stream::unfold(
+            (content, Some(encoder)),
+            |(mut content, encoder)| async move {
+                // the stream ends once the encoder is finished
+                let mut encoder = encoder?;
+                let mut chunk = vec![0; ENCODE_CHUNK_SIZE];
+                match read_chunk(&mut content, &mut chunk) {
+                    Ok(0) => Some((encoder.finish(), (content, None))),
+                    Ok(read) => {
+                        let encoded = encoder.push(&chunk[..read]);
+                        Some((encoded, (content, Some(encoder))))
+                    }
+                    Err(err) => Some((Err(err), (content, None))),
+                }
+            },
+        ))
+    }
+}
+
+/// Bytes of content read at once while encoding it
+const ENCODE_CHUNK_SIZE: usize = 64 * 1024;
+
+fn read_chunk<R: Read>(content: &mut R, chunk: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match content.read(chunk) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Streaming compression of content
+enum Encoder {
+    Identity,
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> io::Result<Self> {
+        Ok(match encoding {
+            Encoding::Identity => Encoder::Identity,
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::best())),
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                11,
+                22,
+            ))),
+            Encoding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 19)?),
+        })
+    }
+
+    /// Encodes a chunk, returning the content encoded so far
+    fn push(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let encoded = match self {
+            Encoder::Identity => return Ok(Bytes::copy_from_slice(chunk)),
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(encoded)))
+    }
+
+    /// Returns the rest of the encoded content
+    fn finish(self) -> io::Result<Bytes> {
+        let encoded = match self {
+            Encoder::Identity => Vec::new(),
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Brotli(mut encoder) => {
+                encoder.flush()?;
+                encoder.into_inner()
+            }
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(encoded))
+    }
+}
+
+/// Streaming decompression of encoded content
+enum Decoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Brotli(Box<brotli::DecompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::zio::Writer<Vec<u8>, zstd::stream::raw::Decoder<'static>>),
+}
+
+impl Decoder {
+    fn new(encoding: Encoding) -> io::Result<Option<Self>> {
+        Ok(match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some(Decoder::Gzip(GzDecoder::new(Vec::new()))),
+            Encoding::Brotli => Some(Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(
+                Vec::new(),
+                4096,
+            )))),
+            Encoding::Zstd => Some(Decoder::Zstd(zstd::stream::zio::Writer::new(
+                Vec::new(),
+                zstd::stream::raw::Decoder::new()?,
+            ))),
+        })
+    }
+
+    /// Decodes a chunk, returning the content decoded so far
+    fn push(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let decoded = match self {
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.get_mut()
+            }
+            Decoder::Brotli(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.get_mut()
+            }
+            Decoder::Zstd(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.writer_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(decoded)))
+    }
+
+    /// Returns the rest of the decoded content, failing if the content is truncated
+    fn finish(self) -> io::Result<Bytes> {
+        let decoded = match self {
+            Decoder::Gzip(decoder) => decoder.finish()?,
+            Decoder::Brotli(decoder) => decoder.into_inner().map_err(|_| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "truncated brotli content")
+            })?,
+            // fails unless the last frame is complete
+            Decoder::Zstd(mut decoder) => {
+                decoder.finish()?;
+                decoder.into_inner().0
+            }
+        };
+        Ok(Bytes::from(decoded))
+    }
+}
+
+/// Decompresses a blob stored with the content encoding its metadata tells,
+/// buffering the decoded content within the given budget so that the decoded blob
+/// has its decoded size. See [`decode_blob_sized`] to stream it when the size is known.
+/// Fails with [`Error::BodyError`] if the encoding is not supported, or if the
+/// content is corrupted or truncated.
+pub async fn decode_blob(blob: Blob, budget: &MemoryBudget) -> Result<Blob> {
+    if encoding_of(&blob)? == Encoding::Identity {
+        return Ok(blob);
+    }
+    let key = blob.key().to_string();
+    let decoded = decode_blob_sized(blob, 0)?;
+    let metadata = decoded.metadata().clone();
+    let buffered = budget.buffer_stream(decoded.into_byte_stream()).await?;
+    Ok(buffered.into_blob(key).with_metadata(metadata))
+}
+
+fn encoding_of(blob: &Blob) -> Result<Encoding> {
+    match blob.metadata().content_encoding.as_deref() {
+        Some(name) => Encoding::from_name(name)
+            .ok_or_else(|| Error::body_error(format!("unsupported content encoding {}", name))),
+        None => Ok(Encoding::Identity),
+    }
+}
+
+/// Decompresses a blob stored with the content encoding its metadata tells as it is
+/// streamed, the decoded blob having the given size, e.g. one recorded when it was
+/// compressed. The content fails if it is corrupted or truncated.
+/// Fails with [`Error::BodyError`] if the encoding is not supported.
+pub fn decode_blob_sized(blob: Blob, size: usize) -> Result<Blob> {
+    let encoding = encoding_of(&blob)?;
+    let decoder = match Decoder::new(encoding).map_err(Error::body_error)? {
+        Some(decoder) => decoder,
+        None => return Ok(blob),
+    };
+
+    let key = blob.key().to_string();
+    let metadata = blob.metadata().clone();
+    // the mutex only makes the decoder shareable, it is never contended, and the
+    // content is fused as it is polled once more after the decoder has finished
+    let state = (blob.into_byte_stream().fuse(), Mutex::new(Some(decoder)));
+    let stream = stream::unfold(state, |(mut content, mut decoder)| async move {
+        loop {
+            let next = content.next().await;
+            let slot = decoder.get_mut().unwrap_or_else(|err| err.into_inner());
+            let decoded = match next {
+                Some(Ok(chunk)) => slot.as_mut()?.push(&chunk),
+                Some(Err(err)) => Err(err),
+                None => match slot.take()?.finish() {
+                    Ok(rest) if rest.is_empty() => return None,
+                    result => result,
+                },
+            };
+            match decoded {
+                Ok(chunk) if chunk.is_empty() => continue,
+                Ok(chunk) => return Some((Ok(chunk), (content, decoder))),
+                Err(err) => {
+                    *slot = None;
+                    return Some((Err(err), (content, decoder)));
+                }
+            }
+        }
+    });
+
+    Ok(Blob::new(key, size, stream).with_metadata(BlobMetadata {
+        content_encoding: None,
+        ..metadata
+    }))
+}
+
+/// Fetches a blob, decompressing it according to its content encoding
+/// unless `skip_decoding` is set. See [`decode_blob`].
+pub async fn get_blob_decoded<P: Provider + ?Sized>(
+    provider: &P,
+    key: &str,
+    skip_decoding: bool,
+    budget: &MemoryBudget,
+) -> Result<Option<Blob>> {
+    match provider.get_blob(key).await? {
+        Some(blob) if !skip_decoding => decode_blob(blob, budget).await.map(Some),
+        blob => Ok(blob),
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A blob variant returned by content negotiation, along with its encoding
+#[derive(Debug)]
+pub struct EncodedBlob {
+    pub encoding: Encoding,
+    pub blob: Blob,
+}
+
+/// Provider wrapper storing pre-compressed variants of every blob under derived keys,
+/// so that the best one can be served according to a client's `Accept-Encoding`.
+///
+/// The identity variant is stored under the original key, so the wrapped provider
+/// remains readable without going through the wrapper, and each variant is stored
+/// with its `content_encoding`. Keys ending with the extension of a stored encoding,
+/// such as `.gz`, are reserved for the variants: they are hidden from listings,
+/// and storing or deleting them fails with [`Error::InvalidKey`].
+/// Blobs and their encoded variants are buffered while being stored, within the
+/// configured [`MemoryBudget`].
+#[derive(Debug)]
+pub struct EncodedProvider<P> {
+    inner: P,
+    encodings: Vec<Encoding>,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> EncodedProvider<P> {
+    /// Wraps a provider, storing gzip and brotli variants alongside the identity one
+    pub fn new(inner: P) -> Self {
+        Self::with_encodings(inner, vec![Encoding::Brotli, Encoding::Gzip])
+    }
+
+    /// Wraps a provider, storing the given encoded variants alongside the identity one.
+    /// The order of the encodings is used as server preference when the client
+    /// accepts more than one of them with the same quality.
+    pub fn with_encodings(inner: P, encodings: Vec<Encoding>) -> Self {
+        let encodings = encodings
+            .into_iter()
+            .filter(|encoding| *encoding != Encoding::Identity)
+            .collect();
+        Self {
+            inner,
+            encodings,
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers blobs within the given budget instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Whether a key is reserved for the variants of another blob
+    fn is_variant_key(&self, key: &str) -> bool {
+        self.encodings
+            .iter()
+            .any(|encoding| key.ends_with(encoding.extension()))
+    }
+
+    fn check_key(&self, key: &str) -> Result<()> {
+        if self.is_variant_key(key) {
+            return Err(Error::invalid_key(key, "reserved for encoded variants"));
+        }
+        Ok(())
+    }
+
+    /// Stores a blob along with its encoded variants, conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        self.check_key(&key)?;
+        let metadata = blob.metadata().clone();
+        let content = self.budget.buffer(blob).await?;
+
+        let mut variants = Vec::with_capacity(self.encodings.len());
+        for encoding in &self.encodings {
+            let encoded = encoding
+                .encode(content.reader()?)
+                .map_err(Error::body_error)?;
+            let encoded = self.budget.buffer_stream(encoded).await?;
+            let metadata = BlobMetadata {
+                content_encoding: Some(encoding.name().to_string()),
+                ..metadata.clone()
+            };
+            variants.push(
+                encoded
+                    .into_blob(encoding.variant_key(&key))
+                    .with_metadata(metadata),
+            );
+        }
+
+        let identity = content.into_blob(key).with_metadata(metadata);
+        if if_absent {
+            // the identity variant decides whether the blob is stored at all
+            let receipt = self.inner.store_blob_if_absent(identity).await?;
+            self.store_variants(variants).await?;
+            return Ok(receipt);
+        }
+        // the identity variant is only stored once the others are
+        self.store_variants(variants).await?;
+        self.inner.store_blob(identity).await
+    }
+
+    async fn store_variants(&self, variants: Vec<Blob>) -> Result<()> {
+        for variant in variants {
+            self.inner.store_blob(variant).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the best variant of a blob for the given `Accept-Encoding` header value.
+    /// Falls back to the next acceptable variant if the preferred one is missing,
+    /// and to the identity variant if nothing else is acceptable, unless the client
+    /// refused it with `identity;q=0`, in which case `None` is returned as well.
+    pub async fn get_blob_encoded(
+        &self,
+        key: &str,
+        accept_encoding: &str,
+    ) -> Result<Option<EncodedBlob>> {
+        for encoding in negotiate(accept_encoding, &self.encodings) {
+            if let Some(blob) = self.inner.get_blob(&encoding.variant_key(key)).await? {
+                let metadata = blob.metadata().clone();
+                let blob =
+                    Blob::new(key, blob.size(), blob.into_byte_stream()).with_metadata(metadata);
+                return Ok(Some(EncodedBlob { encoding, blob }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for EncodedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    /// Only the identity variant is stored conditionally, the others being replaced
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.check_key(key)?;
+        for encoding in &self.encodings {
+            self.inner.delete_blob(&encoding.variant_key(key)).await?;
+        }
+        self.inner.delete_blob(key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        Box::pin(
+            self.inner
+                .list_blobs(prefix)
+                .try_filter(move |entry| future::ready(!self.is_variant_key(&entry.key))),
+        )
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        Box::pin(
+            self.inner
+                .list_blobs_with_options(prefix, options)
+                .try_filter(move |entry| future::ready(!self.is_variant_key(&entry.key))),
+        )
+    }
+
+    /// Pages may hold fewer entries than the limit once the variants are left out
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let mut page = self.inner.list_page(prefix, cursor, limit).await?;
+        page.entries
+            .retain(|entry| !self.is_variant_key(&entry.key));
+        Ok(page)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encoding"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+/// Orders the available encodings by the quality the client assigned to them,
+/// dropping the unacceptable ones. Identity is always the last resort,
+/// unless refused explicitly or by `*;q=0`.
+fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Vec<Encoding> {
+    let accepted: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty())?;
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map(|q| q.parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((name, quality))
+        })
+        .collect();
+    let quality_of = |encoding: Encoding| {
+        accepted
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(encoding.name()))
+            .or_else(|| accepted.iter().find(|(name, _)| *name == "*"))
+            .map(|(_, quality)| *quality)
+    };
+
+    let mut candidates: Vec<(Encoding, f32)> = available
+        .iter()
+        .filter_map(|encoding| quality_of(*encoding).map(|quality| (*encoding, quality)))
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // stable sort, so that server preference is kept between equal qualities
+    candidates.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut encodings: Vec<Encoding> = candidates
+        .into_iter()
+        .map(|(encoding, _)| encoding)
+        .collect();
+    if quality_of(Encoding::Identity).is_none_or(|quality| quality > 0.0) {
+        encodings.push(Encoding::Identity);
+    }
+    encodings
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::{stream, TryStreamExt};
+
+    use crate::blob::Blob;
+    use crate::budget::MemoryBudget;
+    use crate::metadata::BlobMetadata;
+    use crate::middleware::encoding::{decode_blob, decode_blob_sized, negotiate, Encoding};
+
+    const AVAILABLE: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    fn encode(encoding: Encoding, content: &[u8]) -> Vec<u8> {
+        let encoded = encoding.encode(content).unwrap();
+        let chunks: Vec<Bytes> = block_on(encoded.try_collect()).unwrap();
+        chunks.concat()
+    }
+
+    #[test]
+    fn it_prefers_the_highest_quality_encoding() {
+        let encodings = negotiate("gzip;q=1.0, br;q=0.5", &AVAILABLE);
+        assert_eq!(
+            encodings,
+            vec![Encoding::Gzip, Encoding::Brotli, Encoding::Identity]
+        );
+    }
+
+    #[test]
+    fn it_uses_server_preference_between_equal_qualities() {
+        let encodings = negotiate("gzip, deflate, br", &AVAILABLE);
+        assert_eq!(
+            encodings,
+            vec![Encoding::Brotli, Encoding::Gzip, Encoding::Identity]
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_identity() {
+        assert_eq!(negotiate("", &AVAILABLE), vec![Encoding::Identity]);
+        assert_eq!(negotiate("br;q=0", &AVAILABLE), vec![Encoding::Identity]);
+        assert_eq!(
+            negotiate("br;q=0, gzip", &AVAILABLE),
+            vec![Encoding::Gzip, Encoding::Identity]
+        );
+        assert_eq!(
+            negotiate("gzip, identity;q=0", &AVAILABLE),
+            vec![Encoding::Gzip]
+        );
+        assert_eq!(negotiate("*;q=0", &AVAILABLE), Vec::<Encoding>::new());
+        assert_eq!(
+            negotiate("*;q=0.1", &AVAILABLE),
+            vec![Encoding::Brotli, Encoding::Gzip, Encoding::Identity]
+        );
+    }
+
+    #[test]
+    fn it_decodes_blobs_by_content_encoding() {
+        let content = b"hello hello hello hello hello".repeat(100);
+        for encoding in &[Encoding::Gzip, Encoding::Brotli, Encoding::Zstd] {
+            let encoded = encode(*encoding, &content);
+            let chunks: Vec<_> = encoded
+                .chunks(7)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect();
+            let metadata = BlobMetadata {
+                content_encoding: Some(encoding.name().to_string()),
+                ..BlobMetadata::default()
+            };
+            let blob =
+                Blob::new("key", encoded.len(), stream::iter(chunks)).with_metadata(metadata);
+
+            let decoded = block_on(decode_blob(blob, &MemoryBudget::unlimited())).unwrap();
+            assert_eq!(decoded.metadata().content_encoding, None);
+            assert_eq!(decoded.size(), content.len());
+            assert_eq!(block_on(decoded.read_content()).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn it_decodes_unfused_content() {
+        let content = b"hello hello hello hello hello".repeat(100);
+        let encoded = encode(Encoding::Zstd, &content);
+        let chunks: Vec<_> = encoded.chunks(7).map(Bytes::copy_from_slice).collect();
+        // unfold panics if polled again once it has ended
+        let source = stream::unfold(chunks.into_iter(), |mut chunks| async move {
+            let chunk = chunks.next()?;
+            Some((Ok(chunk), chunks))
+        });
+        let metadata = BlobMetadata {
+            content_encoding: Some(Encoding::Zstd.name().to_string()),
+            ..BlobMetadata::default()
+        };
+        let blob = Blob::new("key", encoded.len(), source).with_metadata(metadata);
+
+        let decoded = decode_blob_sized(blob, content.len()).unwrap();
+        assert_eq!(block_on(decoded.read_content()).unwrap(), content);
+    }
+
+    #[test]
+    fn it_fails_decoding_truncated_content() {
+        let content = b"hello hello hello hello hello".repeat(100);
+        for encoding in &[Encoding::Gzip, Encoding::Brotli, Encoding::Zstd] {
+            let mut encoded = encode(*encoding, &content);
+            encoded.truncate(encoded.len() - 4);
+            let metadata = BlobMetadata {
+                content_encoding: Some(encoding.name().to_string()),
+                ..BlobMetadata::default()
+            };
+            let blob = Blob::from_bytes("key", encoded).with_metadata(metadata);
+            let decoded = block_on(decode_blob(blob, &MemoryBudget::unlimited()));
+            assert!(decoded.is_err(), "truncated {} content decoded", encoding);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn it_round_trips_each_encoding() {
+        use crate::memory::MemoryProvider;
+        use crate::middleware::encoding::EncodedProvider;
+        use crate::provider::Provider;
+
+        let content = b"hello hello hello hello hello".repeat(100);
+        for encoding in &[Encoding::Gzip, Encoding::Brotli, Encoding::Zstd] {
+            let provider = EncodedProvider::with_encodings(MemoryProvider::new(), vec![*encoding]);
+            block_on(async {
+                provider
+                    .store_blob(Blob::from_bytes("site/app.js", content.clone()))
+                    .await
+                    .unwrap();
+
+                let encoded = provider
+                    .get_blob_encoded("site/app.js", encoding.name())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(encoded.encoding, *encoding);
+                assert_eq!(
+                    encoded.blob.metadata().content_encoding.as_deref(),
+                    Some(encoding.name())
+                );
+                let decoded = decode_blob(encoded.blob, &MemoryBudget::unlimited())
+                    .await
+                    .unwrap();
+                assert_eq!(decoded.read_content().await.unwrap(), content);
+
+                let identity = provider
+                    .get_blob_encoded("site/app.js", "identity")
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(identity.encoding, Encoding::Identity);
+                assert_eq!(identity.blob.metadata().content_encoding, None);
+
+                // variants are neither listed nor writable directly
+                let keys: Vec<_> = provider
+                    .list_blobs("site/")
+                    .map_ok(|entry| entry.key)
+                    .try_collect()
+                    .await
+                    .unwrap();
+                assert_eq!(keys, vec!["site/app.js"]);
+                let variant = encoding.variant_key("site/app.js");
+                assert!(provider
+                    .store_blob(Blob::from_bytes(variant.as_str(), b"x".to_vec()))
+                    .await
+                    .is_err());
+                assert!(provider.delete_blob(&variant).await.is_err());
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn it_encodes_variants_within_the_budget() {
+        use crate::memory::MemoryProvider;
+        use crate::middleware::encoding::EncodedProvider;
+        use crate::provider::Provider;
+
+        // content that does not compress, its variant being as large as itself
+        let mut state = 1u32;
+        let content: Vec<u8> = (0..3000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let provider = EncodedProvider::with_encodings(MemoryProvider::new(), vec![Encoding::Gzip])
+            .with_budget(MemoryBudget::new(4000));
+        block_on(async {
+            let err = provider
+                .store_blob(Blob::from_bytes("key", content))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), "overloaded");
+            assert!(!provider.is_blob_present("key").await.unwrap());
+        });
+    }
+}
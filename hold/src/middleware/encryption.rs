@@ -0,0 +1,413 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::pin::Pin;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+
+use crate::blob::Blob;
+use crate::listing::{Cursor, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{put_blob, EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Size of the plaintext segments blobs are encrypted in
+const SEGMENT_SIZE: usize = 64 * 1024;
+
+/// Size of the authentication tag appended to each encrypted segment
+const TAG_SIZE: usize = 16;
+
+/// Format version byte starting encrypted content
+const VERSION: u8 = 1;
+
+/// Size of the random nonce prefix following the version byte
+const PREFIX_SIZE: usize = 7;
+
+const HEADER_SIZE: usize = 1 + PREFIX_SIZE;
+
+/// Provider wrapper encrypting blobs client-side with AES-256-GCM, so that the
+/// wrapped provider never sees plaintext.
+///
+/// Content is encrypted while it is streamed, in segments of 64 KiB authenticated
+/// separately, following the STREAM construction: each segment nonce is made of a random
+/// prefix chosen for the blob, the segment counter and a flag marking the last segment,
+/// so that segments cannot be reordered, dropped or truncated unnoticed.
+/// Metadata is stored unencrypted.
+///
+/// Sizes of fetched and listed blobs are those of the plaintext. Ranged reads fetch and
+/// decrypt the whole blob, and copies are made by the wrapped provider.
+pub struct EncryptedProvider<P> {
+    inner: P,
+    cipher: Aes256Gcm,
+}
+
+impl<P: Provider + Send + Sync> EncryptedProvider<P> {
+    pub fn new(inner: P, key: [u8; 32]) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(&key.into()),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Stores a blob, conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        let mut prefix = [0; PREFIX_SIZE];
+        OsRng.fill_bytes(&mut prefix);
+        let content = segments(
+            self.cipher.clone(),
+            Mode::Seal(prefix),
+            blob.into_byte_stream(),
+        );
+        let encrypted = Blob::new(&key, encrypted_size(size), content).with_metadata(metadata);
+        let receipt = put_blob(&self.inner, encrypted, if_absent).await?;
+        Ok(StoreReceipt { size, ..receipt })
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for EncryptedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let blob = match self.inner.get_blob(key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let size = plaintext_size(blob.size());
+        let metadata = blob.metadata().clone();
+        let content = segments(self.cipher.clone(), Mode::Open, blob.into_byte_stream());
+        Ok(Some(Blob::new(key, size, content).with_metadata(metadata)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        // segments are not bound to the key, so the ciphertext is copied as is
+        let receipt = self.inner.copy_blob(src_key, dst_key).await?;
+        Ok(receipt.map(|receipt| StoreReceipt {
+            size: plaintext_size(receipt.size),
+            ..receipt
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let receipt = self
+            .inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await?;
+        Ok(receipt.map(|receipt| StoreReceipt {
+            size: plaintext_size(receipt.size),
+            ..receipt
+        }))
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        Box::pin(self.inner.list_blobs(prefix).map_ok(|mut entry| {
+            entry.size = plaintext_size(entry.size);
+            entry
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let mut page = self.inner.list_page(prefix, cursor, limit).await?;
+        for entry in &mut page.entries {
+            entry.size = plaintext_size(entry.size);
+        }
+        Ok(page)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "encryption"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+impl<P: Debug> Debug for EncryptedProvider<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedProvider")
+            .field("inner", &self.inner)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+fn segment_count(size: usize) -> usize {
+    size.div_ceil(SEGMENT_SIZE).max(1)
+}
+
+fn encrypted_size(size: usize) -> usize {
+    HEADER_SIZE + size + TAG_SIZE * segment_count(size)
+}
+
+/// The plaintext size of encrypted content, or zero if it is too short to be valid
+fn plaintext_size(size: usize) -> usize {
+    let body = size.saturating_sub(HEADER_SIZE);
+    let segments = body.div_ceil(SEGMENT_SIZE + TAG_SIZE).max(1);
+    body.saturating_sub(TAG_SIZE * segments)
+}
+
+/// Whether a segment stream encrypts, with the given nonce prefix, or decrypts
+#[derive(Clone, Copy)]
+enum Mode {
+    Seal([u8; PREFIX_SIZE]),
+    Open,
+}
+
+struct Segments<S> {
+    content: Pin<Box<S>>,
+    cipher: Aes256Gcm,
+    mode: Mode,
+    prefix: Option<[u8; PREFIX_SIZE]>,
+    buffer: BytesMut,
+    counter: u32,
+    header_sent: bool,
+    done: bool,
+    finished: bool,
+}
+
+impl<S> Segments<S> {
+    fn segment_len(&self) -> usize {
+        match self.mode {
+            Mode::Seal(_) => SEGMENT_SIZE,
+            Mode::Open => SEGMENT_SIZE + TAG_SIZE,
+        }
+    }
+
+    /// Reads the header of encrypted content once it is buffered
+    fn read_header(&mut self) -> io::Result<bool> {
+        if self.buffer.len() < HEADER_SIZE {
+            return if self.done {
+                Err(invalid("encrypted content is truncated"))
+            } else {
+                Ok(false)
+            };
+        }
+        let header = self.buffer.split_to(HEADER_SIZE);
+        if header[0] != VERSION {
+            return Err(invalid("unsupported encryption format"));
+        }
+        let mut prefix = [0; PREFIX_SIZE];
+        prefix.copy_from_slice(&header[1..]);
+        self.prefix = Some(prefix);
+        Ok(true)
+    }
+
+    /// Encrypts or decrypts the next segment, if enough content is buffered
+    fn next_segment(&mut self) -> Option<io::Result<Bytes>> {
+        let segment_len = self.segment_len();
+        // a full segment is only known not to be the last one once more content follows
+        if self.buffer.len() <= segment_len && !self.done {
+            return None;
+        }
+        let last = self.done && self.buffer.len() <= segment_len;
+        let segment = self.buffer.split_to(self.buffer.len().min(segment_len));
+        let prefix = self.prefix?;
+        let mut nonce = [0; 12];
+        nonce[..PREFIX_SIZE].copy_from_slice(&prefix);
+        nonce[PREFIX_SIZE..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        let nonce = Nonce::from_slice(&nonce);
+
+        self.finished = last;
+        self.counter = match self.counter.checked_add(1) {
+            Some(counter) => counter,
+            None if last => self.counter,
+            None => {
+                self.finished = true;
+                return Some(Err(invalid("too many encrypted segments")));
+            }
+        };
+        let result = match self.mode {
+            Mode::Seal(_) => self.cipher.encrypt(nonce, &segment[..]),
+            Mode::Open => self.cipher.decrypt(nonce, &segment[..]),
+        };
+        if result.is_err() {
+            self.finished = true;
+        }
+        Some(
+            result
+                .map(Bytes::from)
+                .map_err(|_| invalid("blob content failed to decrypt")),
+        )
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Encrypts or decrypts a content stream segment by segment
+fn segments<S>(cipher: Aes256Gcm, mode: Mode, content: S) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let prefix = match mode {
+        Mode::Seal(prefix) => Some(prefix),
+        Mode::Open => None,
+    };
+    let state = Segments {
+        content: Box::pin(content),
+        cipher,
+        mode,
+        prefix,
+        buffer: BytesMut::new(),
+        counter: 0,
+        header_sent: false,
+        done: false,
+        finished: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+            if let Mode::Seal(prefix) = state.mode {
+                if !state.header_sent {
+                    state.header_sent = true;
+                    let mut header = vec![VERSION];
+                    header.extend_from_slice(&prefix);
+                    return Some((Ok(Bytes::from(header)), state));
+                }
+            }
+            if state.prefix.is_none() {
+                match state.read_header() {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(err) => {
+                        state.finished = true;
+                        return Some((Err(err), state));
+                    }
+                }
+            } else if let Some(segment) = state.next_segment() {
+                return Some((segment, state));
+            }
+            match state.content.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(&chunk),
+                Some(Err(err)) => {
+                    state.finished = true;
+                    return Some((Err(err), state));
+                }
+                None => state.done = true,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use aes_gcm::aead::KeyInit;
+    use aes_gcm::Aes256Gcm;
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::{stream, TryStreamExt};
+
+    use crate::middleware::encryption::{
+        encrypted_size, plaintext_size, segments, Mode, SEGMENT_SIZE,
+    };
+
+    fn run(cipher: &Aes256Gcm, mode: Mode, content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        // split the content in uneven chunks, unaligned with segments
+        let chunks: Vec<_> = content
+            .chunks(1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let output: Vec<Bytes> =
+            block_on(segments(cipher.clone(), mode, stream::iter(chunks)).try_collect())?;
+        Ok(output.concat())
+    }
+
+    #[test]
+    fn it_encrypts_and_decrypts_segmented_content() {
+        let cipher = Aes256Gcm::new(&[7; 32].into());
+        for size in &[0, 10, SEGMENT_SIZE, SEGMENT_SIZE + 1, 3 * SEGMENT_SIZE - 5] {
+            let content: Vec<u8> = (0..*size).map(|i| i as u8).collect();
+            let encrypted = run(&cipher, Mode::Seal([1; 7]), content.clone()).unwrap();
+            assert_eq!(encrypted.len(), encrypted_size(*size));
+            assert_eq!(plaintext_size(encrypted.len()), *size);
+            assert_ne!(encrypted[8..], content[..]);
+
+            assert_eq!(run(&cipher, Mode::Open, encrypted).unwrap(), content);
+        }
+    }
+
+    #[cfg(feature = "memory")]
+    #[test]
+    fn it_never_stores_plaintext() {
+        use crate::blob::Blob;
+        use crate::memory::MemoryProvider;
+        use crate::middleware::encryption::EncryptedProvider;
+        use crate::provider::Provider;
+
+        let provider = EncryptedProvider::new(MemoryProvider::new(), [7; 32]);
+        block_on(async {
+            let receipt = provider
+                .store_blob(Blob::from_bytes("key", b"secret".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(receipt.size, 6);
+
+            let stored = provider.inner().get_blob("key").await.unwrap().unwrap();
+            let stored = stored.read_content().await.unwrap();
+            assert!(!stored.windows(6).any(|window| window == b"secret"));
+
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.size(), 6);
+            assert_eq!(blob.read_content().await.unwrap(), b"secret".to_vec());
+        });
+    }
+
+    #[test]
+    fn it_rejects_tampered_content() {
+        let cipher = Aes256Gcm::new(&[7; 32].into());
+        let content = vec![0; 2 * SEGMENT_SIZE];
+        let encrypted = run(&cipher, Mode::Seal([1; 7]), content).unwrap();
+
+        let mut flipped = encrypted.clone();
+        flipped[100] ^= 1;
+        assert!(run(&cipher, Mode::Open, flipped).is_err());
+
+        // dropping the last segment leaves a segment that is not marked last
+        let truncated = encrypted[..8 + SEGMENT_SIZE + 16].to_vec();
+        assert!(run(&cipher, Mode::Open, truncated).is_err());
+
+        let other = Aes256Gcm::new(&[8; 32].into());
+        assert!(run(&other, Mode::Open, encrypted).is_err());
+    }
+}
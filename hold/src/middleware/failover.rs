@@ -0,0 +1,400 @@
+use std::future::Future;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, RangeRead};
+use crate::clock::{Clock, SystemClock};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Configuration of a [`FailoverProvider`]
+#[derive(Debug, Clone)]
+pub struct FailoverConfig {
+    /// Consecutive failures after which the primary provider is considered down
+    pub failure_threshold: u32,
+
+    /// Time after which a primary provider that is down is tried again
+    pub recovery_after: Duration,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            recovery_after: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    down_since: Option<SystemTime>,
+}
+
+/// Provider wrapper routing operations to a primary provider, and to a secondary one
+/// when the primary fails, e.g. a bucket in another region.
+///
+/// Operations failing on the primary with a [retryable](crate::error::Error::is_retryable)
+/// error are sent to the secondary instead, while other errors are returned as they are.
+/// After `failure_threshold` consecutive failures the primary is considered down and
+/// operations go straight to the secondary, until `recovery_after` has passed and the
+/// primary is tried again: it is back up once an operation succeeds on it.
+///
+/// The content of a blob can only be streamed once, so a store failing on the
+/// primary is not sent to the secondary, but counts as a failure of the primary.
+/// Nothing is synchronized between the providers, see
+/// [`MirrorProvider`](crate::middleware::mirror::MirrorProvider) to write to both.
+#[derive(Debug)]
+pub struct FailoverProvider<P, S> {
+    primary: P,
+    secondary: S,
+    config: FailoverConfig,
+    clock: Arc<dyn Clock>,
+    health: Mutex<Health>,
+}
+
+impl<P: Provider + Send + Sync, S: Provider + Send + Sync> FailoverProvider<P, S> {
+    pub fn new(primary: P, secondary: S, config: FailoverConfig) -> Self {
+        Self {
+            primary,
+            secondary,
+            config,
+            clock: Arc::new(SystemClock),
+            health: Mutex::new(Health::default()),
+        }
+    }
+
+    /// Uses the given clock to decide when to try a primary that is down again
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Whether the primary provider is up, operations going to the secondary while it is not
+    pub fn is_primary_up(&self) -> bool {
+        self.health().down_since.is_none()
+    }
+
+    fn health(&self) -> std::sync::MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn should_try_primary(&self) -> bool {
+        match self.health().down_since {
+            Some(down_since) => down_since + self.config.recovery_after <= self.clock.now(),
+            None => true,
+        }
+    }
+
+    /// Records the outcome of an operation on the primary, returning whether it failed
+    fn record<T>(&self, operation: &str, result: &Result<T>) -> bool {
+        let mut health = self.health();
+        match result {
+            Err(err) if err.is_retryable() => {
+                health.failures += 1;
+                let was_up = health.down_since.is_none();
+                if !was_up || health.failures >= self.config.failure_threshold {
+                    health.down_since = Some(self.clock.now());
+                }
+                log::warn!("{} failed on the primary provider: {}", operation, err);
+                if was_up && health.down_since.is_some() {
+                    log::warn!("Primary provider is down, failing over to the secondary");
+                }
+                true
+            }
+            _ => {
+                if health.down_since.is_some() {
+                    log::info!("Primary provider is back up");
+                }
+                *health = Health::default();
+                false
+            }
+        }
+    }
+
+    async fn failover<T, FP, FS>(&self, operation: &str, primary: FP, secondary: FS) -> Result<T>
+    where
+        FP: Future<Output = Result<T>>,
+        FS: Future<Output = Result<T>>,
+    {
+        if self.should_try_primary() {
+            let result = primary.await;
+            if !self.record(operation, &result) {
+                return result;
+            }
+        }
+        secondary.await
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync, S: Provider + Send + Sync> Provider for FailoverProvider<P, S> {
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.failover(
+            "Getting blob",
+            self.primary.get_blob(key),
+            self.secondary.get_blob(key),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.failover(
+            "Getting blob range",
+            self.primary.get_blob_range(key, range.clone()),
+            self.secondary.get_blob_range(key, range),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.failover(
+            "Getting blob range",
+            self.primary.get_blob_if_range(key, range.clone(), etag),
+            self.secondary.get_blob_if_range(key, range, etag),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        if !self.should_try_primary() {
+            return self.secondary.store_blob(blob).await;
+        }
+        let result = self.primary.store_blob(blob).await;
+        self.record("Storing blob", &result);
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        if !self.should_try_primary() {
+            return self.secondary.store_blob_if_absent(blob).await;
+        }
+        let result = self.primary.store_blob_if_absent(blob).await;
+        self.record("Storing blob", &result);
+        result
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        if !self.should_try_primary() {
+            return self.secondary.open_writer(key, metadata);
+        }
+        BlobWriter::new(move |content| async move {
+            let result = write_through(content, self.primary.open_writer(key, metadata)).await;
+            self.record("Storing blob", &result);
+            result
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.failover(
+            "Checking blob",
+            self.primary.is_blob_present(key),
+            self.secondary.is_blob_present(key),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.failover(
+            "Deleting blob",
+            self.primary.delete_blob(key),
+            self.secondary.delete_blob(key),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.failover(
+            "Copying blob",
+            self.primary.copy_blob(src_key, dst_key),
+            self.secondary.copy_blob(src_key, dst_key),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.failover(
+            "Copying blob",
+            self.primary
+                .copy_blob_with_metadata(src_key, dst_key, metadata.clone()),
+            self.secondary
+                .copy_blob_with_metadata(src_key, dst_key, metadata),
+        )
+        .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        if self.should_try_primary() {
+            self.primary.list_blobs(prefix)
+        } else {
+            self.secondary.list_blobs(prefix)
+        }
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        if self.should_try_primary() {
+            self.primary.list_blobs_with_options(prefix, options)
+        } else {
+            self.secondary.list_blobs_with_options(prefix, options)
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.failover(
+            "Listing blobs",
+            self.primary.list_page(prefix, cursor, limit),
+            self.secondary.list_page(prefix, cursor, limit),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "failover"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.failover(
+            "Warming up blobs",
+            self.primary.warm_up(keys),
+            self.secondary.warm_up(keys),
+        )
+        .await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::clock::ManualClock;
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::failover::{FailoverConfig, FailoverProvider};
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider whose backend can be taken down
+    #[derive(Debug, Default)]
+    struct Outage {
+        inner: MemoryProvider,
+        down: AtomicBool,
+    }
+
+    impl Outage {
+        fn check(&self) -> Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(Error::provider(std::io::Error::other("unavailable")));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Provider for Outage {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            self.check()?;
+            self.inner.get_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.check()?;
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.check()?;
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.check()?;
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_fails_over_while_the_primary_is_down() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let config = FailoverConfig {
+            failure_threshold: 2,
+            recovery_after: Duration::from_secs(10),
+        };
+        let provider = FailoverProvider::new(Outage::default(), MemoryProvider::new(), config)
+            .with_clock(clock.clone());
+        block_on(async {
+            provider
+                .secondary()
+                .store_blob(Blob::from_bytes("key", b"secondary".to_vec()))
+                .await
+                .unwrap();
+            provider.primary().down.store(true, Ordering::SeqCst);
+
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"secondary");
+            assert!(provider.is_primary_up());
+            provider.get_blob("key").await.unwrap();
+            assert!(!provider.is_primary_up());
+
+            // writes go to the secondary while the primary is down
+            provider
+                .store_blob(Blob::from_bytes("other", b"written".to_vec()))
+                .await
+                .unwrap();
+            assert!(provider.secondary().is_blob_present("other").await.unwrap());
+
+            provider.primary().down.store(false, Ordering::SeqCst);
+            assert!(!provider.is_blob_present("missing").await.unwrap());
+            assert!(!provider.is_primary_up());
+            clock.advance(Duration::from_secs(10));
+            assert!(!provider.is_blob_present("other").await.unwrap());
+            assert!(provider.is_primary_up());
+        });
+    }
+}
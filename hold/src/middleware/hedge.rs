@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::future::{self, Either};
+use futures_timer::Delay;
+
+use crate::blob::{Blob, RangeRead};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::BlobWriter;
+use crate::Result;
+
+/// Configuration of a [`HedgedProvider`]
+#[derive(Debug, Clone)]
+pub struct HedgeConfig {
+    /// Latency percentile, between 0 and 1, after which a hedged request is fired
+    pub percentile: f64,
+
+    /// Number of recent latencies the percentile is computed over
+    pub window: usize,
+
+    /// Number of latencies to observe before relying on the percentile
+    pub min_samples: usize,
+
+    /// Delay used before enough latencies have been observed
+    pub initial_delay: Duration,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            window: 1000,
+            min_samples: 20,
+            initial_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Latencies of the recent reads, also kept sorted so that percentiles
+/// are read without sorting the whole window
+#[derive(Debug, Default)]
+struct Latencies {
+    recent: VecDeque<Duration>,
+    sorted: Vec<Duration>,
+}
+
+impl Latencies {
+    fn record(&mut self, latency: Duration, window: usize) {
+        self.recent.push_back(latency);
+        let index = self.sorted.partition_point(|sorted| *sorted < latency);
+        self.sorted.insert(index, latency);
+        while self.recent.len() > window {
+            if let Some(oldest) = self.recent.pop_front() {
+                if let Ok(index) = self.sorted.binary_search(&oldest) {
+                    self.sorted.remove(index);
+                }
+            }
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> Duration {
+        let rank = (percentile.clamp(0.0, 1.0) * (self.sorted.len() - 1) as f64).round();
+        self.sorted[rank as usize]
+    }
+}
+
+/// Provider wrapper hedging slow reads.
+///
+/// When a `get_blob` takes longer than the configured latency percentile, a duplicate
+/// request is fired to the secondary provider (or to the primary one again if there is
+/// no secondary) and the first successful response wins. A hedged request answering
+/// that the blob does not exist does not win, since a lagging secondary may not have
+/// it yet, and the primary response is awaited instead.
+/// All other operations, including ranged reads, only go to the primary provider,
+/// except warming up which prepares both.
+#[derive(Debug)]
+pub struct HedgedProvider<P, S = P> {
+    primary: P,
+    secondary: Option<S>,
+    config: HedgeConfig,
+    latencies: Mutex<Latencies>,
+}
+
+impl<P: Provider + Send + Sync> HedgedProvider<P> {
+    /// Hedges reads by duplicating them to the same provider
+    pub fn new(primary: P, config: HedgeConfig) -> Self {
+        Self {
+            primary,
+            secondary: None,
+            config,
+            latencies: Mutex::new(Latencies::default()),
+        }
+    }
+}
+
+impl<P: Provider + Send + Sync, S: Provider + Send + Sync> HedgedProvider<P, S> {
+    /// Hedges reads by duplicating them to a secondary provider
+    pub fn with_secondary(primary: P, secondary: S, config: HedgeConfig) -> Self {
+        Self {
+            primary,
+            secondary: Some(secondary),
+            config,
+            latencies: Mutex::new(Latencies::default()),
+        }
+    }
+
+    /// The delay after which a read is currently hedged
+    pub fn hedge_delay(&self) -> Duration {
+        let latencies = self.latencies.lock().unwrap_or_else(|err| err.into_inner());
+        if latencies.sorted.len() < self.config.min_samples.max(1) {
+            return self.config.initial_delay;
+        }
+        latencies.percentile(self.config.percentile)
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        self.latencies
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .record(latency, self.config.window.max(1));
+    }
+
+    async fn get_hedged(&self, key: &str) -> Result<Option<Blob>> {
+        match &self.secondary {
+            Some(secondary) => secondary.get_blob(key).await,
+            None => self.primary.get_blob(key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync, S: Provider + Send + Sync> Provider for HedgedProvider<P, S> {
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let started = Instant::now();
+        let primary = Box::pin(self.primary.get_blob(key));
+        let delay = Delay::new(self.hedge_delay());
+
+        let result = match future::select(primary, delay).await {
+            Either::Left((result, _)) => result,
+            Either::Right((_, primary)) => {
+                let hedged = Box::pin(self.get_hedged(key));
+                match future::select(primary, hedged).await {
+                    Either::Left((Ok(blob), _)) => Ok(blob),
+                    Either::Right((Ok(Some(blob)), _)) => Ok(Some(blob)),
+                    Either::Right((Ok(None), primary)) => primary.await,
+                    // the first response failed, so wait for the other one
+                    Either::Left((Err(_), other)) => other.await,
+                    Either::Right((Err(_), other)) => other.await,
+                }
+            }
+        };
+        if result.is_ok() {
+            self.record_latency(started.elapsed());
+        }
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.primary.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.primary.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.primary.store_blob(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.primary.store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        self.primary.open_writer(key, metadata)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.primary.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.primary.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.primary.copy_blob(src_key, dst_key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.primary
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.primary.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.primary.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.primary.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "hedge"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.primary.warm_up(keys).await?;
+        match &self.secondary {
+            Some(secondary) => secondary.warm_up(keys).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::io;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use futures_timer::Delay;
+
+    use crate::blob::Blob;
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::hedge::{HedgeConfig, HedgedProvider};
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider taking a given time to get blobs, holding one blob if given content
+    #[derive(Debug, Default)]
+    struct Delayed {
+        inner: MemoryProvider,
+        delay: Duration,
+        failing: bool,
+    }
+
+    impl Delayed {
+        fn new(delay_ms: u64, content: Option<&[u8]>) -> Self {
+            let inner = MemoryProvider::new();
+            if let Some(content) = content {
+                block_on(inner.store_blob(Blob::from_bytes("key", content.to_vec()))).unwrap();
+            }
+            Self {
+                inner,
+                delay: Duration::from_millis(delay_ms),
+                failing: false,
+            }
+        }
+
+        /// A provider failing to get blobs after the delay
+        fn failing(delay_ms: u64) -> Self {
+            Self {
+                failing: true,
+                ..Self::new(delay_ms, None)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Provider for Delayed {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            Delay::new(self.delay).await;
+            if self.failing {
+                return Err(Error::io(io::Error::other("unavailable")));
+            }
+            self.inner.get_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    fn hedged(primary: Delayed, secondary: Delayed) -> HedgedProvider<Delayed> {
+        let config = HedgeConfig {
+            initial_delay: Duration::from_millis(10),
+            ..HedgeConfig::default()
+        };
+        HedgedProvider::with_secondary(primary, secondary, config)
+    }
+
+    fn read(provider: &HedgedProvider<Delayed>) -> Option<Vec<u8>> {
+        block_on(async {
+            let blob = provider.get_blob("key").await.unwrap()?;
+            Some(blob.read_content().await.unwrap())
+        })
+    }
+
+    #[test]
+    fn it_hedges_slow_reads() {
+        let provider = hedged(
+            Delayed::new(500, Some(b"primary")),
+            Delayed::new(0, Some(b"secondary")),
+        );
+        assert_eq!(read(&provider).unwrap(), b"secondary");
+    }
+
+    #[test]
+    fn it_keeps_the_primary_response_when_it_comes_first() {
+        let provider = hedged(
+            Delayed::new(30, Some(b"primary")),
+            Delayed::new(500, Some(b"secondary")),
+        );
+        assert_eq!(read(&provider).unwrap(), b"primary");
+    }
+
+    #[test]
+    fn it_waits_for_the_primary_when_the_secondary_misses_the_blob() {
+        let provider = hedged(Delayed::new(50, Some(b"primary")), Delayed::new(0, None));
+        assert_eq!(read(&provider).unwrap(), b"primary");
+    }
+
+    #[test]
+    fn it_fails_when_the_secondary_misses_the_blob_and_the_primary_fails() {
+        let provider = hedged(Delayed::failing(50), Delayed::new(0, None));
+        assert!(block_on(provider.get_blob("key")).is_err());
+    }
+
+    #[test]
+    fn it_hedges_after_the_latency_percentile() {
+        let config = HedgeConfig {
+            percentile: 0.9,
+            window: 10,
+            min_samples: 5,
+            ..HedgeConfig::default()
+        };
+        let provider = HedgedProvider::new(MemoryProvider::new(), config);
+        assert_eq!(provider.hedge_delay(), Duration::from_millis(100));
+        for ms in (1..=20).rev() {
+            provider.record_latency(Duration::from_millis(ms));
+        }
+        // only the last ten latencies, from 1 to 10 ms, are kept
+        assert_eq!(provider.hedge_delay(), Duration::from_millis(9));
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::budget::MemoryBudget;
+use crate::digest::{self, from_hex, to_hex, DigestAlgorithm, Digests};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::middleware::unchanged::SHA256_METADATA_KEY;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Stores a blob unless its key already holds a different content, in which case
+/// it fails with [`Error::AlreadyExists`].
+///
+/// The blob is stored with [`Provider::store_blob_if_absent`], so that concurrent
+/// writers of the same key cannot overwrite each other on providers writing
+/// conditionally. When the key is taken, storing the same content again succeeds
+/// without uploading it, so that retries are idempotent.
+///
+/// The new content is buffered within the given budget while it is hashed, and its
+/// SHA-256 is recorded in [`SHA256_METADATA_KEY`]. The existing content is compared
+/// by the SHA-256 it was recorded with, or by its MD5 entity tag, and only streamed
+/// and hashed when it has neither. If the existing blob is deleted in between, the
+/// store fails with [`Error::PreconditionFailed`] and may be retried.
+pub async fn store_blob_immutable<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    blob: Blob,
+    budget: &MemoryBudget,
+) -> Result<StoreReceipt> {
+    let key = blob.key().to_string();
+    let mut metadata = blob.metadata().clone();
+    let content = budget.buffer(blob).await?;
+    let size = content.len();
+    let checksums = digest::digest_reader(
+        &[DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+        &mut content.reader()?,
+    )
+    .map_err(Error::body_error)?;
+    let sha256 = checksums
+        .get(DigestAlgorithm::Sha256)
+        .expect("no SHA-256 computed")
+        .to_vec();
+    metadata
+        .custom
+        .insert(SHA256_METADATA_KEY.to_string(), to_hex(&sha256));
+
+    let blob = content.into_blob(&key).with_metadata(metadata);
+    match provider.store_blob_if_absent(blob).await {
+        Err(Error::PreconditionFailed { .. }) => {}
+        result => {
+            return result.map(|receipt| StoreReceipt {
+                checksums,
+                ..receipt
+            })
+        }
+    }
+
+    let existing = provider
+        .get_blob(&key)
+        .await?
+        .ok_or_else(|| Error::precondition_failed(&key))?;
+    if existing.size() != size {
+        return Err(Error::already_exists(key));
+    }
+    let etag = existing.metadata().etag.clone();
+    let same = match recorded_match(existing.metadata(), &checksums) {
+        Some(same) => same,
+        None => {
+            let (existing, handle) = digest::hashing(existing, &[DigestAlgorithm::Sha256]);
+            existing
+                .into_byte_stream()
+                .try_for_each(|_| async { Ok(()) })
+                .await
+                .map_err(Error::body_error)?;
+            handle.finish().get(DigestAlgorithm::Sha256) == Some(sha256.as_slice())
+        }
+    };
+    if !same {
+        return Err(Error::already_exists(key));
+    }
+    log::debug!("Blob {} already holds the same content", key);
+    Ok(StoreReceipt {
+        etag,
+        checksums,
+        ..StoreReceipt::new(key, size)
+    })
+}
+
+/// Whether an existing blob has the given content, if its metadata tells:
+/// by its recorded SHA-256, or by an entity tag matching the MD5 of the content
+fn recorded_match(existing: &BlobMetadata, checksums: &Digests) -> Option<bool> {
+    if let Some(recorded) = existing.custom.get(SHA256_METADATA_KEY) {
+        return Some(from_hex(recorded).as_deref() == checksums.get(DigestAlgorithm::Sha256));
+    }
+    // entity tags of multipart uploads are not the MD5 of the content, so a mismatch
+    // does not tell anything
+    let md5 = to_hex(checksums.get(DigestAlgorithm::Md5)?);
+    existing
+        .etag
+        .as_deref()
+        .map(|etag| etag.trim_matches('"'))
+        .filter(|etag| etag.eq_ignore_ascii_case(&md5))
+        .map(|_| true)
+}
+
+/// Provider wrapper making blobs immutable once stored, see [`store_blob_immutable`]
+#[derive(Debug)]
+pub struct ImmutableProvider<P> {
+    inner: P,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> ImmutableProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers blobs within the given budget instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for ImmutableProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        store_blob_immutable(&self.inner, blob, &self.budget).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.inner.store_blob_if_absent(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "immutable"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::immutable::ImmutableProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_stores_blobs_once() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = ImmutableProvider::new(inner.clone());
+        block_on(async {
+            let first = provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(first.size, 7);
+
+            // storing the same content again is a no-op
+            let again = provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(again.etag, first.etag);
+            assert_eq!(again.checksums, first.checksums);
+
+            for content in &[&b"changed"[..], &b"longer content"[..]] {
+                let err = provider
+                    .store_blob(Blob::from_bytes("key", content.to_vec()))
+                    .await
+                    .unwrap_err();
+                assert!(matches!(err, Error::AlreadyExists { .. }));
+            }
+            let blob = inner.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content");
+        });
+    }
+
+    #[test]
+    fn it_compares_blobs_stored_without_a_checksum() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = ImmutableProvider::new(inner.clone());
+        block_on(async {
+            inner
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            let err = provider
+                .store_blob(Blob::from_bytes("key", b"conTent".to_vec()))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::AlreadyExists { .. }));
+        });
+    }
+}
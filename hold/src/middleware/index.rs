@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::index::{parent_prefix, Index, IndexOptions};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Provider wrapper keeping the index documents of the prefixes up to date,
+/// see [`write_index`](crate::index::write_index).
+///
+/// After each store, copy or delete, the index of the level of the written key is
+/// generated again, and so are the indexes of the levels above it when a level appears
+/// or becomes empty, the index of an empty level being deleted. Each of them lists the
+/// blobs under its prefix, so this suits buckets written far less than they are read.
+///
+/// A write succeeding while its indexes fail to update returns the error of the index,
+/// without undoing the write. Writes to index documents themselves are not indexed.
+#[derive(Debug)]
+pub struct IndexedProvider<P> {
+    inner: P,
+    options: IndexOptions,
+}
+
+impl<P: Provider + Send + Sync> IndexedProvider<P> {
+    pub fn new(inner: P, options: IndexOptions) -> Self {
+        Self { inner, options }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    /// Updates the indexes of the levels up from the one of a written key
+    async fn reindex(&self, key: &str) -> Result<()> {
+        if self.options.is_document(key) {
+            return Ok(());
+        }
+        let mut prefix = parent_prefix(key);
+        loop {
+            let index = Index::list(&self.inner, prefix, &self.options).await?;
+            let document = self.options.document_key(prefix);
+            let existed = self.inner.is_blob_present(&document).await?;
+            if index.is_empty() && !prefix.is_empty() {
+                if !existed {
+                    return Ok(());
+                }
+                log::debug!("Deleting index {} of an empty level", document);
+                self.inner.delete_blob(&document).await?;
+            } else {
+                log::debug!("Updating index {}", document);
+                self.inner.store_blob(index.to_blob(&self.options)).await?;
+                // the level above only lists this one, which it already did
+                if existed {
+                    return Ok(());
+                }
+            }
+            if prefix.is_empty() {
+                return Ok(());
+            }
+            prefix = parent_prefix(prefix);
+        }
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for IndexedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let receipt = self.inner.store_blob(blob).await?;
+        self.reindex(&key).await?;
+        Ok(receipt)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let receipt = self.inner.store_blob_if_absent(blob).await?;
+        self.reindex(&key).await?;
+        Ok(receipt)
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            let receipt = write_through(content, self.inner.open_writer(key, metadata)).await?;
+            self.reindex(key).await?;
+            Ok(receipt)
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await?;
+        self.reindex(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let receipt = self.inner.copy_blob(src_key, dst_key).await?;
+        if receipt.is_some() {
+            self.reindex(dst_key).await?;
+        }
+        Ok(receipt)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let receipt = self
+            .inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await?;
+        if receipt.is_some() {
+            self.reindex(dst_key).await?;
+        }
+        Ok(receipt)
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "index"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::index::IndexOptions;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::index::IndexedProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_keeps_the_indexes_up_to_date() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = IndexedProvider::new(inner.clone(), IndexOptions::html());
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("site/docs/a b.txt", b"a".to_vec()))
+                .await
+                .unwrap();
+            let mut keys = inner.keys();
+            keys.sort();
+            assert_eq!(
+                keys,
+                vec![
+                    "index.html",
+                    "site/docs/a b.txt",
+                    "site/docs/index.html",
+                    "site/index.html"
+                ]
+            );
+            let index = inner.get_blob("site/index.html").await.unwrap().unwrap();
+            let html = String::from_utf8(index.read_content().await.unwrap()).unwrap();
+            assert!(html.contains(r#"<a href="docs/index.html">docs/</a>"#));
+            let index = inner
+                .get_blob("site/docs/index.html")
+                .await
+                .unwrap()
+                .unwrap();
+            let html = String::from_utf8(index.read_content().await.unwrap()).unwrap();
+            assert!(html.contains(r#"<a href="a%20b.txt">a b.txt</a>"#));
+
+            provider.delete_blob("site/docs/a b.txt").await.unwrap();
+            assert_eq!(inner.keys(), vec!["index.html"]);
+        });
+    }
+}
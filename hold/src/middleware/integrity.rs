@@ -0,0 +1,249 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::budget::MemoryBudget;
+use crate::digest::{self, from_hex, to_hex, DigestAlgorithm};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::middleware::unchanged::SHA256_METADATA_KEY;
+use crate::provider::{put_blob, EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Provider wrapper verifying blobs end to end, with the cheapest checksum the backend
+/// verifies natively, e.g. CRC32C on S3, while always recording their SHA-256.
+///
+/// Checksums and metadata are sent before the content, so stored blobs are buffered
+/// within the given budget while they are hashed. Their SHA-256 is recorded in the
+/// [`SHA256_METADATA_KEY`] metadata, and the first of the
+/// [verified checksums](Provider::verified_checksums) of the wrapped provider is sent
+/// along for the backend to reject a corrupted upload. Receipts hold both checksums.
+/// Blobs are still stored when the backend verifies no checksum, with their SHA-256.
+///
+/// Blobs fetched whole are checked against their recorded SHA-256 as they are streamed,
+/// the content failing at its end if it does not match, while ranges are not checked.
+/// Wrappers do not forward the checksums of the blobs they store, so this one must
+/// wrap the provider directly.
+#[derive(Debug)]
+pub struct IntegrityProvider<P> {
+    inner: P,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> IntegrityProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers blobs within the given budget instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The checksum sent to the backend, if it verifies any
+    pub fn negotiated_checksum(&self) -> Option<DigestAlgorithm> {
+        self.inner.verified_checksums().first().copied()
+    }
+
+    /// Stores a blob, conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let mut metadata = blob.metadata().clone();
+        let mut algorithms = vec![DigestAlgorithm::Sha256];
+        let native = self.negotiated_checksum();
+        if let Some(native) = native.filter(|native| *native != DigestAlgorithm::Sha256) {
+            algorithms.push(native);
+        }
+
+        let content = self.budget.buffer(blob).await?;
+        let checksums = digest::digest_reader(&algorithms, &mut content.reader()?)
+            .map_err(Error::body_error)?;
+        let sha256 = checksums
+            .get(DigestAlgorithm::Sha256)
+            .expect("no SHA-256 computed");
+        metadata
+            .custom
+            .insert(SHA256_METADATA_KEY.to_string(), to_hex(sha256));
+
+        let blob = content
+            .into_blob(key)
+            .with_metadata(metadata)
+            .with_checksums(checksums.only(native.as_slice()));
+        let receipt = put_blob(&self.inner, blob, if_absent).await?;
+        Ok(StoreReceipt {
+            checksums,
+            ..receipt
+        })
+    }
+}
+
+/// Checks a fetched blob against its recorded SHA-256, if it has one
+fn verified(blob: Blob) -> Blob {
+    let expected = blob
+        .metadata()
+        .custom
+        .get(SHA256_METADATA_KEY)
+        .and_then(|sha256| from_hex(sha256));
+    match expected {
+        Some(expected) => digest::verifying(blob, DigestAlgorithm::Sha256, expected),
+        None => blob,
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for IntegrityProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        Ok(self.inner.get_blob(key).await?.map(verified))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    /// Blobs read whole because they changed are checked like fetched blobs
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let read = self.inner.get_blob_if_range(key, range, etag).await?;
+        Ok(read.map(|read| match read {
+            RangeRead::Full(blob) => RangeRead::Full(verified(blob)),
+            range => range,
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.inner.copy_blob(src_key, dst_key).await
+    }
+
+    /// The recorded SHA-256 is replaced with the rest of the metadata,
+    /// so copies are no longer checked unless the new metadata has it
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "integrity"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::digest::DigestAlgorithm;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::integrity::IntegrityProvider;
+    use crate::middleware::unchanged::SHA256_METADATA_KEY;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_records_and_verifies_checksums() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = IntegrityProvider::new(inner.clone());
+        assert_eq!(provider.negotiated_checksum(), Some(DigestAlgorithm::Md5));
+        block_on(async {
+            let receipt = provider
+                .store_blob(Blob::from_bytes("key", b"hello world".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(
+                receipt.checksums.hex(DigestAlgorithm::Md5).unwrap(),
+                "5eb63bbbe01eeed093cb22bb8f5acdc3"
+            );
+
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            let sha256 = blob.metadata().custom[SHA256_METADATA_KEY].clone();
+            assert_eq!(Some(sha256), receipt.checksums.hex(DigestAlgorithm::Sha256));
+            assert_eq!(blob.read_content().await.unwrap(), b"hello world");
+
+            // a content altered behind the wrapper fails to be read
+            let metadata = inner
+                .get_blob("key")
+                .await
+                .unwrap()
+                .unwrap()
+                .metadata()
+                .clone();
+            inner
+                .store_blob(
+                    Blob::from_bytes("key", b"hello w0rld".to_vec()).with_metadata(metadata),
+                )
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert!(blob.read_content().await.is_err());
+        });
+    }
+}
@@ -0,0 +1,422 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::clock::{Clock, SystemClock};
+use crate::digest::{hashing, hashing_stream, DigestAlgorithm};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// An operation recorded by a [`JournalProvider`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalOp {
+    Store,
+    Delete,
+    Copy { src_key: String },
+}
+
+/// An operation read back from the journal of a [`JournalProvider`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Identifier of the entry, ordering entries by the time they were recorded
+    pub id: String,
+
+    pub op: JournalOp,
+
+    /// Key of the blob the operation writes or deletes
+    pub key: String,
+
+    /// Size of the stored content, for stores
+    pub size: Option<usize>,
+
+    /// Whether the operation completed successfully
+    pub completed: bool,
+
+    /// Hex-encoded SHA-256 of the stored content, recorded once a store completed
+    pub checksum: Option<String>,
+}
+
+/// Provider wrapper keeping a write-ahead journal of the operations it executes,
+/// so that batch jobs interrupted by a crash can tell which operations need replaying.
+///
+/// The intent of each store, delete and copy is recorded in the journal provider before
+/// the operation is executed, and its completion after it succeeded, as blobs under the
+/// journal prefix. Operations that failed or were interrupted stay pending, see
+/// [`JournalProvider::pending`].
+#[derive(Debug)]
+pub struct JournalProvider<P, J> {
+    inner: P,
+    journal: J,
+    prefix: String,
+    clock: Arc<dyn Clock>,
+    sequence: AtomicUsize,
+}
+
+impl<P: Provider + Send + Sync, J: Provider + Send + Sync> JournalProvider<P, J> {
+    pub fn new<S: ToString>(inner: P, journal: J, prefix: S) -> Self {
+        Self {
+            inner,
+            journal,
+            prefix: prefix.to_string(),
+            clock: Arc::new(SystemClock),
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    /// Uses the given clock to timestamp journal entries
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn journal(&self) -> &J {
+        &self.journal
+    }
+
+    /// All the entries of the journal, oldest first
+    pub async fn entries(&self) -> Result<Vec<JournalEntry>> {
+        let records: Vec<_> = self.journal.list_blobs(&self.prefix).try_collect().await?;
+        let mut entries = Vec::new();
+        for record in &records {
+            let id = match record.key[self.prefix.len()..].strip_suffix(INTENT_SUFFIX) {
+                Some(id) => id,
+                None => continue,
+            };
+            let intent = match self.journal.get_blob(&record.key).await? {
+                Some(intent) => intent.read_content().await?,
+                None => continue,
+            };
+            let mut entry = decode_intent(id, &intent)?;
+            if let Some(done) = self
+                .journal
+                .get_blob(&self.record_key(id, DONE_SUFFIX))
+                .await?
+            {
+                entry.completed = true;
+                entry.checksum = decode_done(&done.read_content().await?)?;
+            }
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entries)
+    }
+
+    /// The operations that were recorded but never completed, oldest first
+    pub async fn pending(&self) -> Result<Vec<JournalEntry>> {
+        let mut entries = self.entries().await?;
+        entries.retain(|entry| !entry.completed);
+        Ok(entries)
+    }
+
+    /// Removes an entry from the journal, typically once a pending operation was replayed
+    pub async fn forget(&self, id: &str) -> Result<()> {
+        self.journal
+            .delete_blob(&self.record_key(id, DONE_SUFFIX))
+            .await?;
+        self.journal
+            .delete_blob(&self.record_key(id, INTENT_SUFFIX))
+            .await
+    }
+
+    /// Removes the completed entries from the journal, returning how many were removed
+    pub async fn compact(&self) -> Result<usize> {
+        let mut removed = 0;
+        for entry in self.entries().await? {
+            if entry.completed {
+                self.forget(&entry.id).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn record_key(&self, id: &str, suffix: &str) -> String {
+        format!("{}{}{}", self.prefix, id, suffix)
+    }
+
+    async fn begin(&self, op: JournalOp, key: &str, size: Option<usize>) -> Result<String> {
+        let micros = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) % 1_000_000;
+        let id = format!("{:020}-{:06}", micros, sequence);
+        let intent = encode_intent(&op, key, size);
+        self.journal
+            .store_blob(Blob::from_bytes(
+                self.record_key(&id, INTENT_SUFFIX),
+                intent,
+            ))
+            .await?;
+        Ok(id)
+    }
+
+    async fn complete(&self, id: &str, checksum: Option<String>) -> Result<()> {
+        let done = match checksum {
+            Some(checksum) => format!("checksum={}\n", checksum).into_bytes(),
+            None => Vec::new(),
+        };
+        self.journal
+            .store_blob(Blob::from_bytes(self.record_key(id, DONE_SUFFIX), done))
+            .await
+            .map(|_| ())
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync, J: Provider + Send + Sync> Provider for JournalProvider<P, J> {
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let id = self
+            .begin(JournalOp::Store, blob.key(), Some(blob.size()))
+            .await?;
+        let (blob, digests) = hashing(blob, &[DigestAlgorithm::Sha256]);
+        let receipt = self.inner.store_blob(blob).await?;
+        let checksum = digests.finish().hex(DigestAlgorithm::Sha256);
+        self.complete(&id, checksum).await?;
+        Ok(receipt)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let id = self
+            .begin(JournalOp::Store, blob.key(), Some(blob.size()))
+            .await?;
+        let (blob, digests) = hashing(blob, &[DigestAlgorithm::Sha256]);
+        let receipt = self.inner.store_blob_if_absent(blob).await?;
+        let checksum = digests.finish().hex(DigestAlgorithm::Sha256);
+        self.complete(&id, checksum).await?;
+        Ok(receipt)
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            // the size is not known upfront
+            let id = self.begin(JournalOp::Store, key, None).await?;
+            let (content, digests) = hashing_stream(content, &[DigestAlgorithm::Sha256]);
+            let receipt = write_through(content, self.inner.open_writer(key, metadata)).await?;
+            let checksum = digests.finish().hex(DigestAlgorithm::Sha256);
+            self.complete(&id, checksum).await?;
+            Ok(receipt)
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        let id = self.begin(JournalOp::Delete, key, None).await?;
+        self.inner.delete_blob(key).await?;
+        self.complete(&id, None).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let op = JournalOp::Copy {
+            src_key: src_key.to_string(),
+        };
+        let id = self.begin(op, dst_key, None).await?;
+        let receipt = self.inner.copy_blob(src_key, dst_key).await?;
+        self.complete(&id, None).await?;
+        Ok(receipt)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let op = JournalOp::Copy {
+            src_key: src_key.to_string(),
+        };
+        let id = self.begin(op, dst_key, None).await?;
+        let receipt = self
+            .inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await?;
+        self.complete(&id, None).await?;
+        Ok(receipt)
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "journal"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+const INTENT_SUFFIX: &str = ".intent";
+const DONE_SUFFIX: &str = ".done";
+
+fn encode_intent(op: &JournalOp, key: &str, size: Option<usize>) -> Vec<u8> {
+    let mut record = match op {
+        JournalOp::Store => "op=store\n".to_string(),
+        JournalOp::Delete => "op=delete\n".to_string(),
+        JournalOp::Copy { src_key } => format!("op=copy\nsrc-key={}\n", src_key),
+    };
+    record.push_str(&format!("key={}\n", key));
+    if let Some(size) = size {
+        record.push_str(&format!("size={}\n", size));
+    }
+    record.into_bytes()
+}
+
+fn decode_intent(id: &str, record: &[u8]) -> Result<JournalEntry> {
+    let record = std::str::from_utf8(record).map_err(Error::body_error)?;
+    let (mut op, mut src_key, mut key, mut size) = (None, None, None, None);
+    for line in record.lines() {
+        match line.split_once('=') {
+            Some(("op", value)) => op = Some(value),
+            Some(("src-key", value)) => src_key = Some(value.to_string()),
+            Some(("key", value)) => key = Some(value.to_string()),
+            Some(("size", value)) => size = Some(value.parse().map_err(Error::body_error)?),
+            _ => {
+                return Err(Error::body_error(format!(
+                    "invalid journal record: {}",
+                    line
+                )))
+            }
+        }
+    }
+    let op = match (op, src_key) {
+        (Some("store"), None) => JournalOp::Store,
+        (Some("delete"), None) => JournalOp::Delete,
+        (Some("copy"), Some(src_key)) => JournalOp::Copy { src_key },
+        _ => {
+            return Err(Error::body_error(format!(
+                "invalid operation in journal entry {}",
+                id
+            )))
+        }
+    };
+    let key = key.ok_or_else(|| Error::body_error(format!("no key in journal entry {}", id)))?;
+    Ok(JournalEntry {
+        id: id.to_string(),
+        op,
+        key,
+        size,
+        completed: false,
+        checksum: None,
+    })
+}
+
+fn decode_done(record: &[u8]) -> Result<Option<String>> {
+    let record = std::str::from_utf8(record).map_err(Error::body_error)?;
+    let mut checksum = None;
+    for line in record.lines() {
+        match line.split_once('=') {
+            Some(("checksum", value)) => checksum = Some(value.to_string()),
+            _ => {
+                return Err(Error::body_error(format!(
+                    "invalid journal record: {}",
+                    line
+                )))
+            }
+        }
+    }
+    Ok(checksum)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::journal::{JournalOp, JournalProvider};
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_records_pending_and_completed_operations() {
+        let provider = JournalProvider::new(MemoryProvider::new(), MemoryProvider::new(), "jobs/");
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"hello world".to_vec()))
+                .await
+                .unwrap();
+            provider.delete_blob("key").await.unwrap();
+            // an operation interrupted before completing
+            provider
+                .begin(JournalOp::Delete, "other", None)
+                .await
+                .unwrap();
+
+            let entries = provider.entries().await.unwrap();
+            assert_eq!(entries.len(), 3);
+            assert_eq!(entries[0].op, JournalOp::Store);
+            assert_eq!(entries[0].size, Some(11));
+            assert_eq!(
+                entries[0].checksum.as_deref(),
+                Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+            );
+            assert!(entries[1].completed);
+
+            assert_eq!(provider.compact().await.unwrap(), 2);
+            let pending = provider.pending().await.unwrap();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].key, "other");
+        });
+    }
+}
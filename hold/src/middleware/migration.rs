@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+
+use crate::blob::{Blob, RangeRead};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::tee::{tee_store, tee_store_if_absent};
+use crate::Result;
+
+/// Phase of a migration orchestrated by a [`MigrationProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationPhase {
+    /// Writes go to both providers, reads to the old one, while existing blobs
+    /// are copied to the new one
+    DualWriteReadOld,
+
+    /// Writes go to both providers, reads to the new one, falling back to the old one
+    /// for blobs the new one does not have. The old provider can still be switched
+    /// back to, as it has every write.
+    DualWriteReadNew,
+
+    /// Everything goes to the new provider, the old one is no longer used
+    NewOnly,
+}
+
+/// Differences between the blobs of the providers of a [`MigrationProvider`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drift {
+    /// Keys of the blobs of the old provider the new one does not have
+    pub missing_from_new: Vec<String>,
+
+    /// Keys of the blobs of the new provider the old one does not have
+    pub missing_from_old: Vec<String>,
+
+    /// Keys of the blobs both providers have, but with different sizes
+    pub size_mismatches: Vec<String>,
+}
+
+impl Drift {
+    /// Whether both providers have the same blobs
+    pub fn is_empty(&self) -> bool {
+        self.missing_from_new.is_empty()
+            && self.missing_from_old.is_empty()
+            && self.size_mismatches.is_empty()
+    }
+}
+
+/// Provider wrapper migrating blobs from an old provider to a new one without downtime.
+///
+/// Migrations go through the [phases](MigrationPhase) in order, switched at runtime
+/// with [`MigrationProvider::set_phase`]: writes go to both providers until the new
+/// one is the only one left, and reads switch to the new one once the existing blobs
+/// have been copied to it, which [`MigrationProvider::detect_drift`] checks.
+///
+/// While writing to both providers, stores, deletes and copies go to both concurrently,
+/// and succeed when they succeeded on both, like with
+/// [`MirrorProvider`](crate::middleware::mirror::MirrorProvider). They return the
+/// result of the provider reads go to.
+#[derive(Debug)]
+pub struct MigrationProvider<O, N> {
+    old: O,
+    new: N,
+    phase: Mutex<MigrationPhase>,
+}
+
+impl<O: Provider + Send + Sync, N: Provider + Send + Sync> MigrationProvider<O, N> {
+    /// A migration starting in the [`MigrationPhase::DualWriteReadOld`] phase
+    pub fn new(old: O, new: N) -> Self {
+        Self {
+            old,
+            new,
+            phase: Mutex::new(MigrationPhase::DualWriteReadOld),
+        }
+    }
+
+    pub fn old_provider(&self) -> &O {
+        &self.old
+    }
+
+    pub fn new_provider(&self) -> &N {
+        &self.new
+    }
+
+    pub fn phase(&self) -> MigrationPhase {
+        *self.phase.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Switches the migration to another phase, for the operations started after it
+    pub fn set_phase(&self, phase: MigrationPhase) {
+        let mut current = self.phase.lock().unwrap_or_else(|err| err.into_inner());
+        if *current != phase {
+            log::info!("Migration switching from {:?} to {:?}", *current, phase);
+            *current = phase;
+        }
+    }
+
+    /// Compares the blobs listed under a prefix by both providers.
+    /// The old provider is listed in full first, so its keys are held in memory.
+    pub async fn detect_drift(&self, prefix: &str) -> Result<Drift> {
+        let mut old: BTreeMap<String, usize> = self
+            .old
+            .list_blobs(prefix)
+            .map_ok(|entry| (entry.key, entry.size))
+            .try_collect()
+            .await?;
+
+        let mut drift = Drift::default();
+        let mut new = self.new.list_blobs(prefix);
+        while let Some(entry) = new.try_next().await? {
+            match old.remove(&entry.key) {
+                Some(size) if size != entry.size => drift.size_mismatches.push(entry.key),
+                Some(_) => {}
+                None => drift.missing_from_old.push(entry.key),
+            }
+        }
+        drift.missing_from_new = old.into_keys().collect();
+        drift.missing_from_old.sort();
+        drift.size_mismatches.sort();
+        Ok(drift)
+    }
+
+    /// Reads from the provider of the current phase, falling back to the old provider
+    /// for blobs the new one does not have while both are written
+    async fn read<T, FO, FN, FutO, FutN>(&self, read_old: FO, read_new: FN) -> Result<Option<T>>
+    where
+        FO: FnOnce() -> FutO,
+        FN: FnOnce() -> FutN,
+        FutO: Future<Output = Result<Option<T>>>,
+        FutN: Future<Output = Result<Option<T>>>,
+    {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => read_old().await,
+            MigrationPhase::DualWriteReadNew => match read_new().await? {
+                Some(result) => Ok(Some(result)),
+                None => read_old().await,
+            },
+            MigrationPhase::NewOnly => read_new().await,
+        }
+    }
+
+    /// Writes to the providers of the current phase, returning the result
+    /// of the provider reads go to
+    async fn write<T, FO, FN, FutO, FutN>(&self, write_old: FO, write_new: FN) -> Result<T>
+    where
+        FO: FnOnce() -> FutO,
+        FN: FnOnce() -> FutN,
+        FutO: Future<Output = Result<T>>,
+        FutN: Future<Output = Result<T>>,
+    {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => {
+                let (old, new) = future::join(write_old(), write_new()).await;
+                new?;
+                old
+            }
+            MigrationPhase::DualWriteReadNew => {
+                let (old, new) = future::join(write_old(), write_new()).await;
+                old?;
+                new
+            }
+            MigrationPhase::NewOnly => write_new().await,
+        }
+    }
+
+    /// Copies a blob on the providers of the current phase. While the new provider
+    /// is read, blobs it does not have yet are copied from the old one.
+    async fn copy<FO, FN, FutO, FutN>(
+        &self,
+        copy_old: FO,
+        copy_new: FN,
+    ) -> Result<Option<StoreReceipt>>
+    where
+        FO: FnOnce() -> FutO,
+        FN: FnOnce() -> FutN,
+        FutO: Future<Output = Result<Option<StoreReceipt>>>,
+        FutN: Future<Output = Result<Option<StoreReceipt>>>,
+    {
+        match self.phase() {
+            MigrationPhase::DualWriteReadNew => {
+                let (old, new) = future::join(copy_old(), copy_new()).await;
+                let old = old?;
+                Ok(new?.or(old))
+            }
+            _ => self.write(copy_old, copy_new).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<O: Provider + Send + Sync, N: Provider + Send + Sync> Provider for MigrationProvider<O, N> {
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.read(|| self.old.get_blob(key), || self.new.get_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.read(
+            || self.old.get_blob_range(key, range.clone()),
+            || self.new.get_blob_range(key, range.clone()),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.read(
+            || self.old.get_blob_if_range(key, range.clone(), etag),
+            || self.new.get_blob_if_range(key, range.clone(), etag),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let (read, other): (&(dyn Provider + Send + Sync), &(dyn Provider + Send + Sync)) =
+            match self.phase() {
+                MigrationPhase::DualWriteReadOld => (&self.old, &self.new),
+                MigrationPhase::DualWriteReadNew => (&self.new, &self.old),
+                MigrationPhase::NewOnly => return self.new.store_blob(blob).await,
+            };
+        let mut results = tee_store(blob, &[read, other]).await.into_iter();
+        let receipt = results.next().expect("no receipt");
+        results.next().expect("no receipt")?;
+        receipt
+    }
+
+    /// While writing to both providers, the blob must be absent from both of them
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let (read, other): (&(dyn Provider + Send + Sync), &(dyn Provider + Send + Sync)) =
+            match self.phase() {
+                MigrationPhase::DualWriteReadOld => (&self.old, &self.new),
+                MigrationPhase::DualWriteReadNew => (&self.new, &self.old),
+                MigrationPhase::NewOnly => return self.new.store_blob_if_absent(blob).await,
+            };
+        let mut results = tee_store_if_absent(blob, &[read, other]).await.into_iter();
+        let receipt = results.next().expect("no receipt");
+        results.next().expect("no receipt")?;
+        receipt
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => self.old.is_blob_present(key).await,
+            MigrationPhase::DualWriteReadNew => {
+                Ok(self.new.is_blob_present(key).await? || self.old.is_blob_present(key).await?)
+            }
+            MigrationPhase::NewOnly => self.new.is_blob_present(key).await,
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.write(|| self.old.delete_blob(key), || self.new.delete_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.copy(
+            || self.old.copy_blob(src_key, dst_key),
+            || self.new.copy_blob(src_key, dst_key),
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.copy(
+            || {
+                self.old
+                    .copy_blob_with_metadata(src_key, dst_key, metadata.clone())
+            },
+            || {
+                self.new
+                    .copy_blob_with_metadata(src_key, dst_key, metadata.clone())
+            },
+        )
+        .await
+    }
+
+    /// Listings only list the provider reads go to, without falling back,
+    /// so blobs missing from the new provider are not listed once it is read
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => self.old.list_blobs(prefix),
+            _ => self.new.list_blobs(prefix),
+        }
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => self.old.list_blobs_with_options(prefix, options),
+            _ => self.new.list_blobs_with_options(prefix, options),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => self.old.list_page(prefix, cursor, limit).await,
+            _ => self.new.list_page(prefix, cursor, limit).await,
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "migration"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        match self.phase() {
+            MigrationPhase::DualWriteReadOld => self.old.warm_up(keys).await,
+            _ => self.new.warm_up(keys).await,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::migration::{MigrationPhase, MigrationProvider};
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_moves_reads_and_writes_through_the_phases() {
+        let old = Arc::new(MemoryProvider::new());
+        let new = Arc::new(MemoryProvider::new());
+        let provider = MigrationProvider::new(old.clone(), new.clone());
+        block_on(async {
+            old.store_blob(Blob::from_bytes("existing", b"old".to_vec()))
+                .await
+                .unwrap();
+            provider
+                .store_blob(Blob::from_bytes("written", b"both".to_vec()))
+                .await
+                .unwrap();
+            assert!(old.is_blob_present("written").await.unwrap());
+            assert!(new.is_blob_present("written").await.unwrap());
+
+            let drift = provider.detect_drift("").await.unwrap();
+            assert_eq!(drift.missing_from_new, vec!["existing"]);
+            assert!(drift.missing_from_old.is_empty());
+
+            // blobs not copied yet are still read from the old provider
+            provider.set_phase(MigrationPhase::DualWriteReadNew);
+            let blob = provider.get_blob("existing").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"old");
+
+            new.store_blob(Blob::from_bytes("existing", b"old".to_vec()))
+                .await
+                .unwrap();
+            assert!(provider.detect_drift("").await.unwrap().is_empty());
+
+            provider.set_phase(MigrationPhase::NewOnly);
+            provider
+                .store_blob(Blob::from_bytes("late", b"new".to_vec()))
+                .await
+                .unwrap();
+            assert!(!old.is_blob_present("late").await.unwrap());
+            assert_eq!(
+                provider.detect_drift("").await.unwrap().missing_from_old,
+                vec!["late"]
+            );
+        });
+    }
+}
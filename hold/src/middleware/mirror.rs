@@ -0,0 +1,429 @@
+use std::future::Future;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use futures::future;
+
+use crate::blob::{Blob, RangeRead};
+use crate::clock::{Clock, SystemClock};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::tee::{tee_store, tee_store_if_absent};
+use crate::Result;
+
+type Mirror = Box<dyn Provider + Send + Sync>;
+
+/// Configuration of the reads of a [`MirrorProvider`] routed by latency,
+/// see [`MirrorProvider::with_latency_routing`]
+#[derive(Debug, Clone)]
+pub struct LatencyRouting {
+    /// Weight of each read in the moving averages of the latency and error rate
+    pub weight: f64,
+
+    /// Error rate above which a provider is demoted behind the healthy ones
+    pub max_error_rate: f64,
+
+    /// Time after which a provider that was not read from, such as a demoted one,
+    /// is read from first again to update its averages
+    pub probe_after: Duration,
+}
+
+impl Default for LatencyRouting {
+    fn default() -> Self {
+        Self {
+            weight: 0.2,
+            max_error_rate: 0.5,
+            probe_after: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReadStats {
+    latency: Option<Duration>,
+    error_rate: f64,
+    last_read: Option<SystemTime>,
+}
+
+/// Provider duplicating every write to several providers, such as an S3 bucket
+/// and a local backup.
+///
+/// Stores, deletes and copies go to all the providers concurrently, the content of
+/// stored blobs being read only once, see [`tee_store`]. A write succeeds when it
+/// succeeded everywhere, and returns the receipt of the primary provider; otherwise
+/// the first error is returned, and the providers that did succeed are left written.
+///
+/// Reads go to the providers in order, falling back to the next one when a provider
+/// fails. A provider answering that a blob does not exist is not failing, so missing
+/// blobs are not looked up in the mirrors. Streamed listings only list the primary.
+/// With [latency routing](MirrorProvider::with_latency_routing), reads go to the
+/// fastest healthy provider first instead.
+#[derive(Debug)]
+pub struct MirrorProvider {
+    providers: Vec<Mirror>,
+    routing: Option<LatencyRouting>,
+    clock: Arc<dyn Clock>,
+    stats: Mutex<Vec<ReadStats>>,
+}
+
+impl MirrorProvider {
+    pub fn new<P: Provider + Send + Sync + 'static>(primary: P) -> Self {
+        Self {
+            providers: vec![Box::new(primary)],
+            routing: None,
+            clock: Arc::new(SystemClock),
+            stats: Mutex::new(vec![ReadStats::default()]),
+        }
+    }
+
+    /// Adds a provider every write is duplicated to, read from when the providers
+    /// before it fail
+    pub fn with_mirror<P: Provider + Send + Sync + 'static>(mut self, mirror: P) -> Self {
+        self.providers.push(Box::new(mirror));
+        self.stats
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(ReadStats::default());
+        self
+    }
+
+    /// Reads from the providers by ascending average latency rather than in order,
+    /// the providers failing more often than `max_error_rate` coming after the others.
+    ///
+    /// A provider that was not read from for `probe_after` is read from first once,
+    /// so that the averages of the slower and demoted providers stay up to date,
+    /// and a provider that recovered is promoted again.
+    pub fn with_latency_routing(mut self, routing: LatencyRouting) -> Self {
+        self.routing = Some(routing);
+        self
+    }
+
+    /// Uses the given clock to decide when to probe a provider again
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The primary provider, then the mirrors in the order they were added
+    pub fn providers(&self) -> &[Mirror] {
+        &self.providers
+    }
+
+    /// The average latency of the reads from the provider at the given index,
+    /// once one succeeded with latency routing
+    pub fn average_latency(&self, index: usize) -> Option<Duration> {
+        self.stats.lock().unwrap_or_else(|err| err.into_inner())[index].latency
+    }
+
+    /// Indexes of the providers in the order to read from them
+    fn read_order(&self) -> Vec<usize> {
+        let mut order: Vec<_> = (0..self.providers.len()).collect();
+        let routing = match &self.routing {
+            Some(routing) => routing,
+            None => return order,
+        };
+        let now = self.clock.now();
+        let mut stats = self.stats.lock().unwrap_or_else(|err| err.into_inner());
+        let probed = |stats: &ReadStats| match stats.last_read {
+            Some(last_read) => last_read + routing.probe_after <= now,
+            None => true,
+        };
+        order.sort_by_key(|index| {
+            let stats = &stats[*index];
+            (
+                !probed(stats),
+                stats.error_rate > routing.max_error_rate,
+                stats.latency.unwrap_or_default(),
+            )
+        });
+        // marked right away, so that concurrent reads do not all probe the same provider
+        if let Some(first) = stats.get_mut(order[0]) {
+            if probed(first) {
+                first.last_read = Some(now);
+            }
+        }
+        order
+    }
+
+    fn record<T>(&self, index: usize, result: &Result<T>, latency: Duration) {
+        let routing = match &self.routing {
+            Some(routing) => routing,
+            None => return,
+        };
+        let mut stats = self.stats.lock().unwrap_or_else(|err| err.into_inner());
+        let stats = &mut stats[index];
+        stats.last_read = Some(self.clock.now());
+        let error = if result.is_ok() { 0.0 } else { 1.0 };
+        stats.error_rate = stats.error_rate * (1.0 - routing.weight) + error * routing.weight;
+        if result.is_ok() {
+            stats.latency = Some(match stats.latency {
+                Some(average) => {
+                    average.mul_f64(1.0 - routing.weight) + latency.mul_f64(routing.weight)
+                }
+                None => latency,
+            });
+        }
+    }
+
+    async fn read<'a, T, F, Fut>(&'a self, operation: &str, read: F) -> Result<T>
+    where
+        F: Fn(&'a Mirror) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let order = self.read_order();
+        let (last, fallbacks) = order.split_last().expect("no providers");
+        for index in fallbacks {
+            let started = Instant::now();
+            let result = read(&self.providers[*index]).await;
+            self.record(*index, &result, started.elapsed());
+            match result {
+                Ok(result) => return Ok(result),
+                Err(err) => log::warn!(
+                    "{} failed on provider {}, falling back to the next one: {}",
+                    operation,
+                    index,
+                    err
+                ),
+            }
+        }
+        let started = Instant::now();
+        let result = read(&self.providers[*last]).await;
+        self.record(*last, &result, started.elapsed());
+        result
+    }
+}
+
+/// The first result if all succeeded, the first error otherwise
+fn all_succeeded<T>(results: Vec<Result<T>>) -> Result<T> {
+    let mut first = None;
+    for result in results {
+        let result = result?;
+        first.get_or_insert(result);
+    }
+    Ok(first.expect("no providers"))
+}
+
+#[async_trait]
+impl Provider for MirrorProvider {
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.read("Getting blob", |provider| provider.get_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.read("Getting blob range", |provider| {
+            provider.get_blob_range(key, range.clone())
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.read("Getting blob range", |provider| {
+            provider.get_blob_if_range(key, range.clone(), etag)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let providers: Vec<_> = self
+            .providers
+            .iter()
+            .map(|provider| provider.as_ref())
+            .collect();
+        all_succeeded(tee_store(blob, &providers).await)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let providers: Vec<_> = self
+            .providers
+            .iter()
+            .map(|provider| provider.as_ref())
+            .collect();
+        all_succeeded(tee_store_if_absent(blob, &providers).await)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.read("Checking blob", |provider| provider.is_blob_present(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        let deletes = self
+            .providers
+            .iter()
+            .map(|provider| provider.delete_blob(key));
+        all_succeeded(future::join_all(deletes).await)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let copies = self
+            .providers
+            .iter()
+            .map(|provider| provider.copy_blob(src_key, dst_key));
+        all_succeeded(future::join_all(copies).await)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let copies = self
+            .providers
+            .iter()
+            .map(|provider| provider.copy_blob_with_metadata(src_key, dst_key, metadata.clone()));
+        all_succeeded(future::join_all(copies).await)
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.providers[0].list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.providers[0].list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.read("Listing blobs", |provider| {
+            provider.list_page(prefix, cursor, limit)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "mirror"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        let warm_ups = self.providers.iter().map(|provider| provider.warm_up(keys));
+        all_succeeded(future::join_all(warm_ups).await)
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use futures_timer::Delay;
+
+    use crate::blob::Blob;
+    use crate::clock::ManualClock;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::mirror::{LatencyRouting, MirrorProvider};
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider taking a while to get blobs
+    #[derive(Debug, Default)]
+    struct Slow {
+        inner: MemoryProvider,
+    }
+
+    #[async_trait]
+    impl Provider for Slow {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            Delay::new(Duration::from_millis(20)).await;
+            self.inner.get_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_writes_to_every_provider() {
+        let primary = Arc::new(MemoryProvider::new());
+        let backup = Arc::new(MemoryProvider::new());
+        let provider = MirrorProvider::new(primary.clone()).with_mirror(backup.clone());
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            provider.copy_blob("key", "copy").await.unwrap();
+            for mirror in &[&primary, &backup] {
+                assert_eq!(mirror.keys().len(), 2);
+                let blob = mirror.get_blob("copy").await.unwrap().unwrap();
+                assert_eq!(blob.read_content().await.unwrap(), b"content");
+            }
+
+            provider.delete_blob("key").await.unwrap();
+            assert!(!primary.is_blob_present("key").await.unwrap());
+            assert!(!backup.is_blob_present("key").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn it_reads_from_the_fastest_provider() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let primary = Arc::new(Slow::default());
+        let fast = Arc::new(MemoryProvider::new());
+        let provider = MirrorProvider::new(primary.clone())
+            .with_mirror(fast.clone())
+            .with_latency_routing(LatencyRouting::default())
+            .with_clock(clock.clone());
+        block_on(async {
+            primary
+                .store_blob(Blob::from_bytes("key", b"slow".to_vec()))
+                .await
+                .unwrap();
+            fast.store_blob(Blob::from_bytes("key", b"fast".to_vec()))
+                .await
+                .unwrap();
+            let read = || async {
+                let blob = provider.get_blob("key").await.unwrap().unwrap();
+                blob.read_content().await.unwrap()
+            };
+
+            // each provider is probed once, then the fastest is preferred
+            assert_eq!(read().await, b"slow");
+            assert_eq!(read().await, b"fast");
+            assert_eq!(read().await, b"fast");
+            assert!(provider.average_latency(0) > provider.average_latency(1));
+
+            // the slower provider is probed again after a while
+            clock.advance(Duration::from_secs(31));
+            assert_eq!(read().await, b"fast");
+            assert_eq!(read().await, b"slow");
+            assert_eq!(read().await, b"fast");
+        });
+    }
+}
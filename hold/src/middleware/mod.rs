@@ -0,0 +1,32 @@
+//! Providers wrapping other providers to add behaviour on top of them
+//!
+//! Each operation of a wrapper is traced in a span named after the operation, with
+//! the wrapper in its `layer` field. The spans of stacked wrappers nest in the order
+//! the wrappers are stacked, down to the span of the backend request, which records
+//! the `provider` and the arguments of the operation.
+
+pub mod cache;
+pub mod compression;
+pub mod concurrency;
+pub mod content_type;
+pub mod deadline;
+pub mod dedup;
+pub mod dry_run;
+pub mod encoding;
+pub mod encryption;
+pub mod failover;
+pub mod hedge;
+pub mod immutable;
+pub mod index;
+pub mod integrity;
+pub mod journal;
+pub mod migration;
+pub mod mirror;
+pub mod prefix;
+pub mod qos;
+pub mod quota;
+pub mod retention;
+pub mod retry;
+pub mod size_limit;
+pub mod slo;
+pub mod unchanged;
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures::stream;
+
+use crate::blob::{Blob, BlobEntry, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Entries fetched per page by streamed listings
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Provider wrapper confining blobs under a key prefix of the wrapped provider,
+/// so that several logical stores can share a bucket.
+///
+/// Keys are given and returned without the prefix: storing `report.pdf` through a
+/// wrapper with the `tenants/42/` prefix stores `tenants/42/report.pdf`, and listing
+/// it returns `report.pdf`. Prefixes are separated from keys by a `/`, appended
+/// if missing, so that the `a` and `ab` stores never see each other's blobs.
+///
+/// Streamed listings are fetched page by page through [`Provider::list_page`].
+#[derive(Debug)]
+pub struct PrefixedProvider<P> {
+    inner: P,
+    prefix: String,
+}
+
+impl<P: Provider + Send + Sync> PrefixedProvider<P> {
+    pub fn new<S: ToString>(inner: P, prefix: S) -> Self {
+        let mut prefix = prefix.to_string();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Self { inner, prefix }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The key of a blob in the wrapped provider
+    pub fn inner_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn outer_key<'k>(&self, key: &'k str) -> Result<&'k str> {
+        key.strip_prefix(self.prefix.as_str()).ok_or_else(|| {
+            Error::body_error(format!("key {} is outside of prefix {}", key, self.prefix))
+        })
+    }
+
+    fn outer_entry(&self, entry: BlobEntry) -> Result<BlobEntry> {
+        Ok(BlobEntry {
+            key: self.outer_key(&entry.key)?.to_string(),
+            ..entry
+        })
+    }
+
+    fn outer_page(&self, page: Page) -> Result<Page> {
+        let entries = page
+            .entries
+            .into_iter()
+            .map(|entry| self.outer_entry(entry))
+            .collect::<Result<_>>()?;
+        Ok(Page {
+            entries,
+            next: page.next,
+        })
+    }
+}
+
+/// The same blob under another key
+fn rekey(blob: Blob, key: &str) -> Blob {
+    let metadata = blob.metadata().clone();
+    Blob::new(key, blob.size(), blob.into_byte_stream()).with_metadata(metadata)
+}
+
+fn rekey_receipt(receipt: StoreReceipt, key: &str) -> StoreReceipt {
+    StoreReceipt {
+        key: key.to_string(),
+        ..receipt
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for PrefixedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let blob = self.inner.get_blob(&self.inner_key(key)).await?;
+        Ok(blob.map(|blob| rekey(blob, key)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        let blob = self
+            .inner
+            .get_blob_range(&self.inner_key(key), range)
+            .await?;
+        Ok(blob.map(|blob| rekey(blob, key)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let read = self
+            .inner
+            .get_blob_if_range(&self.inner_key(key), range, etag)
+            .await?;
+        Ok(read.map(|read| read.map(|blob| rekey(blob, key))))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(&self.inner_key(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let receipt = self
+            .inner
+            .store_blob(rekey(blob, &self.inner_key(&key)))
+            .await?;
+        Ok(rekey_receipt(receipt, &key))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let receipt = self
+            .inner
+            .store_blob_if_absent(rekey(blob, &self.inner_key(&key)))
+            .await
+            .map_err(|err| match err {
+                Error::PreconditionFailed { .. } => Error::precondition_failed(&key),
+                err => err,
+            })?;
+        Ok(rekey_receipt(receipt, &key))
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            let inner_key = self.inner_key(key);
+            let receipt =
+                write_through(content, self.inner.open_writer(&inner_key, metadata)).await?;
+            Ok(rekey_receipt(receipt, key))
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(&self.inner_key(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(&self.inner_key(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let receipt = self
+            .inner
+            .copy_blob(&self.inner_key(src_key), &self.inner_key(dst_key))
+            .await?;
+        Ok(receipt.map(|receipt| rekey_receipt(receipt, dst_key)))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let receipt = self
+            .inner
+            .copy_blob_with_metadata(&self.inner_key(src_key), &self.inner_key(dst_key), metadata)
+            .await?;
+        Ok(receipt.map(|receipt| rekey_receipt(receipt, dst_key)))
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        // the inner listing cannot borrow the prefixed prefix, so it is paged instead
+        let state = (VecDeque::new(), None, false);
+        let pages = stream::unfold(
+            state,
+            move |(mut entries, mut cursor, mut done)| async move {
+                loop {
+                    if let Some(entry) = entries.pop_front() {
+                        let entry = self.outer_entry(entry);
+                        return Some((entry, (entries, cursor, done)));
+                    }
+                    if done {
+                        return None;
+                    }
+                    let page = self
+                        .inner
+                        .list_page(&self.inner_key(prefix), cursor.as_ref(), LIST_PAGE_SIZE)
+                        .await;
+                    match page {
+                        Ok(page) => {
+                            entries.extend(page.entries);
+                            done = page.next.is_none();
+                            cursor = page.next;
+                        }
+                        Err(err) => return Some((Err(err), (entries, cursor, true))),
+                    }
+                }
+            },
+        );
+        Box::pin(pages)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let page = self
+            .inner
+            .list_page(&self.inner_key(prefix), cursor, limit)
+            .await?;
+        self.outer_page(page)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "prefix"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        let keys: Vec<_> = keys.iter().map(|key| self.inner_key(key)).collect();
+        let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+        self.inner.warm_up(&keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::prefix::PrefixedProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_confines_blobs_under_the_prefix() {
+        let shared = MemoryProvider::new();
+        let a = PrefixedProvider::new(&shared, "a");
+        let ab = PrefixedProvider::new(&shared, "ab/");
+        block_on(async {
+            let receipt = a
+                .store_blob(Blob::from_bytes("x/report", b"a".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(receipt.key, "x/report");
+            ab.store_blob(Blob::from_bytes("x/report", b"ab".to_vec()))
+                .await
+                .unwrap();
+            a.copy_blob("x/report", "y").await.unwrap();
+
+            let mut keys = shared.keys();
+            keys.sort();
+            assert_eq!(keys, vec!["a/x/report", "a/y", "ab/x/report"]);
+
+            let blob = a.get_blob("x/report").await.unwrap().unwrap();
+            assert_eq!(blob.key(), "x/report");
+            assert_eq!(blob.read_content().await.unwrap(), b"a");
+
+            let entries: Vec<_> = a.list_blobs("x/").try_collect().await.unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].key, "x/report");
+            let page = a.list_page("", None, 10).await.unwrap();
+            assert_eq!(page.entries.len(), 2);
+
+            a.delete_blob("y").await.unwrap();
+            assert!(!shared.is_blob_present("a/y").await.unwrap());
+        });
+    }
+}
@@ -0,0 +1,487 @@
+use std::future::Future;
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+
+use async_trait::async_trait;
+use futures::future;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Class of service of a request, from the highest priority to the lowest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QosClass {
+    /// User-facing traffic, never throttled in favour of other classes
+    Interactive,
+    /// Traffic nobody is directly waiting for, such as thumbnail generation
+    Background,
+    /// Large batch jobs, such as backfills and migrations
+    Bulk,
+}
+
+impl QosClass {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Configuration of [`QosProvider`]
+#[derive(Debug, Clone)]
+pub struct QosConfig {
+    /// Requests in flight across all classes
+    pub max_in_flight: usize,
+
+    /// Background requests allowed in flight while interactive requests are active
+    pub background_limit: usize,
+
+    /// Bulk requests allowed in flight while interactive or background requests are active
+    pub bulk_limit: usize,
+}
+
+impl QosConfig {
+    fn limit(&self, class: QosClass) -> usize {
+        match class {
+            QosClass::Interactive => usize::MAX,
+            QosClass::Background => self.background_limit,
+            QosClass::Bulk => self.bulk_limit,
+        }
+    }
+}
+
+impl Default for QosConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 64,
+            background_limit: 4,
+            bulk_limit: 1,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: [usize; 3],
+    queued: [usize; 3],
+    waiters: Vec<Waker>,
+}
+
+/// Provider wrapper prioritizing requests by [`QosClass`], so that batch jobs sharing
+/// a provider with user-facing traffic do not degrade its latency.
+///
+/// While requests of a class are in flight or queued, requests of lower classes are
+/// limited to a few in flight, and the others are queued until the higher class is idle.
+/// Requests made directly through the wrapper are interactive; use [`QosProvider::class`]
+/// to make requests of other classes.
+///
+/// Listings are not limited, and a blob's content is streamed after its request completed.
+#[derive(Debug)]
+pub struct QosProvider<P> {
+    inner: P,
+    config: QosConfig,
+    state: Mutex<State>,
+}
+
+impl<P: Provider + Send + Sync> QosProvider<P> {
+    pub fn new(inner: P, config: QosConfig) -> Self {
+        Self {
+            inner,
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// A provider making requests of the given class through this wrapper
+    pub fn class(&self, class: QosClass) -> QosHandle<'_, P> {
+        QosHandle {
+            provider: self,
+            class,
+        }
+    }
+
+    /// The number of requests of a class currently in flight
+    pub fn in_flight(&self, class: QosClass) -> usize {
+        self.state().in_flight[class.index()]
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn can_start(&self, state: &State, class: QosClass) -> bool {
+        let higher = ..class.index();
+        let total: usize = state.in_flight.iter().sum();
+        if total >= self.config.max_in_flight.max(1) || state.queued[higher].iter().any(|&n| n > 0)
+        {
+            return false;
+        }
+        let contended = state.in_flight[higher].iter().any(|&n| n > 0);
+        !contended || state.in_flight[class.index()] < self.config.limit(class)
+    }
+
+    async fn prioritized<T, F: Future<Output = Result<T>>>(
+        &self,
+        class: QosClass,
+        op: F,
+    ) -> Result<T> {
+        let _permit = self.acquire(class).await;
+        op.await
+    }
+
+    async fn acquire(&self, class: QosClass) -> Permit<'_, P> {
+        let mut queued = Queued {
+            provider: self,
+            class,
+            queued: false,
+        };
+        future::poll_fn(|cx| {
+            let mut state = self.state();
+            if self.can_start(&state, class) {
+                if queued.queued {
+                    state.queued[class.index()] -= 1;
+                    queued.queued = false;
+                }
+                state.in_flight[class.index()] += 1;
+                Poll::Ready(())
+            } else {
+                if !queued.queued {
+                    state.queued[class.index()] += 1;
+                    queued.queued = true;
+                }
+                state.waiters.push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+        Permit {
+            provider: self,
+            class,
+        }
+    }
+
+    fn release(&self, class: QosClass, in_flight: bool) {
+        let mut state = self.state();
+        if in_flight {
+            state.in_flight[class.index()] -= 1;
+        } else {
+            state.queued[class.index()] -= 1;
+        }
+        // lower classes may be allowed to start now, and waiters may have given up,
+        // so wake them all rather than risking a lost wake-up
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// A request waiting for its turn, leaving the queue if it is dropped while waiting
+struct Queued<'a, P: Provider + Send + Sync> {
+    provider: &'a QosProvider<P>,
+    class: QosClass,
+    queued: bool,
+}
+
+impl<P: Provider + Send + Sync> Drop for Queued<'_, P> {
+    fn drop(&mut self) {
+        if self.queued {
+            self.provider.release(self.class, false);
+        }
+    }
+}
+
+struct Permit<'a, P: Provider + Send + Sync> {
+    provider: &'a QosProvider<P>,
+    class: QosClass,
+}
+
+impl<P: Provider + Send + Sync> Drop for Permit<'_, P> {
+    fn drop(&mut self) {
+        self.provider.release(self.class, true);
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for QosProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.class(QosClass::Interactive).get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.class(QosClass::Interactive)
+            .get_blob_range(key, range)
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.class(QosClass::Interactive)
+            .get_blob_if_range(key, range, etag)
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.class(QosClass::Interactive).head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.class(QosClass::Interactive).store_blob(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.class(QosClass::Interactive)
+            .store_blob_if_absent(blob)
+            .await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| {
+            self.prioritized(
+                QosClass::Interactive,
+                write_through(content, self.inner.open_writer(key, metadata)),
+            )
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.class(QosClass::Interactive).is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.class(QosClass::Interactive).delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.class(QosClass::Interactive)
+            .copy_blob(src_key, dst_key)
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.class(QosClass::Interactive)
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+/// Requests of a given class made through a [`QosProvider`]
+#[derive(Debug)]
+pub struct QosHandle<'a, P> {
+    provider: &'a QosProvider<P>,
+    class: QosClass,
+}
+
+impl<P> QosHandle<'_, P> {
+    pub fn class(&self) -> QosClass {
+        self.class
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for QosHandle<'_, P> {
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.get_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.get_blob_range(key, range))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let provider = self.provider;
+        provider
+            .prioritized(
+                self.class,
+                provider.inner.get_blob_if_range(key, range, etag),
+            )
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.head_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.store_blob(blob))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.store_blob_if_absent(blob))
+            .await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        let provider = self.provider;
+        BlobWriter::new(move |content| {
+            provider.prioritized(
+                self.class,
+                write_through(content, provider.inner.open_writer(key, metadata)),
+            )
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.is_blob_present(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.delete_blob(key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let provider = self.provider;
+        provider
+            .prioritized(self.class, provider.inner.copy_blob(src_key, dst_key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let provider = self.provider;
+        let copy = provider
+            .inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata);
+        provider.prioritized(self.class, copy).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.provider.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.provider.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.provider.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "qos"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.provider.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+    use futures::FutureExt;
+
+    use crate::memory::MemoryProvider;
+    use crate::middleware::qos::{QosClass, QosConfig, QosProvider};
+
+    #[test]
+    fn it_queues_lower_classes_while_higher_ones_are_active() {
+        let provider = QosProvider::new(
+            MemoryProvider::new(),
+            QosConfig {
+                bulk_limit: 0,
+                ..QosConfig::default()
+            },
+        );
+
+        let interactive = block_on(provider.acquire(QosClass::Interactive));
+        let background = block_on(provider.acquire(QosClass::Background));
+        assert!(provider.acquire(QosClass::Bulk).now_or_never().is_none());
+
+        drop(interactive);
+        assert_eq!(provider.in_flight(QosClass::Interactive), 0);
+        assert!(provider.acquire(QosClass::Bulk).now_or_never().is_none());
+
+        drop(background);
+        assert!(provider.acquire(QosClass::Bulk).now_or_never().is_some());
+    }
+}
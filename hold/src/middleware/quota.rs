@@ -0,0 +1,436 @@
+use std::ops::Range;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use futures::io::AsyncWriteExt;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::BlobWriter;
+use crate::Result;
+
+/// Limits enforced by a [`QuotaProvider`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum total size of the stored blobs
+    pub max_bytes: Option<usize>,
+
+    /// Maximum number of stored blobs
+    pub max_objects: Option<usize>,
+}
+
+impl Quota {
+    /// A quota on the total size of the stored blobs
+    pub fn bytes(max_bytes: usize) -> Self {
+        Self {
+            max_bytes: Some(max_bytes),
+            max_objects: None,
+        }
+    }
+
+    /// Also limits the number of stored blobs
+    pub fn with_max_objects(mut self, max_objects: usize) -> Self {
+        self.max_objects = Some(max_objects);
+        self
+    }
+}
+
+/// Storage used by the blobs of a [`QuotaProvider`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: usize,
+    pub objects: usize,
+}
+
+/// Provider wrapper rejecting stores and copies with [`Error::QuotaExceeded`] when
+/// they would take the stored blobs over a [`Quota`], e.g. to enforce per-customer
+/// storage plans together with a
+/// [`PrefixedProvider`](crate::middleware::prefix::PrefixedProvider).
+///
+/// Usage is tracked by the wrapper, starting from nothing: stores to a provider that
+/// already holds blobs must be preceded by [`QuotaProvider::recount`], or the usage
+/// given to [`QuotaProvider::with_usage`]. Writes from other clients of the wrapped
+/// provider are not seen until the next recount.
+///
+/// Blobs are checked against the size they declare, which is reserved while they are
+/// stored, and the usage is then corrected with the size actually stored. Blobs written
+/// through [`Provider::open_writer`] reserve their content as it is written instead. The size of
+/// overwritten and deleted blobs is looked up with a single-entry page listed from their key,
+/// which sorts before every other key it prefixes.
+///
+/// Stores are not serialized per key: concurrent stores of a new key both count an
+/// object, until the next recount.
+#[derive(Debug)]
+pub struct QuotaProvider<P> {
+    inner: P,
+    quota: Quota,
+    usage: Mutex<Usage>,
+}
+
+impl<P: Provider + Send + Sync> QuotaProvider<P> {
+    pub fn new(inner: P, quota: Quota) -> Self {
+        Self {
+            inner,
+            quota,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    /// Starts from the given usage instead of nothing
+    pub fn with_usage(self, usage: Usage) -> Self {
+        *self.lock() = usage;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+
+    pub fn usage(&self) -> Usage {
+        *self.lock()
+    }
+
+    /// Measures the usage by listing every blob of the wrapped provider
+    pub async fn recount(&self) -> Result<Usage> {
+        let usage = self
+            .inner
+            .list_blobs("")
+            .try_fold(Usage::default(), |usage, entry| async move {
+                Ok(Usage {
+                    bytes: usage.bytes + entry.size,
+                    objects: usage.objects + 1,
+                })
+            })
+            .await?;
+        *self.lock() = usage;
+        Ok(usage)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Usage> {
+        self.usage.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Size of the blob stored under a key, if any
+    async fn stored_size(&self, key: &str) -> Result<Option<usize>> {
+        let page = self.inner.list_page(key, None, 1).await?;
+        Ok(page
+            .entries
+            .into_iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.size))
+    }
+
+    /// Reserves the usage of a blob of the given size replacing the existing one, if any
+    fn reserve(&self, key: &str, existing: Option<usize>, size: usize) -> Result<()> {
+        let mut usage = self.lock();
+        let bytes = (usage.bytes + size).saturating_sub(existing.unwrap_or(0));
+        let objects = usage.objects + existing.is_none() as usize;
+        if let Some(max_bytes) = self.quota.max_bytes {
+            if bytes > max_bytes {
+                return Err(Error::quota_exceeded(
+                    key,
+                    format!("{} bytes of {} would be used", bytes, max_bytes),
+                ));
+            }
+        }
+        if let Some(max_objects) = self.quota.max_objects {
+            if objects > max_objects {
+                return Err(Error::quota_exceeded(
+                    key,
+                    format!("{} blobs of {} would be stored", objects, max_objects),
+                ));
+            }
+        }
+        *usage = Usage { bytes, objects };
+        Ok(())
+    }
+
+    /// Replaces a reservation with the size actually stored, or gives it back
+    fn settle(&self, existing: Option<usize>, reserved: usize, stored: Option<usize>) {
+        let mut usage = self.lock();
+        let bytes = usage.bytes.saturating_sub(reserved);
+        *usage = match stored {
+            Some(stored) => Usage {
+                bytes: bytes + stored,
+                objects: usage.objects,
+            },
+            None => Usage {
+                bytes: bytes + existing.unwrap_or(0),
+                objects: usage.objects.saturating_sub(existing.is_none() as usize),
+            },
+        };
+    }
+
+    async fn write<F, Fut>(&self, key: &str, size: usize, write: F) -> Result<Option<StoreReceipt>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<Option<StoreReceipt>>>,
+    {
+        let existing = self.stored_size(key).await?;
+        self.reserve(key, existing, size)?;
+        let result = write().await;
+        let stored = match &result {
+            Ok(Some(receipt)) => Some(receipt.size),
+            _ => None,
+        };
+        self.settle(existing, size, stored);
+        result
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for QuotaProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let receipt = self
+            .write(&key, size, || async {
+                self.inner.store_blob(blob).await.map(Some)
+            })
+            .await?;
+        Ok(receipt.expect("stores always return a receipt"))
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let size = blob.size();
+        let receipt = self
+            .write(&key, size, || async {
+                self.inner.store_blob_if_absent(blob).await.map(Some)
+            })
+            .await?;
+        Ok(receipt.expect("stores always return a receipt"))
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            let existing = self.stored_size(key).await?;
+            self.reserve(key, existing, 0)?;
+            let mut reserved = 0;
+            let mut writer = self.inner.open_writer(key, metadata);
+            let result = async {
+                futures::pin_mut!(content);
+                while let Some(chunk) = content.next().await {
+                    // dropping the writer abandons the blob
+                    let chunk = chunk.map_err(Error::body_error)?;
+                    self.reserve(key, Some(0), chunk.len())?;
+                    reserved += chunk.len();
+                    writer.write_all(&chunk).await.map_err(Error::body_error)?;
+                }
+                writer.finish().await
+            }
+            .await;
+            let stored = result.as_ref().ok().map(|receipt| receipt.size);
+            self.settle(existing, reserved, stored);
+            result
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        let existing = self.stored_size(key).await?;
+        self.inner.delete_blob(key).await?;
+        if let Some(size) = existing {
+            let mut usage = self.lock();
+            usage.bytes = usage.bytes.saturating_sub(size);
+            usage.objects = usage.objects.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let size = match self.stored_size(src_key).await? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        self.write(dst_key, size, || self.inner.copy_blob(src_key, dst_key))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let size = match self.stored_size(src_key).await? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        self.write(dst_key, size, || {
+            self.inner
+                .copy_blob_with_metadata(src_key, dst_key, metadata)
+        })
+        .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "quota"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::quota::{Quota, QuotaProvider, Usage};
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_rejects_writes_over_the_quota() {
+        let provider =
+            QuotaProvider::new(MemoryProvider::new(), Quota::bytes(10).with_max_objects(2));
+        block_on(async {
+            let store = |key: &'static str, size: usize| {
+                provider.store_blob(Blob::from_bytes(key, vec![0; size]))
+            };
+            store("a", 4).await.unwrap();
+            store("b", 4).await.unwrap();
+            let err = store("c", 1).await.unwrap_err();
+            assert_eq!(err.code(), "quota_exceeded");
+            assert_eq!(err.key(), Some("c"));
+
+            // overwrites only count the difference
+            store("a", 6).await.unwrap();
+            assert!(store("a", 7).await.is_err());
+            assert_eq!(
+                provider.usage(),
+                Usage {
+                    bytes: 10,
+                    objects: 2
+                }
+            );
+
+            provider.delete_blob("b").await.unwrap();
+            assert_eq!(
+                provider.usage(),
+                Usage {
+                    bytes: 6,
+                    objects: 1
+                }
+            );
+            assert!(provider.copy_blob("a", "copy").await.is_err());
+            assert_eq!(provider.recount().await.unwrap(), provider.usage());
+        });
+    }
+
+    #[test]
+    fn it_only_counts_the_blob_stored_under_the_key() {
+        let provider = QuotaProvider::new(MemoryProvider::new(), Quota::bytes(10));
+        block_on(async {
+            let store = |key: &'static str, size: usize| {
+                provider.store_blob(Blob::from_bytes(key, vec![0; size]))
+            };
+            store("ab", 4).await.unwrap();
+            // "ab" is listed under the prefix "a", but is not replaced by it
+            store("a", 3).await.unwrap();
+            assert_eq!(
+                provider.usage(),
+                Usage {
+                    bytes: 7,
+                    objects: 2
+                }
+            );
+
+            provider.delete_blob("a").await.unwrap();
+            assert_eq!(
+                provider.usage(),
+                Usage {
+                    bytes: 4,
+                    objects: 1
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn it_rejects_writers_going_over_the_quota() {
+        use futures::io::AsyncWriteExt;
+
+        use crate::metadata::BlobMetadata;
+
+        let provider = QuotaProvider::new(MemoryProvider::new(), Quota::bytes(10));
+        block_on(async {
+            let mut writer = provider.open_writer("a", BlobMetadata::default());
+            writer.write_all(&[0; 6]).await.unwrap();
+            assert_eq!(writer.finish().await.unwrap().size, 6);
+
+            let mut writer = provider.open_writer("b", BlobMetadata::default());
+            let _ = writer.write_all(&[0; 3]).await;
+            let _ = writer.write_all(&[0; 3]).await;
+            let err = writer.finish().await.unwrap_err();
+            assert_eq!(err.code(), "quota_exceeded");
+            assert!(!provider.is_blob_present("b").await.unwrap());
+            assert_eq!(
+                provider.usage(),
+                Usage {
+                    bytes: 6,
+                    objects: 1
+                }
+            );
+        });
+    }
+}
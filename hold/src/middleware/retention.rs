@@ -0,0 +1,404 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::retention::{Retention, RetentionProvider};
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Provider wrapper enforcing retention on backends without native WORM support.
+///
+/// The retention state of each blob is persisted in the wrapped provider itself,
+/// in a record stored under `.retention/<key>`. Keys under `.retention/` are reserved
+/// for the records: they are hidden from listings, and writing them through the guard
+/// fails with [`Error::InvalidKey`].
+/// Deleting or overwriting a held blob fails with [`Error::Retained`].
+#[derive(Debug)]
+pub struct RetentionGuard<P> {
+    inner: P,
+    clock: Arc<dyn Clock>,
+}
+
+impl<P: Provider + Send + Sync> RetentionGuard<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Uses the given clock to decide whether retention periods have expired
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    async fn check_not_held(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        if self.get_retention(key).await?.is_held_at(self.clock.now()) {
+            Err(Error::retained(key))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for RetentionGuard<P> {
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.check_not_held(blob.key()).await?;
+        self.inner.store_blob(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.check_not_held(blob.key()).await?;
+        self.inner.store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            self.check_not_held(key).await?;
+            write_through(content, self.inner.open_writer(key, metadata)).await
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.check_not_held(key).await?;
+        self.inner.delete_blob(key).await?;
+        let record_key = record_key(key);
+        if self.inner.is_blob_present(&record_key).await? {
+            self.inner.delete_blob(&record_key).await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        // the copy does not carry the retention of the source blob
+        self.check_not_held(dst_key).await?;
+        self.inner.copy_blob(src_key, dst_key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.check_not_held(dst_key).await?;
+        self.inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        Box::pin(
+            self.inner
+                .list_blobs(prefix)
+                .try_filter(|entry| future::ready(!entry.key.starts_with(RECORD_PREFIX))),
+        )
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        Box::pin(
+            self.inner
+                .list_blobs_with_options(prefix, options)
+                .try_filter(|entry| future::ready(!entry.key.starts_with(RECORD_PREFIX))),
+        )
+    }
+
+    /// Pages may hold fewer entries than the limit once the records are left out
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let mut page = self.inner.list_page(prefix, cursor, limit).await?;
+        page.entries
+            .retain(|entry| !entry.key.starts_with(RECORD_PREFIX));
+        Ok(page)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retention"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> RetentionProvider for RetentionGuard<P> {
+    async fn get_retention(&self, key: &str) -> Result<Retention> {
+        match self.inner.get_blob(&record_key(key)).await? {
+            Some(record) => decode_record(&record.read_content().await?),
+            None => Ok(Retention::default()),
+        }
+    }
+
+    async fn set_retention(&self, key: &str, retention: Retention) -> Result<()> {
+        check_key(key)?;
+        let current = self.get_retention(key).await?;
+        if current.is_shortened_by_at(&retention, self.clock.now()) {
+            return Err(Error::retained(key));
+        }
+        self.inner
+            .store_blob(Blob::from_bytes(record_key(key), encode_record(&retention)))
+            .await
+            .map(|_| ())
+    }
+
+    async fn clear_legal_hold(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let retention = Retention {
+            legal_hold: false,
+            ..self.get_retention(key).await?
+        };
+        self.inner
+            .store_blob(Blob::from_bytes(record_key(key), encode_record(&retention)))
+            .await
+            .map(|_| ())
+    }
+}
+
+const RECORD_PREFIX: &str = ".retention/";
+
+fn record_key(key: &str) -> String {
+    format!("{}{}", RECORD_PREFIX, key)
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if key.starts_with(RECORD_PREFIX) {
+        return Err(Error::invalid_key(key, "reserved for retention records"));
+    }
+    Ok(())
+}
+
+/// Encodes a retention record. Records hold whole seconds, so `retain_until`
+/// is rounded up: a blob is never released before its retention ends.
+fn encode_record(retention: &Retention) -> Vec<u8> {
+    let mut record = format!("legal-hold={}\n", retention.legal_hold);
+    if let Some(until) = retention.retain_until {
+        let since_epoch = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs() + u64::from(since_epoch.subsec_nanos() > 0);
+        record.push_str(&format!("retain-until={}\n", secs));
+    }
+    record.into_bytes()
+}
+
+fn decode_record(record: &[u8]) -> Result<Retention> {
+    let record = std::str::from_utf8(record).map_err(Error::body_error)?;
+    let mut retention = Retention::default();
+    for line in record.lines() {
+        match line.split_once('=') {
+            Some(("legal-hold", value)) => {
+                retention.legal_hold = value.parse().map_err(Error::body_error)?;
+            }
+            Some(("retain-until", value)) => {
+                let secs = value.parse().map_err(Error::body_error)?;
+                retention.retain_until = Some(UNIX_EPOCH + Duration::from_secs(secs));
+            }
+            _ => {
+                return Err(Error::body_error(format!(
+                    "invalid retention record: {}",
+                    line
+                )))
+            }
+        }
+    }
+    Ok(retention)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::middleware::retention::{decode_record, encode_record};
+    use crate::retention::Retention;
+
+    #[test]
+    fn it_round_trips_retention_records() {
+        let retention = Retention {
+            retain_until: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            legal_hold: true,
+        };
+        let decoded = decode_record(&encode_record(&retention)).unwrap();
+
+        assert_eq!(decoded, retention);
+        assert_eq!(
+            decode_record(&encode_record(&Retention::default())).unwrap(),
+            Retention::default()
+        );
+        assert!(decode_record(b"frozen=forever").is_err());
+    }
+
+    #[test]
+    fn it_rounds_retention_up_to_whole_seconds() {
+        let retention = Retention {
+            retain_until: Some(UNIX_EPOCH + Duration::from_millis(1_600_000_000_001)),
+            legal_hold: false,
+        };
+        let decoded = decode_record(&encode_record(&retention)).unwrap();
+
+        assert_eq!(
+            decoded.retain_until,
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_001))
+        );
+    }
+
+    #[cfg(feature = "memory")]
+    mod guard {
+        use std::sync::Arc;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        use futures::executor::block_on;
+        use futures::TryStreamExt;
+
+        use crate::blob::Blob;
+        use crate::clock::ManualClock;
+        use crate::error::Error;
+        use crate::memory::MemoryProvider;
+        use crate::middleware::retention::{record_key, RetentionGuard};
+        use crate::provider::Provider;
+        use crate::retention::{Retention, RetentionProvider};
+
+        fn blob(content: &[u8]) -> Blob {
+            Blob::from_bytes("key", content.to_vec())
+        }
+
+        #[test]
+        fn it_refuses_changing_blobs_until_their_retention_expires() {
+            let clock = ManualClock::new(UNIX_EPOCH);
+            let inner = Arc::new(MemoryProvider::new());
+            let guard = RetentionGuard::new(inner.clone()).with_clock(clock.clone());
+            block_on(async {
+                guard.store_blob(blob(b"v1")).await.unwrap();
+                let until = UNIX_EPOCH + Duration::from_secs(10);
+                guard
+                    .set_retention("key", Retention::until(until))
+                    .await
+                    .unwrap();
+
+                let err = guard.store_blob(blob(b"v2")).await.unwrap_err();
+                assert!(matches!(err, Error::Retained { .. }));
+                let err = guard.delete_blob("key").await.unwrap_err();
+                assert!(matches!(err, Error::Retained { .. }));
+
+                clock.advance(Duration::from_secs(10));
+                guard.store_blob(blob(b"v2")).await.unwrap();
+                guard.delete_blob("key").await.unwrap();
+                // the record goes along with the blob
+                assert!(inner.keys().is_empty());
+            });
+        }
+
+        #[test]
+        fn it_refuses_changing_blobs_under_legal_hold() {
+            let guard = RetentionGuard::new(MemoryProvider::new());
+            block_on(async {
+                guard.store_blob(blob(b"v1")).await.unwrap();
+                guard
+                    .set_retention("key", Retention::legal_hold())
+                    .await
+                    .unwrap();
+                let err = guard.delete_blob("key").await.unwrap_err();
+                assert!(matches!(err, Error::Retained { .. }));
+
+                guard.clear_legal_hold("key").await.unwrap();
+                guard.delete_blob("key").await.unwrap();
+                assert!(!guard.get_retention("key").await.unwrap().is_held());
+            });
+        }
+
+        #[test]
+        fn it_protects_retention_records() {
+            let guard = RetentionGuard::new(MemoryProvider::new());
+            block_on(async {
+                guard.store_blob(blob(b"v1")).await.unwrap();
+                guard
+                    .store_blob(Blob::from_bytes("notes.retention", b"kept".to_vec()))
+                    .await
+                    .unwrap();
+                guard
+                    .set_retention("key", Retention::legal_hold())
+                    .await
+                    .unwrap();
+
+                let record = record_key("key");
+                let err = guard.delete_blob(&record).await.unwrap_err();
+                assert!(matches!(err, Error::InvalidKey { .. }));
+                let err = guard
+                    .store_blob(Blob::from_bytes(record.as_str(), b"".to_vec()))
+                    .await
+                    .unwrap_err();
+                assert!(matches!(err, Error::InvalidKey { .. }));
+                let err = guard.copy_blob("key", &record).await.unwrap_err();
+                assert!(matches!(err, Error::InvalidKey { .. }));
+                assert!(guard.get_retention("key").await.unwrap().is_held());
+
+                let mut keys: Vec<_> = guard
+                    .list_blobs("")
+                    .map_ok(|entry| entry.key)
+                    .try_collect()
+                    .await
+                    .unwrap();
+                keys.sort();
+                assert_eq!(keys, vec!["key", "notes.retention"]);
+                let page = guard.list_page("", None, 10).await.unwrap();
+                let keys: Vec<_> = page
+                    .entries
+                    .iter()
+                    .map(|entry| entry.key.as_str())
+                    .collect();
+                assert_eq!(keys, vec!["key", "notes.retention"]);
+            });
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::retry::{retrying, RetryPolicy};
+use crate::writer::BlobWriter;
+use crate::Result;
+
+/// Provider wrapper attempting operations again when they fail with a
+/// [retryable](crate::error::Error::is_retryable) error, waiting with exponential
+/// backoff and jitter between attempts. Other errors are returned right away.
+///
+/// The content of a blob can only be streamed once, so stores are attempted once:
+/// use [`store_with_retry`](crate::retry::store_with_retry) with a replayable source
+/// to retry uploads. Streamed listings are not retried either, while pages are.
+/// Failures while reading the content of a fetched blob are not retried.
+#[derive(Debug)]
+pub struct RetryProvider<P> {
+    inner: P,
+    policy: RetryPolicy,
+}
+
+impl<P: Provider + Send + Sync> RetryProvider<P> {
+    pub fn new(inner: P, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for RetryProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        retrying(&self.policy, "Getting blob", || self.inner.get_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        retrying(&self.policy, "Getting blob range", || {
+            self.inner.get_blob_range(key, range.clone())
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        retrying(&self.policy, "Getting blob range", || {
+            self.inner.get_blob_if_range(key, range.clone(), etag)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        retrying(&self.policy, "Getting blob metadata", || {
+            self.inner.head_blob(key)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.inner.store_blob(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.inner.store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        // not retried either, as the content is only written once
+        self.inner.open_writer(key, metadata)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        retrying(&self.policy, "Checking blob", || {
+            self.inner.is_blob_present(key)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        retrying(&self.policy, "Deleting blob", || {
+            self.inner.delete_blob(key)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        retrying(&self.policy, "Copying blob", || {
+            self.inner.copy_blob(src_key, dst_key)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        retrying(&self.policy, "Copying blob", || {
+            self.inner
+                .copy_blob_with_metadata(src_key, dst_key, metadata.clone())
+        })
+        .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        retrying(&self.policy, "Listing blobs", || {
+            self.inner.list_page(prefix, cursor, limit)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "retry"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        retrying(&self.policy, "Warming up blobs", || {
+            self.inner.warm_up(keys)
+        })
+        .await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::retry::RetryProvider;
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::retry::RetryPolicy;
+    use crate::Result;
+
+    /// Fails the first gets with a transient error, and checks with a final one
+    #[derive(Debug)]
+    struct Flaky {
+        inner: MemoryProvider,
+        failures: AtomicUsize,
+        checks: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Provider for Flaky {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::provider(std::io::Error::other("throttled")));
+            }
+            self.inner.get_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob(blob).await
+        }
+
+        async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob_if_absent(blob).await
+        }
+
+        async fn is_blob_present(&self, _key: &str) -> Result<bool> {
+            self.checks.fetch_add(1, Ordering::SeqCst);
+            Err(Error::DeadlineExceeded)
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_retries_transient_failures_only() {
+        let flaky = Flaky {
+            inner: MemoryProvider::new(),
+            failures: AtomicUsize::new(2),
+            checks: AtomicUsize::new(0),
+        };
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let provider = RetryProvider::new(flaky, policy);
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content");
+
+            assert!(provider.is_blob_present("key").await.is_err());
+            assert_eq!(provider.inner().checks.load(Ordering::SeqCst), 1);
+        });
+    }
+    #[test]
+    fn it_forwards_conditional_stores() {
+        let flaky = Flaky {
+            inner: MemoryProvider::new(),
+            failures: AtomicUsize::new(0),
+            checks: AtomicUsize::new(0),
+        };
+        let provider = RetryProvider::new(flaky, RetryPolicy::default());
+        block_on(async {
+            let store = || provider.store_blob_if_absent(Blob::from_bytes("key", b"a".to_vec()));
+            store().await.unwrap();
+            let err = store().await.unwrap_err();
+            assert!(matches!(err, Error::PreconditionFailed { .. }));
+            // the conditional store of the wrapped provider did not check the blob first
+            assert_eq!(provider.inner().checks.load(Ordering::SeqCst), 0);
+        });
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::io::AsyncWriteExt;
+use futures::StreamExt;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{put_blob, EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::BlobWriter;
+use crate::Result;
+
+/// Provider wrapper rejecting blobs larger than a maximum size with [`Error::TooLarge`].
+///
+/// Blobs declaring a larger size are rejected before reaching the wrapped provider.
+/// Since the declared size cannot be trusted, the content is also counted while it is
+/// streamed, and the upload is aborted as soon as it goes over the limit. So are blobs
+/// of unknown size written through [`Provider::open_writer`], which are passed on to
+/// the writer of the wrapped provider as they are written.
+#[derive(Debug)]
+pub struct SizeLimitProvider<P> {
+    inner: P,
+    max_size: usize,
+}
+
+impl<P: Provider + Send + Sync> SizeLimitProvider<P> {
+    pub fn new(inner: P, max_size: usize) -> Self {
+        Self { inner, max_size }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Stores a blob, conditionally if `if_absent` is set
+    async fn store(&self, blob: Blob, if_absent: bool) -> Result<StoreReceipt> {
+        let key = blob.key().to_string();
+        let max_size = self.max_size;
+        if blob.size() > max_size {
+            return Err(Error::too_large(key, max_size));
+        }
+
+        let streamed = Arc::new(AtomicUsize::new(0));
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let size = blob.size();
+        let metadata = blob.metadata().clone();
+        let stream = {
+            let exceeded = exceeded.clone();
+            blob.into_byte_stream().map(move |chunk| {
+                let chunk = chunk?;
+                let total = streamed.fetch_add(chunk.len(), Ordering::AcqRel) + chunk.len();
+                if total > max_size {
+                    exceeded.store(true, Ordering::Release);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("blob exceeds the maximum size of {} bytes", max_size),
+                    ));
+                }
+                Ok(chunk)
+            })
+        };
+
+        let blob = Blob::new(&key, size, stream).with_metadata(metadata);
+        let result = put_blob(&self.inner, blob, if_absent).await;
+        if exceeded.load(Ordering::Acquire) {
+            return Err(Error::too_large(key, max_size));
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for SizeLimitProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, false).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.store(blob, true).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| async move {
+            let mut writer = self.inner.open_writer(key, metadata);
+            let mut written = 0;
+            futures::pin_mut!(content);
+            while let Some(chunk) = content.next().await {
+                // dropping the inner writer abandons the blob
+                let chunk = chunk.map_err(Error::body_error)?;
+                written += chunk.len();
+                if written > self.max_size {
+                    return Err(Error::too_large(key, self.max_size));
+                }
+                writer.write_all(&chunk).await.map_err(Error::body_error)?;
+            }
+            writer.finish().await
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "size_limit"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::io::AsyncWriteExt;
+    use futures::stream;
+
+    use crate::blob::Blob;
+    use crate::error::Error;
+    use crate::memory::MemoryProvider;
+    use crate::metadata::BlobMetadata;
+    use crate::middleware::size_limit::SizeLimitProvider;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_rejects_blobs_declared_too_large() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = SizeLimitProvider::new(inner.clone(), 5);
+        let err = block_on(provider.store_blob(Blob::from_bytes("key", b"too large".to_vec())))
+            .unwrap_err();
+        assert!(matches!(err, Error::TooLarge { limit: 5, .. }));
+        assert!(inner.keys().is_empty());
+    }
+
+    #[test]
+    fn it_rejects_blobs_understating_their_size() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = SizeLimitProvider::new(inner.clone(), 5);
+        let content = vec![
+            Ok(Bytes::from_static(b"too ")),
+            Ok(Bytes::from_static(b"large")),
+        ];
+        let blob = Blob::new("key", 3, stream::iter(content));
+        let err = block_on(provider.store_blob(blob)).unwrap_err();
+        assert!(matches!(err, Error::TooLarge { limit: 5, .. }));
+        assert!(inner.keys().is_empty());
+    }
+
+    #[test]
+    fn it_accepts_blobs_at_the_limit() {
+        let provider = SizeLimitProvider::new(MemoryProvider::new(), 5);
+        let receipt = block_on(provider.store_blob(Blob::from_bytes("key", b"exact".to_vec())));
+        assert_eq!(receipt.unwrap().size, 5);
+    }
+
+    #[test]
+    fn it_aborts_writers_going_over_the_limit() {
+        let inner = Arc::new(MemoryProvider::new());
+        let provider = SizeLimitProvider::new(inner.clone(), 5);
+        block_on(async {
+            let mut writer = provider.open_writer("key", BlobMetadata::default());
+            writer.write_all(b"exa").await.unwrap();
+            writer.write_all(b"ct").await.unwrap();
+            assert_eq!(writer.finish().await.unwrap().size, 5);
+
+            let mut writer = provider.open_writer("other", BlobMetadata::default());
+            writer.write_all(b"too ").await.unwrap();
+            // the limit may be noticed on a later write or when finishing
+            let _ = writer.write_all(b"large").await;
+            let err = writer.finish().await.unwrap_err();
+            assert!(matches!(err, Error::TooLarge { limit: 5, .. }));
+            assert_eq!(inner.keys(), vec!["key"]);
+        });
+    }
+}
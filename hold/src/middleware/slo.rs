@@ -0,0 +1,513 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::writer::{write_through, BlobWriter};
+use crate::Result;
+
+/// Service level objectives of storage backends, see [`SloReporter`]
+#[derive(Debug, Clone)]
+pub struct SloConfig {
+    /// Fraction of operations that must succeed, e.g. `0.999`
+    pub availability: f64,
+
+    /// Latency within which operations are considered fast
+    pub latency_threshold: Duration,
+
+    /// Fraction of operations that must be fast, e.g. `0.99`
+    pub latency_objective: f64,
+
+    /// How long outcomes are kept, the longest window reports can cover
+    pub retention: Duration,
+
+    /// Granularity outcomes are aggregated with
+    pub bucket: Duration,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            availability: 0.999,
+            latency_threshold: Duration::from_millis(500),
+            latency_objective: 0.99,
+            retention: Duration::from_secs(6 * 60 * 60),
+            bucket: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Outcomes of the operations on a backend over a window
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SloReport {
+    pub total: u64,
+    pub failures: u64,
+    /// Successful operations slower than the latency threshold
+    pub slow: u64,
+    /// Rate at which the error budget of the availability objective is consumed:
+    /// 1 consumes it exactly over the objective period, 0 when nothing happened
+    pub availability_burn_rate: f64,
+    /// Rate at which the error budget of the latency objective is consumed
+    pub latency_burn_rate: f64,
+}
+
+impl SloReport {
+    /// Fraction of the operations that succeeded, 1 when nothing happened
+    pub fn success_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        1.0 - self.failures as f64 / self.total as f64
+    }
+
+    /// The higher of the two burn rates
+    pub fn burn_rate(&self) -> f64 {
+        self.availability_burn_rate.max(self.latency_burn_rate)
+    }
+}
+
+/// An objective of a backend burning its error budget too fast, see [`SloReporter::on_burn`]
+#[derive(Debug, Clone)]
+pub struct BurnAlert {
+    pub backend: String,
+    pub window: Duration,
+    pub report: SloReport,
+}
+
+type BurnCallback = Arc<dyn Fn(&BurnAlert) + Send + Sync>;
+
+struct BurnRule {
+    window: Duration,
+    threshold: f64,
+    callback: BurnCallback,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    index: u64,
+    total: u64,
+    failures: u64,
+    slow: u64,
+}
+
+#[derive(Debug, Default)]
+struct Backend {
+    buckets: VecDeque<Bucket>,
+    /// Whether each burn rule is currently firing, so that it fires once per episode
+    firing: Vec<bool>,
+}
+
+#[derive(Default)]
+struct State {
+    backends: HashMap<String, Backend>,
+    rules: Vec<BurnRule>,
+}
+
+struct Inner {
+    config: SloConfig,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+/// Tracks the success rate and latency of storage backends against their service level
+/// objectives, to alert on degraded backends from inside the application.
+///
+/// Outcomes are recorded per backend name, typically by a [`SloProvider`], and aggregated
+/// in buckets kept for the configured retention. Reports give the burn rates of the error
+/// budgets over a window: how many times faster than allowed by the objective failures or
+/// slow operations happen: a burn rate of 14.4 sustained for an hour spends 2% of a 30 days budget.
+/// Callbacks registered with [`SloReporter::on_burn`] are called as soon as a burn rate
+/// goes over their threshold.
+///
+/// Clones share the same outcomes, so that a reporter can be handed to several providers.
+#[derive(Clone)]
+pub struct SloReporter {
+    inner: Arc<Inner>,
+}
+
+impl SloReporter {
+    pub fn new(config: SloConfig) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+
+    /// Uses the given clock to bucket outcomes
+    pub fn with_clock<C: Clock + 'static>(config: SloConfig, clock: C) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                clock: Arc::new(clock),
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &SloConfig {
+        &self.inner.config
+    }
+
+    /// Calls `callback` when the burn rate of a backend over `window` goes over
+    /// `threshold`, once until it goes back under it
+    pub fn on_burn<F: Fn(&BurnAlert) + Send + Sync + 'static>(
+        &self,
+        window: Duration,
+        threshold: f64,
+        callback: F,
+    ) {
+        self.lock().rules.push(BurnRule {
+            window,
+            threshold,
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// Records the outcome of an operation on a backend
+    pub fn record(&self, backend: &str, success: bool, latency: Duration) {
+        let slow = success && latency > self.inner.config.latency_threshold;
+        let index = self.bucket_index();
+        let mut alerts = Vec::new();
+        {
+            let mut state = self.lock();
+            let State { backends, rules } = &mut *state;
+            let entry = backends.entry(backend.to_string()).or_default();
+            match entry.buckets.back_mut() {
+                Some(bucket) if bucket.index == index => {
+                    bucket.total += 1;
+                    bucket.failures += !success as u64;
+                    bucket.slow += slow as u64;
+                }
+                _ => entry.buckets.push_back(Bucket {
+                    index,
+                    total: 1,
+                    failures: !success as u64,
+                    slow: slow as u64,
+                }),
+            }
+            let retained = self.buckets_in(self.inner.config.retention);
+            while entry
+                .buckets
+                .front()
+                .is_some_and(|bucket| bucket.index + retained <= index)
+            {
+                entry.buckets.pop_front();
+            }
+
+            entry.firing.resize(rules.len(), false);
+            for (rule, firing) in rules.iter().zip(entry.firing.iter_mut()) {
+                let report = self.summarize(&entry.buckets, index, rule.window);
+                let burning = report.burn_rate() > rule.threshold;
+                if burning && !*firing {
+                    let alert = BurnAlert {
+                        backend: backend.to_string(),
+                        window: rule.window,
+                        report,
+                    };
+                    alerts.push((rule.callback.clone(), alert));
+                }
+                *firing = burning;
+            }
+        }
+        for (callback, alert) in alerts {
+            callback(&alert);
+        }
+    }
+
+    /// Outcomes of the operations on a backend over the last `window`
+    pub fn report(&self, backend: &str, window: Duration) -> SloReport {
+        let index = self.bucket_index();
+        match self.lock().backends.get(backend) {
+            Some(entry) => self.summarize(&entry.buckets, index, window),
+            None => SloReport::default(),
+        }
+    }
+
+    /// Names of the backends outcomes were recorded for
+    pub fn backends(&self) -> Vec<String> {
+        self.lock().backends.keys().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn bucket_width(&self) -> u128 {
+        self.inner.config.bucket.as_millis().max(1)
+    }
+
+    fn bucket_index(&self) -> u64 {
+        let now = self
+            .inner
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (now.as_millis() / self.bucket_width()) as u64
+    }
+
+    /// Number of buckets covering a duration, at least one
+    fn buckets_in(&self, duration: Duration) -> u64 {
+        let width = self.bucket_width();
+        (duration.as_millis().div_ceil(width) as u64).max(1)
+    }
+
+    fn summarize(&self, buckets: &VecDeque<Bucket>, index: u64, window: Duration) -> SloReport {
+        let covered = self.buckets_in(window);
+        let mut report = SloReport::default();
+        for bucket in buckets
+            .iter()
+            .filter(|bucket| bucket.index + covered > index)
+        {
+            report.total += bucket.total;
+            report.failures += bucket.failures;
+            report.slow += bucket.slow;
+        }
+        if report.total > 0 {
+            let config = &self.inner.config;
+            let total = report.total as f64;
+            report.availability_burn_rate =
+                burn_rate(report.failures as f64 / total, config.availability);
+            report.latency_burn_rate =
+                burn_rate(report.slow as f64 / total, config.latency_objective);
+        }
+        report
+    }
+}
+
+/// Ratio of an observed error rate to the error budget of an objective
+fn burn_rate(error_rate: f64, objective: f64) -> f64 {
+    let budget = 1.0 - objective.clamp(0.0, 1.0);
+    if budget <= 0.0 {
+        return if error_rate > 0.0 { f64::INFINITY } else { 0.0 };
+    }
+    error_rate / budget
+}
+
+impl Debug for SloReporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("SloReporter")
+            .field("config", &self.inner.config)
+            .field("backends", &state.backends.len())
+            .field("rules", &state.rules.len())
+            .finish()
+    }
+}
+
+/// Whether an error is the backend failing, rather than the operation being rejected
+fn is_failure(err: &Error) -> bool {
+    err.is_retryable() || matches!(err, Error::DeadlineExceeded)
+}
+
+/// Provider wrapper recording the outcome and latency of every operation in a
+/// [`SloReporter`], under the given backend name.
+///
+/// Operations count as failed when the backend fails, with a
+/// [retryable](crate::error::Error::is_retryable) error or an exceeded deadline:
+/// rejections such as missing or too large blobs do not consume the error budget.
+/// Streamed listings are not recorded.
+#[derive(Debug)]
+pub struct SloProvider<P> {
+    inner: P,
+    backend: String,
+    reporter: SloReporter,
+}
+
+impl<P: Provider + Send + Sync> SloProvider<P> {
+    pub fn new<B: ToString>(inner: P, backend: B, reporter: SloReporter) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            reporter,
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn reporter(&self) -> &SloReporter {
+        &self.reporter
+    }
+
+    async fn observe<T, F: Future<Output = Result<T>>>(&self, operation: F) -> Result<T> {
+        let started = Instant::now();
+        let result = operation.await;
+        let success = !matches!(&result, Err(err) if is_failure(err));
+        self.reporter
+            .record(&self.backend, success, started.elapsed());
+        result
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for SloProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.observe(self.inner.get_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.observe(self.inner.get_blob_range(key, range)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.observe(self.inner.get_blob_if_range(key, range, etag))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.observe(self.inner.head_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.observe(self.inner.store_blob(blob)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.observe(self.inner.store_blob_if_absent(blob)).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        BlobWriter::new(move |content| {
+            self.observe(write_through(
+                content,
+                self.inner.open_writer(key, metadata),
+            ))
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.observe(self.inner.is_blob_present(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.observe(self.inner.delete_blob(key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.observe(self.inner.copy_blob(src_key, dst_key)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.observe(
+            self.inner
+                .copy_blob_with_metadata(src_key, dst_key, metadata),
+        )
+        .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.observe(self.inner.list_page(prefix, cursor, limit))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "slo"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.observe(self.inner.warm_up(keys)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::clock::ManualClock;
+    use crate::middleware::slo::{SloConfig, SloReporter};
+
+    #[test]
+    fn it_reports_burn_rates_over_windows() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let config = SloConfig {
+            availability: 0.9,
+            latency_threshold: Duration::from_millis(100),
+            latency_objective: 0.5,
+            retention: Duration::from_secs(60),
+            bucket: Duration::from_secs(1),
+        };
+        let reporter = SloReporter::with_clock(config, clock.clone());
+        let alerts = Arc::new(AtomicUsize::new(0));
+        {
+            let alerts = alerts.clone();
+            reporter.on_burn(Duration::from_secs(10), 2.0, move |alert| {
+                assert_eq!(alert.backend, "s3");
+                alerts.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        for _ in 0..8 {
+            reporter.record("s3", true, Duration::from_millis(10));
+        }
+        reporter.record("s3", true, Duration::from_millis(200));
+        reporter.record("s3", false, Duration::from_millis(10));
+        let report = reporter.report("s3", Duration::from_secs(10));
+        assert_eq!((report.total, report.failures, report.slow), (10, 1, 1));
+        assert!((report.availability_burn_rate - 1.0).abs() < 1e-9);
+        assert!((report.latency_burn_rate - 0.2).abs() < 1e-9);
+        assert_eq!(alerts.load(Ordering::SeqCst), 0);
+
+        // failures burning the budget fire the alert once
+        clock.advance(Duration::from_secs(20));
+        for _ in 0..3 {
+            reporter.record("s3", false, Duration::from_millis(10));
+        }
+        assert_eq!(alerts.load(Ordering::SeqCst), 1);
+        assert_eq!(reporter.report("s3", Duration::from_secs(10)).total, 3);
+        assert_eq!(reporter.report("s3", Duration::from_secs(60)).total, 13);
+
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(reporter.report("s3", Duration::from_secs(60)).total, 0);
+        assert_eq!(
+            reporter
+                .report("fs", Duration::from_secs(60))
+                .success_rate(),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+
+use crate::blob::{Blob, Head, RangeRead};
+use crate::budget::MemoryBudget;
+use crate::digest::{self, DigestAlgorithm};
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::provider::{EntryStream, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Custom metadata key holding the hex SHA-256 of the content of blobs stored
+/// by [`store_blob_if_changed`]
+pub const SHA256_METADATA_KEY: &str = "hold-sha256";
+
+/// Stores a blob unless its key already holds the same content with the same metadata,
+/// in which case the upload is skipped, e.g. when a retried ingestion stores it again.
+///
+/// Contents are compared by checksum, looking up only the metadata of the existing blob:
+/// by the SHA-256 recorded in [`SHA256_METADATA_KEY`] by previous stores, or otherwise by
+/// the MD5 entity tag that S3 and the memory provider assign to blobs uploaded in one part.
+/// The new content is buffered within the given budget while being hashed once.
+/// Since the check and the write are not atomic, a concurrent writer of the same key
+/// may still change it in between.
+pub async fn store_blob_if_changed<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    blob: Blob,
+    budget: &MemoryBudget,
+) -> Result<StoreReceipt> {
+    let key = blob.key().to_string();
+    let existing = match provider.head_blob(&key).await? {
+        Some(existing) if existing.size == blob.size() => existing.metadata,
+        _ => return provider.store_blob(blob).await,
+    };
+
+    let mut metadata = blob.metadata().clone();
+    let content = budget.buffer(blob).await?;
+    let checksums = digest::digest_reader(
+        &[DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+        &mut content.reader()?,
+    )
+    .map_err(Error::body_error)?;
+    let sha256 = checksums
+        .hex(DigestAlgorithm::Sha256)
+        .expect("no SHA-256 computed");
+    let md5 = checksums
+        .hex(DigestAlgorithm::Md5)
+        .expect("no MD5 computed");
+
+    if same_metadata(&existing, &metadata) && same_content(&existing, &sha256, &md5) {
+        log::debug!("Skipping upload of unchanged blob {}", key);
+        return Ok(StoreReceipt {
+            etag: existing.etag,
+            ..StoreReceipt::new(key, content.len())
+        });
+    }
+    metadata
+        .custom
+        .insert(SHA256_METADATA_KEY.to_string(), sha256);
+    provider
+        .store_blob(content.into_blob(key).with_metadata(metadata))
+        .await
+}
+
+fn same_metadata(existing: &BlobMetadata, new: &BlobMetadata) -> bool {
+    let custom = |metadata: &BlobMetadata| {
+        let mut custom = metadata.custom.clone();
+        custom.remove(SHA256_METADATA_KEY);
+        custom
+    };
+    existing.content_type == new.content_type
+        && existing.content_encoding == new.content_encoding
+        && (new.storage_tier.is_none() || existing.storage_tier == new.storage_tier)
+        && custom(existing) == custom(new)
+}
+
+fn same_content(existing: &BlobMetadata, sha256: &str, md5: &str) -> bool {
+    if let Some(existing) = existing.custom.get(SHA256_METADATA_KEY) {
+        return existing == sha256;
+    }
+    // entity tags of multipart uploads are not the MD5 of the content, and never match
+    existing
+        .etag
+        .as_deref()
+        .map(|etag| etag.trim_matches('"'))
+        .is_some_and(|etag| etag.eq_ignore_ascii_case(md5))
+}
+
+/// Provider wrapper skipping the upload of blobs whose key already holds the same
+/// content, see [`store_blob_if_changed`]
+#[derive(Debug)]
+pub struct SkipUnchangedProvider<P> {
+    inner: P,
+    budget: MemoryBudget,
+}
+
+impl<P: Provider + Send + Sync> SkipUnchangedProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            budget: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Buffers blobs within the given budget instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync> Provider for SkipUnchangedProvider<P> {
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        self.inner.get_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        self.inner.get_blob_range(key, range).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        self.inner.get_blob_if_range(key, range, etag).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        self.inner.head_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        store_blob_if_changed(&self.inner, blob, &self.budget).await
+    }
+
+    /// Nothing can be unchanged when the key holds no blob
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        self.inner.store_blob_if_absent(blob).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        self.inner.is_blob_present(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        self.inner.delete_blob(key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        self.inner.copy_blob(src_key, dst_key).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        self.inner
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        self.inner.list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        self.inner.list_blobs_with_options(prefix, options)
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        self.inner.list_page(prefix, cursor, limit).await
+    }
+
+    #[tracing::instrument(skip_all, fields(layer = "unchanged"))]
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        self.inner.warm_up(keys).await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use crate::blob::{Blob, Head};
+    use crate::memory::MemoryProvider;
+    use crate::middleware::unchanged::{SkipUnchangedProvider, SHA256_METADATA_KEY};
+    use crate::provider::{EntryStream, Provider};
+    use crate::receipt::StoreReceipt;
+    use crate::Result;
+
+    /// A provider counting the blobs fetched with their content
+    #[derive(Debug, Default)]
+    struct CountingGets {
+        inner: MemoryProvider,
+        gets: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Provider for CountingGets {
+        async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_blob(key).await
+        }
+
+        async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+            self.inner.head_blob(key).await
+        }
+
+        async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+            self.inner.store_blob(blob).await
+        }
+
+        async fn is_blob_present(&self, key: &str) -> Result<bool> {
+            self.inner.is_blob_present(key).await
+        }
+
+        async fn delete_blob(&self, key: &str) -> Result<()> {
+            self.inner.delete_blob(key).await
+        }
+
+        fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+            self.inner.list_blobs(prefix)
+        }
+    }
+
+    #[test]
+    fn it_skips_uploads_of_unchanged_blobs() {
+        let provider = SkipUnchangedProvider::new(MemoryProvider::new());
+        block_on(async {
+            let stored = provider
+                .inner()
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+
+            // matched by its entity tag, so it is not stored again with a checksum
+            let receipt = provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(receipt.etag, stored.etag);
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert!(!blob.metadata().custom.contains_key(SHA256_METADATA_KEY));
+
+            provider
+                .store_blob(Blob::from_bytes("key", b"changed".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert!(blob.metadata().custom.contains_key(SHA256_METADATA_KEY));
+            assert_eq!(blob.read_content().await.unwrap(), b"changed");
+        });
+    }
+
+    #[test]
+    fn it_compares_blobs_without_fetching_their_content() {
+        let provider = SkipUnchangedProvider::new(CountingGets::default());
+        block_on(async {
+            for _ in 0..2 {
+                provider
+                    .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(provider.inner().gets.load(Ordering::SeqCst), 0);
+        });
+    }
+}
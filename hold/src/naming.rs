@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use crate::blob::Blob;
+use crate::budget::MemoryBudget;
+use crate::digest::{to_hex, DigestAlgorithm};
+use crate::error::Error;
+use crate::provider::Provider;
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Hex characters of the content hash included in hashed keys
+const DEFAULT_HASH_LEN: usize = 8;
+
+/// Derives a versioned key by inserting a content hash before the extension of a name,
+/// e.g. `static/app.js` to `static/app.<hash>.js`
+pub fn hashed_key(name: &str, hash: &str) -> String {
+    let file_start = name.rfind('/').map_or(0, |slash| slash + 1);
+    match name[file_start..].rfind('.') {
+        // a leading dot marks a hidden file rather than an extension
+        Some(dot) if dot > 0 => {
+            let dot = file_start + dot;
+            format!("{}.{}{}", &name[..dot], hash, &name[dot..])
+        }
+        _ => format!("{}.{}", name, hash),
+    }
+}
+
+/// Mapping of logical names to the hashed keys of their current content
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    /// Points a logical name to a hashed key, returning the key it pointed to before
+    pub fn insert<N: ToString, K: ToString>(&mut self, name: N, key: K) -> Option<String> {
+        self.entries.insert(name.to_string(), key.to_string())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.entries.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, key)| (name.as_str(), key.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.iter()
+            .map(|(name, key)| format!("{}\t{}\n", name, key))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn decode(content: &[u8]) -> Result<Self> {
+        let content = std::str::from_utf8(content).map_err(Error::body_error)?;
+        let mut manifest = Manifest::default();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            match line.split_once('\t') {
+                Some((name, key)) => {
+                    manifest.insert(name, key);
+                }
+                None => {
+                    return Err(Error::body_error(format!(
+                        "invalid manifest entry: {}",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Stores blobs under keys derived from their content, for cache-busted static assets.
+///
+/// Each blob is stored under [`hashed_key`] of its name and SHA-256 hash, and a [`Manifest`]
+/// stored in the wrapped provider maps its name to that key.
+/// Hashed keys are never overwritten nor deleted, so clients holding an old manifest
+/// keep finding the content they expect.
+/// Updates to the manifest are not atomic, so concurrent deployments may lose entries.
+#[derive(Debug)]
+pub struct HashedStore<P> {
+    inner: P,
+    manifest_key: String,
+    budget: MemoryBudget,
+    hash_len: usize,
+}
+
+impl<P: Provider + Send + Sync> HashedStore<P> {
+    pub fn new<K: ToString>(inner: P, manifest_key: K) -> Self {
+        Self {
+            inner,
+            manifest_key: manifest_key.to_string(),
+            budget: MemoryBudget::unlimited(),
+            hash_len: DEFAULT_HASH_LEN,
+        }
+    }
+
+    /// Buffers blobs within the given budget while hashing them instead of without limits
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Includes the given number of hex characters of the hash in keys, 8 by default
+    pub fn with_hash_len(mut self, hash_len: usize) -> Self {
+        self.hash_len = hash_len;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub async fn manifest(&self) -> Result<Manifest> {
+        match self.inner.get_blob(&self.manifest_key).await? {
+            Some(manifest) => Manifest::decode(&manifest.read_content().await?),
+            None => Ok(Manifest::default()),
+        }
+    }
+
+    /// Stores a blob under its hashed key and points its name to it in the manifest.
+    /// Content already stored under the same hashed key is not uploaded again.
+    pub async fn store(&self, blob: Blob) -> Result<StoreReceipt> {
+        let name = blob.key().to_string();
+        let metadata = blob.metadata().clone();
+        let content = self.budget.buffer(blob).await?;
+        let hash = DigestAlgorithm::Sha256
+            .digest_reader(&mut content.reader()?)
+            .map_err(Error::body_error)?;
+        let mut hash = to_hex(&hash);
+        hash.truncate(self.hash_len);
+        let key = hashed_key(&name, &hash);
+
+        let receipt = if self.inner.is_blob_present(&key).await? {
+            StoreReceipt::new(&key, content.len())
+        } else {
+            let blob = content.into_blob(&key).with_metadata(metadata);
+            self.inner.store_blob(blob).await?
+        };
+
+        let mut manifest = self.manifest().await?;
+        if manifest.get(&name) != Some(key.as_str()) {
+            manifest.insert(name, &key);
+            self.inner
+                .store_blob(Blob::from_bytes(&self.manifest_key, manifest.encode()))
+                .await?;
+        }
+        Ok(receipt)
+    }
+
+    /// Resolves a logical name to the hashed key of its current content
+    pub async fn resolve(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.manifest().await?.get(name).map(ToString::to_string))
+    }
+
+    /// Fetches the current content of a logical name
+    pub async fn get_blob(&self, name: &str) -> Result<Option<Blob>> {
+        match self.resolve(name).await? {
+            Some(key) => self.inner.get_blob(&key).await,
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::naming::{hashed_key, Manifest};
+
+    #[test]
+    fn it_inserts_hashes_before_extensions() {
+        assert_eq!(hashed_key("app.js", "abc"), "app.abc.js");
+        assert_eq!(
+            hashed_key("static/app.min.js", "abc"),
+            "static/app.min.abc.js"
+        );
+        assert_eq!(hashed_key("v1.2/LICENSE", "abc"), "v1.2/LICENSE.abc");
+        assert_eq!(hashed_key(".env", "abc"), ".env.abc");
+    }
+
+    #[test]
+    fn it_round_trips_manifests() {
+        let mut manifest = Manifest::default();
+        manifest.insert("app.js", "app.abc.js");
+        manifest.insert("style.css", "style.def.css");
+
+        assert_eq!(Manifest::decode(&manifest.encode()).unwrap(), manifest);
+        assert!(Manifest::decode(b"app.js").is_err());
+    }
+}
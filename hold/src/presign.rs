@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::provider::Provider;
+use crate::Result;
+
+/// A storage provider able to hand out time-limited URLs to its blobs,
+/// so that clients can download them directly from the backend
+#[async_trait]
+pub trait SignedUrlProvider: Provider {
+    /// Returns a URL to download a blob without credentials until it expires.
+    /// The blob is not checked for existence.
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String>;
+}
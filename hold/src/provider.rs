@@ -1,18 +1,107 @@
 use std::fmt::Debug;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{future, stream, Stream, TryStreamExt};
 
-use crate::blob::Blob;
+use crate::blob::{etag_matches, Blob, BlobEntry, Head, RangeRead};
+use crate::digest::DigestAlgorithm;
+use crate::error::Error;
+use crate::listing::{Cursor, ListOptions, Page};
+use crate::metadata::BlobMetadata;
+use crate::receipt::StoreReceipt;
+use crate::writer::BlobWriter;
 use crate::Result;
 
+/// Stream of blob entries returned by [`Provider::list_blobs`]
+pub type EntryStream<'a> = Pin<Box<dyn Stream<Item = Result<BlobEntry>> + Send + 'a>>;
+
 /// An abstract storage provider
 #[async_trait]
 pub trait Provider: Debug {
     /// Fetches a blob from the storage provider given its key
     async fn get_blob(&self, key: &str) -> Result<Option<Blob>>;
 
-    /// Stores the given blob and returns it back
-    async fn store_blob(&self, blob: Blob) -> Result<Blob>;
+    /// Stores the given blob and returns a receipt describing what was stored
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt>;
+
+    /// Stores the given blob unless its key already holds a blob, as with an
+    /// `If-None-Match: *` header, failing with [`Error::PreconditionFailed`] otherwise.
+    /// Providers able to write conditionally should override the default implementation,
+    /// which checks that the blob is absent before storing it, and is not atomic.
+    ///
+    /// [`Error::PreconditionFailed`]: crate::error::Error::PreconditionFailed
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        if self.is_blob_present(blob.key()).await? {
+            return Err(Error::precondition_failed(blob.key()));
+        }
+        self.store_blob(blob).await
+    }
+
+    /// Fetches a byte range of a blob, clamped to its size, e.g. to serve HTTP range requests.
+    /// The returned blob only holds the requested content, and fails with
+    /// [`Error::RangeNotSatisfiable`] if the range starts past its end.
+    /// Providers able to read ranges natively should override the default implementation,
+    /// which fetches the whole blob and discards the content outside the range.
+    ///
+    /// [`Error::RangeNotSatisfiable`]: crate::error::Error::RangeNotSatisfiable
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        match self.get_blob(key).await? {
+            Some(blob) => blob.into_range(range).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a byte range of a blob if its entity tag still matches `etag`, as with an
+    /// `If-Range` header, or the whole blob if it changed, so that resumed downloads never
+    /// combine ranges of different versions.
+    /// Providers able to read ranges conditionally should override the default
+    /// implementation, which fetches the whole blob and discards the content outside the
+    /// range when the tags match. Blobs without an entity tag are always read whole.
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        let blob = match self.get_blob(key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        if etag_matches(blob.metadata().etag.as_deref(), etag) {
+            blob.into_range(range)
+                .map(|blob| Some(RangeRead::Range(blob)))
+        } else {
+            Ok(Some(RangeRead::Full(blob)))
+        }
+    }
+
+    /// Fetches the size and metadata of a blob without its content.
+    /// Providers able to look up metadata natively should override the default
+    /// implementation, which fetches the whole blob and drops its content unread.
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        Ok(self.get_blob(key).await?.as_ref().map(Head::of))
+    }
+
+    /// Opens a writer storing what is written to it as a blob, for content whose size
+    /// is not known upfront. The blob is stored once the writer is finished.
+    /// Providers able to store content of unknown size should override the default
+    /// implementation, which buffers the whole content in memory before storing it.
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a>
+    where
+        Self: Sync,
+    {
+        BlobWriter::new(move |content| async move {
+            let chunks: Vec<Bytes> = content.try_collect().await.map_err(Error::body_error)?;
+            let size = chunks.iter().map(Bytes::len).sum();
+            let content = stream::iter(chunks.into_iter().map(Ok));
+            let blob = Blob::new(key, size, content).with_metadata(metadata);
+            self.store_blob(blob).await
+        })
+    }
 
     /// Checks if the blob exists. Some implementation may still be
     /// loading the blob content in memory if the underlying implementation
@@ -21,4 +110,357 @@ pub trait Provider: Debug {
 
     /// Fetches a blob from the storage provider given its key
     async fn delete_blob(&self, key: &str) -> Result<()>;
+
+    /// Copies a blob to another key, returning `None` if the source blob does not exist.
+    /// Providers with a native copy should override the default implementation,
+    /// which fetches the blob and stores it again.
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        let blob = match self.get_blob(src_key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let metadata = blob.metadata().clone();
+        let copy = Blob::new(dst_key, blob.size(), blob.into_byte_stream()).with_metadata(metadata);
+        self.store_blob(copy).await.map(Some)
+    }
+
+    /// Copies a blob to another key with new metadata, replacing the metadata of the source,
+    /// e.g. to fix the content type of a blob. Returns `None` if the source blob does not exist.
+    /// Providers with a native copy should override the default implementation,
+    /// which fetches the blob and stores it again.
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        let blob = match self.get_blob(src_key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let copy = Blob::new(dst_key, blob.size(), blob.into_byte_stream()).with_metadata(metadata);
+        self.store_blob(copy).await.map(Some)
+    }
+
+    /// Lists the blobs whose key starts with the given prefix.
+    /// Further pages are fetched as the stream is consumed, and the order of the
+    /// entries depends on the implementation.
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a>;
+
+    /// Lists the blobs whose key starts with the given prefix and that match the options,
+    /// e.g. to find the blobs modified since the last run of an incremental job.
+    /// Providers able to filter listings natively should override the default
+    /// implementation, which filters the entries of [`Provider::list_blobs`].
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        Box::pin(
+            self.list_blobs(prefix)
+                .try_filter(move |entry| future::ready(options.matches(entry))),
+        )
+    }
+
+    /// Lists a page of at most `limit` blobs whose key starts with the given prefix,
+    /// resuming after the page the cursor was returned with.
+    /// The default implementation lists the whole prefix for every page, and resumes
+    /// after the last key of the previous page, in key order.
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let after = cursor.map(Cursor::token);
+        let mut entries: Vec<BlobEntry> = self
+            .list_blobs(prefix)
+            .try_filter(|entry| future::ready(after.is_none_or(|after| entry.key.as_str() > after)))
+            .try_collect()
+            .await?;
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let limit = limit.max(1);
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|entry| Cursor::new(&entry.key))
+        } else {
+            None
+        };
+        Ok(Page { entries, next })
+    }
+
+    /// Prepares the provider for its first requests, e.g. by resolving its endpoint,
+    /// opening connections or loading credentials, so that they do not pay for the setup.
+    /// `keys` are blobs likely to be requested soon, which providers may prefetch.
+    /// The default implementation does nothing.
+    async fn warm_up(&self, _keys: &[&str]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Checksums the backend verifies natively when a stored blob carries them,
+    /// cheapest first, see [`Blob::with_checksums`]. Wrappers do not verify the
+    /// checksums of the providers they wrap, which the default implementation reflects
+    /// by verifying none.
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        &[]
+    }
+}
+
+/// Stores a blob with [`Provider::store_blob_if_absent`] if `if_absent` is set,
+/// or with [`Provider::store_blob`] otherwise, for wrappers transforming blobs
+/// the same way in both cases
+pub(crate) async fn put_blob<P: Provider + Sync + ?Sized>(
+    provider: &P,
+    blob: Blob,
+    if_absent: bool,
+) -> Result<StoreReceipt> {
+    if if_absent {
+        provider.store_blob_if_absent(blob).await
+    } else {
+        provider.store_blob(blob).await
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync + ?Sized> Provider for &P {
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        (**self).get_blob(key).await
+    }
+
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        (**self).get_blob_range(key, range).await
+    }
+
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        (**self).get_blob_if_range(key, range, etag).await
+    }
+
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        (**self).head_blob(key).await
+    }
+
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob(blob).await
+    }
+
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        (**self).open_writer(key, metadata)
+    }
+
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        (**self).is_blob_present(key).await
+    }
+
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        (**self).delete_blob(key).await
+    }
+
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        (**self).copy_blob(src_key, dst_key).await
+    }
+
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        (**self)
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        (**self).list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        (**self).list_blobs_with_options(prefix, options)
+    }
+
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        (**self).list_page(prefix, cursor, limit).await
+    }
+
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        (**self).warm_up(keys).await
+    }
+
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        (**self).verified_checksums()
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync + ?Sized> Provider for Arc<P> {
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        (**self).get_blob(key).await
+    }
+
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        (**self).get_blob_range(key, range).await
+    }
+
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        (**self).get_blob_if_range(key, range, etag).await
+    }
+
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        (**self).head_blob(key).await
+    }
+
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob(blob).await
+    }
+
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        (**self).open_writer(key, metadata)
+    }
+
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        (**self).is_blob_present(key).await
+    }
+
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        (**self).delete_blob(key).await
+    }
+
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        (**self).copy_blob(src_key, dst_key).await
+    }
+
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        (**self)
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        (**self).list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        (**self).list_blobs_with_options(prefix, options)
+    }
+
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        (**self).list_page(prefix, cursor, limit).await
+    }
+
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        (**self).warm_up(keys).await
+    }
+
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        (**self).verified_checksums()
+    }
+}
+
+#[async_trait]
+impl<P: Provider + Send + Sync + ?Sized> Provider for Box<P> {
+    async fn get_blob(&self, key: &str) -> Result<Option<Blob>> {
+        (**self).get_blob(key).await
+    }
+
+    async fn get_blob_range(&self, key: &str, range: Range<usize>) -> Result<Option<Blob>> {
+        (**self).get_blob_range(key, range).await
+    }
+
+    async fn get_blob_if_range(
+        &self,
+        key: &str,
+        range: Range<usize>,
+        etag: &str,
+    ) -> Result<Option<RangeRead>> {
+        (**self).get_blob_if_range(key, range, etag).await
+    }
+
+    async fn head_blob(&self, key: &str) -> Result<Option<Head>> {
+        (**self).head_blob(key).await
+    }
+
+    async fn store_blob(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob(blob).await
+    }
+
+    async fn store_blob_if_absent(&self, blob: Blob) -> Result<StoreReceipt> {
+        (**self).store_blob_if_absent(blob).await
+    }
+
+    fn open_writer<'a>(&'a self, key: &'a str, metadata: BlobMetadata) -> BlobWriter<'a> {
+        (**self).open_writer(key, metadata)
+    }
+
+    async fn is_blob_present(&self, key: &str) -> Result<bool> {
+        (**self).is_blob_present(key).await
+    }
+
+    async fn delete_blob(&self, key: &str) -> Result<()> {
+        (**self).delete_blob(key).await
+    }
+
+    async fn copy_blob(&self, src_key: &str, dst_key: &str) -> Result<Option<StoreReceipt>> {
+        (**self).copy_blob(src_key, dst_key).await
+    }
+
+    async fn copy_blob_with_metadata(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        metadata: BlobMetadata,
+    ) -> Result<Option<StoreReceipt>> {
+        (**self)
+            .copy_blob_with_metadata(src_key, dst_key, metadata)
+            .await
+    }
+
+    fn list_blobs<'a>(&'a self, prefix: &'a str) -> EntryStream<'a> {
+        (**self).list_blobs(prefix)
+    }
+
+    fn list_blobs_with_options<'a>(
+        &'a self,
+        prefix: &'a str,
+        options: ListOptions,
+    ) -> EntryStream<'a> {
+        (**self).list_blobs_with_options(prefix, options)
+    }
+
+    async fn list_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        (**self).list_page(prefix, cursor, limit).await
+    }
+
+    async fn warm_up(&self, keys: &[&str]) -> Result<()> {
+        (**self).warm_up(keys).await
+    }
+
+    fn verified_checksums(&self) -> &[DigestAlgorithm] {
+        (**self).verified_checksums()
+    }
 }
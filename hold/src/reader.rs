@@ -0,0 +1,101 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncBufRead, AsyncRead};
+use futures::stream::IntoAsyncRead;
+
+use crate::blob::ByteStream;
+
+/// Reader over the content of a blob, see [`Blob::into_async_read`].
+///
+/// Implements the `futures` I/O traits, and the `tokio` ones with the `tokio` feature.
+///
+/// [`Blob::into_async_read`]: crate::blob::Blob::into_async_read
+pub struct BlobReader {
+    content: IntoAsyncRead<ByteStream>,
+}
+
+impl BlobReader {
+    pub(crate) fn new(content: IntoAsyncRead<ByteStream>) -> Self {
+        Self { content }
+    }
+}
+
+impl AsyncRead for BlobReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.content).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for BlobReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().content).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.content).consume(amt)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for BlobReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let read = match AsyncRead::poll_read(self, cx, buf.initialize_unfilled()) {
+            Poll::Ready(Ok(read)) => read,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncBufRead for BlobReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        AsyncBufRead::poll_fill_buf(self, cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        AsyncBufRead::consume(self, amt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+
+    #[test]
+    fn it_reads_blob_content() {
+        use futures::io::AsyncReadExt;
+
+        let blob = Blob::from_bytes("key", b"content".to_vec());
+        let mut content = Vec::new();
+        block_on(blob.into_async_read().read_to_end(&mut content)).unwrap();
+
+        assert_eq!(content, b"content".to_vec());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn it_reads_blob_content_with_tokio() {
+        use tokio::io::AsyncReadExt;
+
+        let blob = Blob::from_bytes("key", b"content".to_vec());
+        let mut content = String::new();
+        block_on(blob.into_async_read().read_to_string(&mut content)).unwrap();
+
+        assert_eq!(content, "content");
+    }
+}
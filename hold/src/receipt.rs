@@ -0,0 +1,42 @@
+use std::time::SystemTime;
+
+use crate::digest::Digests;
+
+/// Information about a blob that has just been stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreReceipt {
+    /// Key the blob has been stored under
+    pub key: String,
+
+    /// Total binary size in bytes of the stored blob
+    pub size: usize,
+
+    /// Entity tag assigned to the blob by the backend, if any
+    pub etag: Option<String>,
+
+    /// Version identifier assigned to the blob by versioned backends
+    pub version_id: Option<String>,
+
+    /// Checksums of the content computed while storing it
+    pub checksums: Digests,
+
+    /// When the blob has been stored
+    pub stored_at: SystemTime,
+
+    /// Identifier of the backend request that stored the blob, for troubleshooting
+    pub request_id: Option<String>,
+}
+
+impl StoreReceipt {
+    pub fn new<K: ToString>(key: K, size: usize) -> Self {
+        Self {
+            key: key.to_string(),
+            size,
+            etag: None,
+            version_id: None,
+            checksums: Digests::default(),
+            stored_at: SystemTime::now(),
+            request_id: None,
+        }
+    }
+}
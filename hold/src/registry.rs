@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
+
+use crate::config::EnvConfig;
+use crate::digest::from_hex;
+use crate::error::Error;
+use crate::middleware::cache::CachedProvider;
+use crate::middleware::compression::{CompressedProvider, Compression};
+use crate::middleware::dry_run::DryRunProvider;
+use crate::middleware::encryption::EncryptedProvider;
+use crate::middleware::immutable::ImmutableProvider;
+use crate::middleware::retry::RetryProvider;
+use crate::middleware::size_limit::SizeLimitProvider;
+use crate::provider::Provider;
+use crate::retry::RetryPolicy;
+use crate::Result;
+
+/// A provider built from configuration, with its middleware stack
+pub type BoxedProvider = Box<dyn Provider + Send + Sync>;
+
+type ProviderFactory = Box<dyn Fn(&EnvConfig) -> Result<BoxedProvider> + Send + Sync>;
+type MiddlewareFactory =
+    Box<dyn Fn(BoxedProvider, &EnvConfig) -> Result<BoxedProvider> + Send + Sync>;
+type SchemeFactory = Box<dyn Fn(&StorageUrl) -> Result<BoxedProvider> + Send + Sync>;
+
+/// Default size of the cache of the `cache` middleware
+const DEFAULT_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default number of blobs cached by the `cache` middleware
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 1024;
+
+/// The providers and middleware that configuration can refer to by name.
+///
+/// The default registry knows the middleware of this crate and, with the `memory`
+/// feature, the `memory` provider and URL scheme. Backend crates register their own
+/// providers and schemes, e.g. `hold_s3::register(Registry::default())`.
+///
+/// The `PROVIDER` variable names the provider, or the `URL` variable points to it as
+/// described by [`Registry::build_url`], and `MIDDLEWARE` lists the comma separated
+/// middleware wrapped around it, each wrapping the stack before it: with
+/// `MIDDLEWARE=retry,cache`, the cache is in front of the retries.
+/// Built-in middleware read their settings from variables prefixed with their name:
+///
+/// - `retry`: `RETRY_MAX_ATTEMPTS`, `RETRY_INITIAL_BACKOFF_MS`, `RETRY_MAX_BACKOFF_MS`
+/// - `cache`: `CACHE_MAX_BYTES`, `CACHE_MAX_ENTRIES`
+/// - `compression`: `COMPRESSION_ALGORITHM` (`gzip` or `zstd`), `COMPRESSION_LEVEL`
+/// - `encryption`: `ENCRYPTION_KEY`, as 64 hex digits
+/// - `size_limit`: `SIZE_LIMIT_MAX_SIZE`
+/// - `immutable` and `dry_run` have no settings
+pub struct Registry {
+    providers: HashMap<String, ProviderFactory>,
+    middleware: HashMap<String, MiddlewareFactory>,
+    schemes: HashMap<String, SchemeFactory>,
+}
+
+impl Registry {
+    /// A registry without any provider or middleware
+    pub fn empty() -> Self {
+        Self {
+            providers: HashMap::new(),
+            middleware: HashMap::new(),
+            schemes: HashMap::new(),
+        }
+    }
+
+    /// Registers a provider built from the configuration when `PROVIDER` is `name`
+    pub fn with_provider<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&EnvConfig) -> Result<BoxedProvider> + Send + Sync + 'static,
+    {
+        self.providers.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Registers a middleware wrapping the stack when `MIDDLEWARE` lists `name`
+    pub fn with_middleware<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(BoxedProvider, &EnvConfig) -> Result<BoxedProvider> + Send + Sync + 'static,
+    {
+        self.middleware.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Registers a provider built from URLs with the given scheme, see [`Registry::build_url`]
+    pub fn with_scheme<F>(mut self, scheme: &str, factory: F) -> Self
+    where
+        F: Fn(&StorageUrl) -> Result<BoxedProvider> + Send + Sync + 'static,
+    {
+        self.schemes
+            .insert(scheme.to_ascii_lowercase(), Box::new(factory));
+        self
+    }
+
+    /// Builds the provider a URL points to with the factory registered for its scheme,
+    /// e.g. `s3://bucket/prefix?region=eu-west-1` or `file:///var/data`
+    pub fn build_url(&self, url: &str) -> Result<BoxedProvider> {
+        let url: StorageUrl = url.parse()?;
+        let factory = self
+            .schemes
+            .get(url.scheme())
+            .ok_or_else(|| Error::config(format!("unknown URL scheme {}", url.scheme())))?;
+        factory(&url)
+    }
+
+    /// Builds the provider and middleware stack the configuration describes
+    pub fn build(&self, config: &EnvConfig) -> Result<BoxedProvider> {
+        let mut provider = match config.get("URL") {
+            Some(url) => self.build_url(url)?,
+            None => {
+                let name = config.require("PROVIDER")?;
+                let factory = self
+                    .providers
+                    .get(name)
+                    .ok_or_else(|| Error::config(format!("unknown provider {}", name)))?;
+                factory(config)?
+            }
+        };
+        for name in config.list("MIDDLEWARE") {
+            let factory = self
+                .middleware
+                .get(name)
+                .ok_or_else(|| Error::config(format!("unknown middleware {}", name)))?;
+            provider = factory(provider, config)?;
+        }
+        Ok(provider)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let registry = Self::empty()
+            .with_middleware("retry", |inner, config| {
+                let default = RetryPolicy::default();
+                let policy = RetryPolicy {
+                    max_attempts: config
+                        .parse("RETRY_MAX_ATTEMPTS")?
+                        .unwrap_or(default.max_attempts),
+                    initial_backoff: config
+                        .millis("RETRY_INITIAL_BACKOFF_MS")?
+                        .unwrap_or(default.initial_backoff),
+                    max_backoff: config
+                        .millis("RETRY_MAX_BACKOFF_MS")?
+                        .unwrap_or(default.max_backoff),
+                    ..default
+                };
+                Ok(Box::new(RetryProvider::new(inner, policy)))
+            })
+            .with_middleware("cache", |inner, config| {
+                let max_bytes = config
+                    .parse("CACHE_MAX_BYTES")?
+                    .unwrap_or(DEFAULT_CACHE_MAX_BYTES);
+                let max_entries = config
+                    .parse("CACHE_MAX_ENTRIES")?
+                    .unwrap_or(DEFAULT_CACHE_MAX_ENTRIES);
+                Ok(Box::new(CachedProvider::new(inner, max_bytes, max_entries)))
+            })
+            .with_middleware("compression", |inner, config| {
+                let level = config.parse("COMPRESSION_LEVEL")?;
+                let compression = match config.get("COMPRESSION_ALGORITHM").unwrap_or("gzip") {
+                    "gzip" => Compression::Gzip {
+                        level: level.map(|level: i32| level.max(0) as u32).unwrap_or(6),
+                    },
+                    "zstd" => Compression::Zstd {
+                        level: level.unwrap_or(3),
+                    },
+                    algorithm => {
+                        return Err(Error::config(format!(
+                            "unknown compression algorithm {}",
+                            algorithm
+                        )))
+                    }
+                };
+                Ok(Box::new(CompressedProvider::new(inner, compression)))
+            })
+            .with_middleware("encryption", |inner, config| {
+                let key = from_hex(config.require("ENCRYPTION_KEY")?)
+                    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                    .ok_or_else(|| Error::config("the encryption key must be 64 hex digits"))?;
+                Ok(Box::new(EncryptedProvider::new(inner, key)))
+            })
+            .with_middleware("size_limit", |inner, config| {
+                let max_size = config.parse_required("SIZE_LIMIT_MAX_SIZE")?;
+                Ok(Box::new(SizeLimitProvider::new(inner, max_size)))
+            })
+            .with_middleware("immutable", |inner, _| {
+                Ok(Box::new(ImmutableProvider::new(inner)))
+            })
+            .with_middleware("dry_run", |inner, _| {
+                Ok(Box::new(DryRunProvider::new(inner)))
+            });
+
+        #[cfg(feature = "memory")]
+        let registry = registry
+            .with_provider("memory", |_| {
+                Ok(Box::new(crate::memory::MemoryProvider::new()))
+            })
+            .with_scheme("memory", |_| {
+                Ok(Box::new(crate::memory::MemoryProvider::new()))
+            });
+
+        registry
+    }
+}
+
+impl Debug for Registry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut providers: Vec<_> = self.providers.keys().collect();
+        let mut middleware: Vec<_> = self.middleware.keys().collect();
+        let mut schemes: Vec<_> = self.schemes.keys().collect();
+        providers.sort();
+        middleware.sort();
+        schemes.sort();
+        f.debug_struct("Registry")
+            .field("providers", &providers)
+            .field("middleware", &middleware)
+            .field("schemes", &schemes)
+            .finish()
+    }
+}
+
+/// A URL pointing to a storage location, as given to [`Registry::build_url`]:
+/// `s3://bucket/prefix?region=eu-west-1` has the `s3` scheme, the `bucket` host,
+/// the `/prefix` path and a `region` parameter.
+///
+/// Hosts, paths and parameters are percent-decoded, and schemes are lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageUrl {
+    scheme: String,
+    host: String,
+    path: String,
+    params: Vec<(String, String)>,
+}
+
+impl StorageUrl {
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// The host, or bucket, of the URL, empty for `file:///var/data`
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The path of the URL, starting with `/` unless empty
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The path without its leading `/`, as a key prefix
+    pub fn prefix(&self) -> &str {
+        self.path.trim_start_matches('/')
+    }
+
+    /// The value of a query parameter, empty values counting as unset
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Parses the value of a query parameter, if set
+    pub fn parse_param<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.param(name)
+            .map(|value| {
+                value.parse().map_err(|err| {
+                    Error::config(format!("invalid URL parameter {}: {}", name, err))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl FromStr for StorageUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = |message: &str| Error::config(format!("invalid URL {}: {}", url, message));
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| invalid("no scheme"))?;
+        let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+        if !valid_scheme {
+            return Err(invalid("malformed scheme"));
+        }
+        if rest.contains('#') {
+            return Err(invalid("fragments are not supported"));
+        }
+
+        let (location, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (host, path) = match location.find('/') {
+            Some(slash) => location.split_at(slash),
+            None => (location, ""),
+        };
+        let decode = |part: &str| percent_decode(part).ok_or_else(|| invalid("malformed escape"));
+        let params = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                Ok((
+                    decode(&name.replace('+', " "))?,
+                    decode(&value.replace('+', " "))?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            host: decode(host)?,
+            path: decode(path)?,
+            params,
+        })
+    }
+}
+
+fn percent_decode(part: &str) -> Option<String> {
+    let bytes = part.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = part.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::config::EnvConfig;
+    use crate::registry::{Registry, StorageUrl};
+
+    #[test]
+    fn it_builds_configured_stacks() {
+        let config = EnvConfig::from_vars(
+            "HOLD",
+            vec![
+                ("HOLD_PROVIDER", "memory"),
+                ("HOLD_MIDDLEWARE", "retry, compression,cache"),
+                ("HOLD_COMPRESSION_ALGORITHM", "zstd"),
+                ("HOLD_CACHE_MAX_ENTRIES", "10"),
+                ("OTHER_PROVIDER", "s3"),
+            ],
+        );
+        let provider = Registry::default().build(&config).unwrap();
+        assert!(format!("{:?}", provider).starts_with("CachedProvider"));
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content");
+        });
+
+        let config = EnvConfig::from_vars(
+            "HOLD",
+            vec![("HOLD_PROVIDER", "memory"), ("HOLD_MIDDLEWARE", "unknown")],
+        );
+        let err = Registry::default().build(&config).unwrap_err();
+        assert_eq!(err.code(), "config_error");
+    }
+
+    #[test]
+    fn it_parses_storage_urls() {
+        let url: StorageUrl = "S3://bucket/photos/caf%C3%A9?region=eu-west-1&part_size=8388608"
+            .parse()
+            .unwrap();
+        assert_eq!(url.scheme(), "s3");
+        assert_eq!(url.host(), "bucket");
+        assert_eq!(url.prefix(), "photos/café");
+        assert_eq!(url.param("region"), Some("eu-west-1"));
+        assert_eq!(url.parse_param("part_size").unwrap(), Some(8388608_usize));
+
+        let url: StorageUrl = "file:///var/data".parse().unwrap();
+        assert_eq!((url.host(), url.path()), ("", "/var/data"));
+        assert!("/var/data".parse::<StorageUrl>().is_err());
+        assert!("s3://bucket/%zz".parse::<StorageUrl>().is_err());
+    }
+
+    #[test]
+    fn it_builds_providers_from_urls() {
+        let config = EnvConfig::from_vars(
+            "HOLD",
+            vec![("HOLD_URL", "memory://"), ("HOLD_MIDDLEWARE", "immutable")],
+        );
+        let provider = Registry::default().build(&config).unwrap();
+        assert!(format!("{:?}", provider).starts_with("ImmutableProvider"));
+
+        let err = Registry::default().build_url("gs://bucket").unwrap_err();
+        assert_eq!(err.code(), "config_error");
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use crate::provider::Provider;
+use crate::Result;
+
+/// Retention state of a blob.
+/// A blob is held, and therefore cannot be deleted or overwritten, while it has
+/// a legal hold or its retention period has not expired yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The blob is retained until this moment, if set
+    pub retain_until: Option<SystemTime>,
+
+    /// A legal hold retains the blob indefinitely, until explicitly cleared
+    pub legal_hold: bool,
+}
+
+impl Retention {
+    pub fn until(retain_until: SystemTime) -> Self {
+        Self {
+            retain_until: Some(retain_until),
+            legal_hold: false,
+        }
+    }
+
+    pub fn legal_hold() -> Self {
+        Self {
+            retain_until: None,
+            legal_hold: true,
+        }
+    }
+
+    /// Checks if the blob is held at the given moment
+    pub fn is_held_at(&self, now: SystemTime) -> bool {
+        self.legal_hold || self.retain_until.is_some_and(|until| until > now)
+    }
+
+    /// Checks if the blob is currently held
+    pub fn is_held(&self) -> bool {
+        self.is_held_at(SystemTime::now())
+    }
+
+    /// Checks if replacing this retention with the given one would shorten
+    /// the retention period, which is never allowed
+    pub fn is_shortened_by(&self, other: &Retention) -> bool {
+        self.is_shortened_by_at(other, SystemTime::now())
+    }
+
+    /// Checks if replacing this retention with the given one at the given moment
+    /// would shorten the retention period
+    pub fn is_shortened_by_at(&self, other: &Retention, now: SystemTime) -> bool {
+        match (self.retain_until, other.retain_until) {
+            (Some(current), Some(new)) => new < current,
+            (Some(current), None) => current > now,
+            (None, _) => false,
+        }
+    }
+}
+
+/// A storage provider able to manage the retention state of its blobs.
+/// Implementations must refuse deleting or overwriting held blobs with
+/// [`Error::Retained`](crate::error::Error::Retained).
+#[async_trait]
+pub trait RetentionProvider: Provider {
+    /// Fetches the retention state of a blob
+    async fn get_retention(&self, key: &str) -> Result<Retention>;
+
+    /// Sets the retention state of a blob. Retention periods can be extended but not shortened.
+    async fn set_retention(&self, key: &str, retention: Retention) -> Result<()>;
+
+    /// Clears the legal hold of a blob, leaving its retention period untouched
+    async fn clear_legal_hold(&self, key: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use crate::retention::Retention;
+
+    #[test]
+    fn it_holds_blobs_until_retention_expires() {
+        let now = SystemTime::now();
+        let retention = Retention::until(now + Duration::from_secs(60));
+
+        assert!(retention.is_held_at(now));
+        assert!(!retention.is_held_at(now + Duration::from_secs(120)));
+        assert!(Retention::legal_hold().is_held_at(now + Duration::from_secs(120)));
+        assert!(!Retention::default().is_held_at(now));
+    }
+
+    #[test]
+    fn it_detects_shortened_retention() {
+        let now = SystemTime::now();
+        let retention = Retention::until(now + Duration::from_secs(60));
+
+        assert!(retention.is_shortened_by(&Retention::until(now)));
+        assert!(retention.is_shortened_by(&Retention::legal_hold()));
+        assert!(!retention.is_shortened_by(&Retention::until(now + Duration::from_secs(120))));
+    }
+}
@@ -0,0 +1,242 @@
+use std::future::Future;
+use std::time::Duration;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream;
+use futures_timer::Delay;
+
+use crate::blob::Blob;
+use crate::metadata::BlobMetadata;
+use crate::provider::Provider;
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Content that can be read again from the start, so that a failed upload can be retried
+/// with a fresh stream. See [`store_with_retry`].
+///
+/// Closures returning a future of a blob are sources, opening the content on each call.
+#[async_trait]
+pub trait ReplayableSource: Send + Sync {
+    /// Opens the content from the start, as a blob to store
+    async fn open(&self) -> Result<Blob>;
+}
+
+#[async_trait]
+impl<F, Fut> ReplayableSource for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Blob>> + Send,
+{
+    async fn open(&self) -> Result<Blob> {
+        self().await
+    }
+}
+
+/// In-memory content, shared by the blobs opened from it rather than copied
+#[derive(Debug, Clone)]
+pub struct BytesSource {
+    key: String,
+    content: Bytes,
+    metadata: BlobMetadata,
+}
+
+impl BytesSource {
+    pub fn new<K: ToString, C: Into<Bytes>>(key: K, content: C) -> Self {
+        Self {
+            key: key.to_string(),
+            content: content.into(),
+            metadata: BlobMetadata::default(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: BlobMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+#[async_trait]
+impl ReplayableSource for BytesSource {
+    async fn open(&self) -> Result<Blob> {
+        let content = self.content.clone();
+        let blob = Blob::new(
+            &self.key,
+            content.len(),
+            stream::once(async { Ok(content) }),
+        );
+        Ok(blob.with_metadata(self.metadata.clone()))
+    }
+}
+
+/// A file on disk, opened again for each attempt. See [`Blob::from_path`].
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct PathSource {
+    key: String,
+    path: std::path::PathBuf,
+    metadata: BlobMetadata,
+}
+
+#[cfg(feature = "tokio")]
+impl PathSource {
+    pub fn new<K: ToString, P: Into<std::path::PathBuf>>(key: K, path: P) -> Self {
+        Self {
+            key: key.to_string(),
+            path: path.into(),
+            metadata: BlobMetadata::default(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: BlobMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl ReplayableSource for PathSource {
+    async fn open(&self) -> Result<Blob> {
+        let blob = Blob::from_path(&self.key, &self.path).await?;
+        Ok(blob.with_metadata(self.metadata.clone()))
+    }
+}
+
+/// How failed operations are attempted again
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts made in total, including the first one
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, multiplied by `multiplier` after each retry
+    pub initial_backoff: Duration,
+
+    pub max_backoff: Duration,
+
+    pub multiplier: f64,
+
+    /// Fraction of each wait taken off at random, from 0 to 1,
+    /// so that clients failing together do not all retry together
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// The wait before the given retry, starting from 1
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(retry as i32 - 1);
+        let backoff = self
+            .initial_backoff
+            .mul_f64(factor.min(u32::MAX as f64))
+            .min(self.max_backoff);
+        let random = OsRng.next_u32() as f64 / u32::MAX as f64;
+        backoff.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+/// Stores the content of a source, opening it again to retry the upload when it fails
+/// with a [retryable](crate::error::Error::is_retryable) error
+pub async fn store_with_retry<P, S>(
+    provider: &P,
+    source: &S,
+    policy: &RetryPolicy,
+) -> Result<StoreReceipt>
+where
+    P: Provider + ?Sized,
+    S: ReplayableSource + ?Sized,
+{
+    retrying(policy, "Storing blob", || async move {
+        provider.store_blob(source.open().await?).await
+    })
+    .await
+}
+
+/// Runs an operation until it succeeds, fails with an error that is not retryable,
+/// or runs out of attempts. Retries wait at least as long as errors hint at.
+pub(crate) async fn retrying<T, F, Fut>(
+    policy: &RetryPolicy,
+    operation: &str,
+    mut attempt_operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match attempt_operation().await {
+            Err(err) if err.is_retryable() && attempt < policy.max_attempts => {
+                let backoff = policy
+                    .backoff(attempt)
+                    .max(err.retry_after().unwrap_or_default());
+                log::warn!(
+                    "{} failed on attempt {}, retrying in {:?}: {}",
+                    operation,
+                    attempt,
+                    backoff,
+                    err
+                );
+                Delay::new(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures::{stream, StreamExt};
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::provider::Provider;
+    use crate::retry::{store_with_retry, BytesSource, RetryPolicy};
+
+    #[test]
+    fn it_replays_sources_on_retryable_failures() {
+        let provider = MemoryProvider::new();
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+
+        // a source whose stream breaks on the first attempt
+        let attempts = AtomicUsize::new(0);
+        let source = || async {
+            let content = stream::iter(vec![Ok("first".into())]);
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                let broken = std::io::Error::other("connection reset");
+                let content = content.chain(stream::iter(vec![Err(broken)]));
+                Ok(Blob::new("key", 5, content))
+            } else {
+                Ok(Blob::new("key", 5, content))
+            }
+        };
+        let receipt = block_on(store_with_retry(&provider, &source, &policy)).unwrap();
+        assert_eq!(receipt.size, 5);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let source = BytesSource::new("copy", b"content".to_vec());
+        block_on(store_with_retry(&provider, &source, &policy)).unwrap();
+        assert!(block_on(provider.is_blob_present("copy")).unwrap());
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::middleware::cache::CachedProvider;
+use crate::middleware::compression::{CompressedProvider, Compression};
+use crate::middleware::dry_run::DryRunProvider;
+use crate::middleware::encryption::EncryptedProvider;
+use crate::middleware::immutable::ImmutableProvider;
+use crate::middleware::retry::RetryProvider;
+use crate::middleware::size_limit::SizeLimitProvider;
+use crate::provider::Provider;
+use crate::registry::BoxedProvider;
+use crate::retry::RetryPolicy;
+use crate::Result;
+
+/// Builder of a middleware stack around a provider.
+///
+/// Wrappers are stacked in a fixed order whatever the order they are added in,
+/// from the provider outwards: retries, encryption, compression, immutability,
+/// size limit, cache, dry run. Retries only repeat backend calls, content is
+/// compressed before being encrypted, limits apply to the content as given by the
+/// application, and cache hits skip every other wrapper.
+///
+/// [`Stack::build`] fails with [`Error::ConfigError`] when a wrapper is added twice
+/// or configured with settings it cannot work with.
+#[derive(Debug)]
+pub struct Stack {
+    provider: BoxedProvider,
+    layers: Vec<Layer>,
+}
+
+/// The wrappers a [`Stack`] can add, in stacking order
+#[derive(Debug)]
+enum Layer {
+    Retry(RetryPolicy),
+    Encryption(Key),
+    Compression(Compression),
+    Immutable,
+    SizeLimit(usize),
+    Cache {
+        max_bytes: usize,
+        max_entries: usize,
+    },
+    DryRun,
+}
+
+/// An encryption key, redacted from debug output
+struct Key([u8; 32]);
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Layer {
+    fn order(&self) -> usize {
+        match self {
+            Layer::Retry(_) => 0,
+            Layer::Encryption(_) => 1,
+            Layer::Compression(_) => 2,
+            Layer::Immutable => 3,
+            Layer::SizeLimit(_) => 4,
+            Layer::Cache { .. } => 5,
+            Layer::DryRun => 6,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Layer::Retry(_) => "retry",
+            Layer::Encryption(_) => "encryption",
+            Layer::Compression(_) => "compression",
+            Layer::Immutable => "immutable",
+            Layer::SizeLimit(_) => "size limit",
+            Layer::Cache { .. } => "cache",
+            Layer::DryRun => "dry run",
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let invalid = match self {
+            Layer::Retry(policy) if policy.max_attempts == 0 => "at least one attempt is needed",
+            Layer::Compression(Compression::Gzip { level }) if *level > 9 => {
+                "gzip levels range from 0 to 9"
+            }
+            Layer::Compression(Compression::Zstd { level }) if *level > 22 => {
+                "zstd levels go up to 22"
+            }
+            Layer::Cache {
+                max_bytes,
+                max_entries,
+            } if *max_bytes == 0 || *max_entries == 0 => "the cache cannot hold anything",
+            _ => return Ok(()),
+        };
+        Err(Error::config(format!(
+            "invalid {}: {}",
+            self.name(),
+            invalid
+        )))
+    }
+
+    fn wrap(self, inner: BoxedProvider) -> BoxedProvider {
+        match self {
+            Layer::Retry(policy) => Box::new(RetryProvider::new(inner, policy)),
+            Layer::Encryption(Key(key)) => Box::new(EncryptedProvider::new(inner, key)),
+            Layer::Compression(compression) => {
+                Box::new(CompressedProvider::new(inner, compression))
+            }
+            Layer::Immutable => Box::new(ImmutableProvider::new(inner)),
+            Layer::SizeLimit(max_size) => Box::new(SizeLimitProvider::new(inner, max_size)),
+            Layer::Cache {
+                max_bytes,
+                max_entries,
+            } => Box::new(CachedProvider::new(inner, max_bytes, max_entries)),
+            Layer::DryRun => Box::new(DryRunProvider::new(inner)),
+        }
+    }
+}
+
+impl Stack {
+    pub fn new<P: Provider + Send + Sync + 'static>(provider: P) -> Self {
+        Self {
+            provider: Box::new(provider),
+            layers: Vec::new(),
+        }
+    }
+
+    /// Retries transient failures, see [`RetryProvider`]
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        self.with(Layer::Retry(policy))
+    }
+
+    /// Encrypts content client-side, see [`EncryptedProvider`]
+    pub fn with_encryption(self, key: [u8; 32]) -> Self {
+        self.with(Layer::Encryption(Key(key)))
+    }
+
+    /// Compresses content, see [`CompressedProvider`]
+    pub fn with_compression(self, compression: Compression) -> Self {
+        self.with(Layer::Compression(compression))
+    }
+
+    /// Refuses to overwrite blobs, see [`ImmutableProvider`]
+    pub fn with_immutability(self) -> Self {
+        self.with(Layer::Immutable)
+    }
+
+    /// Rejects blobs larger than a maximum size, see [`SizeLimitProvider`]
+    pub fn with_size_limit(self, max_size: usize) -> Self {
+        self.with(Layer::SizeLimit(max_size))
+    }
+
+    /// Caches fetched blobs in memory, see [`CachedProvider`]
+    pub fn with_cache(self, max_bytes: usize, max_entries: usize) -> Self {
+        self.with(Layer::Cache {
+            max_bytes,
+            max_entries,
+        })
+    }
+
+    /// Previews writes without executing them, see [`DryRunProvider`]
+    pub fn with_dry_run(self) -> Self {
+        self.with(Layer::DryRun)
+    }
+
+    fn with(mut self, layer: Layer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Validates the wrappers and stacks them around the provider
+    pub fn build(self) -> Result<Arc<dyn Provider + Send + Sync>> {
+        let mut layers = self.layers;
+        layers.sort_by_key(Layer::order);
+        for (index, layer) in layers.iter().enumerate() {
+            layer.validate()?;
+            if index > 0 && layers[index - 1].order() == layer.order() {
+                return Err(Error::config(format!("{} is added twice", layer.name())));
+            }
+        }
+        let provider = layers
+            .into_iter()
+            .fold(self.provider, |inner, layer| layer.wrap(inner));
+        Ok(Arc::from(provider))
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::middleware::compression::Compression;
+    use crate::retry::RetryPolicy;
+    use crate::stack::Stack;
+
+    #[test]
+    fn it_stacks_wrappers_in_a_fixed_order() {
+        let provider = Stack::new(MemoryProvider::new())
+            .with_cache(1024, 10)
+            .with_compression(Compression::gzip())
+            .with_retry(RetryPolicy::default())
+            .build()
+            .unwrap();
+        let layers = format!("{:?}", provider);
+        let positions: Vec<_> = ["CachedProvider", "CompressedProvider", "RetryProvider"]
+            .iter()
+            .map(|name| layers.find(name).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        block_on(async {
+            provider
+                .store_blob(Blob::from_bytes("key", b"content".to_vec()))
+                .await
+                .unwrap();
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content");
+        });
+
+        let err = Stack::new(MemoryProvider::new())
+            .with_dry_run()
+            .with_dry_run()
+            .build()
+            .unwrap_err();
+        assert_eq!(err.code(), "config_error");
+    }
+}
@@ -0,0 +1,125 @@
+use std::io;
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{future, SinkExt, StreamExt};
+
+use crate::blob::Blob;
+use crate::provider::{put_blob, Provider};
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Chunks buffered for each destination by [`tee_store`]
+const DEFAULT_BUFFER_CHUNKS: usize = 8;
+
+/// Stores a blob in several providers at once, reading its content only once.
+/// See [`tee_store_buffered`].
+pub async fn tee_store(
+    blob: Blob,
+    providers: &[&(dyn Provider + Send + Sync)],
+) -> Vec<Result<StoreReceipt>> {
+    tee_store_buffered(blob, providers, DEFAULT_BUFFER_CHUNKS).await
+}
+
+/// Stores a blob in several providers at once unless their key already holds a blob,
+/// reading its content only once. Each provider stores it with
+/// [`Provider::store_blob_if_absent`], so a failing precondition in one of them
+/// does not stop the others. See [`tee_store_buffered`].
+pub async fn tee_store_if_absent(
+    blob: Blob,
+    providers: &[&(dyn Provider + Send + Sync)],
+) -> Vec<Result<StoreReceipt>> {
+    tee(blob, providers, DEFAULT_BUFFER_CHUNKS, true).await
+}
+
+/// Stores a blob in several providers at once, reading its content only once.
+///
+/// Each destination buffers up to `chunks` chunks of content, and the source is read
+/// at the pace of the slowest destination.
+/// A failing destination does not stop the others, so the results are returned
+/// for each provider, in order.
+pub async fn tee_store_buffered(
+    blob: Blob,
+    providers: &[&(dyn Provider + Send + Sync)],
+    chunks: usize,
+) -> Vec<Result<StoreReceipt>> {
+    tee(blob, providers, chunks, false).await
+}
+
+async fn tee(
+    blob: Blob,
+    providers: &[&(dyn Provider + Send + Sync)],
+    chunks: usize,
+    if_absent: bool,
+) -> Vec<Result<StoreReceipt>> {
+    let key = blob.key().to_string();
+    let size = blob.size();
+    let metadata = blob.metadata().clone();
+
+    let mut senders = Vec::with_capacity(providers.len());
+    let mut stores = Vec::with_capacity(providers.len());
+    for provider in providers {
+        let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(chunks);
+        senders.push(Some(sender));
+        let blob = Blob::new(&key, size, receiver).with_metadata(metadata.clone());
+        stores.push(put_blob(*provider, blob, if_absent));
+    }
+
+    let feed = async move {
+        let mut source = blob.into_byte_stream();
+        while let Some(chunk) = source.next().await {
+            for slot in senders.iter_mut() {
+                let sender = match slot {
+                    Some(sender) => sender,
+                    None => continue,
+                };
+                let item = match &chunk {
+                    Ok(chunk) => Ok(chunk.clone()),
+                    Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+                };
+                // a destination that stopped reading has failed on its own
+                if sender.send(item).await.is_err() {
+                    *slot = None;
+                }
+            }
+            if chunk.is_err() || senders.iter().all(Option::is_none) {
+                break;
+            }
+        }
+    };
+
+    let (_, receipts) = future::join(feed, future::join_all(stores)).await;
+    receipts
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::stream;
+
+    use crate::blob::Blob;
+    use crate::memory::MemoryProvider;
+    use crate::provider::Provider;
+    use crate::tee::tee_store_buffered;
+
+    #[test]
+    fn it_stores_the_same_content_everywhere() {
+        let primary = MemoryProvider::new();
+        let archive = MemoryProvider::new();
+        let content: Vec<u8> = (0..=255).collect();
+        let chunks: Vec<_> = content
+            .chunks(16)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let blob = Blob::new("key", content.len(), stream::iter(chunks));
+
+        let receipts = block_on(tee_store_buffered(blob, &[&primary, &archive], 1));
+
+        assert!(receipts.iter().all(Result::is_ok));
+        for provider in &[primary, archive] {
+            let blob = block_on(provider.get_blob("key")).unwrap().unwrap();
+            assert_eq!(block_on(blob.read_content()).unwrap(), content);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Backend-agnostic storage class of a blob, trading access cost and latency for storage cost.
+/// Providers map each tier to their native storage classes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StorageTier {
+    /// Frequently accessed data
+    Hot,
+
+    /// Infrequently accessed data, still available immediately
+    Cool,
+
+    /// Rarely accessed data, with higher retrieval costs
+    Cold,
+
+    /// Long-term archival, possibly requiring a restore before being read
+    Archive,
+
+    /// A provider-specific storage class, passed as-is to the backend
+    Custom(String),
+}
+
+impl StorageTier {
+    pub fn custom<S: ToString>(class: S) -> Self {
+        StorageTier::Custom(class.to_string())
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            StorageTier::Hot => "hot",
+            StorageTier::Cool => "cool",
+            StorageTier::Cold => "cold",
+            StorageTier::Archive => "archive",
+            StorageTier::Custom(class) => class,
+        }
+    }
+}
+
+impl Display for StorageTier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::clock::{Clock, SystemClock};
+use crate::digest::{from_hex, to_hex};
+use crate::error::Error;
+use crate::Result;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Operation an access token can grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Read,
+    Write,
+    Delete,
+    List,
+}
+
+impl Operation {
+    const ALL: [Operation; 4] = [
+        Operation::Read,
+        Operation::Write,
+        Operation::Delete,
+        Operation::List,
+    ];
+
+    fn code(self) -> char {
+        match self {
+            Operation::Read => 'r',
+            Operation::Write => 'w',
+            Operation::Delete => 'd',
+            Operation::List => 'l',
+        }
+    }
+}
+
+/// What an access token grants: some operations on the keys under a prefix, until it expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessScope {
+    pub prefix: String,
+    pub operations: Vec<Operation>,
+    pub expires_at: SystemTime,
+}
+
+impl AccessScope {
+    /// A scope granting the given operations under a prefix for the given time
+    pub fn new<P: ToString>(prefix: P, operations: &[Operation], ttl: Duration) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            operations: operations.to_vec(),
+            expires_at: SystemTime::now() + ttl,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        self.expires_at <= now
+    }
+
+    /// Checks whether the scope grants an operation on a key, disregarding expiry
+    pub fn allows(&self, operation: Operation, key: &str) -> bool {
+        self.operations.contains(&operation) && key.starts_with(&self.prefix)
+    }
+
+    fn encode(&self) -> String {
+        let expires_at = self
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let operations: String = Operation::ALL
+            .iter()
+            .filter(|operation| self.operations.contains(operation))
+            .map(|operation| operation.code())
+            .collect();
+        format!("{}:{}:{}", expires_at, operations, self.prefix)
+    }
+
+    fn decode(payload: &str) -> Option<Self> {
+        let mut parts = payload.splitn(3, ':');
+        let expires_at = parts.next()?.parse().ok()?;
+        let operations = parts
+            .next()?
+            .chars()
+            .map(|code| {
+                Operation::ALL
+                    .iter()
+                    .copied()
+                    .find(|operation| operation.code() == code)
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            prefix: parts.next()?.to_string(),
+            operations,
+            expires_at: UNIX_EPOCH + Duration::from_secs(expires_at),
+        })
+    }
+}
+
+/// Mints and verifies short-lived access tokens, so that services can delegate
+/// narrow blob access without sharing backend credentials.
+///
+/// Tokens are signed with HMAC-SHA256, so they can only be verified by holders
+/// of the same secret. They are not encrypted: their scope is readable by anyone.
+pub struct TokenSigner {
+    secret: Vec<u8>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TokenSigner {
+    pub fn new<S: Into<Vec<u8>>>(secret: S) -> Self {
+        Self {
+            secret: secret.into(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Uses the given clock to decide whether tokens have expired
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn mint(&self, scope: &AccessScope) -> String {
+        let payload = to_hex(scope.encode().as_bytes());
+        let signature = to_hex(&self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Verifies the signature and expiry of a token, returning the scope it grants
+    pub fn verify(&self, token: &str) -> Result<AccessScope> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| Error::invalid_token("malformed token"))?;
+        let signature =
+            from_hex(signature).ok_or_else(|| Error::invalid_token("malformed token"))?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| Error::invalid_token("invalid signature"))?;
+
+        let scope = from_hex(payload)
+            .and_then(|payload| String::from_utf8(payload).ok())
+            .and_then(|payload| AccessScope::decode(&payload))
+            .ok_or_else(|| Error::invalid_token("malformed token"))?;
+        if scope.is_expired_at(self.clock.now()) {
+            return Err(Error::invalid_token("token expired"));
+        }
+        Ok(scope)
+    }
+
+    /// Verifies a token and checks that it grants an operation on a key
+    pub fn authorize(&self, token: &str, operation: Operation, key: &str) -> Result<AccessScope> {
+        let scope = self.verify(token)?;
+        if !scope.allows(operation, key) {
+            return Err(Error::invalid_token(format!(
+                "token does not grant {:?} on {}",
+                operation, key
+            )));
+        }
+        Ok(scope)
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+impl Debug for TokenSigner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field("secret", &"<redacted>")
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use crate::clock::ManualClock;
+    use crate::token::{AccessScope, Operation, TokenSigner};
+
+    #[test]
+    fn it_grants_the_minted_scope() {
+        let signer = TokenSigner::new("secret");
+        let scope = AccessScope::new("uploads/42/", &[Operation::Read], Duration::from_secs(60));
+        let token = signer.mint(&scope);
+
+        assert!(signer
+            .authorize(&token, Operation::Read, "uploads/42/avatar.png")
+            .is_ok());
+        assert!(signer
+            .authorize(&token, Operation::Write, "uploads/42/avatar.png")
+            .is_err());
+        assert!(signer
+            .authorize(&token, Operation::Read, "uploads/43/avatar.png")
+            .is_err());
+        assert!(TokenSigner::new("other").verify(&token).is_err());
+    }
+
+    #[test]
+    fn it_rejects_expired_tokens() {
+        let signer = TokenSigner::new("secret");
+        let scope = AccessScope {
+            expires_at: SystemTime::now() - Duration::from_secs(1),
+            ..AccessScope::new("", &[Operation::List], Duration::from_secs(0))
+        };
+
+        assert!(signer.verify(&signer.mint(&scope)).is_err());
+    }
+
+    #[test]
+    fn it_expires_tokens_as_the_clock_advances() {
+        let clock = ManualClock::new(SystemTime::now());
+        let signer = TokenSigner::new("secret").with_clock(clock.clone());
+        let scope = AccessScope::new("", &[Operation::List], Duration::from_secs(60));
+        let token = signer.mint(&scope);
+
+        assert!(signer.verify(&token).is_ok());
+        clock.advance(Duration::from_secs(61));
+        assert!(signer.verify(&token).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use crate::blob::Blob;
+use crate::provider::Provider;
+use crate::Result;
+
+/// A storage provider keeping previous versions of its blobs
+#[async_trait]
+pub trait VersionedProvider: Provider {
+    /// Fetches the version of a blob that was current at the given moment.
+    /// Returns `None` if the blob did not exist yet, or was deleted at that moment.
+    async fn get_blob_at(&self, key: &str, at: SystemTime) -> Result<Option<Blob>>;
+}
@@ -0,0 +1,171 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use futures::{future, Sink, Stream, StreamExt};
+
+use crate::error::Error;
+use crate::receipt::StoreReceipt;
+use crate::Result;
+
+/// Chunks written to a [`BlobWriter`] buffered before being consumed
+const BUFFER_CHUNKS: usize = 8;
+
+/// Content stream of a blob written through a [`BlobWriter`]
+pub type WrittenContent = mpsc::Receiver<io::Result<Bytes>>;
+
+type StoreFuture<'a> = Pin<Box<dyn Future<Output = Result<StoreReceipt>> + Send + 'a>>;
+
+/// Writer storing the bytes written to it as a blob, for producers that do not
+/// know the size of the content upfront. See [`Provider::open_writer`].
+///
+/// The blob is stored when the writer is closed, and [`BlobWriter::finish`] returns
+/// the receipt. Dropping the writer before closing it abandons the blob.
+///
+/// [`Provider::open_writer`]: crate::provider::Provider::open_writer
+pub struct BlobWriter<'a> {
+    sender: Option<mpsc::Sender<io::Result<Bytes>>>,
+    store: Option<StoreFuture<'a>>,
+    receipt: Option<Result<StoreReceipt>>,
+}
+
+impl<'a> BlobWriter<'a> {
+    /// A writer passing what is written to the future returned by `store`,
+    /// which is driven as the writer is written to and closed
+    pub fn new<F, Fut>(store: F) -> Self
+    where
+        F: FnOnce(WrittenContent) -> Fut,
+        Fut: Future<Output = Result<StoreReceipt>> + Send + 'a,
+    {
+        let (sender, receiver) = mpsc::channel(BUFFER_CHUNKS);
+        Self {
+            sender: Some(sender),
+            store: Some(Box::pin(store(receiver))),
+            receipt: None,
+        }
+    }
+
+    /// Closes the writer and returns the receipt of the stored blob
+    pub async fn finish(mut self) -> Result<StoreReceipt> {
+        // failures are kept in the receipt
+        let _ = future::poll_fn(|cx| Pin::new(&mut self).poll_close(cx)).await;
+        self.receipt
+            .take()
+            .unwrap_or_else(|| Err(Error::body_error("blob writer already finished")))
+    }
+
+    /// Drives the store future, returning the error it failed with, if any
+    fn poll_store(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(store) = self.store.as_mut() {
+            match store.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    self.receipt = Some(result);
+                    self.store = None;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match &self.receipt {
+            Some(Err(err)) => Poll::Ready(Err(io::Error::other(err.to_string()))),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl AsyncWrite for BlobWriter<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Poll::Ready(Err(err)) = this.poll_store(cx) {
+            return Poll::Ready(Err(err));
+        }
+        let sender = match this.sender.as_mut() {
+            Some(sender) => sender,
+            None => return Poll::Ready(Err(io::Error::other("blob writer is closed"))),
+        };
+        match Pin::new(&mut *sender).poll_ready(cx) {
+            Poll::Ready(Ok(())) => {}
+            // the store stopped reading, so it is about to fail or succeed early
+            Poll::Ready(Err(_)) => {
+                return match this.poll_store(cx) {
+                    Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                    _ => Poll::Ready(Err(io::Error::other("blob content is no longer read"))),
+                }
+            }
+            Poll::Pending => return Poll::Pending,
+        }
+        match Pin::new(sender).start_send(Ok(Bytes::copy_from_slice(buf))) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(io::Error::other("blob content is no longer read"))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // written chunks are handed over as they come, so there is nothing to flush
+        match self.get_mut().poll_store(cx) {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // ends the content stream
+        this.sender = None;
+        this.poll_store(cx)
+    }
+}
+
+/// Writes a content stream to a writer, e.g. the one of a wrapped provider, and finishes it.
+/// Content errors abandon the blob, while write errors are those of the store the writer
+/// reports when finished.
+pub(crate) async fn write_through<S>(content: S, mut writer: BlobWriter<'_>) -> Result<StoreReceipt>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    futures::pin_mut!(content);
+    let mut written = Ok(());
+    while let Some(chunk) = content.next().await {
+        // dropping the writer abandons the blob
+        let chunk = chunk.map_err(Error::body_error)?;
+        written = writer.write_all(&chunk).await;
+        if written.is_err() {
+            break;
+        }
+    }
+    let receipt = writer.finish().await?;
+    written.map_err(Error::body_error)?;
+    Ok(receipt)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod test {
+    use futures::executor::block_on;
+    use futures::io::AsyncWriteExt;
+
+    use crate::memory::MemoryProvider;
+    use crate::metadata::BlobMetadata;
+    use crate::provider::Provider;
+
+    #[test]
+    fn it_stores_written_content_once_finished() {
+        let provider = MemoryProvider::new();
+        block_on(async {
+            let mut writer = provider.open_writer("key", BlobMetadata::default());
+            writer.write_all(b"con").await.unwrap();
+            writer.write_all(b"tent").await.unwrap();
+            assert!(!provider.is_blob_present("key").await.unwrap());
+            assert_eq!(writer.finish().await.unwrap().size, 7);
+
+            let blob = provider.get_blob("key").await.unwrap().unwrap();
+            assert_eq!(blob.read_content().await.unwrap(), b"content".to_vec());
+        });
+    }
+}